@@ -4,7 +4,7 @@ use anyhow::Error as AnyhowError;
 use auth_server_rust::static_files::login_html;
 use handlebars::RenderError;
 use stack_string::StackString;
-use std::fmt::Debug;
+use std::{fmt::Debug, io::Error as IoError};
 use subprocess::PopenError;
 use thiserror::Error;
 
@@ -19,6 +19,10 @@ pub enum ServiceError {
     BadRequest(StackString),
     #[error("Unauthorized")]
     Unauthorized,
+    #[error("Forbidden")]
+    Forbidden,
+    #[error("Invalid token")]
+    InvalidToken,
     #[error("Anyhow error {0}")]
     AnyhowError(#[from] AnyhowError),
     #[error("blocking error {0}")]
@@ -27,6 +31,8 @@ pub enum ServiceError {
     PopenError(#[from] PopenError),
     #[error("Template Parse Error {0}")]
     RenderError(#[from] RenderError),
+    #[error("io Error {0}")]
+    IoError(#[from] IoError),
 }
 
 // impl ResponseError trait allows to convert our errors into http responses
@@ -39,6 +45,8 @@ impl ResponseError for ServiceError {
                 TRIGGER_DB_UPDATE.set();
                 login_html()
             }
+            Self::Forbidden => HttpResponse::Forbidden().json("Forbidden"),
+            Self::InvalidToken => HttpResponse::Unauthorized().json("Invalid token"),
             _ => {
                 HttpResponse::InternalServerError().json("Internal Server Error, Please try later")
             }
@@ -0,0 +1,110 @@
+use actix_web::web::Bytes;
+use futures::{future, stream, Stream, StreamExt};
+use log::debug;
+use stack_string::StackString;
+use std::time::Duration;
+use tokio::{
+    sync::broadcast::{channel, Receiver, RecvError, Sender},
+    time::{delay_for, interval},
+};
+
+use movie_collection_lib::events::{listen_for_events, Event};
+
+use super::errors::ServiceError as Error;
+
+/// Events a slow browser can fall behind by before it starts missing them.
+const EVENT_BUFFER: usize = 64;
+
+/// Proxies drop idle connections, so a comment is sent this often.
+const KEEPALIVE_SECONDS: u64 = 30;
+
+const RECONNECT_SECONDS: u64 = 10;
+
+/// Format an event as a server sent event, the `event:` field is the event
+/// name so the page can use `addEventListener("queue_changed", ...)`.
+pub fn format_sse(event: &Event) -> Result<String, Error> {
+    let data = serde_json::to_string(event).map_err(anyhow::Error::from)?;
+    Ok(format!("event: {}\ndata: {}\n\n", event.name(), data))
+}
+
+/// Fans out the events published through postgres by the library, the
+/// command line tools and the transcode service to every open
+/// `/list/events` stream.
+#[derive(Clone)]
+pub struct EventBus {
+    sender: Sender<Event>,
+}
+
+impl Default for EventBus {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl EventBus {
+    pub fn new() -> Self {
+        let (sender, _) = channel(EVENT_BUFFER);
+        Self { sender }
+    }
+
+    pub fn subscribe(&self) -> Receiver<Event> {
+        self.sender.subscribe()
+    }
+
+    /// Listen for notifications for the lifetime of the server, reconnecting
+    /// whenever the connection is lost.
+    pub async fn run(self, pgurl: StackString) {
+        loop {
+            if let Err(e) = listen_for_events(&pgurl, self.sender.clone()).await {
+                debug!("event listener failed {}", e);
+            }
+            delay_for(Duration::from_secs(RECONNECT_SECONDS)).await;
+        }
+    }
+
+    /// Body of `/list/events`, the events visible to `user` interleaved with
+    /// keepalive comments.
+    pub fn event_stream(&self, user: StackString) -> impl Stream<Item = Result<Bytes, Error>> {
+        let events = stream::unfold(self.subscribe(), |mut receiver| async move {
+            loop {
+                match receiver.recv().await {
+                    Ok(event) => return Some((event, receiver)),
+                    // The page refreshes on the next event anyway
+                    Err(RecvError::Lagged(_)) => continue,
+                    Err(RecvError::Closed) => return None,
+                }
+            }
+        })
+        .filter(move |event| future::ready(event.is_visible_to(&user)))
+        .map(|event| format_sse(&event));
+
+        let keepalive = stream::unfold(
+            interval(Duration::from_secs(KEEPALIVE_SECONDS)),
+            |mut i| async move {
+                i.tick().await;
+                Some((Ok(": keepalive\n\n".to_string()), i))
+            },
+        );
+
+        stream::select(events, keepalive).map(|s| s.map(Bytes::from))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use movie_collection_lib::events::Event;
+
+    use crate::event_bus::format_sse;
+
+    #[test]
+    fn test_format_sse() {
+        let event = Event::TranscodeProgress {
+            prefix: "the_show_s01_ep01".into(),
+            percent: 42.0,
+        };
+        assert_eq!(
+            format_sse(&event).unwrap(),
+            "event: transcode_progress\ndata: {\"type\":\"transcode_progress\",\"prefix\":\"the_show_s01_ep01\",\"percent\":42.0}\n\n"
+        );
+    }
+}
@@ -11,10 +11,14 @@
 #![allow(clippy::used_underscore_binding)]
 
 pub mod errors;
+pub mod event_bus;
 pub mod logged_user;
+pub mod movie_queue_api;
 pub mod movie_queue_app;
 pub mod movie_queue_requests;
 pub mod movie_queue_routes;
+pub mod openapi;
+pub mod templates;
 
 #[async_trait::async_trait]
 pub trait HandleRequest<T> {
@@ -1,29 +1,197 @@
+use actix_web::{dev::Payload, http::header::AUTHORIZATION, web::Data, FromRequest, HttpRequest};
 use anyhow::Error;
 pub use auth_server_rust::logged_user::{
     get_secrets, LoggedUser, AUTHORIZED_USERS, JWT_SECRET, SECRET_KEY, TRIGGER_DB_UPDATE,
 };
+use futures::future::{FutureExt, LocalBoxFuture};
+use lazy_static::lazy_static;
 use log::debug;
+use parking_lot::RwLock;
 use stack_string::StackString;
-use std::env::var;
+use std::{collections::HashMap, env::var, ops::Deref};
 
-use movie_collection_lib::pgpool::PgPool;
+use movie_collection_lib::{
+    api_tokens::{ApiToken, TokenScope},
+    authorized_users::{AuthorizedUser, Role},
+    config::Config,
+    movie_queue::DEFAULT_USER,
+    pgpool::PgPool,
+};
+
+use crate::{errors::ServiceError, movie_queue_app::AppState};
+
+lazy_static! {
+    static ref USER_ROLES: RwLock<HashMap<StackString, Role>> = RwLock::new(HashMap::new());
+}
+
+/// Role of a logged in user, users without a role in `authorized_users` can
+/// only view.
+pub fn get_role(user: &LoggedUser) -> Role {
+    USER_ROLES
+        .read()
+        .get(&user.email)
+        .copied()
+        .unwrap_or(Role::Viewer)
+}
+
+/// A `LoggedUser` with at least the editor role, required by every route that
+/// changes a queue, transcodes, changes trakt or posts sync data.
+pub struct EditorUser(LoggedUser);
+
+impl Deref for EditorUser {
+    type Target = LoggedUser;
+
+    fn deref(&self) -> &LoggedUser {
+        &self.0
+    }
+}
+
+type ExtractFuture<T> = LocalBoxFuture<'static, Result<T, actix_web::Error>>;
+
+fn session_user(req: &HttpRequest, payload: &mut Payload, role: Role) -> ExtractFuture<LoggedUser> {
+    let user = LoggedUser::from_request(req, payload);
+    async move {
+        let user = user.await.map_err(Into::into)?;
+        if get_role(&user) >= role {
+            Ok(user)
+        } else {
+            Err(ServiceError::Forbidden.into())
+        }
+    }
+    .boxed_local()
+}
+
+impl FromRequest for EditorUser {
+    type Error = actix_web::Error;
+    type Future = ExtractFuture<Self>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        session_user(req, payload, Role::Editor)
+            .map(|user| user.map(Self))
+            .boxed_local()
+    }
+}
+
+/// A cookie session or an api token, tokens act as `DEFAULT_USER`.
+pub enum ApiUser {
+    Session(LoggedUser),
+    Token(ApiToken),
+}
+
+impl ApiUser {
+    pub fn user_key(&self, config: &Config) -> StackString {
+        match self {
+            Self::Session(user) => user_key(user, config),
+            Self::Token(_) => DEFAULT_USER.into(),
+        }
+    }
+
+    fn from_request(
+        req: &HttpRequest,
+        payload: &mut Payload,
+        scope: TokenScope,
+        role: Role,
+    ) -> ExtractFuture<Self> {
+        let token = match bearer_token(req) {
+            Some(token) => token,
+            None => {
+                return session_user(req, payload, role)
+                    .map(|user| user.map(Self::Session))
+                    .boxed_local()
+            }
+        };
+        let state = req.app_data::<Data<AppState>>().cloned();
+        async move {
+            let state = state.ok_or(ServiceError::InternalServerError)?;
+            match ApiToken::authenticate(&state.db, &token)
+                .await
+                .map_err(ServiceError::from)?
+            {
+                Some(api_token) if api_token.scope >= scope => Ok(Self::Token(api_token)),
+                Some(_) => Err(ServiceError::Forbidden.into()),
+                None => Err(ServiceError::InvalidToken.into()),
+            }
+        }
+        .boxed_local()
+    }
+}
+
+fn bearer_token(req: &HttpRequest) -> Option<StackString> {
+    let header = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+    header.strip_prefix("Bearer ").map(|t| t.trim().into())
+}
+
+/// Any logged in user or a token with the read scope, for read only routes.
+pub struct ReadUser(ApiUser);
+
+impl Deref for ReadUser {
+    type Target = ApiUser;
+
+    fn deref(&self) -> &ApiUser {
+        &self.0
+    }
+}
+
+impl FromRequest for ReadUser {
+    type Error = actix_web::Error;
+    type Future = ExtractFuture<Self>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        ApiUser::from_request(req, payload, TokenScope::Read, Role::Viewer)
+            .map(|user| user.map(Self))
+            .boxed_local()
+    }
+}
+
+/// An editor or a token with the sync scope, for the sync update routes.
+pub struct SyncUser(ApiUser);
+
+impl Deref for SyncUser {
+    type Target = ApiUser;
+
+    fn deref(&self) -> &ApiUser {
+        &self.0
+    }
+}
+
+impl FromRequest for SyncUser {
+    type Error = actix_web::Error;
+    type Future = ExtractFuture<Self>;
+    type Config = ();
+
+    fn from_request(req: &HttpRequest, payload: &mut Payload) -> Self::Future {
+        ApiUser::from_request(req, payload, TokenScope::Sync, Role::Editor)
+            .map(|user| user.map(Self))
+            .boxed_local()
+    }
+}
+
+/// Key that queue entries and watch state are stored under for `user`, the
+/// configured `default_user` shares the state of the command line tools.
+pub fn user_key(user: &LoggedUser, config: &Config) -> StackString {
+    if user.email == config.default_user {
+        DEFAULT_USER.into()
+    } else {
+        user.email.clone()
+    }
+}
 
 pub async fn fill_from_db(pool: &PgPool) -> Result<(), Error> {
     debug!("{:?}", *TRIGGER_DB_UPDATE);
+    // Roles are reloaded every time so that a changed role takes effect
+    // without waiting for a failed login
+    let authorized_users = AuthorizedUser::get_all(pool).await?;
+    let mut roles: HashMap<_, _> = authorized_users
+        .iter()
+        .map(|u| (u.email.clone(), u.role))
+        .collect();
     let users = if TRIGGER_DB_UPDATE.check() {
-        let query = "SELECT email FROM authorized_users";
-        let results: Result<Vec<_>, Error> = pool
-            .get()
-            .await?
-            .query(query, &[])
-            .await?
-            .iter()
-            .map(|row| {
-                let email: StackString = row.try_get(0)?;
-                Ok(LoggedUser { email })
-            })
-            .collect();
-        results?
+        authorized_users
+            .into_iter()
+            .map(|u| LoggedUser { email: u.email })
+            .collect()
     } else {
         AUTHORIZED_USERS.get_users()
     };
@@ -31,8 +199,10 @@ pub async fn fill_from_db(pool: &PgPool) -> Result<(), Error> {
         let user = LoggedUser {
             email: "user@test".into(),
         };
+        roles.insert(user.email.clone(), Role::Admin);
         AUTHORIZED_USERS.merge_users(&[user])?;
     }
+    *USER_ROLES.write() = roles;
     AUTHORIZED_USERS.merge_users(&users)?;
 
     debug!("{:?}", *AUTHORIZED_USERS);
@@ -0,0 +1,332 @@
+#![allow(clippy::needless_pass_by_value)]
+
+use actix_web::web::{Data, Json, Path, Query};
+use serde::{Deserialize, Serialize};
+use stack_string::StackString;
+use std::path;
+
+use movie_collection_lib::{
+    make_queue::QueueReorder, movie_queue::DEFAULT_QUEUE, search::search_limit,
+    trakt_utils::TraktActions,
+};
+
+use super::{
+    errors::ServiceError as Error,
+    logged_user::{user_key, EditorUser, LoggedUser, ReadUser},
+    movie_queue_app::AppState,
+    movie_queue_requests::{
+        CollectionSearchRequest, FindNewEpisodeQuery, MovieQueueRequest, NewEpisodesRequest,
+        QueueDeleteRequest, QueueNamesRequest, QueueReorderRequest, QueueTransferRequest,
+        SearchQuery, SearchRequest, TvShowsRequest, WatchedActionRequest, WatchedShowsRequest,
+        WatchlistActionRequest, WatchlistShowsRequest,
+    },
+    movie_queue_routes::{publish_transcode_jobs, to_json, watchlist_trakt_action, HttpResult},
+    HandleRequest,
+};
+
+fn parse_action(action: &str) -> Result<TraktActions, Error> {
+    match TraktActions::from(action) {
+        TraktActions::Add => Ok(TraktActions::Add),
+        TraktActions::Remove => Ok(TraktActions::Remove),
+        _ => Err(Error::BadRequest(
+            format!("invalid action {}", action).into(),
+        )),
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct ApiResult {
+    pub result: StackString,
+}
+
+impl ApiResult {
+    fn new(result: impl Into<StackString>) -> Self {
+        Self {
+            result: result.into(),
+        }
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct ApiQueueName {
+    pub name: StackString,
+    pub count: i64,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ApiQueueQuery {
+    /// Comma separated list of patterns to match against the path
+    pub patterns: Option<StackString>,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+pub struct ApiQueueDelete {
+    pub path: StackString,
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct ApiCollectionQuery {
+    /// Comma separated list of search strings
+    pub search: Option<StackString>,
+}
+
+fn split_patterns(patterns: Option<StackString>) -> Vec<StackString> {
+    patterns.map_or_else(Vec::new, |p| {
+        p.split(',')
+            .filter(|s| !s.is_empty())
+            .map(Into::into)
+            .collect()
+    })
+}
+
+pub async fn api_queue_names(user: ReadUser, state: Data<AppState>) -> HttpResult {
+    let user = user.user_key(&state.config);
+    let queues: Vec<_> = state
+        .db
+        .handle(QueueNamesRequest { user })
+        .await?
+        .into_iter()
+        .map(|(name, count)| ApiQueueName { name, count })
+        .collect();
+    to_json(queues)
+}
+
+pub async fn api_queue(
+    queue: Path<StackString>,
+    query: Query<ApiQueueQuery>,
+    user: ReadUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let req = MovieQueueRequest {
+        user: user.user_key(&state.config),
+        queue: queue.into_inner(),
+        patterns: split_patterns(query.into_inner().patterns),
+    };
+    let (entries, _) = state.db.handle(req).await?;
+    to_json(entries)
+}
+
+pub async fn api_queue_delete(
+    queue: Path<StackString>,
+    data: Json<ApiQueueDelete>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let req = QueueDeleteRequest {
+        user: user_key(&user, &state.config),
+        queue: queue.into_inner(),
+        path: data.into_inner().path,
+    };
+    let path = state.db.handle(req).await?;
+    to_json(ApiResult::new(path))
+}
+
+async fn api_reorder_worker(
+    user: &LoggedUser,
+    queue: StackString,
+    reorder: QueueReorder,
+    state: &AppState,
+) -> HttpResult {
+    let req = QueueReorderRequest {
+        user: user_key(user, &state.config),
+        queue,
+        reorder,
+    };
+    let body = state.db.handle(req).await?;
+    to_json(ApiResult::new(body))
+}
+
+pub async fn api_queue_move(
+    path: Path<(StackString, i32, i32)>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let (queue, from, to) = path.into_inner();
+    api_reorder_worker(&user, queue, QueueReorder::Move(from, to), &state).await
+}
+
+pub async fn api_queue_swap(
+    path: Path<(StackString, i32, i32)>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let (queue, idx0, idx1) = path.into_inner();
+    api_reorder_worker(&user, queue, QueueReorder::Swap(idx0, idx1), &state).await
+}
+
+pub async fn api_queue_top(
+    path: Path<(StackString, i32)>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let (queue, idx) = path.into_inner();
+    api_reorder_worker(&user, queue, QueueReorder::Top(idx), &state).await
+}
+
+pub async fn api_queue_bottom(
+    path: Path<(StackString, i32)>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let (queue, idx) = path.into_inner();
+    api_reorder_worker(&user, queue, QueueReorder::Bottom(idx), &state).await
+}
+
+pub async fn api_queue_transfer(
+    path: Path<(StackString, i32, StackString)>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let (queue, idx, target) = path.into_inner();
+    let req = QueueTransferRequest {
+        user: user_key(&user, &state.config),
+        queue,
+        idx,
+        target,
+    };
+    let body = state.db.handle(req).await?;
+    to_json(ApiResult::new(body))
+}
+
+pub async fn api_collection(
+    query: Query<ApiCollectionQuery>,
+    _: ReadUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let req = CollectionSearchRequest {
+        patterns: split_patterns(query.into_inner().search),
+    };
+    let entries = state.db.handle(req).await?;
+    to_json(entries)
+}
+
+pub async fn api_search(
+    query: Query<SearchQuery>,
+    _: ReadUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let query = query.into_inner();
+    let req = SearchRequest {
+        query: query.q,
+        limit: search_limit(query.limit),
+    };
+    let results = state.db.handle(req).await?;
+    to_json(results)
+}
+
+pub async fn api_tvshows(_: ReadUser, state: Data<AppState>) -> HttpResult {
+    let shows = state.db.handle(TvShowsRequest {}).await?;
+    to_json(shows)
+}
+
+pub async fn api_new_episodes(
+    query: Query<FindNewEpisodeQuery>,
+    user: ReadUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let query = query.into_inner();
+    let req = NewEpisodesRequest {
+        source: query.source,
+        shows: query.shows,
+        user: user.user_key(&state.config),
+    };
+    let episodes = state.db.handle(req).await?;
+    to_json(episodes)
+}
+
+pub async fn api_watchlist(user: ReadUser, state: Data<AppState>) -> HttpResult {
+    let user = user.user_key(&state.config);
+    let mut shows: Vec<_> = state
+        .db
+        .handle(WatchlistShowsRequest { user })
+        .await?
+        .into_iter()
+        .map(|(_, (_, show, _))| show)
+        .collect();
+    shows.sort_by(|x, y| x.title.cmp(&y.title));
+    to_json(shows)
+}
+
+pub async fn api_watchlist_action(
+    path: Path<(StackString, StackString)>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let (action, imdb_url) = path.into_inner();
+    let action = parse_action(&action)?;
+    let user = user_key(&user, &state.config);
+    let req = WatchlistActionRequest {
+        action,
+        imdb_url,
+        user: user.clone(),
+    };
+    let imdb_url = state.db.handle(req).await?;
+    let body = watchlist_trakt_action(action, &imdb_url, &user).await?;
+    to_json(ApiResult::new(body))
+}
+
+pub async fn api_watched(
+    path: Path<(StackString, i32)>,
+    user: ReadUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let (show, season) = path.into_inner();
+    let req = WatchedShowsRequest {
+        show,
+        season,
+        user: user.user_key(&state.config),
+    };
+    let episodes = state.db.handle(req).await?;
+    to_json(episodes)
+}
+
+pub async fn api_watched_action(
+    path: Path<(StackString, StackString, i32, i32)>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let (action, imdb_url, season, episode) = path.into_inner();
+    let req = WatchedActionRequest {
+        action: parse_action(&action)?,
+        imdb_url,
+        season,
+        episode,
+        user: user_key(&user, &state.config),
+    };
+    let body = state.db.handle(req).await?;
+    to_json(ApiResult::new(body))
+}
+
+async fn api_transcode_worker(
+    directory: Option<&path::Path>,
+    file: StackString,
+    user: &LoggedUser,
+    state: &AppState,
+) -> HttpResult {
+    let req = MovieQueueRequest {
+        user: user_key(user, &state.config),
+        queue: DEFAULT_QUEUE.into(),
+        patterns: vec![file],
+    };
+    let (entries, _) = state.db.handle(req).await?;
+    let jobs = publish_transcode_jobs(directory, &entries).await?;
+    to_json(jobs)
+}
+
+pub async fn api_transcode(
+    path: Path<StackString>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    api_transcode_worker(None, path.into_inner(), &user, &state).await
+}
+
+pub async fn api_transcode_directory(
+    path: Path<(StackString, StackString)>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let (directory, file) = path.into_inner();
+    let directory = path::Path::new(directory.as_str());
+    api_transcode_worker(Some(directory), file, &user, &state).await
+}
@@ -1,27 +1,227 @@
 #![allow(clippy::needless_pass_by_value)]
 
 use actix_identity::{CookieIdentityPolicy, IdentityService};
-use actix_web::{web, App, HttpServer};
+use actix_web::{web, web::Json, App, HttpServer};
 use anyhow::Error;
 use std::time::Duration;
-use tokio::{fs::remove_dir_all, time::interval};
+use tokio::time::interval;
 
 use super::{
-    logged_user::{fill_from_db, get_secrets, SECRET_KEY, TRIGGER_DB_UPDATE},
+    event_bus::EventBus,
+    logged_user::{fill_from_db, get_secrets, LoggedUser, SECRET_KEY, TRIGGER_DB_UPDATE},
+    movie_queue_api::{
+        api_collection, api_new_episodes, api_queue, api_queue_bottom, api_queue_delete,
+        api_queue_move, api_queue_names, api_queue_swap, api_queue_top, api_queue_transfer,
+        api_search, api_transcode, api_transcode_directory, api_tvshows, api_watched,
+        api_watched_action, api_watchlist, api_watchlist_action, ApiQueueName, ApiResult,
+    },
     movie_queue_routes::{
-        find_new_episodes, frontpage, imdb_episodes_route, imdb_episodes_update,
+        find_new_episodes, frontpage, hls_js, imdb_episodes_route, imdb_episodes_update,
         imdb_ratings_route, imdb_ratings_update, imdb_show, last_modified_route,
-        movie_collection_route, movie_collection_update, movie_queue, movie_queue_delete,
-        movie_queue_play, movie_queue_route, movie_queue_show, movie_queue_transcode,
-        movie_queue_transcode_directory, movie_queue_update, refresh_auth, trakt_auth_url,
-        trakt_cal, trakt_callback, trakt_watched_action, trakt_watched_list, trakt_watched_seasons,
-        trakt_watchlist, trakt_watchlist_action, tvshows, user,
+        movie_collection_route, movie_collection_update, movie_queue, movie_queue_bottom,
+        movie_queue_delete, movie_queue_events, movie_queue_hls_playlist, movie_queue_hls_segment,
+        movie_queue_list, movie_queue_move, movie_queue_named, movie_queue_named_delete,
+        movie_queue_named_show, movie_queue_play, movie_queue_route, movie_queue_show,
+        movie_queue_stream, movie_queue_subtitles, movie_queue_swap, movie_queue_thumbnail,
+        movie_queue_top, movie_queue_transcode, movie_queue_transcode_directory,
+        movie_queue_transfer, movie_queue_update, openapi_json, playback_position,
+        playback_position_update, refresh_auth, search_route, tombstones_route, tombstones_update,
+        trakt_auth_url, trakt_cal, trakt_callback, trakt_watched_action, trakt_watched_list,
+        trakt_watched_seasons, trakt_watchlist, trakt_watchlist_action, tvshows, user,
+    },
+    openapi::{
+        Components, EventStream, Html, Javascript, Jpeg, Object, Operation, Playlist, Video, Vtt,
+    },
+};
+use movie_collection_lib::{
+    config::Config,
+    hls::HlsCache,
+    imdb_episodes::ImdbEpisodes,
+    imdb_ratings::ImdbRatings,
+    movie_collection::{
+        LastModifiedResponse, MovieCollectionResult, MovieCollectionRow, NewEpisodesResult,
+        TvShowsResult,
     },
+    movie_queue::{MovieQueueResult, MovieQueueRow},
+    pgpool::PgPool,
+    playback_position::PlaybackPosition,
+    search::SearchResult,
+    tombstones::Tombstone,
+    trakt_utils::{WatchListShow, WatchedEpisode},
+    transcode_service::TranscodeServiceRequest,
 };
-use movie_collection_lib::{config::Config, pgpool::PgPool};
+
+/// HLS sessions that haven't been touched in this long are removed
+const HLS_IDLE_SECONDS: u64 = 300;
 
 pub struct AppState {
     pub db: PgPool,
+    pub config: Config,
+    pub hls: HlsCache,
+    pub events: EventBus,
+}
+
+/// Registers every route, and describes it for the openapi spec from the
+/// types of the handler's arguments, the response type and the summary.
+macro_rules! app_routes {
+    ($($method:ident $path:literal => $handler:ident -> $response:ty, $summary:literal;)*) => {
+        fn configure_routes(cfg: &mut web::ServiceConfig) {
+            $(cfg.route($path, web::$method().to($handler));)*
+        }
+
+        pub fn operations(components: &mut Components) -> Vec<Operation> {
+            vec![$(
+                Operation::new(stringify!($method), $path, $summary)
+                    .handler(&$handler, components)
+                    .response::<$response>(components),
+            )*]
+        }
+    };
+}
+
+// `/list/{show}` matches any other single segment, so it has to stay last
+// in `/list`.
+app_routes! {
+    get "/list/index.html" => frontpage
+        -> Html, "Front page";
+    get "/list/cal" => find_new_episodes
+        -> Html, "New episodes of shows being watched";
+    get "/list/tvshows" => tvshows
+        -> Html, "Shows in the collection or on the watchlist";
+    get "/list/events" => movie_queue_events
+        -> EventStream, "Server sent events: queue_changed, collection_changed, \
+            transcode_progress and trakt_sync_complete";
+    get "/list/delete/{path}" => movie_queue_delete
+        -> Html, "Remove a path from the default queue";
+    get "/list/transcode/{file}" => movie_queue_transcode
+        -> Html, "Transcode a queued file";
+    get "/list/transcode/{directory}/{file}" => movie_queue_transcode_directory
+        -> Html, "Transcode a queued file into a directory";
+    get "/list/play/{index}" => movie_queue_play
+        -> Html, "Play a file from the collection";
+    get "/list/stream/{index}" => movie_queue_stream
+        -> Video, "Stream a file from the collection";
+    get "/list/thumb/{index}" => movie_queue_thumbnail
+        -> Jpeg, "Thumbnail of a collection file";
+    get "/list/subtitles/{id}" => movie_queue_subtitles
+        -> Vtt, "Subtitle track as WebVTT";
+    get "/list/position/{index}" => playback_position
+        -> Json<PlaybackPosition>, "Saved playback position";
+    post "/list/position/{index}" => playback_position_update
+        -> Json<PlaybackPosition>, "Report the playback position, marks the item watched past 90%";
+    get "/list/static/hls.min.js" => hls_js
+        -> Javascript, "Locally installed hls.js";
+    get "/list/hls/{index}/index.m3u8" => movie_queue_hls_playlist
+        -> Playlist, "HLS playlist of a file from the collection";
+    get "/list/hls/{index}/{segment}" => movie_queue_hls_segment
+        -> Video, "HLS segment, transcoded on demand";
+    get "/list/trakt/auth_url" => trakt_auth_url
+        -> Html, "Trakt authorization url";
+    get "/list/trakt/callback" => trakt_callback
+        -> Html, "Trakt authorization callback";
+    get "/list/trakt/refresh_auth" => refresh_auth
+        -> Html, "Refresh the trakt token";
+    get "/list/trakt/cal" => trakt_cal
+        -> Html, "Trakt calendar";
+    get "/list/trakt/watchlist" => trakt_watchlist
+        -> Html, "Watchlist";
+    get "/list/trakt/watchlist/{action}/{imdb_url}" => trakt_watchlist_action
+        -> Html, "Add or remove a show from the watchlist";
+    get "/list/trakt/watched/list/{imdb_url}" => trakt_watched_seasons
+        -> Html, "Seasons of a watchlist show";
+    get "/list/trakt/watched/list/{imdb_url}/{season}" => trakt_watched_list
+        -> Html, "Watched episodes of a season";
+    get "/list/trakt/watched/{action}/{imdb_url}/{season}/{episode}" => trakt_watched_action
+        -> Html, "Mark an episode watched or unwatched";
+    get "/list/imdb_episodes" => imdb_episodes_route
+        -> Json<Vec<ImdbEpisodes>>, "Sync: imdb episodes modified since";
+    post "/list/imdb_episodes" => imdb_episodes_update
+        -> Html, "Sync: update imdb episodes";
+    get "/list/imdb_ratings" => imdb_ratings_route
+        -> Json<Vec<ImdbRatings>>, "Sync: imdb ratings modified since";
+    post "/list/imdb_ratings" => imdb_ratings_update
+        -> Html, "Sync: update imdb ratings";
+    get "/list/movie_queue" => movie_queue_route
+        -> Json<Vec<MovieQueueRow>>, "Sync: queue entries modified since";
+    post "/list/movie_queue" => movie_queue_update
+        -> Html, "Sync: update queue entries";
+    get "/list/movie_collection" => movie_collection_route
+        -> Json<Vec<MovieCollectionRow>>, "Sync: collection entries modified since";
+    post "/list/movie_collection" => movie_collection_update
+        -> Html, "Sync: update collection entries";
+    get "/list/tombstones" => tombstones_route
+        -> Json<Vec<Tombstone>>, "Sync: deletions recorded since";
+    post "/list/tombstones" => tombstones_update
+        -> Html, "Sync: apply deletions";
+    get "/list/imdb/{show}" => imdb_show
+        -> Html, "Parse imdb for a show";
+    get "/list/last_modified" => last_modified_route
+        -> Json<Vec<LastModifiedResponse>>, "Sync: last modification time per table";
+    get "/list/user" => user
+        -> Json<LoggedUser>, "Logged in user";
+    get "/list/openapi.json" => openapi_json
+        -> Object, "This document";
+    get "/list/full_queue" => movie_queue
+        -> Html, "Default queue";
+    get "/list/queues" => movie_queue_list
+        -> Html, "Queue names";
+    get "/list/search" => search_route
+        -> Html, "Search titles, episodes and paths";
+    get "/list/queue/{queue}/full_queue" => movie_queue_named
+        -> Html, "Named queue";
+    get "/list/queue/{queue}/show/{show}" => movie_queue_named_show
+        -> Html, "Named queue filtered by show";
+    post "/list/queue/{queue}/delete/{path}" => movie_queue_named_delete
+        -> Html, "Remove a path from a queue";
+    post "/list/queue/{queue}/move/{from}/{to}" => movie_queue_move
+        -> Html, "Move a queue entry";
+    post "/list/queue/{queue}/swap/{idx0}/{idx1}" => movie_queue_swap
+        -> Html, "Swap two queue entries";
+    post "/list/queue/{queue}/top/{idx}" => movie_queue_top
+        -> Html, "Move a queue entry to the top";
+    post "/list/queue/{queue}/bottom/{idx}" => movie_queue_bottom
+        -> Html, "Move a queue entry to the bottom";
+    post "/list/queue/{queue}/transfer/{idx}/{target}" => movie_queue_transfer
+        -> Html, "Move a queue entry to another queue";
+    get "/list/{show}" => movie_queue_show
+        -> Html, "Default queue filtered by show";
+
+    get "/api/v1/queues" => api_queue_names
+        -> Json<Vec<ApiQueueName>>, "Queue names";
+    get "/api/v1/queue/{queue}" => api_queue
+        -> Json<Vec<MovieQueueResult>>, "Queue entries";
+    post "/api/v1/queue/{queue}/delete" => api_queue_delete
+        -> Json<ApiResult>, "Remove a path from a queue";
+    post "/api/v1/queue/{queue}/move/{from}/{to}" => api_queue_move
+        -> Json<ApiResult>, "Move a queue entry";
+    post "/api/v1/queue/{queue}/swap/{idx0}/{idx1}" => api_queue_swap
+        -> Json<ApiResult>, "Swap two queue entries";
+    post "/api/v1/queue/{queue}/top/{idx}" => api_queue_top
+        -> Json<ApiResult>, "Move a queue entry to the top";
+    post "/api/v1/queue/{queue}/bottom/{idx}" => api_queue_bottom
+        -> Json<ApiResult>, "Move a queue entry to the bottom";
+    post "/api/v1/queue/{queue}/transfer/{idx}/{target}" => api_queue_transfer
+        -> Json<ApiResult>, "Move a queue entry to another queue";
+    get "/api/v1/collection" => api_collection
+        -> Json<Vec<MovieCollectionResult>>, "Search the collection";
+    get "/api/v1/search" => api_search
+        -> Json<Vec<SearchResult>>, "Search titles, episodes and paths";
+    get "/api/v1/tvshows" => api_tvshows
+        -> Json<Vec<TvShowsResult>>, "Shows in the collection";
+    get "/api/v1/new_episodes" => api_new_episodes
+        -> Json<Vec<NewEpisodesResult>>, "New episodes of shows being watched";
+    get "/api/v1/watchlist" => api_watchlist
+        -> Json<Vec<WatchListShow>>, "Watchlist";
+    post "/api/v1/watchlist/{action}/{imdb_url}" => api_watchlist_action
+        -> Json<ApiResult>, "Add or remove a show from the watchlist";
+    get "/api/v1/watched/{show}/{season}" => api_watched
+        -> Json<Vec<WatchedEpisode>>, "Watched episodes of a season";
+    post "/api/v1/watched/{action}/{imdb_url}/{season}/{episode}" => api_watched_action
+        -> Json<ApiResult>, "Mark an episode watched or unwatched";
+    post "/api/v1/transcode/{file}" => api_transcode
+        -> Json<Vec<TranscodeServiceRequest>>, "Transcode a queued file";
+    post "/api/v1/transcode/{directory}/{file}" => api_transcode_directory
+        -> Json<Vec<TranscodeServiceRequest>>, "Transcode a queued file into a directory";
 }
 
 pub async fn start_app(config: Config) -> Result<(), Error> {
@@ -32,20 +232,40 @@ pub async fn start_app(config: Config) -> Result<(), Error> {
             i.tick().await;
         }
     }
+    async fn _cleanup_hls(hls: HlsCache) {
+        let mut i = interval(Duration::from_secs(60));
+        loop {
+            i.tick().await;
+            hls.cleanup(Duration::from_secs(HLS_IDLE_SECONDS))
+                .await
+                .map(|_| ())
+                .unwrap_or(());
+        }
+    }
     TRIGGER_DB_UPDATE.set();
     get_secrets(&config.secret_path, &config.jwt_secret_path).await?;
 
-    remove_dir_all("/var/www/html/videos/partial").await?;
-
     let domain = config.domain.to_string();
     let port = config.port;
-    let pool = PgPool::new(&config.pgurl);
+    let pool = PgPool::new(&config.pgurl)?;
+
+    let hls = HlsCache::new(&config.hls_cache_dir);
+    hls.init().await?;
 
     actix_rt::spawn(_update_db(pool.clone()));
+    actix_rt::spawn(_cleanup_hls(hls.clone()));
+
+    let events = EventBus::new();
+    actix_rt::spawn(events.clone().run(config.pgurl.clone()));
 
     HttpServer::new(move || {
         App::new()
-            .data(AppState { db: pool.clone() })
+            .data(AppState {
+                db: pool.clone(),
+                config: config.clone(),
+                hls: hls.clone(),
+                events: events.clone(),
+            })
             .wrap(IdentityService::new(
                 CookieIdentityPolicy::new(&SECRET_KEY.load())
                     .name("auth")
@@ -54,76 +274,7 @@ pub async fn start_app(config: Config) -> Result<(), Error> {
                     .max_age(24 * 3600)
                     .secure(false), // this can only be true if you have https
             ))
-            .service(
-                web::scope("/list")
-                    .service(web::resource("/index.html").route(web::get().to(frontpage)))
-                    .service(web::resource("/cal").route(web::get().to(find_new_episodes)))
-                    .service(web::resource("/tvshows").route(web::get().to(tvshows)))
-                    .service(
-                        web::resource("/delete/{path}").route(web::get().to(movie_queue_delete)),
-                    )
-                    .service(
-                        web::resource("/transcode/{file}")
-                            .route(web::get().to(movie_queue_transcode)),
-                    )
-                    .service(
-                        web::resource("/transcode/{directory}/{file}")
-                            .route(web::get().to(movie_queue_transcode_directory)),
-                    )
-                    .service(web::resource("/play/{index}").route(web::get().to(movie_queue_play)))
-                    .service(web::resource("/trakt/auth_url").route(web::get().to(trakt_auth_url)))
-                    .service(web::resource("/trakt/callback").route(web::get().to(trakt_callback)))
-                    .service(
-                        web::resource("/trakt/refresh_auth").route(web::get().to(refresh_auth)),
-                    )
-                    .service(web::resource("/trakt/cal").route(web::get().to(trakt_cal)))
-                    .service(
-                        web::resource("/trakt/watchlist").route(web::get().to(trakt_watchlist)),
-                    )
-                    .service(
-                        web::resource("/trakt/watchlist/{action}/{imdb_url}")
-                            .route(web::get().to(trakt_watchlist_action)),
-                    )
-                    .service(
-                        web::resource("/trakt/watched/list/{imdb_url}")
-                            .route(web::get().to(trakt_watched_seasons)),
-                    )
-                    .service(
-                        web::resource("/trakt/watched/list/{imdb_url}/{season}")
-                            .route(web::get().to(trakt_watched_list)),
-                    )
-                    .service(
-                        web::resource("/trakt/watched/{action}/{imdb_url}/{season}/{episode}")
-                            .route(web::get().to(trakt_watched_action)),
-                    )
-                    .service(
-                        web::resource("/imdb_episodes")
-                            .route(web::get().to(imdb_episodes_route))
-                            .route(web::post().to(imdb_episodes_update)),
-                    )
-                    .service(
-                        web::resource("/imdb_ratings")
-                            .route(web::get().to(imdb_ratings_route))
-                            .route(web::post().to(imdb_ratings_update)),
-                    )
-                    .service(
-                        web::resource("/movie_queue")
-                            .route(web::get().to(movie_queue_route))
-                            .route(web::post().to(movie_queue_update)),
-                    )
-                    .service(
-                        web::resource("/movie_collection")
-                            .route(web::get().to(movie_collection_route))
-                            .route(web::post().to(movie_collection_update)),
-                    )
-                    .service(web::resource("/imdb/{show}").route(web::get().to(imdb_show)))
-                    .service(
-                        web::resource("/last_modified").route(web::get().to(last_modified_route)),
-                    )
-                    .service(web::resource("/user").route(web::get().to(user)))
-                    .service(web::resource("/full_queue").route(web::get().to(movie_queue)))
-                    .service(web::resource("/{show}").route(web::get().to(movie_queue_show))),
-            )
+            .configure(configure_routes)
     })
     .bind(&format!("127.0.0.1:{}", port))
     .unwrap_or_else(|_| panic!("Failed to bind to port {}", port))
@@ -1,6 +1,6 @@
-use anyhow::Error;
+use anyhow::{format_err, Error};
 use async_trait::async_trait;
-use chrono::{DateTime, Utc};
+use chrono::{DateTime, Duration, Local, Utc};
 use serde::{Deserialize, Serialize};
 use stack_string::StackString;
 use std::path;
@@ -9,17 +9,23 @@ use super::HandleRequest;
 use movie_collection_lib::{
     imdb_episodes::ImdbEpisodes,
     imdb_ratings::ImdbRatings,
+    make_queue::QueueReorder,
     movie_collection::{
         find_new_episodes_http_worker, ImdbSeason, LastModifiedResponse, MovieCollection,
-        MovieCollectionRow, TvShowsResult,
+        MovieCollectionResult, MovieCollectionRow, NewEpisodeEntry, NewEpisodesResult,
+        TvShowsResult,
     },
-    movie_queue::{MovieQueueDB, MovieQueueResult, MovieQueueRow},
-    parse_imdb::{ParseImdb, ParseImdbOptions},
+    movie_queue::{MovieQueueDB, MovieQueueResult, MovieQueueRow, DEFAULT_QUEUE, DEFAULT_USER},
+    parse_imdb::{ImdbSearchRow, ParseImdb, ParseImdbOptions},
     pgpool::PgPool,
+    playback_position::{update_playback_position, PlaybackPosition},
+    search::{Search, SearchResult},
+    subtitles::Subtitle,
+    tombstones::Tombstone,
     trakt_utils::{
         get_watched_shows_db, get_watchlist_shows_db_map, trakt_cal_http_worker,
-        watch_list_http_worker, watched_action_http_worker, TraktActions, WatchListMap,
-        WatchListShow, WatchedEpisode, TRAKT_CONN,
+        watch_list_http_worker, watched_action_http_worker, TraktActions, TraktCalHttpEntry,
+        WatchListMap, WatchListShow, WatchedEpisode, WatchedSeason, TRAKT_CONN,
     },
     tv_show_source::TvShowSource,
 };
@@ -35,18 +41,22 @@ impl HandleRequest<TvShowsRequest> for PgPool {
     }
 }
 
-pub struct WatchlistShowsRequest {}
+pub struct WatchlistShowsRequest {
+    pub user: StackString,
+}
 
 #[async_trait]
 impl HandleRequest<WatchlistShowsRequest> for PgPool {
     type Result = Result<WatchListMap, Error>;
 
-    async fn handle(&self, _: WatchlistShowsRequest) -> Self::Result {
-        get_watchlist_shows_db_map(&self).await
+    async fn handle(&self, msg: WatchlistShowsRequest) -> Self::Result {
+        get_watchlist_shows_db_map(&self, &msg.user).await
     }
 }
 
 pub struct QueueDeleteRequest {
+    pub user: StackString,
+    pub queue: StackString,
     pub path: StackString,
 }
 
@@ -55,7 +65,7 @@ impl HandleRequest<QueueDeleteRequest> for PgPool {
     type Result = Result<StackString, Error>;
     async fn handle(&self, msg: QueueDeleteRequest) -> Self::Result {
         if path::Path::new(msg.path.as_str()).exists() {
-            MovieQueueDB::with_pool(&self)
+            MovieQueueDB::with_user(&self, &msg.user, &msg.queue)
                 .remove_from_queue_by_path(&msg.path)
                 .await?;
         }
@@ -63,8 +73,58 @@ impl HandleRequest<QueueDeleteRequest> for PgPool {
     }
 }
 
+pub struct QueueReorderRequest {
+    pub user: StackString,
+    pub queue: StackString,
+    pub reorder: QueueReorder,
+}
+
+#[async_trait]
+impl HandleRequest<QueueReorderRequest> for PgPool {
+    type Result = Result<StackString, Error>;
+    async fn handle(&self, msg: QueueReorderRequest) -> Self::Result {
+        let mq = MovieQueueDB::with_user(&self, &msg.user, &msg.queue);
+        msg.reorder.reorder(&mq).await?;
+        Ok(msg.reorder.to_string().into())
+    }
+}
+
+pub struct QueueTransferRequest {
+    pub user: StackString,
+    pub queue: StackString,
+    pub idx: i32,
+    pub target: StackString,
+}
+
+#[async_trait]
+impl HandleRequest<QueueTransferRequest> for PgPool {
+    type Result = Result<StackString, Error>;
+    async fn handle(&self, msg: QueueTransferRequest) -> Self::Result {
+        MovieQueueDB::with_user(&self, &msg.user, &msg.queue)
+            .move_to_queue(msg.idx, &msg.target)
+            .await?;
+        Ok(format!("move {} from {} to {}", msg.idx, msg.queue, msg.target).into())
+    }
+}
+
+pub struct QueueNamesRequest {
+    pub user: StackString,
+}
+
+#[async_trait]
+impl HandleRequest<QueueNamesRequest> for PgPool {
+    type Result = Result<Vec<(StackString, i64)>, Error>;
+    async fn handle(&self, msg: QueueNamesRequest) -> Self::Result {
+        MovieQueueDB::with_user(&self, &msg.user, DEFAULT_QUEUE)
+            .get_queue_names()
+            .await
+    }
+}
+
 #[derive(Debug)]
 pub struct MovieQueueRequest {
+    pub user: StackString,
+    pub queue: StackString,
     pub patterns: Vec<StackString>,
 }
 
@@ -74,7 +134,7 @@ impl HandleRequest<MovieQueueRequest> for PgPool {
 
     async fn handle(&self, msg: MovieQueueRequest) -> Self::Result {
         let patterns: Vec<_> = msg.patterns.iter().map(StackString::as_str).collect();
-        let queue = MovieQueueDB::with_pool(&self)
+        let queue = MovieQueueDB::with_user(&self, &msg.user, &msg.queue)
             .print_movie_queue(&patterns)
             .await?;
         Ok((queue, msg.patterns))
@@ -96,6 +156,82 @@ impl HandleRequest<MoviePathRequest> for PgPool {
     }
 }
 
+pub struct SubtitlesRequest {
+    pub idx: i32,
+}
+
+#[async_trait]
+impl HandleRequest<SubtitlesRequest> for PgPool {
+    type Result = Result<Vec<Subtitle>, Error>;
+
+    async fn handle(&self, msg: SubtitlesRequest) -> Self::Result {
+        Subtitle::get_by_collection_idx(&self, msg.idx).await
+    }
+}
+
+pub struct SubtitleVttRequest {
+    pub id: i32,
+}
+
+#[async_trait]
+impl HandleRequest<SubtitleVttRequest> for PgPool {
+    type Result = Result<String, Error>;
+
+    async fn handle(&self, msg: SubtitleVttRequest) -> Self::Result {
+        let subtitle = Subtitle::get_by_id(&self, msg.id)
+            .await?
+            .ok_or_else(|| format_err!("Subtitle not found"))?;
+        let video = MovieCollection::with_pool(&self)?
+            .get_collection_path(subtitle.collection_idx)
+            .await?;
+        subtitle.to_vtt(path::Path::new(video.as_str())).await
+    }
+}
+
+pub struct PlaybackPositionRequest {
+    pub idx: i32,
+    pub user: StackString,
+}
+
+#[async_trait]
+impl HandleRequest<PlaybackPositionRequest> for PgPool {
+    type Result = Result<Option<PlaybackPosition>, Error>;
+
+    async fn handle(&self, msg: PlaybackPositionRequest) -> Self::Result {
+        PlaybackPosition::get(&self, msg.idx, &msg.user).await
+    }
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub struct PlaybackPositionUpdate {
+    pub position: f64,
+    pub duration: f64,
+}
+
+pub struct PlaybackPositionUpdateRequest {
+    pub idx: i32,
+    pub update: PlaybackPositionUpdate,
+    pub user: StackString,
+    pub remove_from_queue: bool,
+}
+
+#[async_trait]
+impl HandleRequest<PlaybackPositionUpdateRequest> for PgPool {
+    type Result = Result<PlaybackPosition, Error>;
+
+    async fn handle(&self, msg: PlaybackPositionUpdateRequest) -> Self::Result {
+        update_playback_position(
+            &self,
+            msg.idx,
+            msg.update.position,
+            msg.update.duration,
+            &msg.user,
+            msg.remove_from_queue,
+        )
+        .await
+    }
+}
+
 pub struct ImdbRatingsRequest {
     pub imdb_url: StackString,
 }
@@ -133,6 +269,7 @@ impl HandleRequest<ImdbSeasonsRequest> for PgPool {
 pub struct WatchlistActionRequest {
     pub action: TraktActions,
     pub imdb_url: StackString,
+    pub user: StackString,
 }
 
 #[async_trait]
@@ -141,15 +278,33 @@ impl HandleRequest<WatchlistActionRequest> for PgPool {
 
     async fn handle(&self, msg: WatchlistActionRequest) -> Self::Result {
         match msg.action {
-            TraktActions::Add => {
+            TraktActions::Add if msg.user == DEFAULT_USER => {
                 TRAKT_CONN.init().await;
                 if let Some(show) = TRAKT_CONN.get_watchlist_shows().await?.get(&msg.imdb_url) {
-                    show.insert_show(&self).await?;
+                    show.insert_show(&self, &msg.user).await?;
+                }
+            }
+            TraktActions::Add => {
+                let exists = WatchListShow::get_show_by_link(&msg.imdb_url, &self, &msg.user)
+                    .await?
+                    .is_some();
+                if !exists {
+                    if let Some(show) = ImdbRatings::get_show_by_link(&msg.imdb_url, &self).await? {
+                        WatchListShow {
+                            link: show.link,
+                            title: show.title.unwrap_or(show.show),
+                            year: 0,
+                        }
+                        .insert_show(&self, &msg.user)
+                        .await?;
+                    }
                 }
             }
             TraktActions::Remove => {
-                if let Some(show) = WatchListShow::get_show_by_link(&msg.imdb_url, &self).await? {
-                    show.delete_show(&self).await?;
+                if let Some(show) =
+                    WatchListShow::get_show_by_link(&msg.imdb_url, &self, &msg.user).await?
+                {
+                    show.delete_show(&self, &msg.user).await?;
                 }
             }
             _ => {}
@@ -161,6 +316,7 @@ impl HandleRequest<WatchlistActionRequest> for PgPool {
 pub struct WatchedShowsRequest {
     pub show: StackString,
     pub season: i32,
+    pub user: StackString,
 }
 
 #[async_trait]
@@ -168,7 +324,7 @@ impl HandleRequest<WatchedShowsRequest> for PgPool {
     type Result = Result<Vec<WatchedEpisode>, Error>;
 
     async fn handle(&self, msg: WatchedShowsRequest) -> Self::Result {
-        get_watched_shows_db(&self, &msg.show, Some(msg.season)).await
+        get_watched_shows_db(&self, &msg.show, Some(msg.season), &msg.user).await
     }
 }
 
@@ -191,14 +347,15 @@ impl HandleRequest<ImdbEpisodesRequest> for PgPool {
 pub struct WatchedListRequest {
     pub imdb_url: StackString,
     pub season: i32,
+    pub user: StackString,
 }
 
 #[async_trait]
 impl HandleRequest<WatchedListRequest> for PgPool {
-    type Result = Result<StackString, Error>;
+    type Result = Result<WatchedSeason, Error>;
 
     async fn handle(&self, msg: WatchedListRequest) -> Self::Result {
-        watch_list_http_worker(&self, &msg.imdb_url, msg.season).await
+        watch_list_http_worker(&self, &msg.imdb_url, msg.season, &msg.user).await
     }
 }
 
@@ -207,6 +364,7 @@ pub struct WatchedActionRequest {
     pub imdb_url: StackString,
     pub season: i32,
     pub episode: i32,
+    pub user: StackString,
 }
 
 #[async_trait]
@@ -214,7 +372,15 @@ impl HandleRequest<WatchedActionRequest> for PgPool {
     type Result = Result<StackString, Error>;
 
     async fn handle(&self, msg: WatchedActionRequest) -> Self::Result {
-        watched_action_http_worker(&self, msg.action, &msg.imdb_url, msg.season, msg.episode).await
+        watched_action_http_worker(
+            &self,
+            msg.action,
+            &msg.imdb_url,
+            msg.season,
+            msg.episode,
+            &msg.user,
+        )
+        .await
     }
 }
 
@@ -245,6 +411,7 @@ impl From<ParseImdbRequest> for ParseImdbOptions {
 pub struct ImdbShowRequest {
     pub show: StackString,
     pub query: ParseImdbRequest,
+    pub user: StackString,
 }
 
 impl From<ImdbShowRequest> for ParseImdbOptions {
@@ -258,13 +425,12 @@ impl From<ImdbShowRequest> for ParseImdbOptions {
 
 #[async_trait]
 impl HandleRequest<ImdbShowRequest> for PgPool {
-    type Result = Result<StackString, Error>;
+    type Result = Result<Vec<ImdbSearchRow>, Error>;
 
     async fn handle(&self, msg: ImdbShowRequest) -> Self::Result {
-        let watchlist = get_watchlist_shows_db_map(&self).await?;
+        let watchlist = get_watchlist_shows_db_map(&self, &msg.user).await?;
         let pi = ParseImdb::with_pool(&self)?;
-        let body = pi.parse_imdb_http_worker(&msg.into(), &watchlist).await?;
-        Ok(body)
+        pi.parse_imdb_http_worker(&msg.into(), &watchlist).await
     }
 }
 
@@ -272,7 +438,7 @@ pub struct TraktCalRequest {}
 
 #[async_trait]
 impl HandleRequest<TraktCalRequest> for PgPool {
-    type Result = Result<Vec<StackString>, Error>;
+    type Result = Result<Vec<TraktCalHttpEntry>, Error>;
 
     async fn handle(&self, _: TraktCalRequest) -> Self::Result {
         trakt_cal_http_worker(&self).await
@@ -280,17 +446,86 @@ impl HandleRequest<TraktCalRequest> for PgPool {
 }
 
 #[derive(Serialize, Deserialize)]
+pub struct FindNewEpisodeQuery {
+    pub source: Option<TvShowSource>,
+    pub shows: Option<StackString>,
+}
+
 pub struct FindNewEpisodeRequest {
     pub source: Option<TvShowSource>,
     pub shows: Option<StackString>,
+    pub user: StackString,
 }
 
 #[async_trait]
 impl HandleRequest<FindNewEpisodeRequest> for PgPool {
-    type Result = Result<Vec<StackString>, Error>;
+    type Result = Result<Vec<NewEpisodeEntry>, Error>;
 
     async fn handle(&self, msg: FindNewEpisodeRequest) -> Self::Result {
-        find_new_episodes_http_worker(&self, msg.shows, msg.source).await
+        find_new_episodes_http_worker(&self, msg.shows, msg.source, &msg.user).await
+    }
+}
+
+pub struct NewEpisodesRequest {
+    pub source: Option<TvShowSource>,
+    pub shows: Option<StackString>,
+    pub user: StackString,
+}
+
+#[async_trait]
+impl HandleRequest<NewEpisodesRequest> for PgPool {
+    type Result = Result<Vec<NewEpisodesResult>, Error>;
+
+    async fn handle(&self, msg: NewEpisodesRequest) -> Self::Result {
+        let mindate = (Local::today() + Duration::days(-14)).naive_local();
+        let maxdate = (Local::today() + Duration::days(7)).naive_local();
+        let shows: Option<Vec<&str>> = msg.shows.as_ref().map(|s| s.split(',').collect());
+        let episodes = MovieCollection::with_pool(&self)?
+            .get_new_episodes(mindate, maxdate, msg.source, &msg.user)
+            .await?
+            .into_iter()
+            .filter(|epi| {
+                shows
+                    .as_ref()
+                    .map_or(true, |s| s.contains(&epi.show.as_str()))
+            })
+            .collect();
+        Ok(episodes)
+    }
+}
+
+pub struct CollectionSearchRequest {
+    pub patterns: Vec<StackString>,
+}
+
+#[async_trait]
+impl HandleRequest<CollectionSearchRequest> for PgPool {
+    type Result = Result<Vec<MovieCollectionResult>, Error>;
+
+    async fn handle(&self, msg: CollectionSearchRequest) -> Self::Result {
+        MovieCollection::with_pool(&self)?
+            .search_movie_collection(&msg.patterns)
+            .await
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct SearchQuery {
+    pub q: StackString,
+    pub limit: Option<usize>,
+}
+
+pub struct SearchRequest {
+    pub query: StackString,
+    pub limit: usize,
+}
+
+#[async_trait]
+impl HandleRequest<SearchRequest> for PgPool {
+    type Result = Result<Vec<SearchResult>, Error>;
+
+    async fn handle(&self, msg: SearchRequest) -> Self::Result {
+        Search::new(&self).search(&msg.query, msg.limit).await
     }
 }
 
@@ -402,19 +637,8 @@ impl HandleRequest<MovieQueueUpdateRequest> for PgPool {
     type Result = Result<(), Error>;
 
     async fn handle(&self, msg: MovieQueueUpdateRequest) -> Self::Result {
-        let mq = MovieQueueDB::with_pool(&self);
-        let mc = MovieCollection::with_pool(&self)?;
         for entry in msg.queue {
-            let cidx = if let Some(i) = mc.get_collection_index(entry.path.as_ref()).await? {
-                i
-            } else {
-                mc.insert_into_collection_by_idx(entry.collection_idx, entry.path.as_ref())
-                    .await?;
-                entry.collection_idx
-            };
-            assert_eq!(cidx, entry.collection_idx);
-            mq.insert_into_queue_by_collection_idx(entry.idx, entry.collection_idx)
-                .await?;
+            entry.sync_into(&self).await?;
         }
         Ok(())
     }
@@ -432,14 +656,38 @@ impl HandleRequest<MovieCollectionUpdateRequest> for PgPool {
     async fn handle(&self, msg: MovieCollectionUpdateRequest) -> Self::Result {
         let mc = MovieCollection::with_pool(&self)?;
         for entry in msg.collection {
-            if let Some(cidx) = mc.get_collection_index(entry.path.as_ref()).await? {
-                if cidx == entry.idx {
-                    continue;
-                }
-                mc.remove_from_collection(entry.path.as_ref()).await?;
-            };
-            mc.insert_into_collection_by_idx(entry.idx, entry.path.as_ref())
-                .await?;
+            mc.sync_collection_row(&entry).await?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TombstonesSyncRequest {
+    pub start_timestamp: DateTime<Utc>,
+}
+
+#[async_trait]
+impl HandleRequest<TombstonesSyncRequest> for PgPool {
+    type Result = Result<Vec<Tombstone>, Error>;
+
+    async fn handle(&self, msg: TombstonesSyncRequest) -> Self::Result {
+        Tombstone::get_after_timestamp(msg.start_timestamp, &self).await
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct TombstonesUpdateRequest {
+    pub tombstones: Vec<Tombstone>,
+}
+
+#[async_trait]
+impl HandleRequest<TombstonesUpdateRequest> for PgPool {
+    type Result = Result<(), Error>;
+
+    async fn handle(&self, msg: TombstonesUpdateRequest) -> Self::Result {
+        for tombstone in msg.tombstones {
+            tombstone.apply(&self).await?;
         }
         Ok(())
     }
@@ -1,12 +1,15 @@
 #![allow(clippy::needless_pass_by_value)]
 
+use actix_files::NamedFile;
 use actix_web::{
     web::{Data, Json, Path, Query},
     HttpResponse,
 };
 use anyhow::format_err;
 use maplit::hashmap;
+use mime::Mime;
 use serde::{Deserialize, Serialize};
+use serde_json::json;
 use stack_string::StackString;
 use std::{
     borrow::Borrow,
@@ -14,33 +17,40 @@ use std::{
     hash::{Hash, Hasher},
     path,
 };
-use subprocess::Exec;
 
 use movie_collection_lib::{
     config::Config,
-    make_queue::movie_queue_http,
+    hls::{is_browser_playable, parse_segment_name},
+    make_queue::{movie_queue_http, QueueReorder},
     movie_collection::{ImdbSeason, TvShowsResult},
-    movie_queue::MovieQueueResult,
+    movie_queue::{MovieQueueResult, DEFAULT_QUEUE, DEFAULT_USER},
     pgpool::PgPool,
+    playback_position::format_position,
+    search::search_limit,
+    thumbnails::ThumbnailCache,
     trakt_utils::{TraktActions, WatchListShow, TRAKT_CONN},
     transcode_service::{TranscodeService, TranscodeServiceRequest},
     tv_show_source::TvShowSource,
-    utils::HBR,
 };
 
 use super::{
     errors::ServiceError as Error,
-    logged_user::LoggedUser,
+    logged_user::{user_key, EditorUser, LoggedUser, ReadUser, SyncUser},
     movie_queue_app::AppState,
     movie_queue_requests::{
-        FindNewEpisodeRequest, ImdbEpisodesSyncRequest, ImdbEpisodesUpdateRequest,
-        ImdbRatingsRequest, ImdbRatingsSyncRequest, ImdbRatingsUpdateRequest, ImdbSeasonsRequest,
-        ImdbShowRequest, LastModifiedRequest, MovieCollectionSyncRequest,
-        MovieCollectionUpdateRequest, MoviePathRequest, MovieQueueRequest, MovieQueueSyncRequest,
-        MovieQueueUpdateRequest, ParseImdbRequest, QueueDeleteRequest, TraktCalRequest,
-        TvShowsRequest, WatchedActionRequest, WatchedListRequest, WatchlistActionRequest,
-        WatchlistShowsRequest,
+        FindNewEpisodeQuery, FindNewEpisodeRequest, ImdbEpisodesSyncRequest,
+        ImdbEpisodesUpdateRequest, ImdbRatingsRequest, ImdbRatingsSyncRequest,
+        ImdbRatingsUpdateRequest, ImdbSeasonsRequest, ImdbShowRequest, LastModifiedRequest,
+        MovieCollectionSyncRequest, MovieCollectionUpdateRequest, MoviePathRequest,
+        MovieQueueRequest, MovieQueueSyncRequest, MovieQueueUpdateRequest, ParseImdbRequest,
+        PlaybackPositionRequest, PlaybackPositionUpdate, PlaybackPositionUpdateRequest,
+        QueueDeleteRequest, QueueNamesRequest, QueueReorderRequest, QueueTransferRequest,
+        SearchQuery, SearchRequest, SubtitleVttRequest, SubtitlesRequest, TombstonesSyncRequest,
+        TombstonesUpdateRequest, TraktCalRequest, TvShowsRequest, WatchedActionRequest,
+        WatchedListRequest, WatchlistActionRequest, WatchlistShowsRequest,
     },
+    openapi::openapi_spec,
+    templates::HBR,
     HandleRequest,
 };
 
@@ -52,81 +62,222 @@ fn form_http_response(body: String) -> HttpResult {
         .body(body))
 }
 
-fn to_json<T>(js: T) -> HttpResult
+pub fn to_json<T>(js: T) -> HttpResult
 where
     T: Serialize,
 {
     Ok(HttpResponse::Ok().json(js))
 }
 
-fn movie_queue_body(patterns: &[StackString], entries: &[StackString]) -> StackString {
-    let previous = r#"<a href="javascript:updateMainArticle('/list/tvshows')">Go Back</a><br>"#;
-
+async fn queue_body_resp(
+    queue_name: &str,
+    patterns: Vec<StackString>,
+    queue: Vec<MovieQueueResult>,
+    pool: &PgPool,
+) -> HttpResult {
+    let entries = movie_queue_http(&queue, pool).await?;
     let watchlist_url = if patterns.is_empty() {
         "/list/trakt/watchlist".to_string()
     } else {
         format!("/list/trakt/watched/list/{}", patterns.join("_"))
     };
-
-    let entries = format!(
-        r#"{}<a href="javascript:updateMainArticle('{}')">Watch List</a><table border="0">{}</table>"#,
-        previous,
-        watchlist_url,
-        entries.join("")
-    );
-
-    entries.into()
+    let body = HBR.render(
+        "queue.html",
+        &json!({
+            "queue_name": queue_name,
+            "watchlist_url": watchlist_url,
+            "entries": entries,
+        }),
+    )?;
+    form_http_response(body)
 }
 
-async fn queue_body_resp(
+async fn movie_queue_worker(
+    user: StackString,
+    queue: StackString,
     patterns: Vec<StackString>,
-    queue: Vec<MovieQueueResult>,
-    pool: &PgPool,
+    state: &AppState,
 ) -> HttpResult {
-    let entries = movie_queue_http(&queue, pool).await?;
-    let body = movie_queue_body(&patterns, &entries);
-    form_http_response(body.into())
-}
-
-pub async fn movie_queue(_: LoggedUser, state: Data<AppState>) -> HttpResult {
     let req = MovieQueueRequest {
-        patterns: Vec::new(),
+        user,
+        queue: queue.clone(),
+        patterns,
     };
-    let (queue, _) = state.db.handle(req).await?;
-    queue_body_resp(Vec::new(), queue, &state.db).await
+    let (entries, patterns) = state.db.handle(req).await?;
+    queue_body_resp(&queue, patterns, entries, &state.db).await
+}
+
+pub async fn movie_queue(user: LoggedUser, state: Data<AppState>) -> HttpResult {
+    let user = user_key(&user, &state.config);
+    movie_queue_worker(user, DEFAULT_QUEUE.into(), Vec::new(), &state).await
+}
+
+pub async fn movie_queue_named(
+    queue: Path<StackString>,
+    user: LoggedUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let user = user_key(&user, &state.config);
+    movie_queue_worker(user, queue.into_inner(), Vec::new(), &state).await
 }
 
 pub async fn movie_queue_show(
     path: Path<StackString>,
-    _: LoggedUser,
+    user: LoggedUser,
     state: Data<AppState>,
 ) -> HttpResult {
     let path = path.into_inner();
-    let patterns = vec![path];
+    let user = user_key(&user, &state.config);
+    movie_queue_worker(user, DEFAULT_QUEUE.into(), vec![path], &state).await
+}
 
-    let req = MovieQueueRequest { patterns };
-    let (queue, patterns) = state.db.handle(req).await?;
-    queue_body_resp(patterns, queue, &state.db).await
+pub async fn movie_queue_named_show(
+    path: Path<(StackString, StackString)>,
+    user: LoggedUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let (queue, show) = path.into_inner();
+    let user = user_key(&user, &state.config);
+    movie_queue_worker(user, queue, vec![show], &state).await
+}
+
+pub async fn movie_queue_list(user: LoggedUser, state: Data<AppState>) -> HttpResult {
+    let user = user_key(&user, &state.config);
+    let queues: Vec<_> = state
+        .db
+        .handle(QueueNamesRequest { user })
+        .await?
+        .into_iter()
+        .map(|(name, count)| json!({"name": name, "count": count}))
+        .collect();
+    let body = HBR.render("queues.html", &json!({ "queues": queues }))?;
+    form_http_response(body)
+}
+
+pub async fn search_route(
+    query: Query<SearchQuery>,
+    _: LoggedUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let query = query.into_inner();
+    let req = SearchRequest {
+        query: query.q.clone(),
+        limit: search_limit(query.limit),
+    };
+    let results = state.db.handle(req).await?;
+    let body = HBR.render(
+        "search.html",
+        &json!({"query": query.q, "results": results}),
+    )?;
+    form_http_response(body)
+}
+
+async fn queue_delete_worker(
+    user: StackString,
+    queue: StackString,
+    path: StackString,
+    state: &AppState,
+) -> HttpResult {
+    let req = QueueDeleteRequest { user, queue, path };
+    let body = state.db.handle(req).await?;
+    form_http_response(body.into())
 }
 
 pub async fn movie_queue_delete(
     path: Path<StackString>,
-    _: LoggedUser,
+    user: EditorUser,
     state: Data<AppState>,
 ) -> HttpResult {
     let path = path.into_inner();
+    let user = user_key(&user, &state.config);
+    queue_delete_worker(user, DEFAULT_QUEUE.into(), path, &state).await
+}
+
+pub async fn movie_queue_named_delete(
+    path: Path<(StackString, StackString)>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let (queue, path) = path.into_inner();
+    let user = user_key(&user, &state.config);
+    queue_delete_worker(user, queue, path, &state).await
+}
 
-    let req = QueueDeleteRequest { path };
+async fn queue_reorder_worker(
+    user: &LoggedUser,
+    queue: StackString,
+    reorder: QueueReorder,
+    state: &AppState,
+) -> HttpResult {
+    let user = user_key(user, &state.config);
+    let req = QueueReorderRequest {
+        user,
+        queue,
+        reorder,
+    };
     let body = state.db.handle(req).await?;
     form_http_response(body.into())
 }
 
-async fn transcode_worker(
+pub async fn movie_queue_move(
+    path: Path<(StackString, i32, i32)>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let (queue, from, to) = path.into_inner();
+    queue_reorder_worker(&user, queue, QueueReorder::Move(from, to), &state).await
+}
+
+pub async fn movie_queue_swap(
+    path: Path<(StackString, i32, i32)>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let (queue, idx0, idx1) = path.into_inner();
+    queue_reorder_worker(&user, queue, QueueReorder::Swap(idx0, idx1), &state).await
+}
+
+pub async fn movie_queue_top(
+    path: Path<(StackString, i32)>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let (queue, idx) = path.into_inner();
+    queue_reorder_worker(&user, queue, QueueReorder::Top(idx), &state).await
+}
+
+pub async fn movie_queue_bottom(
+    path: Path<(StackString, i32)>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let (queue, idx) = path.into_inner();
+    queue_reorder_worker(&user, queue, QueueReorder::Bottom(idx), &state).await
+}
+
+pub async fn movie_queue_transfer(
+    path: Path<(StackString, i32, StackString)>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let (queue, idx, target) = path.into_inner();
+    let user = user_key(&user, &state.config);
+    let req = QueueTransferRequest {
+        user,
+        queue,
+        idx,
+        target,
+    };
+    let body = state.db.handle(req).await?;
+    form_http_response(body.into())
+}
+
+pub async fn publish_transcode_jobs(
     directory: Option<&path::Path>,
     entries: &[MovieQueueResult],
-) -> HttpResult {
+) -> Result<Vec<TranscodeServiceRequest>, Error> {
     let config = Config::with_config()?;
-    let remcom_service = TranscodeService::new(config.clone(), &config.remcom_queue);
+    let remcom_service = TranscodeService::new(config.clone(), &config.remcom_queue)?;
     let mut output = Vec::new();
     for entry in entries {
         let payload = TranscodeServiceRequest::create_remcom_request(
@@ -137,119 +288,282 @@ async fn transcode_worker(
         )
         .await?;
         remcom_service.publish_transcode_job(&payload).await?;
-        output.push(format!("{:?}", payload));
+        output.push(payload);
     }
+    Ok(output)
+}
+
+async fn transcode_worker(
+    directory: Option<&path::Path>,
+    entries: &[MovieQueueResult],
+) -> HttpResult {
+    let output: Vec<_> = publish_transcode_jobs(directory, entries)
+        .await?
+        .into_iter()
+        .map(|payload| format!("{:?}", payload))
+        .collect();
     form_http_response(output.join(""))
 }
 
 pub async fn movie_queue_transcode(
     path: Path<StackString>,
-    _: LoggedUser,
+    user: EditorUser,
     state: Data<AppState>,
 ) -> HttpResult {
     let path = path.into_inner();
     let patterns = vec![path];
 
-    let req = MovieQueueRequest { patterns };
+    let req = MovieQueueRequest {
+        user: user_key(&user, &state.config),
+        queue: DEFAULT_QUEUE.into(),
+        patterns,
+    };
     let (entries, _) = state.db.handle(req).await?;
     transcode_worker(None, &entries).await
 }
 
 pub async fn movie_queue_transcode_directory(
     path: Path<(StackString, StackString)>,
-    _: LoggedUser,
+    user: EditorUser,
     state: Data<AppState>,
 ) -> HttpResult {
     let (directory, file) = path.into_inner();
     let patterns = vec![file];
 
-    let req = MovieQueueRequest { patterns };
+    let req = MovieQueueRequest {
+        user: user_key(&user, &state.config),
+        queue: DEFAULT_QUEUE.into(),
+        patterns,
+    };
     let (entries, _) = state.db.handle(req).await?;
     transcode_worker(Some(&path::Path::new(directory.as_str())), &entries).await
 }
 
-fn play_worker(full_path: &path::Path) -> HttpResult {
-    let file_name = full_path
+pub async fn movie_queue_play(
+    idx: Path<i32>,
+    user: LoggedUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let idx = idx.into_inner();
+
+    let req = PlaybackPositionRequest {
+        idx,
+        user: user_key(&user, &state.config),
+    };
+    let resume = state
+        .db
+        .handle(req)
+        .await?
+        .and_then(|p| p.resume_position());
+
+    let subtitles: Vec<_> = state
+        .db
+        .handle(SubtitlesRequest { idx })
+        .await?
+        .into_iter()
+        .map(|s| {
+            json!({
+                "url": format!("/list/subtitles/{}", s.id),
+                "language": s.language,
+                "label": s.label(),
+            })
+        })
+        .collect();
+
+    let req = MoviePathRequest { idx };
+    let movie_path = state.db.handle(req).await?;
+    let movie_path = path::Path::new(movie_path.as_str());
+    let file_name = movie_path
         .file_name()
         .ok_or_else(|| format_err!("Invalid path"))?
         .to_string_lossy();
-    let url = format!("/videos/partial/{}", file_name);
-
-    let body = format!(
-        r#"
-        {}<br>
-        <video width="720" controls>
-        <source src="{}" type="video/mp4">
-        Your browser does not support HTML5 video.
-        </video>
-    "#,
-        file_name, url
-    );
-
-    let command = format!("rm -f /var/www/html/videos/partial/{}", file_name);
-    Exec::shell(&command).join()?;
-    let command = format!(
-        "ln -s {} /var/www/html/videos/partial/{}",
-        full_path.to_string_lossy(),
-        file_name
-    );
-    Exec::shell(&command).join()?;
+    let hls = !is_browser_playable(movie_path);
+    let url = if hls {
+        format!("/list/hls/{}/index.m3u8", idx)
+    } else {
+        format!("/list/stream/{}", idx)
+    };
+
+    let body = HBR.render(
+        "play.html",
+        &json!({
+            "file_name": file_name,
+            "url": url,
+            "hls": hls,
+            "collection_idx": idx,
+            "resume": resume,
+            "resume_label": resume.map(format_position),
+            "subtitles": subtitles,
+        }),
+    )?;
     form_http_response(body)
 }
 
-pub async fn movie_queue_play(idx: Path<i32>, _: LoggedUser, state: Data<AppState>) -> HttpResult {
+pub async fn playback_position(
+    idx: Path<i32>,
+    user: LoggedUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let req = PlaybackPositionRequest {
+        idx: idx.into_inner(),
+        user: user_key(&user, &state.config),
+    };
+    let position = state.db.handle(req).await?;
+    to_json(position)
+}
+
+/// Editors only, reaching the end marks the item watched on trakt and can
+/// remove it from the queue.
+pub async fn playback_position_update(
+    idx: Path<i32>,
+    data: Json<PlaybackPositionUpdate>,
+    user: EditorUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let req = PlaybackPositionUpdateRequest {
+        idx: idx.into_inner(),
+        update: data.into_inner(),
+        user: user_key(&user, &state.config),
+        remove_from_queue: state.config.remove_watched_from_queue,
+    };
+    let position = state.db.handle(req).await?;
+    to_json(position)
+}
+
+/// Only files in `movie_collection` are served, the path never comes from the
+/// request. `NamedFile` takes care of Range requests and the content type.
+pub async fn movie_queue_stream(
+    idx: Path<i32>,
+    _: LoggedUser,
+    state: Data<AppState>,
+) -> Result<NamedFile, Error> {
     let idx = idx.into_inner();
 
     let req = MoviePathRequest { idx };
     let movie_path = state.db.handle(req).await?;
-    let movie_path = path::Path::new(movie_path.as_str());
-    play_worker(&movie_path)
+    let file = NamedFile::open(movie_path.as_str())?;
+    Ok(file)
+}
+
+/// Thumbnails are generated on first request and cached on disk, see
+/// `ThumbnailCache`.
+pub async fn movie_queue_thumbnail(
+    idx: Path<i32>,
+    _: LoggedUser,
+    state: Data<AppState>,
+) -> Result<NamedFile, Error> {
+    let idx = idx.into_inner();
+
+    let req = MoviePathRequest { idx };
+    let movie_path = state.db.handle(req).await?;
+    let thumbnail = ThumbnailCache::new(&state.config.thumbnail_cache_dir)
+        .get_thumbnail(path::Path::new(movie_path.as_str()))
+        .await?;
+    let file = NamedFile::open(thumbnail)?;
+    Ok(file)
+}
+
+pub async fn movie_queue_subtitles(
+    id: Path<i32>,
+    _: LoggedUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let req = SubtitleVttRequest {
+        id: id.into_inner(),
+    };
+    let vtt = state.db.handle(req).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/vtt; charset=utf-8")
+        .body(vtt))
+}
+
+/// hls.js is served from the local install rather than a CDN, so no third
+/// party script runs in the authenticated page.
+pub async fn hls_js(_: LoggedUser, state: Data<AppState>) -> Result<NamedFile, Error> {
+    let file = NamedFile::open(&state.config.hls_js_path)?;
+    Ok(file)
+}
+
+pub async fn movie_queue_hls_playlist(
+    idx: Path<i32>,
+    _: LoggedUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let idx = idx.into_inner();
+
+    let req = MoviePathRequest { idx };
+    let movie_path = state.db.handle(req).await?;
+    let playlist = state
+        .hls
+        .playlist(idx, path::Path::new(movie_path.as_str()))
+        .await?;
+    Ok(HttpResponse::Ok()
+        .content_type("application/vnd.apple.mpegurl")
+        .body(playlist))
+}
+
+pub async fn movie_queue_hls_segment(
+    path: Path<(i32, StackString)>,
+    _: LoggedUser,
+    state: Data<AppState>,
+) -> Result<NamedFile, Error> {
+    let (idx, segment) = path.into_inner();
+    let segment = parse_segment_name(&segment)
+        .ok_or_else(|| Error::BadRequest(format!("Invalid segment {}", segment).into()))?;
+
+    let req = MoviePathRequest { idx };
+    let movie_path = state.db.handle(req).await?;
+    let segment_path = state
+        .hls
+        .segment(idx, path::Path::new(movie_path.as_str()), segment)
+        .await?;
+    let content_type: Mime = "video/mp2t".parse().map_err(anyhow::Error::from)?;
+    let file = NamedFile::open(segment_path)?.set_content_type(content_type);
+    Ok(file)
 }
 
 pub async fn imdb_show(
     path: Path<StackString>,
     query: Query<ParseImdbRequest>,
-    _: LoggedUser,
+    user: LoggedUser,
     state: Data<AppState>,
 ) -> HttpResult {
     let show = path.into_inner();
     let query = query.into_inner();
 
-    let req = ImdbShowRequest { show, query };
-    let x = state.db.handle(req).await?;
-    form_http_response(x.into())
-}
-
-fn new_episode_worker(entries: &[StackString]) -> HttpResult {
-    let previous = r#"
-        <a href="javascript:updateMainArticle('/list/tvshows')">Go Back</a><br>
-        <input type="button" name="list_cal" value="TVCalendar" onclick="updateMainArticle('/list/cal');"/>
-        <input type="button" name="list_cal" value="NetflixCalendar" onclick="updateMainArticle('/list/cal?source=netflix');"/>
-        <input type="button" name="list_cal" value="AmazonCalendar" onclick="updateMainArticle('/list/cal?source=amazon');"/>
-        <input type="button" name="list_cal" value="HuluCalendar" onclick="updateMainArticle('/list/cal?source=hulu');"/><br>
-        <button name="remcomout" id="remcomoutput"> &nbsp; </button>
-    "#;
-    let entries = format!(
-        r#"{}<table border="0">{}</table>"#,
-        previous,
-        entries.join("")
-    );
-    form_http_response(entries)
+    let user = user_key(&user, &state.config);
+    let req = ImdbShowRequest { show, query, user };
+    let rows = state.db.handle(req).await?;
+    let body = HBR.render("imdb_show.html", &json!({ "rows": rows }))?;
+    form_http_response(body)
 }
 
 pub async fn find_new_episodes(
-    query: Query<FindNewEpisodeRequest>,
-    _: LoggedUser,
+    query: Query<FindNewEpisodeQuery>,
+    user: LoggedUser,
     state: Data<AppState>,
 ) -> HttpResult {
-    let req = query.into_inner();
-    let entries = state.db.handle(req).await?;
-    new_episode_worker(&entries)
+    let query = query.into_inner();
+    let cal_url = match query.source.as_ref() {
+        Some(s) => format!("/list/cal?source={}", s),
+        None => "/list/cal".to_string(),
+    };
+    let req = FindNewEpisodeRequest {
+        source: query.source,
+        shows: query.shows,
+        user: user_key(&user, &state.config),
+    };
+    let episodes = state.db.handle(req).await?;
+    let body = HBR.render(
+        "new_episodes.html",
+        &json!({"cal_url": cal_url, "episodes": episodes}),
+    )?;
+    form_http_response(body)
 }
 
 pub async fn imdb_episodes_route(
     query: Query<ImdbEpisodesSyncRequest>,
-    _: LoggedUser,
+    _: ReadUser,
     state: Data<AppState>,
 ) -> HttpResult {
     let req = query.into_inner();
@@ -259,7 +573,7 @@ pub async fn imdb_episodes_route(
 
 pub async fn imdb_episodes_update(
     data: Json<ImdbEpisodesUpdateRequest>,
-    _: LoggedUser,
+    _: SyncUser,
     state: Data<AppState>,
 ) -> HttpResult {
     let episodes = data.into_inner();
@@ -271,7 +585,7 @@ pub async fn imdb_episodes_update(
 
 pub async fn imdb_ratings_route(
     query: Query<ImdbRatingsSyncRequest>,
-    _: LoggedUser,
+    _: ReadUser,
     state: Data<AppState>,
 ) -> HttpResult {
     let req = query.into_inner();
@@ -281,7 +595,7 @@ pub async fn imdb_ratings_route(
 
 pub async fn imdb_ratings_update(
     data: Json<ImdbRatingsUpdateRequest>,
-    _: LoggedUser,
+    _: SyncUser,
     state: Data<AppState>,
 ) -> HttpResult {
     let shows = data.into_inner();
@@ -293,7 +607,7 @@ pub async fn imdb_ratings_update(
 
 pub async fn movie_queue_route(
     query: Query<MovieQueueSyncRequest>,
-    _: LoggedUser,
+    _: ReadUser,
     state: Data<AppState>,
 ) -> HttpResult {
     let req = query.into_inner();
@@ -303,7 +617,7 @@ pub async fn movie_queue_route(
 
 pub async fn movie_queue_update(
     data: Json<MovieQueueUpdateRequest>,
-    _: LoggedUser,
+    _: SyncUser,
     state: Data<AppState>,
 ) -> HttpResult {
     let queue = data.into_inner();
@@ -315,7 +629,7 @@ pub async fn movie_queue_update(
 
 pub async fn movie_collection_route(
     query: Query<MovieCollectionSyncRequest>,
-    _: LoggedUser,
+    _: ReadUser,
     state: Data<AppState>,
 ) -> HttpResult {
     let req = query.into_inner();
@@ -325,7 +639,7 @@ pub async fn movie_collection_route(
 
 pub async fn movie_collection_update(
     data: Json<MovieCollectionUpdateRequest>,
-    _: LoggedUser,
+    _: SyncUser,
     state: Data<AppState>,
 ) -> HttpResult {
     let collection = data.into_inner();
@@ -335,7 +649,37 @@ pub async fn movie_collection_update(
     form_http_response("Success".to_string())
 }
 
-pub async fn last_modified_route(_: LoggedUser, state: Data<AppState>) -> HttpResult {
+pub async fn tombstones_route(
+    query: Query<TombstonesSyncRequest>,
+    _: ReadUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let req = query.into_inner();
+    let x = state.db.handle(req).await?;
+    to_json(x)
+}
+
+pub async fn tombstones_update(
+    data: Json<TombstonesUpdateRequest>,
+    _: SyncUser,
+    state: Data<AppState>,
+) -> HttpResult {
+    let req = data.into_inner();
+    state.db.handle(req).await?;
+    form_http_response("Success".to_string())
+}
+
+/// Server sent events for the web UI, see `EventBus`.
+pub async fn movie_queue_events(user: LoggedUser, state: Data<AppState>) -> HttpResult {
+    let user = user_key(&user, &state.config);
+    let stream = state.events.event_stream(user);
+    Ok(HttpResponse::Ok()
+        .content_type("text/event-stream")
+        .header("Cache-Control", "no-cache")
+        .streaming(Box::pin(stream)))
+}
+
+pub async fn last_modified_route(_: ReadUser, state: Data<AppState>) -> HttpResult {
     let req = LastModifiedRequest {};
     let x = state.db.handle(req).await?;
     to_json(x)
@@ -353,6 +697,7 @@ struct ProcessShowItem {
     title: StackString,
     link: StackString,
     source: Option<TvShowSource>,
+    collection_idx: Option<i32>,
 }
 
 impl PartialEq for ProcessShowItem {
@@ -383,6 +728,7 @@ impl From<TvShowsResult> for ProcessShowItem {
             title: item.title,
             link: item.link,
             source: item.source,
+            collection_idx: Some(item.collection_idx),
         }
     }
 }
@@ -403,6 +749,7 @@ fn tvshows_worker(res1: TvShowsMap, tvshows: Vec<TvShowsResult>) -> Result<Stack
                 title: s.title,
                 link: s.link,
                 source,
+                collection_idx: None,
             };
             debug_assert!(link.as_str() == item.link.as_str());
             item
@@ -410,35 +757,34 @@ fn tvshows_worker(res1: TvShowsMap, tvshows: Vec<TvShowsResult>) -> Result<Stack
         .collect();
 
     let shows = process_shows(tvshows, watchlist)?;
-
-    let previous = r#"
-        <a href="javascript:updateMainArticle('/list/watchlist')">Go Back</a><br>
-        <a href="javascript:updateMainArticle('/list/trakt/watchlist')">Watch List</a>
-        <button name="remcomout" id="remcomoutput"> &nbsp; </button><br>
-    "#;
-
-    let entries = format!(
-        r#"{}<table border="0">{}</table>"#,
-        previous,
-        shows.join("")
-    )
-    .into();
-
-    Ok(entries)
+    let body = HBR.render("tvshows.html", &json!({ "shows": shows }))?;
+    Ok(body.into())
 }
 
-pub async fn tvshows(_: LoggedUser, state: Data<AppState>) -> HttpResult {
+pub async fn tvshows(user: LoggedUser, state: Data<AppState>) -> HttpResult {
     let s = state.clone();
     let shows = s.db.handle(TvShowsRequest {}).await?;
-    let res1 = state.db.handle(WatchlistShowsRequest {}).await?;
+    let user = user_key(&user, &state.config);
+    let res1 = state.db.handle(WatchlistShowsRequest { user }).await?;
     let entries = tvshows_worker(res1, shows)?;
     form_http_response(entries.into())
 }
 
+#[derive(Serialize)]
+struct TvShowsEntry {
+    show: StackString,
+    title: StackString,
+    link: StackString,
+    source: Option<TvShowSource>,
+    collection_idx: Option<i32>,
+    in_collection: bool,
+    on_watchlist: bool,
+}
+
 fn process_shows(
     tvshows: HashSet<ProcessShowItem>,
     watchlist: HashSet<ProcessShowItem>,
-) -> Result<Vec<StackString>, Error> {
+) -> Result<Vec<TvShowsEntry>, Error> {
     let watchlist_shows: Vec<_> = watchlist
         .iter()
         .filter(|item| tvshows.get(item.link.as_str()).is_none())
@@ -447,97 +793,64 @@ fn process_shows(
     let mut shows: Vec<_> = tvshows.iter().chain(watchlist_shows.into_iter()).collect();
     shows.sort_by(|x, y| x.show.cmp(&y.show));
 
-    let button_add = r#"<td><button type="submit" id="ID" onclick="watchlist_add('SHOW');">add to watchlist</button></td>"#;
-    let button_rm = r#"<td><button type="submit" id="ID" onclick="watchlist_rm('SHOW');">remove from watchlist</button></td>"#;
-
-    let shows: Vec<_> = shows
+    let shows = shows
         .into_iter()
-        .map(|item| {
-            let has_watchlist = watchlist.contains(item.link.as_str());
-            format!(
-                r#"<tr><td>{}</td>
-                <td><a href="https://www.imdb.com/title/{}" target="_blank">imdb</a></td><td>{}</td><td>{}</td><td>{}</td></tr>"#,
-                if tvshows.contains(item.link.as_str()) {
-                    format!(r#"<a href="javascript:updateMainArticle('/list/{}')">{}</a>"#, item.show, item.title)
-                } else {
-                    format!(
-                        r#"<a href="javascript:updateMainArticle('/list/trakt/watched/list/{}')">{}</a>"#,
-                        item.link, item.title
-                    )
-                },
-                item.link,
-                match item.source {
-                    Some(TvShowSource::Netflix) => r#"<a href="https://netflix.com" target="_blank">netflix</a>"#,
-                    Some(TvShowSource::Hulu) => r#"<a href="https://hulu.com" target="_blank">hulu</a>"#,
-                    Some(TvShowSource::Amazon) => r#"<a href="https://amazon.com" target="_blank">amazon</a>"#,
-                    _ => "",
-                },
-                if has_watchlist {
-                    format!(r#"<a href="javascript:updateMainArticle('/list/trakt/watched/list/{}')">watchlist</a>"#, item.link)
-                } else {
-                    "".to_string()
-                },
-                if has_watchlist {
-                    button_rm.replace("SHOW", &item.link)
-                } else {
-                    button_add.replace("SHOW", &item.link)
-                },
-            ).into()
+        .map(|item| TvShowsEntry {
+            show: item.show.clone(),
+            title: item.title.clone(),
+            link: item.link.clone(),
+            source: link_source(item.source),
+            collection_idx: item.collection_idx,
+            in_collection: tvshows.contains(item.link.as_str()),
+            on_watchlist: watchlist.contains(item.link.as_str()),
         })
         .collect();
     Ok(shows)
 }
 
+/// Only streaming services get a link, `TvShowSource::All` is a filter
+fn link_source(source: Option<TvShowSource>) -> Option<TvShowSource> {
+    match source {
+        Some(TvShowSource::All) | None => None,
+        s => s,
+    }
+}
+
 fn watchlist_worker(
     shows: HashMap<StackString, (StackString, WatchListShow, Option<TvShowSource>)>,
 ) -> HttpResult {
     let mut shows: Vec<_> = shows
         .into_iter()
-        .map(|(_, (_, s, source))| (s.title, s.link, source))
+        .map(|(_, (_, s, source))| (s.title, s.link, link_source(source)))
         .collect();
 
     shows.sort();
 
     let shows: Vec<_> = shows
         .into_iter()
-        .map(|(title, link, source)| {
-            format!(
-                r#"<tr><td>{}</td>
-            <td><a href="https://www.imdb.com/title/{}" target="_blank">imdb</a> {} </tr>"#,
-                format!(
-                    r#"<a href="javascript:updateMainArticle('/list/trakt/watched/list/{}')">{}</a>"#,
-                    link, title
-                ),
-                link,
-                match source {
-                    Some(TvShowSource::Netflix) => {
-                        r#"<td><a href="https://netflix.com" target="_blank">netflix</a>"#
-                    }
-                    Some(TvShowSource::Hulu) => r#"<td><a href="https://hulu.com" target="_blank">netflix</a>"#,
-                    Some(TvShowSource::Amazon) => r#"<td><a href="https://amazon.com" target="_blank">netflix</a>"#,
-                    _ => "",
-                },
-            )
-        })
+        .map(|(title, link, source)| json!({"title": title, "link": link, "source": source}))
         .collect();
 
-    let previous = r#"<a href="javascript:updateMainArticle('/list/tvshows')">Go Back</a><br>"#;
-    let entries = format!(
-        r#"{}<table border="0">{}</table>"#,
-        previous,
-        shows.join("")
-    );
-
-    form_http_response(entries)
+    let body = HBR.render("watchlist.html", &json!({ "shows": shows }))?;
+    form_http_response(body)
 }
 
-pub async fn trakt_watchlist(_: LoggedUser, state: Data<AppState>) -> HttpResult {
-    let req = WatchlistShowsRequest {};
+pub async fn trakt_watchlist(user: LoggedUser, state: Data<AppState>) -> HttpResult {
+    let user = user_key(&user, &state.config);
+    let req = WatchlistShowsRequest { user };
     let x = state.db.handle(req).await?;
     watchlist_worker(x)
 }
 
-async fn watchlist_action_worker(action: TraktActions, imdb_url: &str) -> HttpResult {
+/// Push a watchlist change to trakt, only the default user is synced.
+pub async fn watchlist_trakt_action(
+    action: TraktActions,
+    imdb_url: &str,
+    user: &str,
+) -> Result<String, Error> {
+    if user != DEFAULT_USER {
+        return Ok(imdb_url.to_string());
+    }
     TRAKT_CONN.init().await;
     let body = match action {
         TraktActions::Add => TRAKT_CONN.add_watchlist_show(&imdb_url).await?.to_string(),
@@ -547,20 +860,30 @@ async fn watchlist_action_worker(action: TraktActions, imdb_url: &str) -> HttpRe
             .to_string(),
         _ => "".to_string(),
     };
+    Ok(body)
+}
+
+async fn watchlist_action_worker(action: TraktActions, imdb_url: &str, user: &str) -> HttpResult {
+    let body = watchlist_trakt_action(action, imdb_url, user).await?;
     form_http_response(body)
 }
 
 pub async fn trakt_watchlist_action(
     path: Path<(StackString, StackString)>,
-    _: LoggedUser,
+    user: EditorUser,
     state: Data<AppState>,
 ) -> HttpResult {
     let (action, imdb_url) = path.into_inner();
     let action = action.parse().expect("impossible");
 
-    let req = WatchlistActionRequest { action, imdb_url };
+    let user = user_key(&user, &state.config);
+    let req = WatchlistActionRequest {
+        action,
+        imdb_url,
+        user: user.clone(),
+    };
     let imdb_url = state.db.handle(req).await?;
-    watchlist_action_worker(action, &imdb_url).await
+    watchlist_action_worker(action, &imdb_url, &user).await
 }
 
 fn trakt_watched_seasons_worker(
@@ -568,40 +891,11 @@ fn trakt_watched_seasons_worker(
     imdb_url: &str,
     entries: &[ImdbSeason],
 ) -> Result<StackString, Error> {
-    let button_add = r#"
-        <td>
-        <button type="submit" id="ID"
-            onclick="imdb_update('SHOW', 'LINK', SEASON, '/list/trakt/watched/list/LINK');"
-            >update database</button></td>"#;
-
-    let entries: Vec<_> = entries
-        .iter()
-        .map(|s| {
-            format!(
-                "<tr><td>{}<td>{}<td>{}<td>{}</tr>",
-                format!(
-                    r#"<a href="javascript:updateMainArticle('/list/trakt/watched/list/{}/{}')">{}</t>"#,
-                    imdb_url, s.season, s.title
-                ),
-                s.season,
-                s.nepisodes,
-                button_add
-                    .replace("SHOW", &s.show)
-                    .replace("LINK", &link)
-                    .replace("SEASON", &s.season.to_string())
-            )
-        })
-        .collect();
-
-    let previous =
-        r#"<a href="javascript:updateMainArticle('/list/trakt/watchlist')">Go Back</a><br>"#;
-    let entries = format!(
-        r#"{}<table border="0">{}</table>"#,
-        previous,
-        entries.join("")
-    )
-    .into();
-    Ok(entries)
+    let body = HBR.render(
+        "watched_seasons.html",
+        &json!({"link": link, "imdb_url": imdb_url, "seasons": entries}),
+    )?;
+    Ok(body.into())
 }
 
 pub async fn trakt_watched_seasons(
@@ -622,19 +916,25 @@ pub async fn trakt_watched_seasons(
 
 pub async fn trakt_watched_list(
     path: Path<(StackString, i32)>,
-    _: LoggedUser,
+    user: LoggedUser,
     state: Data<AppState>,
 ) -> HttpResult {
     let (imdb_url, season) = path.into_inner();
 
-    let req = WatchedListRequest { imdb_url, season };
-    let x = state.db.handle(req).await?;
-    form_http_response(x.into())
+    let user = user_key(&user, &state.config);
+    let req = WatchedListRequest {
+        imdb_url,
+        season,
+        user,
+    };
+    let watched = state.db.handle(req).await?;
+    let body = HBR.render("watched_list.html", &watched)?;
+    form_http_response(body)
 }
 
 pub async fn trakt_watched_action(
     path: Path<(StackString, StackString, i32, i32)>,
-    _: LoggedUser,
+    user: EditorUser,
     state: Data<AppState>,
 ) -> HttpResult {
     let (action, imdb_url, season, episode) = path.into_inner();
@@ -644,32 +944,28 @@ pub async fn trakt_watched_action(
         imdb_url,
         season,
         episode,
+        user: user_key(&user, &state.config),
     };
     let x = state.db.handle(req).await?;
     form_http_response(x.into())
 }
 
-fn trakt_cal_worker(entries: &[StackString]) -> HttpResult {
-    let previous = r#"<a href="javascript:updateMainArticle('/list/tvshows')">Go Back</a><br>"#;
-    let entries = format!(
-        r#"{}<table border="0">{}</table>"#,
-        previous,
-        entries.join("")
-    );
-    form_http_response(entries)
-}
-
 pub async fn trakt_cal(_: LoggedUser, state: Data<AppState>) -> HttpResult {
     let req = TraktCalRequest {};
     let entries = state.db.handle(req).await?;
-    trakt_cal_worker(&entries)
+    let body = HBR.render("trakt_cal.html", &json!({ "entries": entries }))?;
+    form_http_response(body)
 }
 
 pub async fn user(user: LoggedUser) -> HttpResult {
     to_json(user)
 }
 
-pub async fn trakt_auth_url(_: LoggedUser, _: Data<AppState>) -> HttpResult {
+pub async fn openapi_json(_: LoggedUser) -> HttpResult {
+    to_json(openapi_spec())
+}
+
+pub async fn trakt_auth_url(_: EditorUser, _: Data<AppState>) -> HttpResult {
     TRAKT_CONN.init().await;
     let url = TRAKT_CONN.get_auth_url().await?;
     form_http_response(url.to_string())
@@ -683,7 +979,7 @@ pub struct TraktCallbackRequest {
 
 pub async fn trakt_callback(
     query: Query<TraktCallbackRequest>,
-    _: LoggedUser,
+    _: EditorUser,
     _: Data<AppState>,
 ) -> HttpResult {
     TRAKT_CONN.init().await;
@@ -697,7 +993,7 @@ pub async fn trakt_callback(
     form_http_response(body.to_string())
 }
 
-pub async fn refresh_auth(_: LoggedUser, _: Data<AppState>) -> HttpResult {
+pub async fn refresh_auth(_: EditorUser, _: Data<AppState>) -> HttpResult {
     TRAKT_CONN.init().await;
     TRAKT_CONN.exchange_refresh_token().await?;
     form_http_response("finished".to_string())
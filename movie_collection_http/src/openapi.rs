@@ -0,0 +1,908 @@
+use actix_web::web::{Data, Json, Path, Query};
+use chrono::{DateTime, NaiveDate, Utc};
+use serde::Serialize;
+use serde_json::{json, Map, Value};
+use stack_string::StackString;
+use std::path::PathBuf;
+
+use movie_collection_lib::{
+    imdb_episodes::ImdbEpisodes,
+    imdb_ratings::ImdbRatings,
+    movie_collection::{
+        LastModifiedResponse, MovieCollectionResult, MovieCollectionRow, NewEpisodesResult,
+        TvShowsResult,
+    },
+    movie_queue::{MovieQueueResult, MovieQueueRow},
+    playback_position::PlaybackPosition,
+    search::{SearchKind, SearchResult},
+    tombstones::Tombstone,
+    trakt_utils::{WatchListShow, WatchedEpisode},
+    transcode_service::{JobType, SubtitleMode, SubtitleOption, TranscodeServiceRequest},
+    tv_show_source::TvShowSource,
+};
+
+use crate::{
+    logged_user::{EditorUser, LoggedUser, ReadUser, SyncUser},
+    movie_queue_api::{ApiCollectionQuery, ApiQueueDelete, ApiQueueName, ApiQueueQuery, ApiResult},
+    movie_queue_app::operations,
+    movie_queue_requests::{
+        FindNewEpisodeQuery, ImdbEpisodesSyncRequest, ImdbEpisodesUpdateRequest,
+        ImdbRatingsSyncRequest, ImdbRatingsUpdateRequest, MovieCollectionSyncRequest,
+        MovieCollectionUpdateRequest, MovieQueueSyncRequest, MovieQueueUpdateRequest,
+        ParseImdbRequest, PlaybackPositionUpdate, SearchQuery, TombstonesSyncRequest,
+        TombstonesUpdateRequest,
+    },
+    movie_queue_routes::TraktCallbackRequest,
+};
+
+/// Schemas referenced from the operations, by name.
+pub type Components = Map<String, Value>;
+
+/// Description of a single route, built by `app_routes!` from the types of
+/// the handler's arguments and the response type given in the route table.
+pub struct Operation {
+    pub method: &'static str,
+    pub path: &'static str,
+    pub summary: &'static str,
+    pub path_params: Vec<Value>,
+    pub query: Vec<Field>,
+    pub body: Option<Value>,
+    pub content: Value,
+    pub ranges: bool,
+    pub editor: bool,
+    pub token: Option<&'static str>,
+}
+
+impl Operation {
+    pub fn new(method: &'static str, path: &'static str, summary: &'static str) -> Self {
+        Self {
+            method,
+            path,
+            summary,
+            path_params: Vec::new(),
+            query: Vec::new(),
+            body: None,
+            content: Html::content(&mut Components::new()),
+            ranges: false,
+            editor: false,
+            token: None,
+        }
+    }
+
+    pub fn handler<H: Describe<Args>, Args>(
+        mut self,
+        handler: &H,
+        components: &mut Components,
+    ) -> Self {
+        handler.describe(&mut self, components);
+        self
+    }
+
+    pub fn response<R: ApiResponse>(mut self, components: &mut Components) -> Self {
+        self.content = R::content(components);
+        self.ranges = R::ranges();
+        self
+    }
+
+    fn path_names(&self) -> impl Iterator<Item = &str> {
+        self.path
+            .split('/')
+            .filter(|s| s.starts_with('{') && s.ends_with('}'))
+            .map(|s| s.trim_start_matches('{').trim_end_matches('}'))
+    }
+
+    fn to_value(&self) -> Value {
+        let mut parameters: Vec<_> = self
+            .path_names()
+            .zip(self.path_params.iter())
+            .map(|(name, schema)| {
+                json!({
+                    "name": name,
+                    "in": "path",
+                    "required": true,
+                    "schema": schema,
+                })
+            })
+            .collect();
+        parameters.extend(self.query.iter().map(|field| {
+            json!({
+                "name": field.name,
+                "in": "query",
+                "required": field.required,
+                "schema": field.schema,
+            })
+        }));
+        if self.ranges {
+            parameters.push(
+                json!({"name": "Range", "in": "header", "required": false, "schema": string()}),
+            );
+        }
+
+        let mut op = json!({
+            "summary": self.summary,
+            "parameters": parameters,
+            "responses": {
+                "200": {"description": "Success", "content": self.content},
+                "400": {"description": "Bad Request"},
+                "401": {"description": "Not logged in, the login page is returned"},
+                "500": {"description": "Internal Server Error"},
+            },
+        });
+        if self.ranges {
+            op["responses"]["206"] =
+                json!({"description": "Partial Content", "content": self.content});
+            op["responses"]["416"] = json!({"description": "Range Not Satisfiable"});
+        }
+        if self.editor {
+            op["responses"]["403"] = json!({"description": "Requires the editor or admin role"});
+        }
+        if let Some(scope) = self.token {
+            op["security"] = json!([{"cookieAuth": []}, {"bearerAuth": []}]);
+            op["x-token-scope"] = json!(scope);
+        }
+        if let Some(body) = &self.body {
+            op["requestBody"] = json!({
+                "required": true,
+                "content": {"application/json": {"schema": body}},
+            });
+        }
+        op
+    }
+}
+
+/// A property of an object schema, or a query parameter.
+pub struct Field {
+    pub name: &'static str,
+    pub schema: Value,
+    pub required: bool,
+}
+
+impl Field {
+    pub fn new<T: SchemaType>(name: &'static str, components: &mut Components) -> Self {
+        Self {
+            name,
+            schema: T::schema(components),
+            required: !T::optional(),
+        }
+    }
+}
+
+/// A type that appears in a request or response, structs are added to
+/// `components` and referenced.
+pub trait SchemaType {
+    fn schema(components: &mut Components) -> Value;
+
+    /// `Option` fields may be null or left out
+    fn optional() -> bool {
+        false
+    }
+}
+
+/// A struct documented as an object schema, implemented with `api_schema!`.
+pub trait ApiSchema {
+    const NAME: &'static str;
+
+    fn fields(components: &mut Components) -> Vec<Field>;
+}
+
+/// Adds `T` to `components` the first time it is seen and references it.
+fn register<T: ApiSchema>(components: &mut Components) -> Value {
+    if !components.contains_key(T::NAME) {
+        // Reserve the name first so that a schema referencing itself ends.
+        components.insert(T::NAME.into(), Value::Null);
+        let fields = T::fields(components);
+        components.insert(T::NAME.into(), object(&fields));
+    }
+    reference(T::NAME)
+}
+
+/// Implements `ApiSchema` for structs with public fields. The listed fields
+/// are destructured, so the build fails if a field is missing from the list
+/// or has another type than in the struct.
+macro_rules! api_schema {
+    ($($ty:ident { $($field:ident: $fty:ty),* $(,)? })*) => {$(
+        impl ApiSchema for $ty {
+            const NAME: &'static str = stringify!($ty);
+
+            fn fields(components: &mut Components) -> Vec<Field> {
+                vec![$(Field::new::<$fty>(stringify!($field), components)),*]
+            }
+        }
+
+        impl SchemaType for $ty {
+            fn schema(components: &mut Components) -> Value {
+                register::<Self>(components)
+            }
+        }
+
+        const _: () = {
+            #[allow(dead_code)]
+            fn check(value: $ty) {
+                let $ty { $($field),* } = value;
+                $(let _: $fty = $field;)*
+            }
+        };
+    )*};
+}
+
+api_schema! {
+    PlaybackPosition {
+        collection_idx: i32,
+        position: f64,
+        duration: f64,
+        watched: bool,
+    }
+    PlaybackPositionUpdate {
+        position: f64,
+        duration: f64,
+    }
+    MovieQueueResult {
+        idx: i32,
+        path: StackString,
+        link: Option<StackString>,
+        istv: bool,
+        show: Option<StackString>,
+        eplink: Option<StackString>,
+        season: Option<i32>,
+        episode: Option<i32>,
+    }
+    MovieCollectionResult {
+        path: StackString,
+        show: StackString,
+        rating: f64,
+        title: StackString,
+        istv: bool,
+        eprating: Option<f64>,
+        season: Option<i32>,
+        episode: Option<i32>,
+        eptitle: Option<StackString>,
+        epurl: Option<StackString>,
+        volume: Option<StackString>,
+    }
+    SearchResult {
+        kind: SearchKind,
+        score: f64,
+        show: StackString,
+        title: StackString,
+        link: Option<StackString>,
+        path: Option<StackString>,
+        season: Option<i32>,
+        episode: Option<i32>,
+    }
+    TvShowsResult {
+        show: StackString,
+        link: StackString,
+        count: i64,
+        title: StackString,
+        source: Option<TvShowSource>,
+        collection_idx: i32,
+    }
+    NewEpisodesResult {
+        show: StackString,
+        link: StackString,
+        title: StackString,
+        season: i32,
+        episode: i32,
+        epurl: StackString,
+        airdate: NaiveDate,
+        rating: f64,
+        eprating: f64,
+        eptitle: StackString,
+    }
+    WatchListShow {
+        link: StackString,
+        title: StackString,
+        year: i32,
+    }
+    WatchedEpisode {
+        title: StackString,
+        imdb_url: StackString,
+        episode: i32,
+        season: i32,
+    }
+    SubtitleOption {
+        language: StackString,
+        mode: SubtitleMode,
+    }
+    ImdbEpisodes {
+        show: StackString,
+        title: StackString,
+        season: i32,
+        episode: i32,
+        airdate: NaiveDate,
+        rating: f64,
+        eptitle: StackString,
+        epurl: StackString,
+    }
+    ImdbRatings {
+        index: i32,
+        show: StackString,
+        title: Option<StackString>,
+        link: StackString,
+        rating: Option<f64>,
+        istv: Option<bool>,
+        source: Option<TvShowSource>,
+    }
+    MovieQueueRow {
+        user_email: StackString,
+        queue_name: StackString,
+        idx: i32,
+        collection_idx: i32,
+        path: StackString,
+        show: StackString,
+    }
+    MovieCollectionRow {
+        idx: i32,
+        path: StackString,
+        show: StackString,
+    }
+    LastModifiedResponse {
+        table: StackString,
+        last_modified: DateTime<Utc>,
+    }
+    Tombstone {
+        table_name: StackString,
+        key: Vec<StackString>,
+        deleted_at: DateTime<Utc>,
+    }
+    ImdbEpisodesUpdateRequest {
+        episodes: Vec<ImdbEpisodes>,
+    }
+    ImdbRatingsUpdateRequest {
+        shows: Vec<ImdbRatings>,
+    }
+    MovieQueueUpdateRequest {
+        queue: Vec<MovieQueueRow>,
+    }
+    MovieCollectionUpdateRequest {
+        collection: Vec<MovieCollectionRow>,
+    }
+    TombstonesUpdateRequest {
+        tombstones: Vec<Tombstone>,
+    }
+    ApiResult {
+        result: StackString,
+    }
+    ApiQueueName {
+        name: StackString,
+        count: i64,
+    }
+    ApiQueueDelete {
+        path: StackString,
+    }
+    SearchQuery {
+        q: StackString,
+        limit: Option<usize>,
+    }
+    ParseImdbRequest {
+        all: Option<bool>,
+        database: Option<bool>,
+        tv: Option<bool>,
+        update: Option<bool>,
+        link: Option<StackString>,
+        season: Option<i32>,
+    }
+    FindNewEpisodeQuery {
+        source: Option<TvShowSource>,
+        shows: Option<StackString>,
+    }
+    ImdbEpisodesSyncRequest {
+        start_timestamp: DateTime<Utc>,
+    }
+    ImdbRatingsSyncRequest {
+        start_timestamp: DateTime<Utc>,
+    }
+    MovieQueueSyncRequest {
+        start_timestamp: DateTime<Utc>,
+    }
+    MovieCollectionSyncRequest {
+        start_timestamp: DateTime<Utc>,
+    }
+    TombstonesSyncRequest {
+        start_timestamp: DateTime<Utc>,
+    }
+    TraktCallbackRequest {
+        code: StackString,
+        state: StackString,
+    }
+    ApiQueueQuery {
+        patterns: Option<StackString>,
+    }
+    ApiCollectionQuery {
+        search: Option<StackString>,
+    }
+}
+
+// The fields of `TranscodeServiceRequest` and `LoggedUser` aren't public, so
+// they can't go through `api_schema!`; the tests compare them to their
+// serialized form instead.
+impl ApiSchema for TranscodeServiceRequest {
+    const NAME: &'static str = "TranscodeServiceRequest";
+
+    fn fields(components: &mut Components) -> Vec<Field> {
+        vec![
+            Field::new::<JobType>("job_type", components),
+            Field::new::<StackString>("prefix", components),
+            Field::new::<PathBuf>("input_path", components),
+            Field::new::<PathBuf>("output_path", components),
+            Field::new::<Option<SubtitleOption>>("subtitles", components),
+        ]
+    }
+}
+
+impl SchemaType for TranscodeServiceRequest {
+    fn schema(components: &mut Components) -> Value {
+        register::<Self>(components)
+    }
+}
+
+impl ApiSchema for LoggedUser {
+    const NAME: &'static str = "LoggedUser";
+
+    fn fields(components: &mut Components) -> Vec<Field> {
+        vec![Field::new::<StackString>("email", components)]
+    }
+}
+
+impl SchemaType for LoggedUser {
+    fn schema(components: &mut Components) -> Value {
+        register::<Self>(components)
+    }
+}
+
+macro_rules! plain_schema {
+    ($($ty:ty => $schema:expr,)*) => {$(
+        impl SchemaType for $ty {
+            fn schema(_: &mut Components) -> Value {
+                $schema
+            }
+        }
+    )*};
+}
+
+plain_schema! {
+    StackString => string(),
+    PathBuf => string(),
+    bool => boolean(),
+    i32 => integer(),
+    i64 => integer(),
+    usize => integer(),
+    f64 => number(),
+    NaiveDate => json!({"type": "string", "format": "date"}),
+    DateTime<Utc> => json!({"type": "string", "format": "date-time"}),
+    TvShowSource => enumeration(&[
+        TvShowSource::Netflix,
+        TvShowSource::Hulu,
+        TvShowSource::Amazon,
+        TvShowSource::All,
+    ]),
+    SearchKind => enumeration(&[SearchKind::Collection, SearchKind::Show, SearchKind::Episode]),
+    JobType => enumeration(&[JobType::Transcode, JobType::Move]),
+    SubtitleMode => enumeration(&[SubtitleMode::Burn, SubtitleMode::PassThrough]),
+}
+
+impl<T: SchemaType> SchemaType for Option<T> {
+    fn schema(components: &mut Components) -> Value {
+        let mut schema = T::schema(components);
+        schema["nullable"] = Value::Bool(true);
+        schema
+    }
+
+    fn optional() -> bool {
+        true
+    }
+}
+
+impl<T: SchemaType> SchemaType for Vec<T> {
+    fn schema(components: &mut Components) -> Value {
+        json!({"type": "array", "items": T::schema(components)})
+    }
+}
+
+/// Types of the path segments of a route, in order.
+pub trait PathParams {
+    fn schemas(components: &mut Components) -> Vec<Value>;
+}
+
+impl PathParams for StackString {
+    fn schemas(_: &mut Components) -> Vec<Value> {
+        vec![string()]
+    }
+}
+
+impl PathParams for i32 {
+    fn schemas(_: &mut Components) -> Vec<Value> {
+        vec![integer()]
+    }
+}
+
+macro_rules! path_tuple {
+    ($($t:ident),*) => {
+        impl<$($t: SchemaType),*> PathParams for ($($t,)*) {
+            fn schemas(components: &mut Components) -> Vec<Value> {
+                vec![$($t::schema(components)),*]
+            }
+        }
+    };
+}
+
+path_tuple!(A, B);
+path_tuple!(A, B, C);
+path_tuple!(A, B, C, D);
+
+/// Argument of a handler, adds what it extracts or requires to the
+/// operation.
+pub trait DescribeArg {
+    fn describe(_op: &mut Operation, _components: &mut Components) {}
+}
+
+impl<T: PathParams> DescribeArg for Path<T> {
+    fn describe(op: &mut Operation, components: &mut Components) {
+        op.path_params = T::schemas(components);
+    }
+}
+
+impl<T: ApiSchema> DescribeArg for Query<T> {
+    fn describe(op: &mut Operation, components: &mut Components) {
+        op.query = T::fields(components);
+    }
+}
+
+impl<T: SchemaType> DescribeArg for Json<T> {
+    fn describe(op: &mut Operation, components: &mut Components) {
+        op.body = Some(T::schema(components));
+    }
+}
+
+impl<T> DescribeArg for Data<T> {}
+
+impl DescribeArg for LoggedUser {}
+
+impl DescribeArg for EditorUser {
+    fn describe(op: &mut Operation, _: &mut Components) {
+        op.editor = true;
+    }
+}
+
+impl DescribeArg for ReadUser {
+    fn describe(op: &mut Operation, _: &mut Components) {
+        op.token = Some("read");
+    }
+}
+
+impl DescribeArg for SyncUser {
+    fn describe(op: &mut Operation, _: &mut Components) {
+        op.editor = true;
+        op.token = Some("sync");
+    }
+}
+
+/// Implemented for every handler whose arguments are all `DescribeArg`.
+pub trait Describe<Args> {
+    fn describe(&self, op: &mut Operation, components: &mut Components);
+}
+
+macro_rules! describe_handler {
+    ($($arg:ident),*) => {
+        impl<Func, Res, $($arg: DescribeArg),*> Describe<($($arg,)*)> for Func
+        where
+            Func: Fn($($arg),*) -> Res,
+        {
+            fn describe(&self, op: &mut Operation, components: &mut Components) {
+                $(<$arg as DescribeArg>::describe(op, components);)*
+            }
+        }
+    };
+}
+
+describe_handler!(A);
+describe_handler!(A, B);
+describe_handler!(A, B, C);
+describe_handler!(A, B, C, D);
+
+/// Content of a successful response, given per route in `app_routes!`.
+pub trait ApiResponse {
+    fn content(components: &mut Components) -> Value;
+
+    /// Whether the route answers `Range` requests
+    fn ranges() -> bool {
+        false
+    }
+}
+
+impl<T: SchemaType> ApiResponse for Json<T> {
+    fn content(components: &mut Components) -> Value {
+        json!({"application/json": {"schema": T::schema(components)}})
+    }
+}
+
+macro_rules! response_types {
+    ($($ty:ident => $mime:literal: $schema:expr,)*) => {$(
+        pub struct $ty;
+
+        impl ApiResponse for $ty {
+            fn content(_: &mut Components) -> Value {
+                json!({$mime: {"schema": $schema}})
+            }
+        }
+    )*};
+}
+
+response_types! {
+    Html => "text/html": string(),
+    Object => "application/json": json!({"type": "object"}),
+    Playlist => "application/vnd.apple.mpegurl": string(),
+    Vtt => "text/vtt": string(),
+    Jpeg => "image/jpeg": binary(),
+    Javascript => "application/javascript": string(),
+    EventStream => "text/event-stream": string(),
+}
+
+pub struct Video;
+
+impl ApiResponse for Video {
+    fn content(_: &mut Components) -> Value {
+        json!({"video/*": {"schema": binary()}})
+    }
+
+    fn ranges() -> bool {
+        true
+    }
+}
+
+fn string() -> Value {
+    json!({"type": "string"})
+}
+
+fn binary() -> Value {
+    json!({"type": "string", "format": "binary"})
+}
+
+fn integer() -> Value {
+    json!({"type": "integer"})
+}
+
+fn number() -> Value {
+    json!({"type": "number"})
+}
+
+fn boolean() -> Value {
+    json!({"type": "boolean"})
+}
+
+/// String enum of the serialized names of `variants`
+fn enumeration<T: Serialize>(variants: &[T]) -> Value {
+    let names: Vec<_> = variants
+        .iter()
+        .map(|v| serde_json::to_value(v).expect("enum variants serialize"))
+        .collect();
+    json!({"type": "string", "enum": names})
+}
+
+fn reference(name: &str) -> Value {
+    json!({ "$ref": format!("#/components/schemas/{}", name) })
+}
+
+fn object(fields: &[Field]) -> Value {
+    let required: Vec<_> = fields
+        .iter()
+        .filter(|f| f.required)
+        .map(|f| f.name)
+        .collect();
+    let properties: Map<String, Value> = fields
+        .iter()
+        .map(|f| (f.name.to_string(), f.schema.clone()))
+        .collect();
+    json!({"type": "object", "properties": properties, "required": required})
+}
+
+pub fn openapi_spec() -> Value {
+    let mut components = Components::new();
+    let mut paths = Map::new();
+    for op in operations(&mut components) {
+        let entry = paths
+            .entry(op.path.to_string())
+            .or_insert_with(|| json!({}));
+        entry[op.method] = op.to_value();
+    }
+    json!({
+        "openapi": "3.0.3",
+        "info": {
+            "title": "movie_collection_http",
+            "version": env!("CARGO_PKG_VERSION"),
+        },
+        "security": [{"cookieAuth": []}],
+        "paths": paths,
+        "components": {
+            "securitySchemes": {
+                "cookieAuth": {
+                    "type": "apiKey",
+                    "in": "cookie",
+                    "name": "auth",
+                    "description": "JWT issued by auth_server_rust on login",
+                },
+                "bearerAuth": {
+                    "type": "http",
+                    "scheme": "bearer",
+                    "description": "Token from `movie-queue-cli token create`, accepted by \
+                                    operations with an x-token-scope the token's scope covers",
+                },
+            },
+            "schemas": components,
+        },
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use serde::Serialize;
+    use serde_json::Value;
+    use std::{collections::BTreeSet, path::Path};
+
+    use movie_collection_lib::{
+        imdb_ratings::ImdbRatings,
+        movie_collection::{MovieCollectionResult, MovieCollectionRow, TvShowsResult},
+        movie_queue::{MovieQueueResult, MovieQueueRow},
+        playback_position::PlaybackPosition,
+        search::SearchResult,
+        trakt_utils::{WatchListShow, WatchedEpisode},
+        transcode_service::{JobType, TranscodeServiceRequest},
+    };
+
+    use crate::{
+        movie_queue_api::{ApiQueueDelete, ApiQueueName, ApiResult},
+        movie_queue_requests::PlaybackPositionUpdate,
+        openapi::openapi_spec,
+    };
+
+    fn operation<'a>(spec: &'a Value, method: &str, path: &str) -> &'a Value {
+        let op = &spec["paths"][path][method];
+        assert!(op.is_object(), "{} {} missing", method, path);
+        op
+    }
+
+    fn parameters(op: &Value, location: &str) -> Vec<(String, Value)> {
+        op["parameters"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .filter(|p| p["in"] == location)
+            .map(|p| (p["name"].as_str().unwrap().to_string(), p.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn test_path_params_documented() {
+        let spec = openapi_spec();
+        for (path, methods) in spec["paths"].as_object().unwrap() {
+            let names: Vec<_> = path
+                .split('/')
+                .filter(|s| s.starts_with('{'))
+                .map(|s| s.trim_start_matches('{').trim_end_matches('}'))
+                .collect();
+            for (method, op) in methods.as_object().unwrap() {
+                let params: Vec<_> = parameters(op, "path")
+                    .into_iter()
+                    .map(|(name, _)| name)
+                    .collect();
+                assert_eq!(params, names, "path parameters of {} {}", method, path);
+            }
+        }
+        let op = operation(&spec, "post", "/list/queue/{queue}/move/{from}/{to}");
+        let params = parameters(op, "path");
+        assert_eq!(params[0].1["schema"]["type"], "string");
+        assert_eq!(params[1].1["schema"]["type"], "integer");
+        assert_eq!(params[2].1["schema"]["type"], "integer");
+    }
+
+    #[test]
+    fn test_roles_documented() {
+        let spec = openapi_spec();
+
+        let op = operation(&spec, "post", "/list/queue/{queue}/delete/{path}");
+        assert!(op["responses"]["403"].is_object());
+        assert!(op.get("x-token-scope").is_none());
+
+        let op = operation(&spec, "get", "/list/queue/{queue}/full_queue");
+        assert!(op["responses"].get("403").is_none());
+
+        let op = operation(&spec, "get", "/api/v1/queues");
+        assert_eq!(op["x-token-scope"], "read");
+        assert!(op["responses"].get("403").is_none());
+
+        let op = operation(&spec, "post", "/list/movie_queue");
+        assert_eq!(op["x-token-scope"], "sync");
+        assert!(op["responses"]["403"].is_object());
+        assert_eq!(
+            op["requestBody"]["content"]["application/json"]["schema"]["$ref"],
+            "#/components/schemas/MovieQueueUpdateRequest"
+        );
+    }
+
+    #[test]
+    fn test_query_params_documented() {
+        let spec = openapi_spec();
+        let op = operation(&spec, "get", "/api/v1/search");
+        let params = parameters(op, "query");
+        let names: Vec<_> = params.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["q", "limit"]);
+        assert_eq!(params[0].1["required"], true);
+        assert_eq!(params[1].1["required"], false);
+        assert_eq!(params[1].1["schema"]["type"], "integer");
+
+        let op = operation(&spec, "get", "/list/tombstones");
+        let params = parameters(op, "query");
+        assert_eq!(params[0].0, "start_timestamp");
+        assert_eq!(params[0].1["schema"]["format"], "date-time");
+    }
+
+    #[test]
+    fn test_responses_documented() {
+        let spec = openapi_spec();
+        let op = operation(&spec, "get", "/list/stream/{index}");
+        assert!(op["responses"]["206"]["content"]["video/*"].is_object());
+        assert_eq!(parameters(op, "header")[0].0, "Range");
+
+        let op = operation(&spec, "get", "/api/v1/collection");
+        let schema = &op["responses"]["200"]["content"]["application/json"]["schema"];
+        assert_eq!(schema["type"], "array");
+        assert_eq!(
+            schema["items"]["$ref"],
+            "#/components/schemas/MovieCollectionResult"
+        );
+    }
+
+    #[test]
+    fn test_schema_references_resolve() {
+        let spec = openapi_spec();
+        let schemas = spec["components"]["schemas"].as_object().unwrap();
+        let text = serde_json::to_string(&spec).unwrap();
+        for reference in text.split("#/components/schemas/").skip(1) {
+            let name = reference.split('"').next().unwrap();
+            assert!(schemas.contains_key(name), "missing schema {}", name);
+        }
+        for (name, schema) in schemas {
+            assert_eq!(schema["type"], "object", "schema {}", name);
+        }
+    }
+
+    fn assert_properties<T: Serialize>(name: &str, value: T) {
+        let value = serde_json::to_value(value).unwrap();
+        let fields: BTreeSet<_> = value.as_object().unwrap().keys().cloned().collect();
+        let spec = openapi_spec();
+        let properties: BTreeSet<_> = spec["components"]["schemas"][name]["properties"]
+            .as_object()
+            .unwrap_or_else(|| panic!("schema {} missing", name))
+            .keys()
+            .cloned()
+            .collect();
+        assert_eq!(fields, properties, "schema {} out of date", name);
+    }
+
+    #[test]
+    fn test_schemas_match_serialized_structs() {
+        assert_properties("MovieQueueResult", MovieQueueResult::default());
+        assert_properties("MovieCollectionResult", MovieCollectionResult::default());
+        assert_properties("TvShowsResult", TvShowsResult::default());
+        assert_properties("WatchListShow", WatchListShow::default());
+        assert_properties("WatchedEpisode", WatchedEpisode::default());
+        assert_properties("ImdbRatings", ImdbRatings::default());
+        assert_properties("MovieQueueRow", MovieQueueRow::default());
+        assert_properties("MovieCollectionRow", MovieCollectionRow::default());
+        assert_properties("ApiResult", ApiResult::default());
+        assert_properties("ApiQueueName", ApiQueueName::default());
+        assert_properties("ApiQueueDelete", ApiQueueDelete::default());
+        assert_properties("PlaybackPosition", PlaybackPosition::default());
+        assert_properties("PlaybackPositionUpdate", PlaybackPositionUpdate::default());
+        assert_properties("SearchResult", SearchResult::default());
+        assert_properties(
+            "TranscodeServiceRequest",
+            TranscodeServiceRequest::new(
+                JobType::Transcode,
+                "prefix",
+                Path::new("input.mkv"),
+                Path::new("output.mp4"),
+            ),
+        );
+    }
+}
@@ -0,0 +1,117 @@
+use anyhow::Error;
+use handlebars::{
+    html_escape, Context, Handlebars, Helper, HelperResult, Output, RenderContext, RenderError,
+};
+use lazy_static::lazy_static;
+use serde_json::Value;
+
+lazy_static! {
+    pub static ref HBR: Handlebars<'static> = get_templates().expect("Failed to parse templates");
+}
+
+/// Concatenate the parameters into a javascript string literal that is safe
+/// to embed in an html attribute, e.g. `onclick="watchlist_add({{js link}});"`
+fn js_string(
+    h: &Helper,
+    _: &Handlebars,
+    _: &Context,
+    _: &mut RenderContext,
+    out: &mut dyn Output,
+) -> HelperResult {
+    let s: String = h
+        .params()
+        .iter()
+        .map(|p| match p.value() {
+            Value::String(s) => s.clone(),
+            Value::Null => String::new(),
+            v => v.to_string(),
+        })
+        .collect();
+    let s = serde_json::to_string(&s).map_err(|e| RenderError::new(e.to_string()))?;
+    out.write(&html_escape(&s))?;
+    Ok(())
+}
+
+fn get_templates() -> Result<Handlebars<'static>, Error> {
+    let mut h = Handlebars::new();
+    h.register_helper("js", Box::new(js_string));
+    let templates = [
+        ("index.html", include_str!("../../templates/index.html")),
+        ("queue.html", include_str!("../../templates/queue.html")),
+        ("queues.html", include_str!("../../templates/queues.html")),
+        ("tvshows.html", include_str!("../../templates/tvshows.html")),
+        (
+            "watchlist.html",
+            include_str!("../../templates/watchlist.html"),
+        ),
+        (
+            "watched_seasons.html",
+            include_str!("../../templates/watched_seasons.html"),
+        ),
+        (
+            "watched_list.html",
+            include_str!("../../templates/watched_list.html"),
+        ),
+        (
+            "new_episodes.html",
+            include_str!("../../templates/new_episodes.html"),
+        ),
+        (
+            "trakt_cal.html",
+            include_str!("../../templates/trakt_cal.html"),
+        ),
+        (
+            "imdb_show.html",
+            include_str!("../../templates/imdb_show.html"),
+        ),
+        ("play.html", include_str!("../../templates/play.html")),
+        ("search.html", include_str!("../../templates/search.html")),
+    ];
+    for (name, template) in &templates {
+        h.register_template_string(name, template)?;
+    }
+    Ok(h)
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use crate::templates::HBR;
+
+    #[test]
+    fn test_titles_are_escaped() {
+        let data = json!({
+            "shows": [{
+                "title": "<script>alert(1)</script>",
+                "link": "tt1');alert(1);//",
+                "source": null,
+            }],
+        });
+        let body = HBR.render("watchlist.html", &data).unwrap();
+        assert!(!body.contains("<script>"));
+        assert!(body.contains("&lt;script&gt;"));
+        assert!(!body.contains("tt1');"));
+        assert!(body.contains("updateMainArticle(&quot;/list/trakt/watched/list/tt1"));
+    }
+
+    #[test]
+    fn test_search_results_are_escaped() {
+        let data = json!({
+            "query": "<b>robot",
+            "results": [{
+                "kind": "collection",
+                "title": "<script>alert(1)</script>",
+                "show": "mr_robot');alert(1);//",
+                "path": "/tv/mr_robot/mr_robot_s01_ep01.mp4",
+                "season": 1,
+                "episode": 1,
+            }],
+        });
+        let body = HBR.render("search.html", &data).unwrap();
+        assert!(!body.contains("<script>"));
+        assert!(!body.contains("<b>robot"));
+        assert!(!body.contains("mr_robot');"));
+        assert!(body.contains("s1 ep1"));
+    }
+}
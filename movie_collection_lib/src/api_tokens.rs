@@ -0,0 +1,216 @@
+use anyhow::{format_err, Error};
+use chrono::{DateTime, Utc};
+use rand::{distributions::Alphanumeric, thread_rng, Rng};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use stack_string::StackString;
+use std::{fmt, str::FromStr};
+use tokio_postgres::Row;
+
+use crate::pgpool::PgPool;
+
+const TOKEN_PREFIX: &str = "mct_";
+const TOKEN_LENGTH: usize = 40;
+
+/// What a token may be used for, `Sync` includes `Read`.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "lowercase")]
+pub enum TokenScope {
+    /// The read only routes, including the sync exports
+    Read,
+    /// The sync exports and the sync update routes
+    Sync,
+}
+
+impl TokenScope {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Read => "read",
+            Self::Sync => "sync",
+        }
+    }
+}
+
+impl fmt::Display for TokenScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for TokenScope {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "read" => Ok(Self::Read),
+            "sync" => Ok(Self::Sync),
+            _ => Err(format_err!("Invalid scope {}", s)),
+        }
+    }
+}
+
+fn generate_token() -> StackString {
+    let token: String = thread_rng()
+        .sample_iter(&Alphanumeric)
+        .take(TOKEN_LENGTH)
+        .collect();
+    format!("{}{}", TOKEN_PREFIX, token).into()
+}
+
+/// Only the hash of a token is stored, the token itself is shown once when it
+/// is created.
+fn hash_token(token: &str) -> StackString {
+    Sha256::digest(token.as_bytes())
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>()
+        .into()
+}
+
+/// Bearer token for machine to machine clients such as the cron job syncing
+/// two servers, tokens act as `DEFAULT_USER`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct ApiToken {
+    pub id: i32,
+    pub scope: TokenScope,
+    pub description: StackString,
+    pub created_at: DateTime<Utc>,
+    pub last_used: Option<DateTime<Utc>>,
+    pub revoked_at: Option<DateTime<Utc>>,
+}
+
+impl fmt::Display for ApiToken {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {} created {} last used {}",
+            self.id,
+            self.scope,
+            self.description,
+            self.created_at,
+            self.last_used
+                .map_or_else(|| "never".to_string(), |t| t.to_string())
+        )?;
+        if let Some(revoked_at) = self.revoked_at {
+            write!(f, " revoked {}", revoked_at)?;
+        }
+        Ok(())
+    }
+}
+
+impl ApiToken {
+    fn from_row(row: &Row) -> Result<Self, Error> {
+        let scope: StackString = row.try_get("scope")?;
+        Ok(Self {
+            id: row.try_get("id")?,
+            scope: scope.parse()?,
+            description: row.try_get("description")?,
+            created_at: row.try_get("created_at")?,
+            last_used: row.try_get("last_used")?,
+            revoked_at: row.try_get("revoked_at")?,
+        })
+    }
+
+    /// Create a new token, returns the token itself along with the stored
+    /// entry.
+    pub async fn create(
+        pool: &PgPool,
+        scope: TokenScope,
+        description: &str,
+    ) -> Result<(Self, StackString), Error> {
+        let token = generate_token();
+        let query = postgres_query::query!(
+            r#"
+                INSERT INTO api_tokens (token_hash, scope, description, created_at)
+                VALUES ($token_hash, $scope, $description, now())
+                RETURNING id, scope, description, created_at, last_used, revoked_at
+            "#,
+            token_hash = hash_token(&token),
+            scope = scope.as_str(),
+            description = description
+        );
+        let row = pool
+            .get()
+            .await?
+            .query_one(query.sql(), query.parameters())
+            .await?;
+        Ok((Self::from_row(&row)?, token))
+    }
+
+    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = r#"
+            SELECT id, scope, description, created_at, last_used, revoked_at
+            FROM api_tokens
+            ORDER BY id
+        "#;
+        pool.get()
+            .await?
+            .query(query, &[])
+            .await?
+            .iter()
+            .map(Self::from_row)
+            .collect()
+    }
+
+    /// Returns false if there is no such token or it was already revoked.
+    pub async fn revoke(pool: &PgPool, id: i32) -> Result<bool, Error> {
+        let query = postgres_query::query!(
+            "UPDATE api_tokens SET revoked_at = now() WHERE id = $id AND revoked_at IS NULL",
+            id = id
+        );
+        let rows = pool
+            .get()
+            .await?
+            .execute(query.sql(), query.parameters())
+            .await?;
+        Ok(rows > 0)
+    }
+
+    /// Look up an unrevoked token and record that it was used.
+    pub async fn authenticate(pool: &PgPool, token: &str) -> Result<Option<Self>, Error> {
+        if !token.starts_with(TOKEN_PREFIX) {
+            return Ok(None);
+        }
+        let query = postgres_query::query!(
+            r#"
+                UPDATE api_tokens SET last_used = now()
+                WHERE token_hash = $token_hash AND revoked_at IS NULL
+                RETURNING id, scope, description, created_at, last_used, revoked_at
+            "#,
+            token_hash = hash_token(token)
+        );
+        pool.get()
+            .await?
+            .query(query.sql(), query.parameters())
+            .await?
+            .get(0)
+            .map(Self::from_row)
+            .transpose()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::api_tokens::{generate_token, hash_token, TokenScope};
+
+    #[test]
+    fn test_generate_token() {
+        let token = generate_token();
+        assert!(token.starts_with("mct_"));
+        assert_eq!(token.len(), 44);
+        assert_ne!(token, generate_token());
+        assert_eq!(hash_token(&token), hash_token(&token));
+        assert_eq!(hash_token(&token).len(), 64);
+        assert_eq!(
+            hash_token("abc").as_str(),
+            "ba7816bf8f01cfea414140de5dae2223b00361a396177a9cb410ff61f20015ad"
+        );
+    }
+
+    #[test]
+    fn test_token_scope() {
+        assert_eq!("sync".parse::<TokenScope>().unwrap(), TokenScope::Sync);
+        assert!("write".parse::<TokenScope>().is_err());
+        assert!(TokenScope::Sync > TokenScope::Read);
+    }
+}
@@ -0,0 +1,127 @@
+use anyhow::{format_err, Error};
+use serde::{Deserialize, Serialize};
+use stack_string::StackString;
+use std::{fmt, str::FromStr};
+
+use crate::pgpool::PgPool;
+
+/// What an authorized user may do in the web interface, ordered so that a
+/// role includes everything the roles before it can do.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    /// Browse, play and keep track of playback positions
+    Viewer,
+    /// Edit queues, transcode, change trakt and post to the sync endpoints
+    Editor,
+    /// Everything, user management is only available from `movie-queue-cli`
+    Admin,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Viewer => "viewer",
+            Self::Editor => "editor",
+            Self::Admin => "admin",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for Role {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "viewer" => Ok(Self::Viewer),
+            "editor" => Ok(Self::Editor),
+            "admin" => Ok(Self::Admin),
+            _ => Err(format_err!("Invalid role {}", s)),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct AuthorizedUser {
+    pub email: StackString,
+    pub role: Role,
+}
+
+impl fmt::Display for AuthorizedUser {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.email, self.role)
+    }
+}
+
+impl AuthorizedUser {
+    pub async fn get_all(pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = "SELECT email, role FROM authorized_users ORDER BY email";
+        pool.get()
+            .await?
+            .query(query, &[])
+            .await?
+            .iter()
+            .map(|row| {
+                let email: StackString = row.try_get("email")?;
+                let role: StackString = row.try_get("role")?;
+                Ok(Self {
+                    email,
+                    role: role.parse()?,
+                })
+            })
+            .collect()
+    }
+
+    /// Add the user, or change the role of an existing one.
+    pub async fn upsert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = postgres_query::query!(
+            r#"
+                INSERT INTO authorized_users (email, role)
+                VALUES ($email, $role)
+                ON CONFLICT (email) DO UPDATE SET role=EXCLUDED.role
+            "#,
+            email = self.email,
+            role = self.role.as_str()
+        );
+        pool.get()
+            .await?
+            .execute(query.sql(), query.parameters())
+            .await?;
+        Ok(())
+    }
+
+    /// Returns false if there was no such user.
+    pub async fn delete(pool: &PgPool, email: &str) -> Result<bool, Error> {
+        let query = postgres_query::query!(
+            "DELETE FROM authorized_users WHERE email = $email",
+            email = email
+        );
+        let rows = pool
+            .get()
+            .await?
+            .execute(query.sql(), query.parameters())
+            .await?;
+        Ok(rows > 0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::authorized_users::Role;
+
+    #[test]
+    fn test_role() {
+        for role in &[Role::Viewer, Role::Editor, Role::Admin] {
+            assert_eq!(role.as_str().parse::<Role>().unwrap(), *role);
+        }
+        assert!("root".parse::<Role>().is_err());
+        assert!(Role::Admin > Role::Editor);
+        assert!(Role::Editor > Role::Viewer);
+    }
+}
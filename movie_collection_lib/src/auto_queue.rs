@@ -0,0 +1,547 @@
+use anyhow::{format_err, Error};
+use chrono::{naive::MIN_DATE, Local};
+use log::debug;
+use postgres_query::FromSqlRow;
+use serde::{Deserialize, Serialize};
+use stack_string::StackString;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::Path,
+    str::FromStr,
+};
+
+use crate::{
+    movie_collection::MovieCollection,
+    movie_queue::{MovieQueueDB, DEFAULT_QUEUE, QUEUE_END},
+    pgpool::PgPool,
+    trakt_utils::{get_watchlist_shows_db, get_watchlist_users, WatchedEpisode},
+    tv_show_source::TvShowSource,
+    utils::parse_file_stem,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum AutoQueuePolicy {
+    #[serde(rename = "queue")]
+    Queue,
+    #[serde(rename = "skip")]
+    Skip,
+}
+
+impl Default for AutoQueuePolicy {
+    fn default() -> Self {
+        Self::Queue
+    }
+}
+
+impl fmt::Display for AutoQueuePolicy {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Queue => "queue",
+                Self::Skip => "skip",
+            }
+        )
+    }
+}
+
+impl FromStr for AutoQueuePolicy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "queue" => Ok(Self::Queue),
+            "skip" => Ok(Self::Skip),
+            _ => Err(format_err!("Is not AutoQueuePolicy")),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct AutoQueueRule {
+    pub show: StackString,
+    pub policy: AutoQueuePolicy,
+    pub min_rating: Option<f64>,
+}
+
+impl fmt::Display for AutoQueueRule {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.show, self.policy)?;
+        if let Some(min_rating) = self.min_rating {
+            write!(f, " {}", min_rating)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(FromSqlRow)]
+struct AutoQueueRuleRow {
+    show: StackString,
+    policy: StackString,
+    min_rating: Option<f64>,
+}
+
+impl AutoQueueRule {
+    fn from_db_row(row: AutoQueueRuleRow) -> Result<Self, Error> {
+        Ok(Self {
+            show: row.show,
+            policy: row.policy.parse()?,
+            min_rating: row.min_rating,
+        })
+    }
+
+    pub async fn get_rules(pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = "SELECT show, policy, min_rating FROM auto_queue_rules ORDER BY show";
+        pool.get()
+            .await?
+            .query(query, &[])
+            .await?
+            .iter()
+            .map(|row| Self::from_db_row(AutoQueueRuleRow::from_row(row)?))
+            .collect()
+    }
+
+    pub async fn get_rule(show: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = postgres_query::query!(
+            "SELECT show, policy, min_rating FROM auto_queue_rules WHERE show = $show",
+            show = show
+        );
+        if let Some(row) = pool
+            .get()
+            .await?
+            .query(query.sql(), query.parameters())
+            .await?
+            .get(0)
+        {
+            Ok(Some(Self::from_db_row(AutoQueueRuleRow::from_row(row)?)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub async fn set(&self, pool: &PgPool) -> Result<(), Error> {
+        let policy = self.policy.to_string();
+        let query = postgres_query::query!(
+            r#"
+                INSERT INTO auto_queue_rules (show, policy, min_rating, last_modified)
+                VALUES ($show, $policy, $min_rating, now())
+                ON CONFLICT (show) DO UPDATE
+                SET policy=EXCLUDED.policy, min_rating=EXCLUDED.min_rating, last_modified=now()
+            "#,
+            show = self.show,
+            policy = policy,
+            min_rating = self.min_rating
+        );
+        pool.get()
+            .await?
+            .execute(query.sql(), query.parameters())
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    pub async fn delete(show: &str, pool: &PgPool) -> Result<(), Error> {
+        let query = postgres_query::query!(
+            "DELETE FROM auto_queue_rules WHERE show = $show",
+            show = show
+        );
+        pool.get()
+            .await?
+            .execute(query.sql(), query.parameters())
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    /// Returns the reason an episode should not be queued, shows without a
+    /// rule are queued.
+    pub fn rejects(rule: Option<&Self>, eprating: f64) -> Option<StackString> {
+        let rule = rule?;
+        match rule.policy {
+            AutoQueuePolicy::Skip => Some("rule is skip".into()),
+            AutoQueuePolicy::Queue => match rule.min_rating {
+                Some(min_rating) if eprating < min_rating => {
+                    Some(format!("rating {} below {}", eprating, min_rating).into())
+                }
+                _ => None,
+            },
+        }
+    }
+}
+
+async fn get_last_episode_of_season(
+    show: &str,
+    season: i32,
+    pool: &PgPool,
+) -> Result<Option<i32>, Error> {
+    let query = postgres_query::query!(
+        "SELECT max(episode) FROM imdb_episodes WHERE show = $show AND season = $season",
+        show = show,
+        season = season
+    );
+    if let Some(row) = pool
+        .get()
+        .await?
+        .query(query.sql(), query.parameters())
+        .await?
+        .get(0)
+    {
+        let episode: Option<i32> = row.try_get(0)?;
+        Ok(episode)
+    } else {
+        Ok(None)
+    }
+}
+
+/// Collected episodes of `show` by season and episode.
+async fn get_collection_episodes(
+    show: &str,
+    pool: &PgPool,
+) -> Result<HashMap<(i32, i32), StackString>, Error> {
+    let query = postgres_query::query!(
+        "SELECT path FROM movie_collection WHERE show = $show",
+        show = show
+    );
+    let mut episodes = HashMap::new();
+    for row in pool
+        .get()
+        .await?
+        .query(query.sql(), query.parameters())
+        .await?
+    {
+        let path: StackString = row.try_get("path")?;
+        let file_stem = Path::new(path.as_str())
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let (_, season, episode) = parse_file_stem(&file_stem);
+        if season >= 0 && episode >= 0 {
+            episodes.insert((season, episode), path);
+        }
+    }
+    Ok(episodes)
+}
+
+async fn get_decided_paths(pool: &PgPool, user: &str) -> Result<HashSet<StackString>, Error> {
+    let query = postgres_query::query!(
+        "SELECT path FROM auto_queue_decisions WHERE user_email = $user",
+        user = user
+    );
+    pool.get()
+        .await?
+        .query(query.sql(), query.parameters())
+        .await?
+        .iter()
+        .map(|row| row.try_get("path").map_err(Into::into))
+        .collect()
+}
+
+async fn record_decision(
+    pool: &PgPool,
+    user: &str,
+    path: &str,
+    decision: &str,
+) -> Result<(), Error> {
+    let query = postgres_query::query!(
+        r#"
+            INSERT INTO auto_queue_decisions (user_email, path, decision, last_modified)
+            VALUES ($user, $path, $decision, now())
+            ON CONFLICT (user_email, path) DO UPDATE
+            SET decision=EXCLUDED.decision, last_modified=now()
+        "#,
+        user = user,
+        path = path,
+        decision = decision
+    );
+    pool.get()
+        .await?
+        .execute(query.sql(), query.parameters())
+        .await?;
+    Ok(())
+}
+
+/// What is known about a collected episode when deciding whether to queue it.
+#[derive(Debug, Default, Clone, Copy)]
+pub struct EpisodeState {
+    pub queued: bool,
+    pub watched: bool,
+    /// `None` if imdb doesn't know the episode yet.
+    pub rating: Option<f64>,
+    /// The previous episode, `None` for the first episode of a show.
+    pub previous: Option<PreviousEpisode>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PreviousEpisode {
+    pub season: i32,
+    pub episode: i32,
+    pub queued: bool,
+    pub watched: bool,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum AutoQueueDecision {
+    Queue,
+    /// Final, recorded so the episode isn't considered again, e.g. after it
+    /// was dequeued without being watched.
+    Done(StackString),
+    /// Considered again on the next run, the rule, the rating or the
+    /// previous episode may change.
+    Skip(StackString),
+}
+
+impl AutoQueueDecision {
+    pub fn decide(rule: Option<&AutoQueueRule>, state: &EpisodeState) -> Self {
+        if state.watched {
+            return Self::Done("already watched".into());
+        }
+        if state.queued {
+            return Self::Done("already queued".into());
+        }
+        let rating = match state.rating {
+            Some(rating) => rating,
+            None => return Self::Skip("not in imdb_episodes".into()),
+        };
+        if let Some(reason) = AutoQueueRule::rejects(rule, rating) {
+            return Self::Skip(reason);
+        }
+        if let Some(previous) = state.previous {
+            if !previous.queued && !previous.watched {
+                return Self::Skip(
+                    format!(
+                        "s{:02} ep{:02} neither watched nor queued",
+                        previous.season, previous.episode
+                    )
+                    .into(),
+                );
+            }
+        }
+        Self::Queue
+    }
+}
+
+/// Queue collected episodes of watchlist shows whose previous episode has
+/// been watched or is already queued, for every user with a watchlist. Every
+/// decision is logged. Episodes that were queued, or were already queued,
+/// are recorded per user in `auto_queue_decisions` and not considered again.
+pub async fn auto_queue_new_episodes(mc: &MovieCollection) -> Result<Vec<StackString>, Error> {
+    let pool = &mc.pool;
+    let rules: HashMap<StackString, AutoQueueRule> = AutoQueueRule::get_rules(pool)
+        .await?
+        .into_iter()
+        .map(|r| (r.show.clone(), r))
+        .collect();
+
+    let mut collection: HashMap<StackString, HashMap<(i32, i32), StackString>> = HashMap::new();
+    let mut added = Vec::new();
+    for user in get_watchlist_users(pool).await? {
+        let mq = MovieQueueDB::with_user(pool, &user, DEFAULT_QUEUE);
+        let watchlist: HashSet<StackString> = get_watchlist_shows_db(pool, &user)
+            .await?
+            .into_iter()
+            .map(|s| s.link)
+            .collect();
+        let decided = get_decided_paths(pool, &user).await?;
+
+        let mut queued: HashSet<(StackString, i32, i32)> = HashSet::new();
+        for result in mq.print_movie_queue(&[]).await? {
+            if let (Some(show), Some(season), Some(episode)) =
+                (result.show, result.season, result.episode)
+            {
+                queued.insert((show, season, episode));
+            }
+        }
+
+        // every aired episode, not only recent ones, so that a newly
+        // collected episode of an older season is considered too
+        let episodes = mc
+            .get_new_episodes(
+                MIN_DATE,
+                Local::today().naive_local(),
+                Some(TvShowSource::All),
+                &user,
+            )
+            .await?;
+        for epi in episodes {
+            if !watchlist.contains(&epi.link) {
+                continue;
+            }
+            if !collection.contains_key(&epi.show) {
+                let episodes = get_collection_episodes(&epi.show, pool).await?;
+                collection.insert(epi.show.clone(), episodes);
+            }
+            let path = match collection[&epi.show].get(&(epi.season, epi.episode)) {
+                Some(path) if !decided.contains(path) => path.clone(),
+                _ => continue,
+            };
+            let label = format!(
+                "{} {} s{:02} ep{:02}",
+                user, epi.show, epi.season, epi.episode
+            );
+            let log_decision = |msg: &str| {
+                let msg = format!("auto-queue {}: {}", label, msg);
+                debug!("{}", msg);
+                mc.stdout.send(msg);
+            };
+
+            let previous = if epi.episode > 1 {
+                Some((epi.season, epi.episode - 1))
+            } else if epi.season > 1 {
+                get_last_episode_of_season(&epi.show, epi.season - 1, pool)
+                    .await?
+                    .map(|e| (epi.season - 1, e))
+            } else {
+                None
+            };
+            let previous = match previous {
+                Some((season, episode)) => {
+                    let is_queued = queued.contains(&(epi.show.clone(), season, episode));
+                    let watched = !is_queued
+                        && WatchedEpisode::get_watched_episode(
+                            pool, &epi.link, season, episode, &user,
+                        )
+                        .await?
+                        .is_some();
+                    Some(PreviousEpisode {
+                        season,
+                        episode,
+                        queued: is_queued,
+                        watched,
+                    })
+                }
+                None => None,
+            };
+            // `get_new_episodes` leaves out watched episodes
+            let state = EpisodeState {
+                queued: queued.contains(&(epi.show.clone(), epi.season, epi.episode)),
+                watched: false,
+                rating: Some(epi.eprating),
+                previous,
+            };
+
+            match AutoQueueDecision::decide(rules.get(&epi.show), &state) {
+                AutoQueueDecision::Skip(reason) => {
+                    log_decision(&format!("skip, {}", reason));
+                }
+                AutoQueueDecision::Done(reason) => {
+                    record_decision(pool, &user, &path, &reason).await?;
+                    log_decision(&format!("skip, {}", reason));
+                }
+                AutoQueueDecision::Queue => {
+                    if !Path::new(path.as_str()).exists() {
+                        log_decision(&format!("skip, {} isn't mounted", path));
+                        continue;
+                    }
+                    mq.insert_into_queue(QUEUE_END, &path).await?;
+                    record_decision(pool, &user, &path, "queued").await?;
+                    log_decision(&format!("queued {}", path));
+                    queued.insert((epi.show.clone(), epi.season, epi.episode));
+                    added.push(path);
+                }
+            }
+        }
+    }
+    Ok(added)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::auto_queue::{
+        AutoQueueDecision, AutoQueuePolicy, AutoQueueRule, EpisodeState, PreviousEpisode,
+    };
+
+    #[test]
+    fn test_rejects() {
+        assert!(AutoQueueRule::rejects(None, 0.0).is_none());
+
+        let mut rule = AutoQueueRule {
+            show: "the_expanse".into(),
+            policy: AutoQueuePolicy::Queue,
+            min_rating: Some(8.0),
+        };
+        assert!(AutoQueueRule::rejects(Some(&rule), 8.5).is_none());
+        assert!(AutoQueueRule::rejects(Some(&rule), 7.5).is_some());
+
+        rule.policy = AutoQueuePolicy::Skip;
+        assert!(AutoQueueRule::rejects(Some(&rule), 9.0).is_some());
+        assert_eq!(
+            "skip".parse::<AutoQueuePolicy>().unwrap(),
+            AutoQueuePolicy::Skip
+        );
+    }
+
+    #[test]
+    fn test_decide_previous_episode() {
+        let previous = PreviousEpisode {
+            season: 2,
+            episode: 4,
+            queued: false,
+            watched: false,
+        };
+        let mut state = EpisodeState {
+            rating: Some(8.0),
+            previous: Some(previous),
+            ..EpisodeState::default()
+        };
+        match AutoQueueDecision::decide(None, &state) {
+            AutoQueueDecision::Skip(reason) => {
+                assert_eq!(reason.as_str(), "s02 ep04 neither watched nor queued")
+            }
+            d => panic!("unexpected {:?}", d),
+        }
+
+        state.previous = Some(PreviousEpisode {
+            watched: true,
+            ..previous
+        });
+        assert_eq!(
+            AutoQueueDecision::decide(None, &state),
+            AutoQueueDecision::Queue
+        );
+        state.previous = Some(PreviousEpisode {
+            queued: true,
+            ..previous
+        });
+        assert_eq!(
+            AutoQueueDecision::decide(None, &state),
+            AutoQueueDecision::Queue
+        );
+
+        // the first episode of a show only depends on the rule
+        state.previous = None;
+        assert_eq!(
+            AutoQueueDecision::decide(None, &state),
+            AutoQueueDecision::Queue
+        );
+        let rule = AutoQueueRule {
+            show: "the_expanse".into(),
+            policy: AutoQueuePolicy::Queue,
+            min_rating: Some(8.5),
+        };
+        assert!(matches!(
+            AutoQueueDecision::decide(Some(&rule), &state),
+            AutoQueueDecision::Skip(_)
+        ));
+
+        // dequeued or watched episodes are never queued again
+        state.watched = true;
+        assert!(matches!(
+            AutoQueueDecision::decide(None, &state),
+            AutoQueueDecision::Done(_)
+        ));
+        state.watched = false;
+        state.queued = true;
+        assert!(matches!(
+            AutoQueueDecision::decide(None, &state),
+            AutoQueueDecision::Done(_)
+        ));
+        state.queued = false;
+        state.rating = None;
+        assert!(matches!(
+            AutoQueueDecision::decide(None, &state),
+            AutoQueueDecision::Skip(_)
+        ));
+    }
+}
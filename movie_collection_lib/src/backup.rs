@@ -0,0 +1,426 @@
+use anyhow::{format_err, Error};
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Transaction;
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use futures::{pin_mut, TryStreamExt};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use stack_string::StackString;
+use std::{
+    collections::{BTreeMap, BTreeSet},
+    fmt,
+    fs::File,
+    io::{BufRead, BufReader, Write},
+    iter,
+    path::Path,
+};
+use tokio::{fs, io::AsyncWriteExt};
+use tokio_postgres::types::ToSql;
+
+use crate::pgpool::PgPool;
+
+/// Version of the archive layout, bumped when the manifest or the table
+/// files change in a way older restores can't read.
+pub const BACKUP_SCHEMA_VERSION: u32 = 1;
+
+const MANIFEST: &str = "manifest.json";
+
+/// Rows sent per insert on restore.
+const RESTORE_CHUNK: usize = 1000;
+
+/// Compressed bytes buffered before they are written to the table file.
+const WRITE_CHUNK: usize = 1 << 16;
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct TableManifest {
+    pub name: StackString,
+    pub columns: Vec<StackString>,
+    pub rows: usize,
+    pub file: StackString,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct SequenceState {
+    pub name: StackString,
+    pub last_value: i64,
+    pub is_called: bool,
+}
+
+/// `manifest.json` of a backup directory, tables are listed in the order
+/// they are restored so that referenced rows go first.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct BackupManifest {
+    pub schema_version: u32,
+    pub server_version: StackString,
+    pub created_at: DateTime<Utc>,
+    pub tables: Vec<TableManifest>,
+    pub sequences: Vec<SequenceState>,
+}
+
+impl fmt::Display for BackupManifest {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "backup version {} postgres {} at {}",
+            self.schema_version, self.server_version, self.created_at
+        )?;
+        for table in &self.tables {
+            writeln!(f, "{} {}", table.name, table.rows)?;
+        }
+        Ok(())
+    }
+}
+
+/// Quote an identifier read from the catalog or a manifest.
+pub fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+/// Up to `size` non-empty lines, an empty batch means the input is done.
+fn next_batch<R: BufRead>(reader: &mut R, size: usize) -> Result<Vec<String>, Error> {
+    let mut batch = Vec::with_capacity(size);
+    let mut line = String::new();
+    while batch.len() < size {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            break;
+        }
+        let row = line.trim();
+        if !row.is_empty() {
+            batch.push(row.to_string());
+        }
+    }
+    Ok(batch)
+}
+
+/// Tables ordered so that every table comes after the tables it references,
+/// ties and cycles are broken by name.
+fn restore_order(
+    tables: &BTreeSet<StackString>,
+    references: &[(StackString, StackString)],
+) -> Vec<StackString> {
+    let mut depends: BTreeMap<&StackString, BTreeSet<&StackString>> =
+        tables.iter().map(|t| (t, BTreeSet::new())).collect();
+    for (table, referenced) in references {
+        if table != referenced && tables.contains(referenced) {
+            if let Some(d) = depends.get_mut(table) {
+                d.insert(referenced);
+            }
+        }
+    }
+    let mut order = Vec::new();
+    while !depends.is_empty() {
+        let next = depends
+            .iter()
+            .find(|(_, d)| d.is_empty())
+            .or_else(|| depends.iter().next())
+            .map(|(t, _)| *t)
+            .expect("not empty");
+        depends.remove(next);
+        for d in depends.values_mut() {
+            d.remove(next);
+        }
+        order.push(next.clone());
+    }
+    order
+}
+
+/// Dumps every table to gzipped NDJSON, one `row_to_json` object per line,
+/// within one repeatable read transaction so the tables are consistent with
+/// each other, and loads such a backup into an empty database. Rows go
+/// through json rather than the binary or COPY formats so that a backup
+/// restores across postgres versions.
+///
+/// The archive is the backup directory itself:
+///
+/// ```text
+/// manifest.json          BackupManifest: versions, tables in restore order
+///                        with their columns and row counts, sequences
+/// <table>.ndjson.gz      one per table, named by TableManifest::file
+/// ```
+///
+/// Nothing else is read on restore, so the directory can be packed with any
+/// archiver as long as the files stay next to the manifest.
+pub struct Backup {
+    pool: PgPool,
+}
+
+impl Backup {
+    pub fn new(pool: &PgPool) -> Self {
+        Self { pool: pool.clone() }
+    }
+
+    async fn get_tables_in(tran: &Transaction<'_>) -> Result<Vec<StackString>, Error> {
+        let query = r#"
+            SELECT table_name::text
+            FROM information_schema.tables
+            WHERE table_schema = 'public' AND table_type = 'BASE TABLE'
+        "#;
+        let tables: Result<BTreeSet<StackString>, Error> = tran
+            .query(query, &[])
+            .await?
+            .iter()
+            .map(|row| row.try_get(0).map_err(Into::into))
+            .collect();
+        let query = r#"
+            SELECT a.relname::text, b.relname::text
+            FROM pg_constraint c
+            JOIN pg_class a ON c.conrelid = a.oid
+            JOIN pg_class b ON c.confrelid = b.oid
+            WHERE c.contype = 'f'
+        "#;
+        let references: Result<Vec<(StackString, StackString)>, Error> = tran
+            .query(query, &[])
+            .await?
+            .iter()
+            .map(|row| Ok((row.try_get(0)?, row.try_get(1)?)))
+            .collect();
+        Ok(restore_order(&tables?, &references?))
+    }
+
+    async fn get_columns_in(
+        tran: &Transaction<'_>,
+        table: &str,
+    ) -> Result<Vec<StackString>, Error> {
+        let query = postgres_query::query!(
+            r#"
+                SELECT column_name::text
+                FROM information_schema.columns
+                WHERE table_schema = 'public' AND table_name = $table
+                ORDER BY ordinal_position
+            "#,
+            table = table
+        );
+        tran.query(query.sql(), query.parameters())
+            .await?
+            .iter()
+            .map(|row| row.try_get(0).map_err(Into::into))
+            .collect()
+    }
+
+    pub async fn backup(&self, directory: &Path) -> Result<BackupManifest, Error> {
+        fs::create_dir_all(directory).await?;
+
+        let mut conn = self.pool.get().await?;
+        let tran = conn.transaction().await?;
+        tran.batch_execute("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ, READ ONLY")
+            .await?;
+        let tables = Self::get_tables_in(&tran).await?;
+
+        let server_version: StackString = tran
+            .query_one("SHOW server_version", &[])
+            .await?
+            .try_get(0)?;
+        let mut manifest = BackupManifest {
+            schema_version: BACKUP_SCHEMA_VERSION,
+            server_version,
+            created_at: Utc::now(),
+            tables: Vec::new(),
+            sequences: Vec::new(),
+        };
+
+        for table in tables {
+            let query = format!("SELECT row_to_json(t)::text FROM {} t", quote_ident(&table));
+            let file = format!("{}.ndjson.gz", table);
+            let mut output = fs::File::create(directory.join(&file)).await?;
+            let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+            let mut rows = 0;
+            let stream = tran
+                .query_raw(query.as_str(), iter::empty::<&dyn ToSql>())
+                .await?;
+            pin_mut!(stream);
+            while let Some(row) = stream.try_next().await? {
+                let line: &str = row.try_get(0)?;
+                encoder.write_all(line.as_bytes())?;
+                encoder.write_all(b"\n")?;
+                rows += 1;
+                if encoder.get_ref().len() >= WRITE_CHUNK {
+                    output.write_all(encoder.get_ref()).await?;
+                    encoder.get_mut().clear();
+                }
+            }
+            output.write_all(&encoder.finish()?).await?;
+            output.flush().await?;
+            debug!("backup {} {}", table, rows);
+            manifest.tables.push(TableManifest {
+                columns: Self::get_columns_in(&tran, &table).await?,
+                name: table,
+                rows,
+                file: file.into(),
+            });
+        }
+
+        let query = r#"
+            SELECT sequence_name::text
+            FROM information_schema.sequences
+            WHERE sequence_schema = 'public'
+            ORDER BY sequence_name
+        "#;
+        for row in tran.query(query, &[]).await? {
+            let name: StackString = row.try_get(0)?;
+            let query = format!("SELECT last_value, is_called FROM {}", quote_ident(&name));
+            let row = tran.query_one(query.as_str(), &[]).await?;
+            manifest.sequences.push(SequenceState {
+                name,
+                last_value: row.try_get("last_value")?,
+                is_called: row.try_get("is_called")?,
+            });
+        }
+        tran.commit().await?;
+
+        fs::write(
+            directory.join(MANIFEST),
+            serde_json::to_vec_pretty(&manifest)?,
+        )
+        .await?;
+        Ok(manifest)
+    }
+
+    /// Every table of the backup has to exist and be empty, columns that
+    /// were added since the backup was taken get their defaults.
+    pub async fn restore(&self, directory: &Path) -> Result<BackupManifest, Error> {
+        let manifest: BackupManifest =
+            serde_json::from_slice(&fs::read(directory.join(MANIFEST)).await?)?;
+        if manifest.schema_version > BACKUP_SCHEMA_VERSION {
+            return Err(format_err!(
+                "Backup version {} is newer than {}",
+                manifest.schema_version,
+                BACKUP_SCHEMA_VERSION
+            ));
+        }
+
+        let mut conn = self.pool.get().await?;
+        let tran = conn.transaction().await?;
+        let mut columns = BTreeMap::new();
+        for table in &manifest.tables {
+            let existing = Self::get_columns_in(&tran, &table.name).await?;
+            if existing.is_empty() {
+                return Err(format_err!("Table {} doesn't exist", table.name));
+            }
+            let shared: Vec<_> = table
+                .columns
+                .iter()
+                .filter(|c| existing.contains(c))
+                .map(|c| quote_ident(c))
+                .collect();
+            columns.insert(table.name.clone(), shared.join(", "));
+        }
+
+        let mut not_empty = Vec::new();
+        for table in &manifest.tables {
+            let query = format!("SELECT EXISTS (SELECT 1 FROM {})", quote_ident(&table.name));
+            let exists: bool = tran.query_one(query.as_str(), &[]).await?.try_get(0)?;
+            if exists {
+                not_empty.push(table.name.as_str());
+            }
+        }
+        if !not_empty.is_empty() {
+            return Err(format_err!("Tables aren't empty: {}", not_empty.join(", ")));
+        }
+
+        for table in &manifest.tables {
+            let query = format!(
+                "INSERT INTO {table} ({columns}) SELECT {columns} FROM json_populate_recordset(NULL::{table}, $1::text::json)",
+                table = quote_ident(&table.name),
+                columns = columns[&table.name],
+            );
+            let file = File::open(directory.join(table.file.as_str()))?;
+            let mut reader = BufReader::new(GzDecoder::new(file));
+            let mut rows = 0;
+            loop {
+                let batch = next_batch(&mut reader, RESTORE_CHUNK)?;
+                if batch.is_empty() {
+                    break;
+                }
+                rows += batch.len();
+                let batch = format!("[{}]", batch.join(","));
+                tran.execute(query.as_str(), &[&batch]).await?;
+            }
+            if rows != table.rows {
+                return Err(format_err!(
+                    "{} has {} rows, the manifest says {}",
+                    table.file,
+                    rows,
+                    table.rows
+                ));
+            }
+            debug!("restore {} {}", table.name, table.rows);
+        }
+
+        for sequence in &manifest.sequences {
+            tran.execute(
+                "SELECT setval($1::text::regclass, $2, $3)",
+                &[
+                    &quote_ident(&sequence.name),
+                    &sequence.last_value,
+                    &sequence.is_called,
+                ],
+            )
+            .await?;
+        }
+        tran.commit().await?;
+        Ok(manifest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use stack_string::StackString;
+    use std::collections::BTreeSet;
+
+    use crate::backup::{next_batch, quote_ident, restore_order};
+
+    #[test]
+    fn test_restore_order() {
+        let tables: BTreeSet<StackString> = [
+            "imdb_episodes",
+            "imdb_ratings",
+            "movie_collection",
+            "movie_queue",
+            "tombstones",
+        ]
+        .iter()
+        .map(|t| (*t).into())
+        .collect();
+        let references: Vec<(StackString, StackString)> = vec![
+            ("movie_queue".into(), "movie_collection".into()),
+            ("movie_collection".into(), "imdb_ratings".into()),
+            ("imdb_episodes".into(), "imdb_ratings".into()),
+            ("movie_collection".into(), "movie_collection".into()),
+        ];
+        let order: Vec<_> = restore_order(&tables, &references)
+            .into_iter()
+            .map(|t| t.to_string())
+            .collect();
+        assert_eq!(
+            order,
+            vec![
+                "imdb_ratings",
+                "imdb_episodes",
+                "movie_collection",
+                "movie_queue",
+                "tombstones"
+            ]
+        );
+    }
+
+    #[test]
+    fn test_quote_ident() {
+        assert_eq!(quote_ident("movie_queue"), r#""movie_queue""#);
+        assert_eq!(
+            quote_ident(r#"a"; DROP TABLE b; --"#),
+            r#""a""; DROP TABLE b; --""#
+        );
+    }
+
+    #[test]
+    fn test_next_batch() {
+        let mut input = "{\"a\":1}\n\n{\"a\":2}\n{\"a\":3}".as_bytes();
+        assert_eq!(
+            next_batch(&mut input, 2).unwrap(),
+            vec![r#"{"a":1}"#, r#"{"a":2}"#]
+        );
+        assert_eq!(next_batch(&mut input, 2).unwrap(), vec![r#"{"a":3}"#]);
+        assert!(next_batch(&mut input, 2).unwrap().is_empty());
+    }
+}
@@ -0,0 +1,582 @@
+use anyhow::{format_err, Error};
+use chrono::NaiveDate;
+use deadpool_postgres::Transaction;
+use serde::Serialize;
+use serde_json::Value;
+use stack_string::StackString;
+use std::{fmt, str::FromStr, vec::IntoIter};
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader};
+
+use crate::{
+    imdb_episodes::ImdbEpisodes,
+    imdb_ratings::ImdbRatings,
+    movie_collection::{MovieCollection, MovieCollectionRow},
+    movie_queue::MovieQueueRow,
+    pgpool::PgPool,
+    tombstones::Tombstone,
+};
+
+/// Tables accepted by `BulkImport`, the same json rows the export command
+/// and the `/list/{table}` routes produce.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ImportTable {
+    ImdbRatings,
+    ImdbEpisodes,
+    MovieCollection,
+    MovieQueue,
+    Tombstones,
+}
+
+impl ImportTable {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ImdbRatings => "imdb_ratings",
+            Self::ImdbEpisodes => "imdb_episodes",
+            Self::MovieCollection => "movie_collection",
+            Self::MovieQueue => "movie_queue",
+            Self::Tombstones => "tombstones",
+        }
+    }
+
+    fn parse_row(self, value: Value) -> Result<ImportRow, Error> {
+        let row = match self {
+            Self::ImdbRatings => ImportRow::Show(serde_json::from_value(value)?),
+            Self::ImdbEpisodes => ImportRow::Episode(serde_json::from_value(value)?),
+            Self::MovieCollection => ImportRow::Collection(serde_json::from_value(value)?),
+            Self::MovieQueue => ImportRow::Queue(serde_json::from_value(value)?),
+            Self::Tombstones => ImportRow::Tombstone(serde_json::from_value(value)?),
+        };
+        Ok(row)
+    }
+}
+
+impl FromStr for ImportTable {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "imdb_ratings" => Ok(Self::ImdbRatings),
+            "imdb_episodes" => Ok(Self::ImdbEpisodes),
+            "movie_collection" => Ok(Self::MovieCollection),
+            "movie_queue" => Ok(Self::MovieQueue),
+            "tombstones" => Ok(Self::Tombstones),
+            _ => Err(format_err!("Can't import table {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ImportTable {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ImportAction {
+    Inserted,
+    Updated,
+    Unchanged,
+    Deleted,
+}
+
+impl ImportAction {
+    /// Prefix of the line for this change in a dry run diff.
+    pub fn symbol(self) -> char {
+        match self {
+            Self::Inserted => '+',
+            Self::Updated => '~',
+            Self::Unchanged => '=',
+            Self::Deleted => '-',
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ImportChange {
+    pub line: usize,
+    pub action: ImportAction,
+    pub key: StackString,
+}
+
+impl fmt::Display for ImportChange {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} {}", self.action.symbol(), self.key)
+    }
+}
+
+/// A row that couldn't be parsed or applied, `key` is missing when the row
+/// couldn't be parsed.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ImportError {
+    pub line: usize,
+    pub key: Option<StackString>,
+    pub message: StackString,
+}
+
+impl fmt::Display for ImportError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.key {
+            Some(key) => write!(f, "line {}: {}: {}", self.line, key, self.message),
+            None => write!(f, "line {}: {}", self.line, self.message),
+        }
+    }
+}
+
+/// Outcome of an import. The counts cover every row that applied cleanly,
+/// `committed` and `rolled_back` say how many of those were kept. Rows of a
+/// watched history that couldn't be matched to an imdb id are `unmatched`.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct ImportReport {
+    pub table: StackString,
+    pub dry_run: bool,
+    pub inserted: usize,
+    pub updated: usize,
+    pub unchanged: usize,
+    pub deleted: usize,
+    pub committed: usize,
+    pub rolled_back: usize,
+    pub changes: Vec<ImportChange>,
+    pub errors: Vec<ImportError>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub unmatched: Vec<ImportError>,
+}
+
+impl ImportReport {
+    pub(crate) fn new(table: &str, dry_run: bool) -> Self {
+        Self {
+            table: table.into(),
+            dry_run,
+            inserted: 0,
+            updated: 0,
+            unchanged: 0,
+            deleted: 0,
+            committed: 0,
+            rolled_back: 0,
+            changes: Vec::new(),
+            errors: Vec::new(),
+            unmatched: Vec::new(),
+        }
+    }
+
+    pub(crate) fn add_change(&mut self, line: usize, action: ImportAction, key: StackString) {
+        match action {
+            ImportAction::Inserted => self.inserted += 1,
+            ImportAction::Updated => self.updated += 1,
+            ImportAction::Unchanged => self.unchanged += 1,
+            ImportAction::Deleted => self.deleted += 1,
+        }
+        self.changes.push(ImportChange { line, action, key });
+    }
+
+    pub(crate) fn add_error(&mut self, line: usize, key: Option<StackString>, error: &Error) {
+        self.errors.push(ImportError {
+            line,
+            key,
+            message: error.to_string().into(),
+        });
+    }
+}
+
+impl fmt::Display for ImportReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} inserted {} updated {} unchanged {} deleted {} committed {} rolled back {} errors {}",
+            self.table,
+            self.inserted,
+            self.updated,
+            self.unchanged,
+            self.deleted,
+            self.committed,
+            self.rolled_back,
+            self.errors.len(),
+        )?;
+        if !self.unmatched.is_empty() {
+            write!(f, " unmatched {}", self.unmatched.len())?;
+        }
+        if self.dry_run {
+            f.write_str(" (dry run)")?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Default, Clone)]
+pub struct ImportOptions {
+    /// Apply every row and roll back, so the report shows what would change.
+    pub dry_run: bool,
+    /// Rows per transaction, 0 imports everything in one transaction.
+    pub batch_size: usize,
+    /// Import movie_queue rows for this user instead of the one in the row.
+    pub user: Option<StackString>,
+}
+
+enum ImportRow {
+    Show(ImdbRatings),
+    Episode(ImdbEpisodes),
+    Collection(MovieCollectionRow),
+    Queue(MovieQueueRow),
+    Tombstone(Tombstone),
+}
+
+impl ImportRow {
+    fn key(&self) -> StackString {
+        match self {
+            Self::Show(show) => show.show.clone(),
+            Self::Episode(episode) => format!(
+                "{} s{:02} ep{:02}",
+                episode.show, episode.season, episode.episode
+            )
+            .into(),
+            Self::Collection(row) => row.path.clone(),
+            Self::Queue(row) => format!(
+                "{} {} {} {}",
+                row.user_email, row.queue_name, row.idx, row.path
+            )
+            .into(),
+            Self::Tombstone(tombstone) => {
+                let key: Vec<_> = tombstone.key.iter().map(StackString::as_str).collect();
+                format!("{} {}", tombstone.table_name, key.join(" ")).into()
+            }
+        }
+    }
+
+    async fn apply(&self, tran: &Transaction<'_>) -> Result<ImportAction, Error> {
+        match self {
+            Self::Show(show) => upsert_show(tran, show).await,
+            Self::Episode(episode) => upsert_episode(tran, episode).await,
+            Self::Collection(row) => {
+                let (_, inserted) = MovieCollection::sync_collection_row_in(tran, row).await?;
+                if inserted {
+                    Ok(ImportAction::Inserted)
+                } else {
+                    Ok(ImportAction::Unchanged)
+                }
+            }
+            Self::Queue(row) => match row.sync_in(tran).await? {
+                None => Ok(ImportAction::Inserted),
+                Some(idx) if idx == row.idx => Ok(ImportAction::Unchanged),
+                Some(_) => Ok(ImportAction::Updated),
+            },
+            Self::Tombstone(tombstone) => {
+                if tombstone.apply_in(tran).await? {
+                    Ok(ImportAction::Deleted)
+                } else {
+                    Ok(ImportAction::Unchanged)
+                }
+            }
+        }
+    }
+}
+
+async fn upsert_show(tran: &Transaction<'_>, show: &ImdbRatings) -> Result<ImportAction, Error> {
+    let query = postgres_query::query!(
+        "SELECT title, rating FROM imdb_ratings WHERE show = $show",
+        show = show.show
+    );
+    let action = match tran.query(query.sql(), query.parameters()).await?.get(0) {
+        Some(row) => {
+            let title: Option<StackString> = row.try_get("title")?;
+            let rating: Option<f64> = row.try_get("rating")?;
+            if title == show.title && rating == show.rating {
+                return Ok(ImportAction::Unchanged);
+            }
+            ImportAction::Updated
+        }
+        None => ImportAction::Inserted,
+    };
+    let source = show.source.as_ref().map(ToString::to_string);
+    let query = postgres_query::query!(
+        r#"
+            INSERT INTO imdb_ratings
+            (show, title, link, rating, istv, source, last_modified)
+            VALUES
+            ($show, $title, $link, $rating, $istv, $source, now())
+            ON CONFLICT (show) DO UPDATE
+            SET rating=EXCLUDED.rating, title=EXCLUDED.title, last_modified=now()
+        "#,
+        show = show.show,
+        title = show.title,
+        link = show.link,
+        rating = show.rating,
+        istv = show.istv,
+        source = source
+    );
+    tran.execute(query.sql(), query.parameters()).await?;
+    Ok(action)
+}
+
+async fn upsert_episode(
+    tran: &Transaction<'_>,
+    episode: &ImdbEpisodes,
+) -> Result<ImportAction, Error> {
+    let query = postgres_query::query!(
+        r#"
+            SELECT cast(rating as double precision) as rating, eptitle, epurl, airdate
+            FROM imdb_episodes
+            WHERE show = $show AND season = $season AND episode = $episode
+        "#,
+        show = episode.show,
+        season = episode.season,
+        episode = episode.episode
+    );
+    let action = match tran.query(query.sql(), query.parameters()).await?.get(0) {
+        Some(row) => {
+            let rating: f64 = row.try_get("rating")?;
+            let eptitle: StackString = row.try_get("eptitle")?;
+            let epurl: StackString = row.try_get("epurl")?;
+            let airdate: NaiveDate = row.try_get("airdate")?;
+            if (rating - episode.rating).abs() < f64::EPSILON
+                && eptitle == episode.eptitle
+                && epurl == episode.epurl
+                && airdate == episode.airdate
+            {
+                return Ok(ImportAction::Unchanged);
+            }
+            let query = postgres_query::query!(
+                r#"
+                    UPDATE imdb_episodes
+                    SET rating=$rating::double precision, eptitle=$eptitle, epurl=$epurl,
+                        airdate=$airdate, last_modified=now()
+                    WHERE show=$show AND season=$season AND episode=$episode
+                "#,
+                rating = episode.rating,
+                eptitle = episode.eptitle,
+                epurl = episode.epurl,
+                airdate = episode.airdate,
+                show = episode.show,
+                season = episode.season,
+                episode = episode.episode
+            );
+            tran.execute(query.sql(), query.parameters()).await?;
+            ImportAction::Updated
+        }
+        None => {
+            let query = postgres_query::query!(
+                r#"
+                    INSERT INTO imdb_episodes
+                    (show, season, episode, airdate, rating, eptitle, epurl, last_modified)
+                    VALUES
+                    ($show, $season, $episode, $airdate, $rating::double precision, $eptitle,
+                     $epurl, now())
+                "#,
+                show = episode.show,
+                season = episode.season,
+                episode = episode.episode,
+                airdate = episode.airdate,
+                rating = episode.rating,
+                eptitle = episode.eptitle,
+                epurl = episode.epurl
+            );
+            tran.execute(query.sql(), query.parameters()).await?;
+            ImportAction::Inserted
+        }
+    };
+    Ok(action)
+}
+
+/// Yields the rows of NDJSON input one line at a time. Input starting with
+/// `[` is the json array older exports wrote, it is read in one go and its
+/// elements are numbered like lines.
+struct RowReader<R> {
+    reader: BufReader<R>,
+    table: ImportTable,
+    line: usize,
+    started: bool,
+    array: Option<IntoIter<Value>>,
+}
+
+impl<R: AsyncRead + Unpin> RowReader<R> {
+    fn new(input: R, table: ImportTable) -> Self {
+        Self {
+            reader: BufReader::new(input),
+            table,
+            line: 0,
+            started: false,
+            array: None,
+        }
+    }
+
+    fn next_in_array(&mut self) -> Option<(usize, Result<ImportRow, Error>)> {
+        let value = self.array.as_mut()?.next()?;
+        self.line += 1;
+        Some((self.line, self.table.parse_row(value)))
+    }
+
+    /// Errors reading the input abort the import, a row that doesn't parse
+    /// is returned for the report.
+    async fn next(&mut self) -> Result<Option<(usize, Result<ImportRow, Error>)>, Error> {
+        if self.array.is_some() {
+            return Ok(self.next_in_array());
+        }
+        let mut buf = String::new();
+        loop {
+            buf.clear();
+            if self.reader.read_line(&mut buf).await? == 0 {
+                return Ok(None);
+            }
+            self.line += 1;
+            let line = buf.trim();
+            if line.is_empty() {
+                continue;
+            }
+            if !self.started && line.starts_with('[') {
+                let mut data = buf.clone();
+                self.reader.read_to_string(&mut data).await?;
+                let values: Vec<Value> = serde_json::from_str(&data)?;
+                self.line = 0;
+                self.array = Some(values.into_iter());
+                return Ok(self.next_in_array());
+            }
+            self.started = true;
+            let row = serde_json::from_str(line)
+                .map_err(Into::into)
+                .and_then(|value| self.table.parse_row(value));
+            return Ok(Some((self.line, row)));
+        }
+    }
+}
+
+/// Imports the rows of one table in file order, in a single transaction or
+/// in batches of `batch_size` rows. Each row is applied under a savepoint so
+/// that a bad row is reported and the rest are still checked, but a batch
+/// with any error is rolled back as a whole.
+pub struct BulkImport {
+    pool: PgPool,
+    table: ImportTable,
+    options: ImportOptions,
+}
+
+impl BulkImport {
+    pub fn new(pool: &PgPool, table: ImportTable, options: ImportOptions) -> Self {
+        Self {
+            pool: pool.clone(),
+            table,
+            options,
+        }
+    }
+
+    pub async fn run<R: AsyncRead + Unpin>(&self, input: R) -> Result<ImportReport, Error> {
+        let mut report = ImportReport::new(self.table.as_str(), self.options.dry_run);
+        let mut rows = RowReader::new(input, self.table);
+        let mut conn = self.pool.get().await?;
+        let mut done = false;
+        while !done {
+            let tran = conn.transaction().await?;
+            let mut applied = 0;
+            let mut failed = false;
+            loop {
+                if self.options.batch_size > 0 && applied >= self.options.batch_size {
+                    break;
+                }
+                let (line, row) = match rows.next().await? {
+                    Some(next) => next,
+                    None => {
+                        done = true;
+                        break;
+                    }
+                };
+                match row {
+                    Ok(row) => {
+                        if self.import_row(&tran, line, row, &mut report).await? {
+                            applied += 1;
+                        } else {
+                            failed = true;
+                        }
+                    }
+                    Err(e) => {
+                        report.add_error(line, None, &e);
+                        failed = true;
+                    }
+                }
+            }
+            if failed || self.options.dry_run || applied == 0 {
+                tran.rollback().await?;
+                report.rolled_back += applied;
+            } else {
+                tran.commit().await?;
+                report.committed += applied;
+            }
+        }
+        Ok(report)
+    }
+
+    /// Returns whether the row applied.
+    async fn import_row(
+        &self,
+        tran: &Transaction<'_>,
+        line: usize,
+        mut row: ImportRow,
+        report: &mut ImportReport,
+    ) -> Result<bool, Error> {
+        if let (ImportRow::Queue(queue_row), Some(user)) = (&mut row, &self.options.user) {
+            queue_row.user_email = user.clone();
+        }
+        let key = row.key();
+        tran.batch_execute("SAVEPOINT import_row").await?;
+        match row.apply(tran).await {
+            Ok(action) => {
+                tran.batch_execute("RELEASE SAVEPOINT import_row").await?;
+                report.add_change(line, action, key);
+                Ok(true)
+            }
+            Err(e) => {
+                tran.batch_execute("ROLLBACK TO SAVEPOINT import_row")
+                    .await?;
+                report.add_error(line, Some(key), &e);
+                Ok(false)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use crate::bulk_import::{ImportRow, ImportTable, RowReader};
+
+    #[tokio::test]
+    async fn test_row_reader() -> Result<(), Error> {
+        let ndjson = concat!(
+            r#"{"idx": 1, "path": "/a/b.mkv", "show": "b"}"#,
+            "\n\n",
+            "not json\n",
+            r#"{"idx": 3, "path": "/a/c.mkv", "show": "c"}"#,
+            "\n",
+        );
+        let table: ImportTable = "movie_collection".parse()?;
+        let mut rows = RowReader::new(ndjson.as_bytes(), table);
+        let mut lines = Vec::new();
+        while let Some((line, row)) = rows.next().await? {
+            match row {
+                Ok(ImportRow::Collection(row)) => lines.push((line, Some(row.path))),
+                Ok(_) => panic!("wrong table"),
+                Err(_) => lines.push((line, None)),
+            }
+        }
+        assert_eq!(
+            lines,
+            vec![
+                (1, Some("/a/b.mkv".into())),
+                (3, None),
+                (4, Some("/a/c.mkv".into())),
+            ]
+        );
+
+        let array = r#"[{"idx": 1, "path": "/a/b.mkv", "show": "b"},
+                        {"idx": 2, "path": "/a/c.mkv", "show": "c"}]"#;
+        let mut rows = RowReader::new(array.as_bytes(), table);
+        let mut count = 0;
+        while let Some((line, row)) = rows.next().await? {
+            count += 1;
+            assert_eq!(line, count);
+            assert!(row.is_ok());
+        }
+        assert_eq!(count, 2);
+
+        assert!("authorized_users".parse::<ImportTable>().is_err());
+        Ok(())
+    }
+}
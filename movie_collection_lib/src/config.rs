@@ -44,6 +44,20 @@ pub struct ConfigInner {
     pub secret_path: PathBuf,
     #[serde(default = "default_secret_path")]
     pub jwt_secret_path: PathBuf,
+    #[serde(default)]
+    pub default_user: StackString,
+    #[serde(default = "default_hls_cache_dir")]
+    pub hls_cache_dir: PathBuf,
+    #[serde(default = "default_thumbnail_cache_dir")]
+    pub thumbnail_cache_dir: PathBuf,
+    /// hls.js as installed by `scripts/vendor_hls_js.sh`
+    #[serde(default = "default_hls_js_path")]
+    pub hls_js_path: PathBuf,
+    /// Remove items from the default queue once playback marks them watched
+    #[serde(default)]
+    pub remove_watched_from_queue: bool,
+    /// Sync scoped api token of the remote used by `movie-queue-cli sync`
+    pub sync_token: Option<StackString>,
 }
 
 fn default_suffixes() -> Vec<StackString> {
@@ -85,6 +99,18 @@ fn default_remcom_queue() -> StackString {
 fn default_trakt_endpoint() -> StackString {
     "https://api.trakt.tv".into()
 }
+fn default_hls_cache_dir() -> PathBuf {
+    std::env::temp_dir().join("movie_collection_hls")
+}
+fn default_thumbnail_cache_dir() -> PathBuf {
+    dirs::cache_dir()
+        .unwrap_or_else(std::env::temp_dir)
+        .join("movie_collection_rust")
+        .join("thumbnails")
+}
+fn default_hls_js_path() -> PathBuf {
+    "/usr/share/movie_collection_rust/hls.min.js".into()
+}
 fn default_secret_path() -> PathBuf {
     dirs::config_dir()
         .unwrap()
@@ -103,6 +129,9 @@ impl ConfigInner {
             port: default_port(),
             domain: default_domain(),
             n_db_workers: default_n_db_workers(),
+            hls_cache_dir: default_hls_cache_dir(),
+            thumbnail_cache_dir: default_thumbnail_cache_dir(),
+            hls_js_path: default_hls_js_path(),
             ..Self::default()
         }
     }
@@ -0,0 +1,131 @@
+use anyhow::{format_err, Error};
+use deadpool_postgres::Transaction;
+use futures::{future, stream, StreamExt};
+use log::debug;
+use serde::{Deserialize, Serialize};
+use stack_string::StackString;
+use tokio::sync::broadcast::Sender;
+use tokio_postgres::{AsyncMessage, NoTls};
+
+use crate::pgpool::PgPool;
+
+/// Postgres notification channel used to pass events between the command line
+/// tools, the transcode service and the http server.
+pub const EVENT_CHANNEL: &str = "movie_collection_events";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    QueueChanged {
+        user: StackString,
+        queue: StackString,
+    },
+    CollectionChanged,
+    TranscodeProgress {
+        prefix: StackString,
+        percent: f64,
+    },
+    TraktSyncComplete,
+}
+
+impl Event {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::QueueChanged { .. } => "queue_changed",
+            Self::CollectionChanged => "collection_changed",
+            Self::TranscodeProgress { .. } => "transcode_progress",
+            Self::TraktSyncComplete => "trakt_sync_complete",
+        }
+    }
+
+    /// Queue changes are only interesting to the owner of the queue, everything
+    /// else is shared.
+    pub fn is_visible_to(&self, user: &str) -> bool {
+        match self {
+            Self::QueueChanged { user: owner, .. } => owner.as_str() == user,
+            _ => true,
+        }
+    }
+
+    pub async fn publish(&self, pool: &PgPool) -> Result<(), Error> {
+        let payload = serde_json::to_string(self)?;
+        pool.get()
+            .await?
+            .execute("SELECT pg_notify($1, $2)", &[&EVENT_CHANNEL, &payload])
+            .await?;
+        Ok(())
+    }
+
+    /// Notifications sent inside a transaction are only delivered if it
+    /// commits, and identical ones are delivered once.
+    pub async fn publish_in(&self, tran: &Transaction<'_>) -> Result<(), Error> {
+        let payload = serde_json::to_string(self)?;
+        tran.execute("SELECT pg_notify($1, $2)", &[&EVENT_CHANNEL, &payload])
+            .await?;
+        Ok(())
+    }
+}
+
+/// LISTEN on `EVENT_CHANNEL` on a dedicated connection (pooled connections
+/// can't be used, notifications arrive on the connection that listens) and
+/// forward every event to `sender`. Returns when the connection is closed.
+pub async fn listen_for_events(pgurl: &str, sender: Sender<Event>) -> Result<(), Error> {
+    let (client, mut connection) = tokio_postgres::connect(pgurl, NoTls).await?;
+    let mut messages = stream::poll_fn(move |cx| connection.poll_message(cx));
+    let (tx, rx) = futures::channel::oneshot::channel();
+
+    let forward = async move {
+        while let Some(message) = messages.next().await {
+            if let AsyncMessage::Notification(n) = message? {
+                if n.channel() != EVENT_CHANNEL {
+                    continue;
+                }
+                match serde_json::from_str::<Event>(n.payload()) {
+                    // An error only means that nobody is subscribed
+                    Ok(event) => {
+                        sender.send(event).ok();
+                    }
+                    Err(e) => debug!("invalid event {} {}", n.payload(), e),
+                }
+            }
+        }
+        tx.send(()).ok();
+        Ok::<_, Error>(())
+    };
+    let listen = async {
+        client
+            .batch_execute(&format!("LISTEN {}", EVENT_CHANNEL))
+            .await?;
+        // Keep the client alive until the connection goes away
+        rx.await
+            .map_err(|_| format_err!("Event connection dropped"))
+    };
+    future::try_join(forward, listen).await?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::events::Event;
+
+    #[test]
+    fn test_event_serialization() {
+        let event = Event::QueueChanged {
+            user: "user@localhost".into(),
+            queue: "default".into(),
+        };
+        let payload = serde_json::to_string(&event).unwrap();
+        assert_eq!(
+            payload,
+            r#"{"type":"queue_changed","user":"user@localhost","queue":"default"}"#
+        );
+        assert_eq!(serde_json::from_str::<Event>(&payload).unwrap(), event);
+        assert!(event.is_visible_to("user@localhost"));
+        assert!(!event.is_visible_to("other@localhost"));
+
+        let payload = serde_json::to_string(&Event::CollectionChanged).unwrap();
+        assert_eq!(payload, r#"{"type":"collection_changed"}"#);
+        assert!(Event::CollectionChanged.is_visible_to("other@localhost"));
+        assert_eq!(Event::TraktSyncComplete.name(), "trakt_sync_complete");
+    }
+}
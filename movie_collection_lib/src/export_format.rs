@@ -0,0 +1,110 @@
+use anyhow::{format_err, Error};
+use serde::Serialize;
+use std::{fmt, str::FromStr};
+
+/// How `movie-queue-cli export` writes the rows of a table.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ExportFormat {
+    /// One json array, the default
+    Json,
+    /// One json object per line, `import` streams it instead of reading the
+    /// whole file
+    Ndjson,
+    /// A header of the field names and one record per row
+    Csv,
+}
+
+impl Default for ExportFormat {
+    fn default() -> Self {
+        Self::Json
+    }
+}
+
+impl ExportFormat {
+    pub fn to_vec<T: Serialize>(self, rows: &[T]) -> Result<Vec<u8>, Error> {
+        match self {
+            Self::Json => serde_json::to_vec(rows).map_err(Into::into),
+            Self::Ndjson => {
+                let mut buf = Vec::new();
+                for row in rows {
+                    serde_json::to_writer(&mut buf, row)?;
+                    buf.push(b'\n');
+                }
+                Ok(buf)
+            }
+            Self::Csv => {
+                let mut writer = csv::Writer::from_writer(Vec::new());
+                for row in rows {
+                    writer.serialize(row)?;
+                }
+                writer.into_inner().map_err(|e| format_err!("{}", e))
+            }
+        }
+    }
+}
+
+impl FromStr for ExportFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Self::Json),
+            "ndjson" => Ok(Self::Ndjson),
+            "csv" => Ok(Self::Csv),
+            _ => Err(format_err!("Invalid format {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for ExportFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Self::Json => "json",
+            Self::Ndjson => "ndjson",
+            Self::Csv => "csv",
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use serde::Serialize;
+
+    use crate::export_format::ExportFormat;
+
+    #[derive(Serialize)]
+    struct Row {
+        idx: i32,
+        path: &'static str,
+        rating: Option<f64>,
+    }
+
+    #[test]
+    fn test_export_formats() -> Result<(), Error> {
+        let rows = [
+            Row {
+                idx: 1,
+                path: "/a/b, c.mkv",
+                rating: Some(7.5),
+            },
+            Row {
+                idx: 2,
+                path: "/a/d.mkv",
+                rating: None,
+            },
+        ];
+        let csv = ExportFormat::Csv.to_vec(&rows)?;
+        assert_eq!(
+            String::from_utf8(csv)?,
+            "idx,path,rating\n1,\"/a/b, c.mkv\",7.5\n2,/a/d.mkv,\n"
+        );
+        let ndjson = ExportFormat::Ndjson.to_vec(&rows)?;
+        assert_eq!(String::from_utf8(ndjson)?.lines().count(), 2);
+        let json = ExportFormat::Json.to_vec(&rows)?;
+        assert!(json.starts_with(b"[{"));
+        assert_eq!("csv".parse::<ExportFormat>()?, ExportFormat::Csv);
+        assert!("xml".parse::<ExportFormat>().is_err());
+        Ok(())
+    }
+}
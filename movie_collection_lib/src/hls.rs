@@ -0,0 +1,377 @@
+use anyhow::{format_err, Error};
+use log::debug;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+    process::Stdio,
+    sync::Arc,
+    time::{Duration, Instant},
+};
+use tokio::{
+    fs,
+    process::{Child, Command},
+    sync::Mutex,
+    time::delay_for,
+};
+
+/// Length of each HLS segment in seconds.
+pub const SEGMENT_SECONDS: u64 = 6;
+
+/// A request this far past the last finished segment restarts ffmpeg at the
+/// requested segment instead of waiting for it to catch up.
+const SEGMENT_LOOKAHEAD: u64 = 4;
+
+const SEGMENT_TIMEOUT: Duration = Duration::from_secs(60);
+
+/// At most this many ffmpeg processes run at once, starting another one
+/// stops the transcode of the least recently used session.
+pub const MAX_TRANSCODES: usize = 2;
+
+/// Extensions that browsers can play directly from `/list/stream/{idx}`.
+pub fn is_browser_playable(path: &Path) -> bool {
+    path.extension()
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .map_or(false, |e| e == "mp4" || e == "m4v" || e == "webm")
+}
+
+pub fn segment_name(segment: u64) -> String {
+    format!("segment_{}.ts", segment)
+}
+
+pub fn parse_segment_name(name: &str) -> Option<u64> {
+    let segment = name.strip_prefix("segment_")?.strip_suffix(".ts")?;
+    if segment.is_empty() || !segment.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    segment.parse().ok()
+}
+
+fn number_of_segments(duration: f64) -> u64 {
+    (duration / SEGMENT_SECONDS as f64).ceil() as u64
+}
+
+/// The full VOD playlist is known up front from the duration, which lets the
+/// player seek to segments that have not been transcoded yet.
+pub fn hls_playlist(duration: f64) -> String {
+    let mut playlist = format!(
+        "#EXTM3U\n#EXT-X-VERSION:3\n#EXT-X-TARGETDURATION:{}\n#EXT-X-MEDIA-SEQUENCE:0\n#EXT-X-PLAYLIST-TYPE:VOD\n",
+        SEGMENT_SECONDS
+    );
+    for segment in 0..number_of_segments(duration) {
+        let remaining = duration - (segment * SEGMENT_SECONDS) as f64;
+        playlist.push_str(&format!(
+            "#EXTINF:{:.3},\n{}\n",
+            remaining.min(SEGMENT_SECONDS as f64),
+            segment_name(segment)
+        ));
+    }
+    playlist.push_str("#EXT-X-ENDLIST\n");
+    playlist
+}
+
+pub async fn probe_duration(input: &Path) -> Result<f64, Error> {
+    let output = Command::new("ffprobe")
+        .args(&[
+            "-v",
+            "error",
+            "-show_entries",
+            "format=duration",
+            "-of",
+            "default=noprint_wrappers=1:nokey=1",
+        ])
+        .arg(input)
+        .stdin(Stdio::null())
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(format_err!("ffprobe failed for {:?}", input));
+    }
+    String::from_utf8_lossy(&output.stdout)
+        .trim()
+        .parse()
+        .map_err(Into::into)
+}
+
+struct HlsSession {
+    input: PathBuf,
+    directory: PathBuf,
+    duration: f64,
+    start: u64,
+    process: Option<Child>,
+    last_access: Instant,
+}
+
+impl HlsSession {
+    fn segment_path(&self, segment: u64) -> PathBuf {
+        self.directory.join(segment_name(segment))
+    }
+
+    /// First segment at or after `start` that ffmpeg hasn't finished, segments
+    /// are written to a temp file and renamed so existence means complete.
+    fn next_segment(&self) -> u64 {
+        let mut segment = self.start;
+        while self.segment_path(segment).exists() {
+            segment += 1;
+        }
+        segment
+    }
+
+    fn start_transcode(&mut self, segment: u64) -> Result<(), Error> {
+        // Dropping the old child kills it
+        self.process.take();
+
+        let start = (segment * SEGMENT_SECONDS).to_string();
+        let keyframes = format!("expr:gte(t,n_forced*{})", SEGMENT_SECONDS);
+        let hls_time = SEGMENT_SECONDS.to_string();
+        let start_number = segment.to_string();
+        let segment_pattern = self.directory.join("segment_%d.ts");
+        let playlist = self.directory.join("ffmpeg.m3u8");
+
+        debug!("hls transcode {:?} from segment {}", self.input, segment);
+        let process = Command::new("ffmpeg")
+            .args(&["-nostdin", "-loglevel", "error", "-ss", &start, "-i"])
+            .arg(&self.input)
+            .args(&[
+                "-copyts",
+                "-map",
+                "0:v:0",
+                "-map",
+                "0:a:0?",
+                "-c:v",
+                "libx264",
+                "-preset",
+                "veryfast",
+                "-pix_fmt",
+                "yuv420p",
+                "-force_key_frames",
+                &keyframes,
+                "-c:a",
+                "aac",
+                "-ac",
+                "2",
+                "-f",
+                "hls",
+                "-hls_time",
+                &hls_time,
+                "-hls_list_size",
+                "0",
+                "-hls_flags",
+                "temp_file",
+                "-start_number",
+                &start_number,
+                "-hls_segment_filename",
+            ])
+            .arg(&segment_pattern)
+            .arg(&playlist)
+            .kill_on_drop(true)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .spawn()?;
+        self.process = Some(process);
+        self.start = segment;
+        Ok(())
+    }
+}
+
+/// On demand HLS transcodes of collection files, keyed by `collection_idx`.
+/// Each file gets its own directory under `cache_dir` and a single ffmpeg
+/// process which is restarted when the player seeks outside of what it has
+/// produced.
+#[derive(Clone)]
+pub struct HlsCache {
+    cache_dir: PathBuf,
+    sessions: Arc<Mutex<HashMap<i32, HlsSession>>>,
+}
+
+impl HlsCache {
+    pub fn new(cache_dir: &Path) -> Self {
+        Self {
+            cache_dir: cache_dir.to_path_buf(),
+            sessions: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// Remove anything left over from a previous run, the cache directory is
+    /// only readable by the server.
+    pub async fn init(&self) -> Result<(), Error> {
+        if self.cache_dir.exists() {
+            fs::remove_dir_all(&self.cache_dir).await?;
+        }
+        fs::create_dir_all(&self.cache_dir).await?;
+        #[cfg(unix)]
+        {
+            use std::{fs::Permissions, os::unix::fs::PermissionsExt};
+            fs::set_permissions(&self.cache_dir, Permissions::from_mode(0o700)).await?;
+        }
+        Ok(())
+    }
+
+    /// ffprobe can take a while, so it runs before taking the sessions lock
+    /// and only if there is no session for `input` yet.
+    async fn probe_new_session(&self, idx: i32, input: &Path) -> Result<Option<f64>, Error> {
+        let exists = self
+            .sessions
+            .lock()
+            .await
+            .get(&idx)
+            .map_or(false, |s| s.input == input);
+        if exists {
+            Ok(None)
+        } else {
+            probe_duration(input).await.map(Some)
+        }
+    }
+
+    async fn get_session<'a>(
+        &self,
+        sessions: &'a mut HashMap<i32, HlsSession>,
+        idx: i32,
+        input: &Path,
+        duration: Option<f64>,
+    ) -> Result<&'a mut HlsSession, Error> {
+        if sessions.get(&idx).map_or(false, |s| s.input != input) {
+            if let Some(session) = sessions.remove(&idx) {
+                fs::remove_dir_all(&session.directory).await?;
+            }
+        }
+        if !sessions.contains_key(&idx) {
+            let duration = match duration {
+                Some(duration) => duration,
+                // the session was cleaned up after `probe_new_session`
+                None => probe_duration(input).await?,
+            };
+            let directory = self.cache_dir.join(idx.to_string());
+            fs::create_dir_all(&directory).await?;
+            sessions.insert(
+                idx,
+                HlsSession {
+                    input: input.to_path_buf(),
+                    directory,
+                    duration,
+                    start: 0,
+                    process: None,
+                    last_access: Instant::now(),
+                },
+            );
+        }
+        let session = sessions
+            .get_mut(&idx)
+            .ok_or_else(|| format_err!("No session"))?;
+        session.last_access = Instant::now();
+        Ok(session)
+    }
+
+    /// Stop the least recently used transcodes of other sessions until there
+    /// is room for one more.
+    fn limit_transcodes(sessions: &mut HashMap<i32, HlsSession>, idx: i32) {
+        let mut running: Vec<_> = sessions
+            .iter_mut()
+            .filter(|(i, s)| **i != idx && s.process.is_some())
+            .collect();
+        running.sort_by_key(|(_, s)| s.last_access);
+        let excess = (running.len() + 1).saturating_sub(MAX_TRANSCODES);
+        for (i, session) in running.into_iter().take(excess) {
+            debug!("hls stop transcode {}", i);
+            session.process.take();
+        }
+    }
+
+    pub async fn playlist(&self, idx: i32, input: &Path) -> Result<String, Error> {
+        let duration = self.probe_new_session(idx, input).await?;
+        let mut sessions = self.sessions.lock().await;
+        let session = self
+            .get_session(&mut sessions, idx, input, duration)
+            .await?;
+        Ok(hls_playlist(session.duration))
+    }
+
+    /// Path of a finished segment, starting or restarting ffmpeg if the
+    /// segment isn't on its way.
+    pub async fn segment(&self, idx: i32, input: &Path, segment: u64) -> Result<PathBuf, Error> {
+        let duration = self.probe_new_session(idx, input).await?;
+        let path = {
+            let mut sessions = self.sessions.lock().await;
+            let session = self
+                .get_session(&mut sessions, idx, input, duration)
+                .await?;
+            if segment >= number_of_segments(session.duration) {
+                return Err(format_err!("Segment {} out of range", segment));
+            }
+            let path = session.segment_path(segment);
+            let next = session.next_segment();
+            if !path.exists()
+                && (session.process.is_none()
+                    || segment < session.start
+                    || segment > next + SEGMENT_LOOKAHEAD)
+            {
+                if session.process.is_none() {
+                    Self::limit_transcodes(&mut sessions, idx);
+                }
+                sessions
+                    .get_mut(&idx)
+                    .ok_or_else(|| format_err!("No session"))?
+                    .start_transcode(segment)?;
+            }
+            path
+        };
+
+        let started = Instant::now();
+        while !path.exists() {
+            if started.elapsed() > SEGMENT_TIMEOUT {
+                return Err(format_err!("Timed out waiting for {:?}", path));
+            }
+            delay_for(Duration::from_millis(250)).await;
+        }
+        Ok(path)
+    }
+
+    /// Kill the transcode and remove the segments of every session that
+    /// hasn't been accessed in `idle`.
+    pub async fn cleanup(&self, idle: Duration) -> Result<Vec<i32>, Error> {
+        let mut sessions = self.sessions.lock().await;
+        let expired: Vec<_> = sessions
+            .iter()
+            .filter(|(_, s)| s.last_access.elapsed() > idle)
+            .map(|(idx, _)| *idx)
+            .collect();
+        for idx in &expired {
+            if let Some(session) = sessions.remove(idx) {
+                debug!("hls cleanup {}", idx);
+                fs::remove_dir_all(&session.directory).await?;
+            }
+        }
+        Ok(expired)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use crate::hls::{hls_playlist, is_browser_playable, parse_segment_name, segment_name};
+
+    #[test]
+    fn test_hls_playlist() {
+        let playlist = hls_playlist(14.5);
+        assert!(playlist.starts_with("#EXTM3U\n"));
+        assert!(playlist.contains("#EXT-X-PLAYLIST-TYPE:VOD\n"));
+        assert!(playlist.contains("#EXTINF:6.000,\nsegment_0.ts\n"));
+        assert!(playlist.contains("#EXTINF:6.000,\nsegment_1.ts\n"));
+        assert!(playlist.contains("#EXTINF:2.500,\nsegment_2.ts\n"));
+        assert!(!playlist.contains("segment_3.ts"));
+        assert!(playlist.ends_with("#EXT-X-ENDLIST\n"));
+    }
+
+    #[test]
+    fn test_segment_name() {
+        assert_eq!(parse_segment_name(&segment_name(12)), Some(12));
+        assert_eq!(parse_segment_name("segment_.ts"), None);
+        assert_eq!(parse_segment_name("segment_+1.ts"), None);
+        assert_eq!(parse_segment_name("../segment_1.ts"), None);
+        assert_eq!(parse_segment_name("segment_1.ts/.."), None);
+        assert!(is_browser_playable(Path::new("/a/b.MP4")));
+        assert!(!is_browser_playable(Path::new("/a/b.mkv")));
+        assert!(!is_browser_playable(Path::new("/a/b")));
+    }
+}
@@ -126,23 +126,22 @@ impl ImdbEpisodes {
         if self.get_index(pool).await?.is_some() {
             return self.update_episode(pool).await;
         }
-        let query = postgres_query::query_dyn!(
-            &format!(
-                r#"
-                    INSERT INTO imdb_episodes
-                    (show, season, episode, airdate, rating, eptitle, epurl, last_modified)
-                    VALUES
-                    ($show, $season, $episode, $airdate, {}, $eptitle, $epurl, now())
-                "#,
-                self.rating
-            ),
+        let query = postgres_query::query!(
+            r#"
+                INSERT INTO imdb_episodes
+                (show, season, episode, airdate, rating, eptitle, epurl, last_modified)
+                VALUES
+                ($show, $season, $episode, $airdate, cast($rating as double precision),
+                 $eptitle, $epurl, now())
+            "#,
             show = self.show,
             season = self.season,
             episode = self.episode,
             airdate = self.airdate,
+            rating = self.rating,
             eptitle = self.eptitle,
             epurl = self.epurl
-        )?;
+        );
         pool.get()
             .await?
             .execute(query.sql(), query.parameters())
@@ -152,22 +151,21 @@ impl ImdbEpisodes {
     }
 
     pub async fn update_episode(&self, pool: &PgPool) -> Result<(), Error> {
-        let query = postgres_query::query_dyn!(
-            &format!(
-                r#"
+        let query = postgres_query::query!(
+            r#"
                 UPDATE imdb_episodes
-                SET rating={},eptitle=$eptitle,epurl=$epurl,airdate=$airdate,last_modified=now()
+                SET rating=cast($rating as double precision),eptitle=$eptitle,epurl=$epurl,
+                    airdate=$airdate,last_modified=now()
                 WHERE show=$show AND season=$season AND episode=$episode
             "#,
-                self.rating
-            ),
+            rating = self.rating,
             eptitle = self.eptitle,
             epurl = self.epurl,
             airdate = self.airdate,
             show = self.show,
             season = self.season,
             episode = self.episode
-        )?;
+        );
         pool.get()
             .await?
             .execute(query.sql(), query.parameters())
@@ -11,7 +11,15 @@
 #![allow(clippy::struct_excessive_bools)]
 #![allow(clippy::used_underscore_binding)]
 
+pub mod api_tokens;
+pub mod authorized_users;
+pub mod auto_queue;
+pub mod backup;
+pub mod bulk_import;
 pub mod config;
+pub mod events;
+pub mod export_format;
+pub mod hls;
 pub mod imdb_episodes;
 pub mod imdb_ratings;
 pub mod imdb_utils;
@@ -20,11 +28,23 @@ pub mod make_list;
 pub mod make_queue;
 pub mod movie_collection;
 pub mod movie_queue;
+pub mod nfo;
+pub mod offline_archive;
 pub mod parse_imdb;
 pub mod pgpool;
+pub mod playback_position;
+pub mod query_builder;
+pub mod remote_sync;
+pub mod search;
+pub mod sqlite_storage;
 pub mod stdout_channel;
+pub mod storage;
+pub mod subtitles;
+pub mod thumbnails;
+pub mod tombstones;
 pub mod trakt_connection;
 pub mod trakt_utils;
 pub mod transcode_service;
 pub mod tv_show_source;
 pub mod utils;
+pub mod watched_import;
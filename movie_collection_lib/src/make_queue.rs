@@ -2,6 +2,7 @@ use anyhow::{format_err, Error};
 use derive_more::Display;
 use futures::future::try_join_all;
 use rayon::iter::{IntoParallelIterator, ParallelIterator};
+use serde::Serialize;
 use stack_string::StackString;
 use std::{
     ffi::OsStr,
@@ -10,10 +11,11 @@ use std::{
 };
 
 use crate::{
-    movie_collection::MovieCollection,
-    movie_queue::{MovieQueueDB, MovieQueueResult},
+    movie_collection::{MovieCollection, MovieCollectionRow},
+    movie_queue::{MovieQueueDB, MovieQueueResult, DEFAULT_USER, QUEUE_END},
     pgpool::PgPool,
     stdout_channel::StdoutChannel,
+    storage::Storage,
     utils::{get_video_runtime, parse_file_stem},
 };
 
@@ -46,8 +48,79 @@ impl From<&Path> for PathOrIndex {
     }
 }
 
+#[derive(Debug, Clone, Copy, Display)]
+pub enum QueueReorder {
+    #[display(fmt = "move {} to {}", _0, _1)]
+    Move(i32, i32),
+    #[display(fmt = "swap {} and {}", _0, _1)]
+    Swap(i32, i32),
+    #[display(fmt = "move {} to top", _0)]
+    Top(i32),
+    #[display(fmt = "move {} to bottom", _0)]
+    Bottom(i32),
+}
+
+impl QueueReorder {
+    pub async fn reorder(self, mq: &MovieQueueDB) -> Result<(), Error> {
+        match self {
+            Self::Move(from, to) => mq.move_in_queue(from, to).await,
+            Self::Swap(idx0, idx1) => mq.swap(idx0, idx1).await,
+            Self::Top(idx) => mq.move_to_top(idx).await,
+            Self::Bottom(idx) => mq.move_to_bottom(idx).await,
+        }
+    }
+}
+
+pub async fn make_queue_reorder(
+    queue: &str,
+    reorder: &[QueueReorder],
+    stdout: &StdoutChannel,
+) -> Result<(), Error> {
+    let mc = MovieCollection::new();
+    let mq = MovieQueueDB::with_queue(&mc.pool, queue);
+
+    for r in reorder {
+        r.reorder(&mq).await?;
+        stdout.send(r.to_string());
+    }
+    Ok(())
+}
+
+/// Move entries from `queue` to the end of `target`, the indices refer to
+/// positions in `queue` before any of the entries are moved.
+pub async fn make_queue_transfer(
+    queue: &str,
+    indices: &[i32],
+    target: &str,
+    stdout: &StdoutChannel,
+) -> Result<(), Error> {
+    let mc = MovieCollection::new();
+    let mq = MovieQueueDB::with_queue(&mc.pool, queue);
+
+    let mut indices = indices.to_vec();
+    indices.sort_unstable();
+    for idx in indices.into_iter().rev() {
+        mq.move_to_queue(idx, target).await?;
+        stdout.send(format!("move {} from {} to {}", idx, queue, target));
+    }
+    Ok(())
+}
+
+pub async fn make_queue_list(stdout: &StdoutChannel) -> Result<(), Error> {
+    let mc = MovieCollection::new();
+    let queues: Vec<_> = MovieQueueDB::with_pool(&mc.pool)
+        .get_queue_names()
+        .await?
+        .into_iter()
+        .map(|(name, count)| format!("{} {}", name, count))
+        .collect();
+    stdout.send(queues.join("\n"));
+    Ok(())
+}
+
 #[allow(clippy::cognitive_complexity)]
 pub async fn make_queue_worker(
+    queue: &str,
     add_files: &[PathOrIndex],
     del_files: &[PathOrIndex],
     do_time: bool,
@@ -56,7 +129,7 @@ pub async fn make_queue_worker(
     stdout: &StdoutChannel,
 ) -> Result<(), Error> {
     let mc = MovieCollection::new();
-    let mq = MovieQueueDB::with_pool(&mc.pool);
+    let mq = MovieQueueDB::with_queue(&mc.pool, queue);
 
     if do_shows {
         let shows: Vec<_> = mc
@@ -78,20 +151,7 @@ pub async fn make_queue_worker(
         }
     } else if add_files.is_empty() {
         let movie_queue = mq.print_movie_queue(&patterns).await?;
-        if do_time {
-            let results: Result<Vec<_>, Error> = movie_queue
-                .into_par_iter()
-                .map(|result| {
-                    let path = Path::new(result.path.as_str());
-                    let timeval = get_video_runtime(path)?;
-                    Ok(format!("{} {}", result, timeval))
-                })
-                .collect();
-            stdout.send(results?.join("\n"));
-        } else {
-            let results: Vec<_> = movie_queue.into_iter().map(|x| x.to_string()).collect();
-            stdout.send(results.join("\n"));
-        }
+        send_movie_queue(movie_queue, do_time, stdout)?;
     } else if add_files.len() == 1 {
         let max_idx = mq.get_max_queue_index().await?;
         if let PathOrIndex::Path(path) = &add_files[0] {
@@ -134,78 +194,275 @@ pub async fn make_queue_worker(
     Ok(())
 }
 
+fn send_movie_queue(
+    movie_queue: Vec<MovieQueueResult>,
+    do_time: bool,
+    stdout: &StdoutChannel,
+) -> Result<(), Error> {
+    if do_time {
+        let results: Result<Vec<_>, Error> = movie_queue
+            .into_par_iter()
+            .map(|result| {
+                let path = Path::new(result.path.as_str());
+                let timeval = get_video_runtime(path)?;
+                Ok(format!("{} {}", result, timeval))
+            })
+            .collect();
+        stdout.send(results?.join("\n"));
+    } else {
+        let results: Vec<_> = movie_queue.into_iter().map(|x| x.to_string()).collect();
+        stdout.send(results.join("\n"));
+    }
+    Ok(())
+}
+
+/// `make-queue` on a `sqlite://` pgurl. Only listing, adding and removing
+/// entries go through `Storage`, the caller rejects the other options.
+pub async fn make_queue_storage_worker(
+    storage: &dyn Storage,
+    queue: &str,
+    add_files: &[PathOrIndex],
+    del_files: &[PathOrIndex],
+    do_time: bool,
+    patterns: &[&str],
+    stdout: &StdoutChannel,
+) -> Result<(), Error> {
+    if !del_files.is_empty() {
+        for file in del_files {
+            let collection_idx = match file {
+                PathOrIndex::Index(idx) => storage
+                    .get_queue(DEFAULT_USER, queue)
+                    .await?
+                    .into_iter()
+                    .find(|row| row.idx == *idx)
+                    .map(|row| row.collection_idx),
+                PathOrIndex::Path(path) => {
+                    storage
+                        .get_collection_index(&path.to_string_lossy())
+                        .await?
+                }
+            };
+            if let Some(collection_idx) = collection_idx {
+                storage
+                    .remove_from_queue(DEFAULT_USER, queue, collection_idx)
+                    .await?;
+            }
+        }
+    } else if add_files.is_empty() {
+        let movie_queue = storage_movie_queue(storage, queue, patterns).await?;
+        send_movie_queue(movie_queue, do_time, stdout)?;
+    } else if let [PathOrIndex::Index(idx), file] = add_files {
+        stdout.send(format!("inserting into {}", idx));
+        storage_insert_into_queue(storage, queue, *idx, file).await?;
+    } else {
+        for file in add_files {
+            storage_insert_into_queue(storage, queue, QUEUE_END, file).await?;
+        }
+    }
+    Ok(())
+}
+
+async fn storage_insert_into_queue(
+    storage: &dyn Storage,
+    queue: &str,
+    idx: i32,
+    file: &PathOrIndex,
+) -> Result<(), Error> {
+    let path = match file {
+        PathOrIndex::Path(path) => path,
+        PathOrIndex::Index(_) => return Err(format_err!("{} is not a path", file)),
+    };
+    if !path.exists() {
+        return Err(format_err!("File doesn't exist"));
+    }
+    let row = MovieCollectionRow {
+        path: path.to_string_lossy().as_ref().into(),
+        ..MovieCollectionRow::default()
+    };
+    let collection_idx = storage.sync_collection_row(&row).await?;
+    storage
+        .insert_into_queue(DEFAULT_USER, queue, idx, collection_idx)
+        .await
+}
+
+/// The same listing as `MovieQueueDB::print_movie_queue`, with the patterns
+/// matched here rather than in sql.
+async fn storage_movie_queue(
+    storage: &dyn Storage,
+    queue: &str,
+    patterns: &[&str],
+) -> Result<Vec<MovieQueueResult>, Error> {
+    let mut results = Vec::new();
+    for row in storage.get_queue(DEFAULT_USER, queue).await? {
+        if !patterns.is_empty() && !patterns.iter().any(|p| row.path.as_str().contains(p)) {
+            continue;
+        }
+        let mut result = MovieQueueResult {
+            idx: row.idx,
+            path: row.path,
+            ..MovieQueueResult::default()
+        };
+        if let Some(show) = storage.get_show_by_link(&row.show).await? {
+            result.istv = show.istv.unwrap_or(false);
+            result.link = Some(show.link);
+        }
+        if result.istv {
+            let file_stem = Path::new(result.path.as_str())
+                .file_stem()
+                .ok_or_else(|| format_err!("Invalid path"))?
+                .to_string_lossy()
+                .to_string();
+            let (show, season, episode) = parse_file_stem(&file_stem);
+            if let Some(ep) = storage
+                .get_episodes(&show)
+                .await?
+                .into_iter()
+                .find(|ep| ep.season == season && ep.episode == episode)
+            {
+                result.eplink = Some(ep.epurl);
+                result.show = Some(show);
+                result.season = Some(season);
+                result.episode = Some(episode);
+            }
+        }
+        results.push(result);
+    }
+    Ok(results)
+}
+
+/// Queue entry as displayed by the web frontend
+#[derive(Serialize)]
+pub struct MovieQueueEntry {
+    pub idx: i32,
+    pub file_name: StackString,
+    pub link: Option<StackString>,
+    /// Collection index of mp4 files which can be played
+    pub collection_idx: Option<i32>,
+    /// Files other than mp4 still need to be transcoded
+    pub transcode: bool,
+    /// Transcode into this directory, for files that aren't tv episodes
+    pub directory: Option<StackString>,
+}
+
 pub async fn movie_queue_http(
     queue: &[MovieQueueResult],
     pool: &PgPool,
-) -> Result<Vec<StackString>, Error> {
+) -> Result<Vec<MovieQueueEntry>, Error> {
     let mc = Arc::new(MovieCollection::with_pool(pool)?);
 
-    let button = r#"<td><button type="submit" id="ID" onclick="delete_show('SHOW');"> remove </button></td>"#;
-
     let futures = queue.iter().map(|row| {
         let mc = mc.clone();
         async move {
-        let path = Path::new(row.path.as_str());
-        let ext = path
-            .extension()
-            .ok_or_else(|| format_err!("Cannot determine extension"))?
-            .to_string_lossy();
-        let file_name = path
-            .file_name()
-            .ok_or_else(|| format_err!("Invalid path"))?
-            .to_string_lossy()
-            .to_string();
-        let file_stem = path
-            .file_stem()
-            .ok_or_else(|| format_err!("Invalid path"))?
-            .to_string_lossy();
-        let (_, season, episode) = parse_file_stem(&file_stem);
-
-        let entry = if ext == "mp4" {
-            let collection_idx = mc.get_collection_index(&row.path).await?.unwrap_or(-1);
-            format!(
-                r#"<a href="javascript:updateMainArticle('{}');">{}</a>"#,
-                &format!("{}/{}", "/list/play", collection_idx),
-                file_name
-            )
-        } else {
-            file_name.clone()
-        };
+            let path = Path::new(row.path.as_str());
+            let ext = path
+                .extension()
+                .ok_or_else(|| format_err!("Cannot determine extension"))?
+                .to_string_lossy();
+            let file_name = path
+                .file_name()
+                .ok_or_else(|| format_err!("Invalid path"))?
+                .to_string_lossy()
+                .to_string();
+            let file_stem = path
+                .file_stem()
+                .ok_or_else(|| format_err!("Invalid path"))?
+                .to_string_lossy();
+            let (_, season, episode) = parse_file_stem(&file_stem);
 
-        let entry = if let Some(link) = row.link.as_ref() {
-            format!(
-                r#"<tr><td>{}</td><td><a href={} target="_blank">imdb</a></td>"#,
-                entry,
-                &format!("https://www.imdb.com/title/{}", link)
-            )
-        } else {
-            format!("<tr>\n<td>{}</td>\n", entry)
-        };
-
-        let entry = format!(
-            "{}\n{}",
-            entry,
-            button.replace("ID", &file_name).replace("SHOW", &file_name)
-        ).into();
-
-        let entry = if ext == "mp4" {
-            entry
-        } else if season != -1 && episode != -1 {
-            format!(
-                r#"{}<td><button type="submit" id="{}" onclick="transcode('{}');"> transcode </button></td>"#,
-                entry, file_name, file_name
-            ).into()
-        } else {
-            let entries: Vec<_> = row.path.split('/').collect();
-            let len_entries = entries.len();
-            let directory = entries[len_entries - 2];
-            format!(
-                r#"{}<td><button type="submit" id="{}" onclick="transcode_directory('{}', '{}');"> transcode </button></td>"#,
-                entry, file_name, file_name, directory
-            ).into()
-        };
-        Ok(entry)
+            let is_mp4 = ext == "mp4";
+            let collection_idx = mc.get_collection_index(&row.path).await?;
+            let directory = if is_mp4 || (season != -1 && episode != -1) {
+                None
+            } else {
+                path.parent()
+                    .and_then(Path::file_name)
+                    .map(|d| d.to_string_lossy().to_string().into())
+            };
+            Ok(MovieQueueEntry {
+                idx: row.idx,
+                file_name: file_name.into(),
+                link: row.link.clone(),
+                collection_idx,
+                transcode: !is_mp4,
+                directory,
+            })
         }
     });
     try_join_all(futures).await
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use std::{
+        env::temp_dir,
+        fs::{create_dir_all, remove_dir_all, File},
+    };
+
+    use crate::{
+        make_queue::{make_queue_storage_worker, PathOrIndex},
+        movie_queue::{DEFAULT_QUEUE, DEFAULT_USER},
+        stdout_channel::StdoutChannel,
+        storage::{open_storage, Storage},
+    };
+
+    async fn queue_paths(storage: &dyn Storage) -> Result<Vec<String>, Error> {
+        let queue = storage.get_queue(DEFAULT_USER, DEFAULT_QUEUE).await?;
+        Ok(queue.into_iter().map(|row| row.path.to_string()).collect())
+    }
+
+    #[tokio::test]
+    async fn test_make_queue_storage_worker() -> Result<(), Error> {
+        let dir = temp_dir().join("test_make_queue_storage_worker");
+        create_dir_all(&dir)?;
+        let a = dir.join("a_movie.mp4");
+        let b = dir.join("b_movie.mp4");
+        File::create(&a)?;
+        File::create(&b)?;
+        let a_str = a.to_string_lossy().to_string();
+        let b_str = b.to_string_lossy().to_string();
+
+        let storage = open_storage("sqlite::memory:")?;
+        let storage = storage.as_ref();
+        let stdout = StdoutChannel::new();
+
+        let add = [PathOrIndex::Path(a.clone()), PathOrIndex::Path(b.clone())];
+        make_queue_storage_worker(storage, DEFAULT_QUEUE, &add, &[], false, &[], &stdout).await?;
+        assert_eq!(
+            queue_paths(storage).await?,
+            vec![a_str.clone(), b_str.clone()]
+        );
+
+        let add = [PathOrIndex::Index(0), PathOrIndex::Path(b.clone())];
+        make_queue_storage_worker(storage, DEFAULT_QUEUE, &add, &[], false, &[], &stdout).await?;
+        assert_eq!(queue_paths(storage).await?, vec![b_str, a_str.clone()]);
+
+        make_queue_storage_worker(storage, DEFAULT_QUEUE, &[], &[], false, &["a_mov"], &stdout)
+            .await?;
+
+        let del = [PathOrIndex::Index(0)];
+        make_queue_storage_worker(storage, DEFAULT_QUEUE, &[], &del, false, &[], &stdout).await?;
+        assert_eq!(queue_paths(storage).await?, vec![a_str]);
+
+        let del = [PathOrIndex::Path(a.clone())];
+        make_queue_storage_worker(storage, DEFAULT_QUEUE, &[], &del, false, &[], &stdout).await?;
+        assert!(queue_paths(storage).await?.is_empty());
+
+        let add = [PathOrIndex::Path(dir.join("missing.mp4"))];
+        assert!(
+            make_queue_storage_worker(storage, DEFAULT_QUEUE, &add, &[], false, &[], &stdout)
+                .await
+                .is_err()
+        );
+        let add = [PathOrIndex::Index(1)];
+        assert!(
+            make_queue_storage_worker(storage, DEFAULT_QUEUE, &add, &[], false, &[], &stdout)
+                .await
+                .is_err()
+        );
+
+        stdout.close().await?;
+        remove_dir_all(&dir)?;
+        Ok(())
+    }
+}
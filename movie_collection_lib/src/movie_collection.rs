@@ -1,7 +1,9 @@
 use anyhow::{format_err, Error};
 use chrono::{DateTime, Duration, Local, NaiveDate, Utc};
+use deadpool_postgres::Transaction;
 use futures::future::try_join_all;
 use itertools::Itertools;
+use log::debug;
 use postgres_query::FromSqlRow;
 use rayon::iter::{IntoParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use serde::{Deserialize, Serialize};
@@ -16,16 +18,22 @@ use std::{
 
 use crate::{
     config::Config,
+    events::Event,
     imdb_episodes::ImdbEpisodes,
     imdb_ratings::ImdbRatings,
-    movie_queue::MovieQueueDB,
+    movie_queue::{MovieQueueDB, DEFAULT_QUEUE, DEFAULT_USER},
+    offline_archive::OfflineArchiveEntry,
     pgpool::PgPool,
+    query_builder::{contains_pattern, QueryBuilder},
     stdout_channel::StdoutChannel,
+    subtitles::scan_subtitles,
+    thumbnails::ThumbnailCache,
+    tombstones::Tombstone,
     tv_show_source::TvShowSource,
     utils::{option_string_wrapper, parse_file_stem, walk_directory},
 };
 
-#[derive(FromSqlRow)]
+#[derive(Serialize, FromSqlRow)]
 pub struct NewEpisodesResult {
     pub show: StackString,
     pub link: StackString,
@@ -58,13 +66,14 @@ impl fmt::Display for NewEpisodesResult {
     }
 }
 
-#[derive(Default, FromSqlRow)]
+#[derive(Default, Serialize, FromSqlRow)]
 pub struct TvShowsResult {
     pub show: StackString,
     pub link: StackString,
     pub count: i64,
     pub title: StackString,
     pub source: Option<TvShowSource>,
+    pub collection_idx: i32,
 }
 
 impl fmt::Display for TvShowsResult {
@@ -89,7 +98,7 @@ pub struct MovieCollectionRow {
     pub show: StackString,
 }
 
-#[derive(Default, FromSqlRow)]
+#[derive(Default, Serialize, FromSqlRow)]
 pub struct MovieCollectionResult {
     pub path: StackString,
     pub show: StackString,
@@ -101,11 +110,14 @@ pub struct MovieCollectionResult {
     pub episode: Option<i32>,
     pub eptitle: Option<StackString>,
     pub epurl: Option<StackString>,
+    pub volume: Option<StackString>,
 }
 
 impl fmt::Display for MovieCollectionResult {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        if self.istv {
+        if let Some(volume) = &self.volume {
+            write!(f, "{} {} [offline: {}]", self.path, self.show, volume)
+        } else if self.istv {
             write!(
                 f,
                 "{} {} {:.1}/{:.1} s{:02} ep{:02} {} {} {}",
@@ -129,7 +141,7 @@ impl fmt::Display for MovieCollectionResult {
     }
 }
 
-#[derive(Debug, Default, FromSqlRow)]
+#[derive(Debug, Default, Serialize, FromSqlRow)]
 pub struct ImdbSeason {
     pub show: StackString,
     pub title: StackString,
@@ -174,7 +186,7 @@ impl Default for MovieCollection {
 impl MovieCollection {
     pub fn new() -> Self {
         let config = Config::with_config().expect("Init config failed");
-        let pool = PgPool::new(&config.pgurl);
+        let pool = PgPool::new(&config.pgurl).expect("Init pool failed");
         let stdout = StdoutChannel::new();
         Self {
             pool,
@@ -207,17 +219,17 @@ impl MovieCollection {
         show: &str,
         istv: bool,
     ) -> Result<Vec<ImdbRatings>, Error> {
-        let query = format!("SELECT show FROM imdb_ratings WHERE show like '%{}%'", show);
-        let query = if istv {
-            format!("{} AND istv", query)
-        } else {
-            query
-        };
+        let mut builder = QueryBuilder::new();
+        builder.and_contains_any("show", &[show]);
+        if istv {
+            builder.and("istv");
+        }
+        let query = format!("SELECT show FROM imdb_ratings {}", builder.where_clause());
         let shows: HashSet<StackString> = self
             .get_pool()
             .get()
             .await?
-            .query(query.as_str(), &[])
+            .query(query.as_str(), &builder.params())
             .await?
             .iter()
             .map(|r| r.get(0))
@@ -239,25 +251,24 @@ impl MovieCollection {
                 rating: f64,
             }
 
-            let query = postgres_query::query_dyn!(
-                &format!(
-                    r#"
-                            SELECT index, show, title, link, rating
-                            FROM imdb_ratings
-                            WHERE link is not null AND
-                                  rating is not null AND
-                                  show = $show {}
-                        "#,
-                    if istv { "AND istv" } else { "" },
-                ),
-                show = show
-            )?;
+            let mut builder = QueryBuilder::new();
+            builder
+                .and("link is not null")
+                .and("rating is not null")
+                .and_eq("show", show);
+            if istv {
+                builder.and("istv");
+            }
+            let query = format!(
+                "SELECT index, show, title, link, rating FROM imdb_ratings {}",
+                builder.where_clause()
+            );
 
             let results: Result<Vec<_>, Error> = self
                 .get_pool()
                 .get()
                 .await?
-                .query(query.sql(), query.parameters())
+                .query(query.as_str(), &builder.params())
                 .await?
                 .iter()
                 .map(|row| {
@@ -285,31 +296,29 @@ impl MovieCollection {
         show: &str,
         season: Option<i32>,
     ) -> Result<Vec<ImdbEpisodes>, Error> {
-        let query = postgres_query::query_dyn!(
-            &format!(
-                r#"
-                    SELECT a.show, b.title, a.season, a.episode,
-                        a.airdate,
-                        cast(a.rating as double precision),
-                        a.eptitle, a.epurl
-                    FROM imdb_episodes a
-                    JOIN imdb_ratings b ON a.show=b.show
-                    WHERE a.show = $show {}
-                    ORDER BY a.season, a.episode
-                "#,
-                if let Some(season) = season {
-                    format!("AND a.season = {}", season)
-                } else {
-                    "".to_string()
-                }
-            ),
-            show = show,
-        )?;
+        let mut builder = QueryBuilder::new();
+        builder.and_eq("a.show", show.to_string());
+        if let Some(season) = season {
+            builder.and_eq("a.season", season);
+        }
+        let query = format!(
+            r#"
+                SELECT a.show, b.title, a.season, a.episode,
+                    a.airdate,
+                    cast(a.rating as double precision),
+                    a.eptitle, a.epurl
+                FROM imdb_episodes a
+                JOIN imdb_ratings b ON a.show=b.show
+                {}
+                ORDER BY a.season, a.episode
+            "#,
+            builder.where_clause()
+        );
 
         self.get_pool()
             .get()
             .await?
-            .query(query.sql(), query.parameters())
+            .query(query.as_str(), &builder.params())
             .await?
             .iter()
             .map(|row| {
@@ -360,7 +369,9 @@ impl MovieCollection {
             istv: Option<bool>,
         }
 
-        let query = postgres_query::query_dyn!(&format!(
+        let mut builder = QueryBuilder::new();
+        builder.and_contains_any("a.path", search_strs);
+        let query = format!(
             r#"
                 SELECT a.path, a.show,
                 COALESCE(b.rating, -1) as rating,
@@ -370,22 +381,14 @@ impl MovieCollection {
                 LEFT JOIN imdb_ratings b ON a.show_id = b.index
                 {}
             "#,
-            if search_strs.is_empty() {
-                "".to_string()
-            } else {
-                let search_strs: Vec<_> = search_strs
-                    .iter()
-                    .map(|s| format!("a.path like '%{}%'", s.as_ref()))
-                    .collect();
-                format!("WHERE {}", search_strs.join(" OR "))
-            },
-        ),)?;
+            builder.where_clause()
+        );
 
         let results: Result<Vec<_>, Error> = self
             .get_pool()
             .get()
             .await?
-            .query(query.sql(), &[])
+            .query(query.as_str(), &builder.params())
             .await?
             .iter()
             .map(|row| {
@@ -448,6 +451,23 @@ impl MovieCollection {
         let results: Result<Vec<_>, Error> = try_join_all(futures).await;
         let mut results = results?;
         results.sort_by_key(|r| (r.season, r.episode));
+
+        if !search_strs.is_empty() {
+            let offline =
+                OfflineArchiveEntry::search_offline_archive(search_strs, self.get_pool()).await?;
+            results.extend(offline.into_iter().map(|entry| {
+                let file_stem = Path::new(entry.path.as_str())
+                    .file_stem()
+                    .map_or_else(|| "".into(), OsStr::to_string_lossy);
+                let (show, _, _) = parse_file_stem(&file_stem);
+                MovieCollectionResult {
+                    path: entry.path,
+                    show,
+                    volume: Some(entry.label),
+                    ..MovieCollectionResult::default()
+                }
+            }));
+        }
         Ok(results)
     }
 
@@ -456,13 +476,15 @@ impl MovieCollection {
             r#"DELETE FROM movie_collection WHERE path = $path"#,
             path = path
         );
-        self.get_pool()
-            .get()
-            .await?
-            .execute(query.sql(), query.parameters())
-            .await
-            .map(|_| ())
-            .map_err(Into::into)
+        let mut conn = self.get_pool().get().await?;
+        let tran = conn.transaction().await?;
+        if tran.execute(query.sql(), query.parameters()).await? > 0 {
+            Tombstone::new("movie_collection", &[path])
+                .record_in(&tran)
+                .await?;
+        }
+        Event::CollectionChanged.publish_in(&tran).await?;
+        tran.commit().await.map_err(Into::into)
     }
 
     pub async fn get_collection_index(&self, path: &str) -> Result<Option<i32>, Error> {
@@ -499,15 +521,24 @@ impl MovieCollection {
         Ok(path)
     }
 
+    /// Find the sidecar and embedded subtitles of a collection entry
+    pub async fn update_subtitles(&self, path: &str) -> Result<usize, Error> {
+        let idx = self
+            .get_collection_index(path)
+            .await?
+            .ok_or_else(|| format_err!("{} not in collection", path))?;
+        scan_subtitles(self.get_pool(), idx, Path::new(path)).await
+    }
+
     pub async fn get_collection_index_match(&self, path: &str) -> Result<Option<i32>, Error> {
-        let query = postgres_query::query_dyn!(&format!(
-            r#"SELECT idx FROM movie_collection WHERE path like '%{}%'"#,
-            path
-        ))?;
+        let query = postgres_query::query!(
+            r#"SELECT idx FROM movie_collection WHERE path like $pattern"#,
+            pattern = contains_pattern(path)
+        );
         self.get_pool()
             .get()
             .await?
-            .query(query.sql(), &[])
+            .query(query.sql(), query.parameters())
             .await?
             .iter()
             .map(|row| row.try_get("idx"))
@@ -530,13 +561,12 @@ impl MovieCollection {
             path = path,
             show = show
         );
-        self.get_pool()
-            .get()
-            .await?
-            .execute(query.sql(), query.parameters())
-            .await
-            .map(|_| ())
-            .map_err(Into::into)
+        let mut conn = self.get_pool().get().await?;
+        let tran = conn.transaction().await?;
+        tran.execute(query.sql(), query.parameters()).await?;
+        Tombstone::clear_in(&tran, "movie_collection", &[path]).await?;
+        Event::CollectionChanged.publish_in(&tran).await?;
+        tran.commit().await.map_err(Into::into)
     }
 
     pub async fn insert_into_collection_by_idx(&self, idx: i32, path: &str) -> Result<(), Error> {
@@ -551,13 +581,69 @@ impl MovieCollection {
             path = path,
             show = show
         );
-        self.get_pool()
-            .get()
+        let mut conn = self.get_pool().get().await?;
+        let tran = conn.transaction().await?;
+        tran.execute(query.sql(), query.parameters()).await?;
+        Tombstone::clear_in(&tran, "movie_collection", &[path]).await?;
+        Event::CollectionChanged.publish_in(&tran).await?;
+        tran.commit().await.map_err(Into::into)
+    }
+
+    /// Add an entry exported by another node and return its local idx.
+    /// Entries are matched by path, a path that is already present keeps its
+    /// idx and a new one only keeps the remote idx if it isn't taken by a
+    /// different path here, otherwise it gets the next free idx.
+    pub async fn sync_collection_row(&self, row: &MovieCollectionRow) -> Result<i32, Error> {
+        let mut conn = self.get_pool().get().await?;
+        let tran = conn.transaction().await?;
+        let (idx, _) = Self::sync_collection_row_in(&tran, row).await?;
+        tran.commit().await?;
+        Ok(idx)
+    }
+
+    /// Returns the local idx and whether the entry was added.
+    pub async fn sync_collection_row_in(
+        tran: &Transaction<'_>,
+        row: &MovieCollectionRow,
+    ) -> Result<(i32, bool), Error> {
+        let query = postgres_query::query!(
+            "SELECT idx FROM movie_collection WHERE path = $path",
+            path = row.path
+        );
+        if let Some(existing) = tran.query(query.sql(), query.parameters()).await?.get(0) {
+            return Ok((existing.try_get("idx")?, false));
+        }
+        let file_stem = Path::new(row.path.as_str())
+            .file_stem()
+            .ok_or_else(|| format_err!("Invalid path {}", row.path))?
+            .to_string_lossy();
+        let (show, _, _) = parse_file_stem(&file_stem);
+        let query = postgres_query::query!(
+            r#"
+                INSERT INTO movie_collection (idx, path, show, last_modified)
+                VALUES (
+                    CASE WHEN EXISTS (SELECT 1 FROM movie_collection WHERE idx = $idx)
+                        THEN (SELECT max(idx) + 1 FROM movie_collection)
+                        ELSE $idx
+                    END,
+                    $path, $show, now()
+                )
+                RETURNING idx
+            "#,
+            idx = row.idx,
+            path = row.path,
+            show = show
+        );
+        let idx: i32 = tran
+            .query_one(query.sql(), query.parameters())
             .await?
-            .execute(query.sql(), query.parameters())
-            .await
-            .map(|_| ())
-            .map_err(Into::into)
+            .try_get("idx")?;
+        if idx != row.idx {
+            debug!("{} moved from idx {} to {}", row.path, row.idx, idx);
+        }
+        Tombstone::clear_in(tran, "movie_collection", &[row.path.as_str()]).await?;
+        Event::CollectionChanged.publish_in(tran).await?;
+        Ok((idx, true))
     }
 
     pub async fn fix_collection_show_id(&self) -> Result<u64, Error> {
@@ -574,6 +660,9 @@ impl MovieCollection {
             WHERE idx in (SELECT a.idx FROM a)
         "#;
         let rows = self.get_pool().get().await?.execute(query, &[]).await?;
+        if rows > 0 {
+            Event::CollectionChanged.publish(self.get_pool()).await?;
+        }
         Ok(rows)
     }
 
@@ -619,24 +708,23 @@ impl MovieCollection {
         let episode_list = episode_list?;
 
         let query = r#"
-            SELECT b.path, a.idx
+            SELECT b.path, a.user_email, a.queue_name, a.idx
             FROM movie_queue a
             JOIN movie_collection b ON a.collection_idx=b.idx
         "#;
-        let movie_queue: Result<HashMap<StackString, i32>, Error> = self
-            .get_pool()
-            .get()
-            .await?
-            .query(query, &[])
-            .await?
-            .iter()
-            .map(|row| {
-                let path: StackString = row.try_get("path")?;
-                let idx: i32 = row.try_get("idx")?;
-                Ok((path, idx))
-            })
-            .collect();
-        let movie_queue = Arc::new(movie_queue?);
+        let mut movie_queue: HashMap<StackString, Vec<(StackString, StackString, i32)>> =
+            HashMap::new();
+        for row in self.get_pool().get().await?.query(query, &[]).await? {
+            let path: StackString = row.try_get("path")?;
+            let user_email: StackString = row.try_get("user_email")?;
+            let queue_name: StackString = row.try_get("queue_name")?;
+            let idx: i32 = row.try_get("idx")?;
+            movie_queue
+                .entry(path)
+                .or_default()
+                .push((user_email, queue_name, idx));
+        }
+        let movie_queue = Arc::new(movie_queue);
 
         let query = "SELECT path, show FROM movie_collection";
         let collection_map: Result<HashMap<StackString, StackString>, Error> = self
@@ -684,21 +772,42 @@ impl MovieCollection {
                     if self.get_config().suffixes.contains(&ext) {
                         self.stdout.send(format!("not in collection {}", f));
                         self.insert_into_collection(f).await?;
+                        return Ok(Some(f));
                     }
                 }
-                Ok(())
+                Ok(None)
             }
         });
         let results: Result<Vec<_>, Error> = try_join_all(futures).await;
-        results?;
+        let thumbnails = ThumbnailCache::new(&self.get_config().thumbnail_cache_dir);
+        for f in results?.into_iter().flatten() {
+            match self.update_subtitles(f).await {
+                Ok(0) => {}
+                Ok(n) => self.stdout.send(format!("{} subtitles {}", n, f)),
+                Err(e) => self
+                    .stdout
+                    .send(format!("subtitle scan failed {} {}", f, e)),
+            }
+            if let Err(e) = thumbnails.get_thumbnail(Path::new(f)).await {
+                self.stdout.send(format!("thumbnail failed {} {}", f, e));
+            }
+        }
 
         for (key, val) in collection_map.iter() {
             if !file_list.contains(key.as_str()) {
                 if let Some(v) = movie_queue.get(key) {
-                    self.stdout
-                        .send(format!("in queue but not disk {} {}", key, v));
-                    let mq = MovieQueueDB::with_pool(self.get_pool());
-                    mq.remove_from_queue_by_path(&key).await?;
+                    for entry in OfflineArchiveEntry::find_by_path(key, self.get_pool()).await? {
+                        self.stdout
+                            .send(format!("mount volume {} for {}", entry.label, entry.path));
+                    }
+                    for (user_email, queue_name, idx) in v {
+                        self.stdout.send(format!(
+                            "in queue but not disk {} {} {} {}",
+                            key, user_email, queue_name, idx
+                        ));
+                        let mq = MovieQueueDB::with_user(self.get_pool(), user_email, queue_name);
+                        mq.remove_from_queue_by_path(&key).await?;
+                    }
                 } else {
                     self.stdout.send(format!("not on disk {} {}", key, val));
                 }
@@ -791,7 +900,8 @@ impl MovieCollection {
 
     pub async fn print_tv_shows(&self) -> Result<Vec<TvShowsResult>, Error> {
         let query = r#"
-            SELECT b.show, c.link, c.title, c.source, count(*) as count
+            SELECT b.show, c.link, c.title, c.source, count(*) as count,
+                   min(b.idx) as collection_idx
             FROM movie_queue a
             JOIN movie_collection b ON a.collection_idx=b.idx
             JOIN imdb_ratings c ON b.show_id=c.index
@@ -814,50 +924,54 @@ impl MovieCollection {
         mindate: NaiveDate,
         maxdate: NaiveDate,
         source: Option<TvShowSource>,
+        user: &str,
     ) -> Result<Vec<NewEpisodesResult>, Error> {
-        let query = postgres_query::query_dyn!(
-            &format!(
-                r#"
-                    WITH active_links AS (
-                        SELECT c.link
-                        FROM movie_queue a
-                        JOIN movie_collection b ON a.collection_idx=b.idx
-                        JOIN imdb_ratings c ON b.show_id=c.index
-                        JOIN imdb_episodes d ON c.show = d.show
-                        UNION
-                        SELECT link
-                        FROM trakt_watchlist
-                    )
-                    SELECT c.show,
-                            c.link,
-                            c.title,
-                            d.season,
-                            d.episode,
-                            d.epurl,
-                            d.airdate,
-                            c.rating,
-                            cast(d.rating as double precision) as eprating,
-                            d.eptitle
-                    FROM imdb_ratings c
+        // `All` matches every source, no source only shows without one
+        let any_source = source == Some(TvShowSource::All);
+        let source = source.map(|s| s.to_string());
+        let query = postgres_query::query!(
+            r#"
+                WITH active_links AS (
+                    SELECT c.link
+                    FROM movie_queue a
+                    JOIN movie_collection b ON a.collection_idx=b.idx
+                    JOIN imdb_ratings c ON b.show_id=c.index
                     JOIN imdb_episodes d ON c.show = d.show
-                    LEFT JOIN trakt_watched_episodes e
-                        ON c.link=e.link AND d.season=e.season AND d.episode=e.episode
-                    WHERE c.link in (SELECT link FROM active_links GROUP BY link) AND
-                        e.episode is null AND
-                        c.istv AND d.airdate >= $mindate AND
-                        d.airdate <= $maxdate {}
-                    GROUP BY 1,2,3,4,5,6,7,8,9,10
-                    ORDER BY d.airdate, c.show, d.season, d.episode
-                "#,
-                match source {
-                    Some(TvShowSource::All) => "".to_string(),
-                    Some(s) => format!("AND c.source = '{}'", s),
-                    None => "AND c.source is null".to_string(),
-                }
-            ),
+                    WHERE a.user_email = $user
+                    UNION
+                    SELECT link
+                    FROM trakt_watchlist
+                    WHERE user_email = $user
+                )
+                SELECT c.show,
+                        c.link,
+                        c.title,
+                        d.season,
+                        d.episode,
+                        d.epurl,
+                        d.airdate,
+                        c.rating,
+                        cast(d.rating as double precision) as eprating,
+                        d.eptitle
+                FROM imdb_ratings c
+                JOIN imdb_episodes d ON c.show = d.show
+                LEFT JOIN trakt_watched_episodes e
+                    ON c.link=e.link AND d.season=e.season AND d.episode=e.episode
+                        AND e.user_email = $user
+                WHERE c.link in (SELECT link FROM active_links GROUP BY link) AND
+                    e.episode is null AND
+                    c.istv AND d.airdate >= $mindate AND
+                    d.airdate <= $maxdate AND
+                    ($any_source OR c.source IS NOT DISTINCT FROM $source)
+                GROUP BY 1,2,3,4,5,6,7,8,9,10
+                ORDER BY d.airdate, c.show, d.season, d.episode
+            "#,
             mindate = mindate,
-            maxdate = maxdate
-        )?;
+            maxdate = maxdate,
+            user = user,
+            any_source = any_source,
+            source = source
+        );
         self.get_pool()
             .get()
             .await?
@@ -881,7 +995,12 @@ impl MovieCollection {
         let mut output = Vec::new();
 
         let episodes = self
-            .get_new_episodes(mindate.naive_local(), maxdate.naive_local(), source)
+            .get_new_episodes(
+                mindate.naive_local(),
+                maxdate.naive_local(),
+                source,
+                DEFAULT_USER,
+            )
             .await?;
         'outer: for epi in episodes {
             let movie_queue = mq.print_movie_queue(&[epi.show.as_str()]).await?;
@@ -930,25 +1049,20 @@ impl MovieCollection {
     }
 }
 
+#[derive(Serialize)]
+pub struct NewEpisodeEntry {
+    #[serde(flatten)]
+    pub episode: NewEpisodesResult,
+    /// Set when the episode is queued and can be played
+    pub collection_idx: Option<i32>,
+}
+
 pub async fn find_new_episodes_http_worker<T: AsRef<str>>(
     pool: &PgPool,
     shows: Option<T>,
     source: Option<TvShowSource>,
-) -> Result<Vec<StackString>, Error> {
-    let button_add = format!(
-        "{}{}",
-        r#"<td><button type="submit" id="ID" "#,
-        format!(
-            r#"onclick="imdb_update('SHOW', 'LINK', SEASON,
-            '/list/cal{}');"
-            >update database</button></td>"#,
-            match source.as_ref() {
-                Some(s) => format!("?source={}", s.to_string()),
-                None => "".to_string(),
-            }
-        ),
-    );
-
+    user: &str,
+) -> Result<Vec<NewEpisodeEntry>, Error> {
     let mc = MovieCollection::with_pool(&pool)?;
     let shows_filter: Option<HashSet<StackString>> =
         shows.map(|s| s.as_ref().split(',').map(Into::into).collect());
@@ -956,9 +1070,9 @@ pub async fn find_new_episodes_http_worker<T: AsRef<str>>(
     let mindate = (Local::today() + Duration::days(-14)).naive_local();
     let maxdate = (Local::today() + Duration::days(7)).naive_local();
 
-    let mq = MovieQueueDB::with_pool(&pool);
+    let mq = MovieQueueDB::with_user(&pool, user, DEFAULT_QUEUE);
 
-    let episodes = mc.get_new_episodes(mindate, maxdate, source).await?;
+    let episodes = mc.get_new_episodes(mindate, maxdate, source, user).await?;
 
     let shows: HashSet<StackString> = episodes
         .iter()
@@ -1001,31 +1115,10 @@ pub async fn find_new_episodes_http_worker<T: AsRef<str>>(
         .into_iter()
         .map(|epi| {
             let key = (epi.show.clone(), epi.season, epi.episode);
-            format!(
-                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td>{}</tr>",
-                format!(
-                    r#"<a href="javascript:updateMainArticle('/list/trakt/watched/list/{}/{}')">{}</a>"#,
-                    epi.link, epi.season, epi.title
-                ),
-                match queue.get(&key) {
-                    Some(idx) => format!(
-                        r#"<a href="javascript:updateMainArticle('{}');">{}</a>"#,
-                        &format!(r#"{}/{}"#, "/list/play", idx),
-                        epi.eptitle
-                    ),
-                    None => epi.eptitle.to_string(),
-                },
-                format!(
-                    r#"<a href="https://www.imdb.com/title/{}" target="_blank">s{} ep{}</a>"#,
-                    epi.epurl, epi.season, epi.episode
-                ),
-                format!("rating: {:0.1} / {:0.1}", epi.eprating, epi.rating,),
-                epi.airdate,
-                button_add
-                    .replace("SHOW", &epi.show)
-                    .replace("LINK", &epi.link)
-                    .replace("SEASON", &epi.season.to_string()),
-            ).into()
+            NewEpisodeEntry {
+                collection_idx: queue.get(&key).copied(),
+                episode: epi,
+            }
         })
         .collect();
 
@@ -1045,13 +1138,15 @@ impl LastModifiedResponse {
             "imdb_ratings",
             "movie_collection",
             "movie_queue",
+            "tombstones",
         ];
 
         let futures = tables.into_iter().map(|table| async move {
             let query = format!("SELECT max(last_modified) FROM {}", table);
             if let Some(row) = pool.get().await?.query(query.as_str(), &[]).await?.get(0) {
-                let last_modified: DateTime<Utc> = row.try_get(0)?;
-                Ok(Some(LastModifiedResponse {
+                // max() of an empty table is NULL
+                let last_modified: Option<DateTime<Utc>> = row.try_get(0)?;
+                Ok(last_modified.map(|last_modified| LastModifiedResponse {
                     table: (*table).into(),
                     last_modified,
                 }))
@@ -1064,3 +1159,42 @@ impl LastModifiedResponse {
         Ok(results)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use crate::{
+        config::Config, movie_collection::MovieCollection, movie_queue::MovieQueueDB,
+        offline_archive::OfflineArchiveEntry, pgpool::PgPool, trakt_utils::get_watched_shows_db,
+    };
+
+    const HOSTILE: &[&str] = &[
+        "grey's_anatomy",
+        "'; DROP TABLE movie_queue; --",
+        "x' OR '1'='1",
+        "%",
+        r"\",
+    ];
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_hostile_filters() -> Result<(), Error> {
+        let config = Config::with_config()?;
+        let pool = PgPool::new(&config.pgurl)?;
+        let mc = MovieCollection::with_pool(&pool)?;
+        let mq = MovieQueueDB::with_pool(&pool);
+        for s in HOSTILE {
+            assert!(mc.print_imdb_shows(s, true).await?.is_empty());
+            assert!(mc.print_imdb_episodes(s, Some(1)).await?.is_empty());
+            assert!(mc.search_movie_collection(&[s]).await?.is_empty());
+            assert_eq!(mc.get_collection_index_match(s).await?, None);
+            assert!(mq.print_movie_queue(&[*s]).await?.is_empty());
+            assert!(OfflineArchiveEntry::search_offline_archive(&[s], &pool)
+                .await?
+                .is_empty());
+            assert!(get_watched_shows_db(&pool, s, None, s).await?.is_empty());
+        }
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::{format_err, Error};
 use chrono::{DateTime, Utc};
+use deadpool_postgres::Transaction;
 use futures::future::try_join_all;
 use log::debug;
 use postgres_query::FromSqlRow;
@@ -7,10 +8,29 @@ use serde::{Deserialize, Serialize};
 use stack_string::StackString;
 use std::{fmt, path::Path};
 
-use crate::{config::Config, movie_collection::MovieCollection, pgpool::PgPool};
+use crate::{
+    config::Config,
+    events::Event,
+    movie_collection::{MovieCollection, MovieCollectionRow},
+    offline_archive::OfflineArchiveEntry,
+    pgpool::PgPool,
+    query_builder::QueryBuilder,
+    tombstones::Tombstone,
+};
 
 use crate::utils::{option_string_wrapper, parse_file_stem};
 
+const QUEUE_LOCK_KEY: i64 = 0x6d6f_7669_655f_7175;
+
+pub const DEFAULT_QUEUE: &str = "default";
+
+/// Inserting at an index past the end appends, this always does.
+pub const QUEUE_END: i32 = i32::MAX;
+
+/// Queue and watch state that isn't tied to a web user, this is what the
+/// command line tools and the trakt sync operate on.
+pub const DEFAULT_USER: &str = "";
+
 #[derive(Default, Serialize)]
 pub struct MovieQueueResult {
     pub idx: i32,
@@ -38,6 +58,8 @@ impl fmt::Display for MovieQueueResult {
 #[derive(Clone)]
 pub struct MovieQueueDB {
     pool: PgPool,
+    user: StackString,
+    queue: StackString,
 }
 
 impl Default for MovieQueueDB {
@@ -50,54 +72,79 @@ impl MovieQueueDB {
     pub fn new() -> Self {
         let config = Config::with_config().expect("Init config failed");
         Self {
-            pool: PgPool::new(&config.pgurl),
+            pool: PgPool::new(&config.pgurl).expect("Init pool failed"),
+            user: DEFAULT_USER.into(),
+            queue: DEFAULT_QUEUE.into(),
         }
     }
 
     pub fn with_pool(pool: &PgPool) -> Self {
-        Self { pool: pool.clone() }
+        Self::with_queue(pool, DEFAULT_QUEUE)
     }
 
-    pub async fn remove_from_queue_by_idx(&self, idx: i32) -> Result<(), Error> {
-        let mut conn = self.pool.get().await?;
-        let tran = conn.transaction().await?;
+    pub fn with_queue(pool: &PgPool, queue: &str) -> Self {
+        Self::with_user(pool, DEFAULT_USER, queue)
+    }
 
-        let query = r#"SELECT max(idx) FROM movie_queue"#;
-        let max_idx: i32 = tran
-            .query(query, &[])
-            .await?
-            .get(0)
-            .map_or(-1, |r| r.get(0));
-        if idx > max_idx || idx < 0 {
-            return Ok(());
+    pub fn with_user(pool: &PgPool, user: &str, queue: &str) -> Self {
+        Self {
+            pool: pool.clone(),
+            user: user.into(),
+            queue: queue.into(),
         }
-        let diff = max_idx - idx;
+    }
 
-        let query =
-            postgres_query::query!(r#"DELETE FROM movie_queue WHERE idx = $idx"#, idx = idx);
-        tran.execute(query.sql(), query.parameters()).await?;
+    /// For the `_in` methods, which only use the transaction they are given.
+    pub fn for_transaction(user: &str, queue: &str) -> Self {
+        Self::with_user(&PgPool::default(), user, queue)
+    }
 
-        let query = postgres_query::query!(
-            r#"
-                UPDATE movie_queue
-                SET idx = idx + $diff, last_modified = now()
-                WHERE idx > $idx
-            "#,
-            diff = diff,
-            idx = idx
-        );
-        tran.execute(query.sql(), query.parameters()).await?;
+    pub fn get_user(&self) -> &str {
+        self.user.as_str()
+    }
 
+    pub fn get_queue_name(&self) -> &str {
+        self.queue.as_str()
+    }
+
+    pub async fn get_queue_names(&self) -> Result<Vec<(StackString, i64)>, Error> {
         let query = postgres_query::query!(
             r#"
-                UPDATE movie_queue
-                SET idx = idx - $diff - 1, last_modified = now()
-                WHERE idx > $idx
+                SELECT queue_name, count(*) as count
+                FROM movie_queue
+                WHERE user_email = $user
+                GROUP BY queue_name
+                ORDER BY queue_name
             "#,
-            diff = diff,
-            idx = idx
+            user = self.user
         );
-        tran.execute(query.sql(), query.parameters()).await?;
+        self.pool
+            .get()
+            .await?
+            .query(query.sql(), query.parameters())
+            .await?
+            .iter()
+            .map(|row| {
+                let queue_name: StackString = row.try_get("queue_name")?;
+                let count: i64 = row.try_get("count")?;
+                Ok((queue_name, count))
+            })
+            .collect()
+    }
+
+    pub async fn remove_from_queue_by_idx(&self, idx: i32) -> Result<(), Error> {
+        let mut conn = self.pool.get().await?;
+        let tran = conn.transaction().await?;
+        Self::lock_queue(&tran).await?;
+
+        if self
+            .get_collection_idx_at(&tran, &self.queue, idx)
+            .await?
+            .is_none()
+        {
+            return Ok(());
+        }
+        self.remove_at(&tran, &self.queue, idx).await?;
 
         tran.commit().await.map_err(Into::into)
     }
@@ -106,22 +153,26 @@ impl MovieQueueDB {
         &self,
         collection_idx: i32,
     ) -> Result<(), Error> {
-        let query = postgres_query::query!(
-            r#"SELECT idx FROM movie_queue WHERE collection_idx=$idx"#,
-            idx = collection_idx
-        );
-        if let Some(row) = self
-            .pool
-            .get()
-            .await?
-            .query(query.sql(), query.parameters())
-            .await?
-            .get(0)
-        {
-            let idx = row.try_get("idx")?;
-            self.remove_from_queue_by_idx(idx).await?;
+        let mut conn = self.pool.get().await?;
+        let tran = conn.transaction().await?;
+        self.remove_from_queue_by_collection_idx_in(&tran, collection_idx)
+            .await?;
+        tran.commit().await.map_err(Into::into)
+    }
+
+    /// Returns false if the entry wasn't queued.
+    pub async fn remove_from_queue_by_collection_idx_in(
+        &self,
+        tran: &Transaction<'_>,
+        collection_idx: i32,
+    ) -> Result<bool, Error> {
+        Self::lock_queue(tran).await?;
+        if let Some(idx) = self.get_idx_of(tran, &self.queue, collection_idx).await? {
+            self.remove_at(tran, &self.queue, idx).await?;
+            Ok(true)
+        } else {
+            Ok(false)
         }
-        Ok(())
     }
 
     pub async fn remove_from_queue_by_path(&self, path: &str) -> Result<(), Error> {
@@ -136,6 +187,17 @@ impl MovieQueueDB {
 
     pub async fn insert_into_queue(&self, idx: i32, path: &str) -> Result<(), Error> {
         if !Path::new(&path).exists() {
+            if let Some(entry) = OfflineArchiveEntry::find_by_path(path, &self.pool)
+                .await?
+                .pop()
+            {
+                return Err(format_err!(
+                    "{} is only available offline, mount volume {} ({})",
+                    path,
+                    entry.label,
+                    entry.path
+                ));
+            }
             return Err(format_err!("File doesn't exist"));
         }
         let mc = MovieCollection::with_pool(&self.pool)?;
@@ -157,76 +219,341 @@ impl MovieQueueDB {
         idx: i32,
         collection_idx: i32,
     ) -> Result<(), Error> {
-        let query = postgres_query::query!(
-            r#"SELECT idx FROM movie_queue WHERE collection_idx = $idx"#,
-            idx = collection_idx
-        );
-        let current_idx: i32 = self
-            .pool
-            .get()
-            .await?
-            .query(query.sql(), query.parameters())
+        let mut conn = self.pool.get().await?;
+        let tran = conn.transaction().await?;
+        self.insert_into_queue_by_collection_idx_in(&tran, idx, collection_idx)
+            .await?;
+        tran.commit().await.map_err(Into::into)
+    }
+
+    /// Returns where the entry was before, an entry that is already at `idx`
+    /// is left alone. An `idx` past the end of the queue appends, the end is
+    /// looked up under the queue lock so that concurrent appends don't
+    /// collide.
+    pub async fn insert_into_queue_by_collection_idx_in(
+        &self,
+        tran: &Transaction<'_>,
+        idx: i32,
+        collection_idx: i32,
+    ) -> Result<Option<i32>, Error> {
+        Self::lock_queue(tran).await?;
+
+        let current_idx = self.get_idx_of(tran, &self.queue, collection_idx).await?;
+        if current_idx == Some(idx) {
+            return Ok(current_idx);
+        }
+        if let Some(current_idx) = current_idx {
+            self.remove_at(tran, &self.queue, current_idx).await?;
+        }
+        let idx = idx.min(self.get_max_idx(tran, &self.queue).await? + 1);
+
+        debug!("insert {} at {} {}", collection_idx, self.queue, idx);
+        self.insert_at(tran, &self.queue, idx, collection_idx)
+            .await?;
+        Ok(current_idx)
+    }
+
+    /// Move the entry at `from` to position `to`, shifting the entries in between.
+    pub async fn move_in_queue(&self, from: i32, to: i32) -> Result<(), Error> {
+        let mut conn = self.pool.get().await?;
+        let tran = conn.transaction().await?;
+        Self::lock_queue(&tran).await?;
+
+        let collection_idx = self
+            .get_collection_idx_at(&tran, &self.queue, from)
             .await?
-            .iter()
-            .last()
-            .map_or(Ok(-1), |row| row.try_get("idx"))?;
+            .ok_or_else(|| format_err!("No queue entry at {}", from))?;
+        let to = to.max(self.get_min_idx(&tran, &self.queue).await?);
+        let to = to.min(self.get_max_idx(&tran, &self.queue).await?);
+        if from != to {
+            self.remove_at(&tran, &self.queue, from).await?;
+            self.insert_at(&tran, &self.queue, to, collection_idx)
+                .await?;
+        }
 
-        if current_idx != -1 {
-            self.remove_from_queue_by_idx(current_idx).await?;
+        tran.commit().await.map_err(Into::into)
+    }
+
+    pub async fn swap(&self, idx0: i32, idx1: i32) -> Result<(), Error> {
+        let mut conn = self.pool.get().await?;
+        let tran = conn.transaction().await?;
+        Self::lock_queue(&tran).await?;
+
+        for idx in &[idx0, idx1] {
+            if self
+                .get_collection_idx_at(&tran, &self.queue, *idx)
+                .await?
+                .is_none()
+            {
+                return Err(format_err!("No queue entry at {}", idx));
+            }
+        }
+        if idx0 != idx1 {
+            let query = postgres_query::query!(
+                r#"
+                    UPDATE movie_queue
+                    SET idx = CASE WHEN idx = $idx0 THEN -1 - $idx1 ELSE -1 - $idx0 END,
+                        last_modified = now()
+                    WHERE user_email = $user AND queue_name = $queue
+                        AND (idx = $idx0 OR idx = $idx1)
+                "#,
+                user = self.user,
+                queue = self.queue,
+                idx0 = idx0,
+                idx1 = idx1
+            );
+            tran.execute(query.sql(), query.parameters()).await?;
+            self.restore_negated(&tran, &self.queue).await?;
+            self.queue_changed(&tran, &self.queue).await?;
         }
 
+        tran.commit().await.map_err(Into::into)
+    }
+
+    pub async fn move_to_top(&self, idx: i32) -> Result<(), Error> {
+        self.move_in_queue(idx, i32::MIN).await
+    }
+
+    pub async fn move_to_bottom(&self, idx: i32) -> Result<(), Error> {
+        self.move_in_queue(idx, i32::MAX).await
+    }
+
+    /// Move the entry at `idx` to the end of another queue.
+    pub async fn move_to_queue(&self, idx: i32, target: &str) -> Result<(), Error> {
+        if target == self.queue.as_str() {
+            return self.move_to_bottom(idx).await;
+        }
         let mut conn = self.pool.get().await?;
         let tran = conn.transaction().await?;
+        Self::lock_queue(&tran).await?;
+
+        let collection_idx = self
+            .get_collection_idx_at(&tran, &self.queue, idx)
+            .await?
+            .ok_or_else(|| format_err!("No queue entry at {}", idx))?;
+        self.remove_at(&tran, &self.queue, idx).await?;
+
+        if let Some(current_idx) = self.get_idx_of(&tran, target, collection_idx).await? {
+            self.remove_at(&tran, target, current_idx).await?;
+        }
+        let max_idx = self.get_max_idx(&tran, target).await?;
+        self.insert_at(&tran, target, max_idx + 1, collection_idx)
+            .await?;
+
+        tran.commit().await.map_err(Into::into)
+    }
+
+    /// Serialize all reordering of the queues, the shifts below are only
+    /// consistent if no other transaction renumbers rows concurrently.
+    async fn lock_queue(tran: &Transaction<'_>) -> Result<(), Error> {
+        tran.execute("SELECT pg_advisory_xact_lock($1)", &[&QUEUE_LOCK_KEY])
+            .await?;
+        Ok(())
+    }
+
+    async fn get_collection_idx_at(
+        &self,
+        tran: &Transaction<'_>,
+        queue: &str,
+        idx: i32,
+    ) -> Result<Option<i32>, Error> {
+        let query = postgres_query::query!(
+            r#"
+                SELECT collection_idx
+                FROM movie_queue
+                WHERE user_email = $user AND queue_name = $queue AND idx = $idx
+            "#,
+            user = self.user,
+            queue = queue,
+            idx = idx
+        );
+        tran.query(query.sql(), query.parameters())
+            .await?
+            .get(0)
+            .map(|row| row.try_get("collection_idx"))
+            .transpose()
+            .map_err(Into::into)
+    }
 
-        let query = r#"SELECT max(idx) FROM movie_queue"#;
-        let max_idx: i32 = tran
-            .query(query, &[])
+    async fn get_idx_of(
+        &self,
+        tran: &Transaction<'_>,
+        queue: &str,
+        collection_idx: i32,
+    ) -> Result<Option<i32>, Error> {
+        let query = postgres_query::query!(
+            r#"
+                SELECT idx
+                FROM movie_queue
+                WHERE user_email = $user AND queue_name = $queue
+                    AND collection_idx = $collection_idx
+            "#,
+            user = self.user,
+            queue = queue,
+            collection_idx = collection_idx
+        );
+        tran.query(query.sql(), query.parameters())
             .await?
             .get(0)
-            .map_or(-1, |r| r.get(0));
-        let diff = max_idx - idx + 2;
-        debug!("{} {} {}", max_idx, idx, diff);
+            .map(|row| row.try_get("idx"))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    async fn get_min_idx(&self, tran: &Transaction<'_>, queue: &str) -> Result<i32, Error> {
+        let query = postgres_query::query!(
+            "SELECT min(idx) FROM movie_queue WHERE user_email = $user AND queue_name = $queue",
+            user = self.user,
+            queue = queue
+        );
+        let min_idx: Option<i32> = tran
+            .query_one(query.sql(), query.parameters())
+            .await?
+            .try_get(0)?;
+        Ok(min_idx.unwrap_or(0))
+    }
 
+    async fn get_max_idx(&self, tran: &Transaction<'_>, queue: &str) -> Result<i32, Error> {
+        let query = postgres_query::query!(
+            "SELECT max(idx) FROM movie_queue WHERE user_email = $user AND queue_name = $queue",
+            user = self.user,
+            queue = queue
+        );
+        let max_idx: Option<i32> = tran
+            .query_one(query.sql(), query.parameters())
+            .await?
+            .try_get(0)?;
+        Ok(max_idx.unwrap_or(-1))
+    }
+
+    // (user_email, queue_name, idx) is the primary key and uniqueness is checked row by
+    // row, so rows are shifted by first negating them (-idx - 1) and then
+    // flipping them back.
+    async fn restore_negated(&self, tran: &Transaction<'_>, queue: &str) -> Result<(), Error> {
         let query = postgres_query::query!(
             r#"
                 UPDATE movie_queue
-                SET idx = idx + $diff, last_modified = now()
-                WHERE idx >= $idx
+                SET idx = -idx - 1, last_modified = now()
+                WHERE user_email = $user AND queue_name = $queue AND idx < 0
             "#,
-            diff = diff,
-            idx = idx
+            user = self.user,
+            queue = queue
         );
         tran.execute(query.sql(), query.parameters()).await?;
+        Ok(())
+    }
 
+    /// Moves within and between queues are a `remove_at` followed by an
+    /// `insert_at` in the same transaction, so the tombstone recorded here is
+    /// cleared again unless the entry really left the queue.
+    async fn remove_at(&self, tran: &Transaction<'_>, queue: &str, idx: i32) -> Result<(), Error> {
         let query = postgres_query::query!(
             r#"
-                INSERT INTO movie_queue (idx, collection_idx, last_modified)
-                VALUES ($idx, $collection_idx, now())
+                DELETE FROM movie_queue a
+                USING movie_collection b
+                WHERE a.collection_idx = b.idx
+                AND a.user_email = $user AND a.queue_name = $queue AND a.idx = $idx
+                RETURNING b.path
             "#,
-            idx = idx,
-            collection_idx = collection_idx
+            user = self.user,
+            queue = queue,
+            idx = idx
+        );
+        for row in tran.query(query.sql(), query.parameters()).await? {
+            let path: StackString = row.try_get("path")?;
+            Tombstone::new("movie_queue", &[self.user.as_str(), queue, path.as_str()])
+                .record_in(tran)
+                .await?;
+        }
+
+        let query = postgres_query::query!(
+            r#"
+                UPDATE movie_queue
+                SET idx = -idx
+                WHERE user_email = $user AND queue_name = $queue AND idx > $idx
+            "#,
+            user = self.user,
+            queue = queue,
+            idx = idx
         );
         tran.execute(query.sql(), query.parameters()).await?;
+        self.restore_negated(tran, queue).await?;
+        self.queue_changed(tran, queue).await
+    }
 
+    async fn insert_at(
+        &self,
+        tran: &Transaction<'_>,
+        queue: &str,
+        idx: i32,
+        collection_idx: i32,
+    ) -> Result<(), Error> {
         let query = postgres_query::query!(
             r#"
                 UPDATE movie_queue
-                SET idx = idx - $diff + 1, last_modified = now()
-                WHERE idx > $idx
+                SET idx = -idx - 2
+                WHERE user_email = $user AND queue_name = $queue AND idx >= $idx
             "#,
-            diff = diff,
+            user = self.user,
+            queue = queue,
             idx = idx
         );
         tran.execute(query.sql(), query.parameters()).await?;
+        self.restore_negated(tran, queue).await?;
 
-        tran.commit().await.map_err(Into::into)
+        let query = postgres_query::query!(
+            r#"
+                INSERT INTO movie_queue (user_email, queue_name, idx, collection_idx, last_modified)
+                VALUES ($user, $queue, $idx, $collection_idx, now())
+            "#,
+            user = self.user,
+            queue = queue,
+            idx = idx,
+            collection_idx = collection_idx
+        );
+        tran.execute(query.sql(), query.parameters()).await?;
+
+        let query = postgres_query::query!(
+            "SELECT path FROM movie_collection WHERE idx = $collection_idx",
+            collection_idx = collection_idx
+        );
+        for row in tran.query(query.sql(), query.parameters()).await? {
+            let path: StackString = row.try_get("path")?;
+            Tombstone::clear_in(
+                tran,
+                "movie_queue",
+                &[self.user.as_str(), queue, path.as_str()],
+            )
+            .await?;
+        }
+        self.queue_changed(tran, queue).await
+    }
+
+    async fn queue_changed(&self, tran: &Transaction<'_>, queue: &str) -> Result<(), Error> {
+        Event::QueueChanged {
+            user: self.user.clone(),
+            queue: queue.into(),
+        }
+        .publish_in(tran)
+        .await
     }
 
     pub async fn get_max_queue_index(&self) -> Result<i32, Error> {
-        let query = r#"SELECT max(idx) FROM movie_queue"#;
-        if let Some(row) = self.pool.get().await?.query(query, &[]).await?.get(0) {
-            let max_idx: i32 = row.try_get(0)?;
-            Ok(max_idx)
+        let query = postgres_query::query!(
+            r#"SELECT max(idx) FROM movie_queue WHERE user_email = $user AND queue_name = $queue"#,
+            user = self.user,
+            queue = self.queue
+        );
+        if let Some(row) = self
+            .pool
+            .get()
+            .await?
+            .query(query.sql(), query.parameters())
+            .await?
+            .get(0)
+        {
+            let max_idx: Option<i32> = row.try_get(0)?;
+            Ok(max_idx.unwrap_or(-1))
         } else {
             Ok(-1)
         }
@@ -244,7 +571,12 @@ impl MovieQueueDB {
             istv: Option<bool>,
         }
 
-        let query = postgres_query::query_dyn!(&format!(
+        let mut builder = QueryBuilder::new();
+        builder
+            .and_eq("a.user_email", self.user.clone())
+            .and_eq("a.queue_name", self.queue.clone())
+            .and_contains_any("b.path", patterns);
+        let query = format!(
             r#"
                 SELECT a.idx, b.path, c.link, c.istv
                 FROM movie_queue a
@@ -253,22 +585,14 @@ impl MovieQueueDB {
                 {}
                 ORDER BY a.idx
             "#,
-            if patterns.is_empty() {
-                "".to_string()
-            } else {
-                let constraints: Vec<_> = patterns
-                    .iter()
-                    .map(|p| format!("b.path like '%{}%'", p))
-                    .collect();
-                format!("WHERE {}", constraints.join(" OR "))
-            }
-        ),)?;
+            builder.where_clause()
+        );
 
         let results: Result<Vec<_>, Error> = self
             .pool
             .get()
             .await?
-            .query(query.sql(), &[])
+            .query(query.as_str(), &builder.params())
             .await?
             .iter()
             .map(|row| {
@@ -324,16 +648,19 @@ impl MovieQueueDB {
         Ok(results)
     }
 
+    /// Rows from every user and queue are returned so that all queues replicate,
+    /// in queue order so that importing them in order rebuilds each queue.
     pub async fn get_queue_after_timestamp(
         &self,
         timestamp: DateTime<Utc>,
     ) -> Result<Vec<MovieQueueRow>, Error> {
         let query = postgres_query::query!(
             r#"
-                SELECT a.idx, a.collection_idx, b.path, b.show
+                SELECT a.user_email, a.queue_name, a.idx, a.collection_idx, b.path, b.show
                 FROM movie_queue a
                 JOIN movie_collection b ON a.collection_idx = b.idx
                 WHERE a.last_modified >= $timestamp
+                ORDER BY a.user_email, a.queue_name, a.idx
             "#,
             timestamp = timestamp
         );
@@ -350,8 +677,41 @@ impl MovieQueueDB {
 
 #[derive(Default, Debug, Serialize, Deserialize, FromSqlRow)]
 pub struct MovieQueueRow {
+    #[serde(default)]
+    pub user_email: StackString,
+    #[serde(default = "default_queue_name")]
+    pub queue_name: StackString,
     pub idx: i32,
     pub collection_idx: i32,
     pub path: StackString,
     pub show: StackString,
 }
+
+fn default_queue_name() -> StackString {
+    DEFAULT_QUEUE.into()
+}
+
+impl MovieQueueRow {
+    /// Add an entry exported by another node, `collection_idx` is the remote
+    /// idx so the collection entry is looked up (or added) by path.
+    pub async fn sync_into(&self, pool: &PgPool) -> Result<(), Error> {
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        self.sync_in(&tran).await?;
+        tran.commit().await.map_err(Into::into)
+    }
+
+    /// Returns where the entry was in the queue before, if it was queued.
+    pub async fn sync_in(&self, tran: &Transaction<'_>) -> Result<Option<i32>, Error> {
+        let collection_row = MovieCollectionRow {
+            idx: self.collection_idx,
+            path: self.path.clone(),
+            show: self.show.clone(),
+        };
+        let (collection_idx, _) =
+            MovieCollection::sync_collection_row_in(tran, &collection_row).await?;
+        MovieQueueDB::for_transaction(&self.user_email, &self.queue_name)
+            .insert_into_queue_by_collection_idx_in(tran, self.idx, collection_idx)
+            .await
+    }
+}
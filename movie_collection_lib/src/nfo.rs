@@ -0,0 +1,330 @@
+use anyhow::Error;
+use log::debug;
+use postgres_query::FromSqlRow;
+use stack_string::StackString;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt::{self, Write},
+    path::{Path, PathBuf},
+};
+use tokio::fs;
+
+use crate::{
+    imdb_episodes::ImdbEpisodes, imdb_ratings::ImdbRatings, pgpool::PgPool, utils::parse_file_stem,
+};
+
+const NFO_HEADER: &str = r#"<?xml version="1.0" encoding="UTF-8" standalone="yes" ?>"#;
+
+fn escape_xml(s: &str) -> String {
+    let mut escaped = String::with_capacity(s.len());
+    for c in s.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&apos;"),
+            _ => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn push_element(buf: &mut String, name: &str, value: &str) {
+    writeln!(buf, "    <{0}>{1}</{0}>", name, escape_xml(value)).unwrap();
+}
+
+fn push_rating(buf: &mut String, rating: Option<f64>) {
+    if let Some(rating) = rating.filter(|r| *r >= 0.0) {
+        buf.push_str("    <ratings>\n");
+        buf.push_str("        <rating name=\"imdb\" max=\"10\" default=\"true\">\n");
+        writeln!(buf, "            <value>{:.1}</value>", rating).unwrap();
+        buf.push_str("        </rating>\n");
+        buf.push_str("    </ratings>\n");
+    }
+}
+
+fn push_imdb_id(buf: &mut String, link: &str) {
+    if !link.is_empty() {
+        writeln!(
+            buf,
+            "    <uniqueid type=\"imdb\" default=\"true\">{}</uniqueid>",
+            escape_xml(link)
+        )
+        .unwrap();
+    }
+}
+
+fn show_title(show: &ImdbRatings) -> &str {
+    show.title
+        .as_ref()
+        .map_or(show.show.as_str(), StackString::as_str)
+}
+
+pub fn tvshow_nfo(show: &ImdbRatings) -> String {
+    let mut buf = format!("{}\n<tvshow>\n", NFO_HEADER);
+    push_element(&mut buf, "title", show_title(show));
+    push_rating(&mut buf, show.rating);
+    push_imdb_id(&mut buf, &show.link);
+    buf.push_str("</tvshow>\n");
+    buf
+}
+
+pub fn episode_nfo(show: &ImdbRatings, episode: &ImdbEpisodes) -> String {
+    let mut buf = format!("{}\n<episodedetails>\n", NFO_HEADER);
+    push_element(&mut buf, "title", &episode.eptitle);
+    push_element(&mut buf, "showtitle", show_title(show));
+    push_element(&mut buf, "season", &episode.season.to_string());
+    push_element(&mut buf, "episode", &episode.episode.to_string());
+    push_element(&mut buf, "aired", &episode.airdate.to_string());
+    push_rating(&mut buf, Some(episode.rating));
+    push_imdb_id(&mut buf, &episode.epurl);
+    buf.push_str("</episodedetails>\n");
+    buf
+}
+
+pub fn movie_nfo(show: &ImdbRatings) -> String {
+    let mut buf = format!("{}\n<movie>\n", NFO_HEADER);
+    push_element(&mut buf, "title", show_title(show));
+    push_rating(&mut buf, show.rating);
+    push_imdb_id(&mut buf, &show.link);
+    buf.push_str("</movie>\n");
+    buf
+}
+
+/// `show/season1/show_s01_ep01.mp4` has its `tvshow.nfo` in `show/`, an
+/// episode that isn't in a season directory has it next to it.
+fn show_dir(video: &Path) -> Option<&Path> {
+    let parent = video.parent()?;
+    let in_season_dir = parent
+        .file_name()
+        .map_or(false, |d| d.to_string_lossy().starts_with("season"));
+    if in_season_dir {
+        parent.parent()
+    } else {
+        Some(parent)
+    }
+}
+
+/// Files written by `NfoExporter::export`, entries without a file on disk or
+/// without imdb data are counted as missing.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct NfoSummary {
+    pub written: usize,
+    pub skipped: usize,
+    pub missing: usize,
+}
+
+impl fmt::Display for NfoSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "nfo written {} skipped {} missing {}",
+            self.written, self.skipped, self.missing
+        )
+    }
+}
+
+#[derive(FromSqlRow)]
+struct CollectionEntry {
+    path: StackString,
+    show: StackString,
+}
+
+/// Writes Kodi / Jellyfin style nfo files next to the videos in the
+/// collection: `<file stem>.nfo` with `<episodedetails>` or `<movie>`, which
+/// both read like a `movie.nfo`, and a `tvshow.nfo` per show directory.
+pub struct NfoExporter {
+    pool: PgPool,
+    overwrite: bool,
+}
+
+impl NfoExporter {
+    pub fn new(pool: &PgPool, overwrite: bool) -> Self {
+        Self {
+            pool: pool.clone(),
+            overwrite,
+        }
+    }
+
+    async fn get_shows(&self) -> Result<HashMap<StackString, ImdbRatings>, Error> {
+        let query = r#"
+            SELECT index, show, title, link, rating, istv, source
+            FROM imdb_ratings
+        "#;
+        self.pool
+            .get()
+            .await?
+            .query(query, &[])
+            .await?
+            .iter()
+            .map(|row| {
+                let show = ImdbRatings::from_row(row)?;
+                Ok((show.show.clone(), show))
+            })
+            .collect()
+    }
+
+    async fn get_episodes(&self) -> Result<HashMap<(StackString, i32, i32), ImdbEpisodes>, Error> {
+        let query = r#"
+            SELECT a.show, b.title, a.season, a.episode, a.airdate,
+                   cast(a.rating as double precision) as rating, a.eptitle, a.epurl
+            FROM imdb_episodes a
+            JOIN imdb_ratings b ON a.show = b.show
+        "#;
+        self.pool
+            .get()
+            .await?
+            .query(query, &[])
+            .await?
+            .iter()
+            .map(|row| {
+                let episode = ImdbEpisodes::from_row(row)?;
+                let key = (episode.show.clone(), episode.season, episode.episode);
+                Ok((key, episode))
+            })
+            .collect()
+    }
+
+    async fn get_collection(&self) -> Result<Vec<CollectionEntry>, Error> {
+        let query = "SELECT path, show FROM movie_collection ORDER BY path";
+        self.pool
+            .get()
+            .await?
+            .query(query, &[])
+            .await?
+            .iter()
+            .map(|row| CollectionEntry::from_row(row).map_err(Into::into))
+            .collect()
+    }
+
+    /// Returns whether the file was written.
+    async fn write_nfo(&self, path: &Path, contents: &str) -> Result<bool, Error> {
+        if !self.overwrite && path.exists() {
+            return Ok(false);
+        }
+        debug!("write {:?}", path);
+        fs::write(path, contents).await?;
+        Ok(true)
+    }
+
+    /// Export every collection entry, or only those of `show`.
+    pub async fn export(&self, show: Option<&str>) -> Result<NfoSummary, Error> {
+        let shows = self.get_shows().await?;
+        let episodes = self.get_episodes().await?;
+        let mut show_dirs: HashSet<PathBuf> = HashSet::new();
+        let mut summary = NfoSummary::default();
+
+        for entry in self.get_collection().await? {
+            if show.map_or(false, |s| s != entry.show.as_str()) {
+                continue;
+            }
+            let video = Path::new(entry.path.as_str());
+            let file_stem = match video.file_stem() {
+                Some(file_stem) if video.exists() => file_stem.to_string_lossy(),
+                _ => {
+                    summary.missing += 1;
+                    continue;
+                }
+            };
+            let (show_name, season, episode) = parse_file_stem(&file_stem);
+            let nfo_path = video.with_extension("nfo");
+
+            let written = if season != -1 && episode != -1 {
+                let (imdb_show, imdb_episode) = match (
+                    shows.get(&show_name),
+                    episodes.get(&(show_name.clone(), season, episode)),
+                ) {
+                    (Some(imdb_show), Some(imdb_episode)) => (imdb_show, imdb_episode),
+                    _ => {
+                        summary.missing += 1;
+                        continue;
+                    }
+                };
+                if let Some(dir) = show_dir(video) {
+                    if show_dirs.insert(dir.to_path_buf()) {
+                        let tvshow = dir.join("tvshow.nfo");
+                        if self.write_nfo(&tvshow, &tvshow_nfo(imdb_show)).await? {
+                            summary.written += 1;
+                        } else {
+                            summary.skipped += 1;
+                        }
+                    }
+                }
+                self.write_nfo(&nfo_path, &episode_nfo(imdb_show, imdb_episode))
+                    .await?
+            } else {
+                match shows.get(&entry.show) {
+                    Some(imdb_show) if !imdb_show.istv.unwrap_or(false) => {
+                        self.write_nfo(&nfo_path, &movie_nfo(imdb_show)).await?
+                    }
+                    _ => {
+                        summary.missing += 1;
+                        continue;
+                    }
+                }
+            };
+            if written {
+                summary.written += 1;
+            } else {
+                summary.skipped += 1;
+            }
+        }
+        Ok(summary)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+    use std::path::Path;
+
+    use crate::{
+        imdb_episodes::ImdbEpisodes,
+        imdb_ratings::ImdbRatings,
+        nfo::{episode_nfo, movie_nfo, show_dir},
+    };
+
+    #[test]
+    fn test_show_dir() {
+        assert_eq!(
+            show_dir(Path::new("/tv/the_show/season1/the_show_s01_ep01.mp4")),
+            Some(Path::new("/tv/the_show"))
+        );
+        assert_eq!(
+            show_dir(Path::new("/tv/the_show/the_show_s01_ep01.mp4")),
+            Some(Path::new("/tv/the_show"))
+        );
+    }
+
+    #[test]
+    fn test_nfo() {
+        let show = ImdbRatings {
+            show: "the_show".into(),
+            title: Some("Law & Order".into()),
+            link: "tt0098844".into(),
+            rating: Some(7.84),
+            ..ImdbRatings::default()
+        };
+        let movie = movie_nfo(&show);
+        assert!(movie.contains("<title>Law &amp; Order</title>"));
+        assert!(movie.contains("<value>7.8</value>"));
+        assert!(movie.contains(r#"<uniqueid type="imdb" default="true">tt0098844</uniqueid>"#));
+
+        let episode = ImdbEpisodes {
+            show: "the_show".into(),
+            season: 2,
+            episode: 5,
+            airdate: NaiveDate::from_ymd(1991, 10, 1),
+            rating: -1.0,
+            eptitle: "<Pilot>".into(),
+            ..ImdbEpisodes::new()
+        };
+        let episode = episode_nfo(&show, &episode);
+        assert!(episode.contains("<title>&lt;Pilot&gt;</title>"));
+        assert!(episode.contains("<season>2</season>"));
+        assert!(episode.contains("<aired>1991-10-01</aired>"));
+        assert!(!episode.contains("<ratings>"));
+        assert!(!episode.contains("<uniqueid"));
+    }
+}
@@ -0,0 +1,305 @@
+use anyhow::{format_err, Error};
+use postgres_query::FromSqlRow;
+use serde::{Deserialize, Serialize};
+use stack_string::StackString;
+use std::{fmt, fs, path::Path};
+
+use crate::{
+    pgpool::PgPool,
+    query_builder::QueryBuilder,
+    utils::{option_string_wrapper, walk_directory},
+};
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize, FromSqlRow)]
+pub struct OfflineVolume {
+    pub id: i32,
+    pub label: StackString,
+    pub description: Option<StackString>,
+}
+
+impl fmt::Display for OfflineVolume {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.id,
+            self.label,
+            option_string_wrapper(&self.description)
+        )
+    }
+}
+
+impl OfflineVolume {
+    pub async fn get_by_label(label: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+        let query = postgres_query::query!(
+            "SELECT id, label, description FROM offline_volumes WHERE label = $label",
+            label = label
+        );
+        if let Some(row) = pool
+            .get()
+            .await?
+            .query(query.sql(), query.parameters())
+            .await?
+            .get(0)
+        {
+            Ok(Some(Self::from_row(row)?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    pub async fn get_volumes(pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = "SELECT id, label, description FROM offline_volumes ORDER BY label";
+        pool.get()
+            .await?
+            .query(query, &[])
+            .await?
+            .iter()
+            .map(|row| Ok(Self::from_row(row)?))
+            .collect()
+    }
+
+    /// Register a volume by label, updating the description if it already exists.
+    pub async fn register(
+        label: &str,
+        description: Option<&str>,
+        pool: &PgPool,
+    ) -> Result<Self, Error> {
+        let query = postgres_query::query!(
+            r#"
+                INSERT INTO offline_volumes (label, description, last_modified)
+                VALUES ($label, $description, now())
+                ON CONFLICT (label) DO UPDATE
+                SET description=EXCLUDED.description, last_modified=now()
+                RETURNING id, label, description
+            "#,
+            label = label,
+            description = description
+        );
+        let row = pool
+            .get()
+            .await?
+            .query_one(query.sql(), query.parameters())
+            .await?;
+        Self::from_row(&row).map_err(Into::into)
+    }
+
+    /// Replace the indexed contents of this volume with the video files found
+    /// under `mount_path`, paths are stored relative to the mount point.
+    pub async fn index_volume<T: AsRef<str>>(
+        &self,
+        mount_path: &Path,
+        suffixes: &[T],
+        pool: &PgPool,
+    ) -> Result<usize, Error> {
+        if !mount_path.exists() {
+            return Err(format_err!("{:?} is not mounted", mount_path));
+        }
+        let entries: Result<Vec<(StackString, i64)>, Error> = walk_directory(mount_path, suffixes)?
+            .into_iter()
+            .filter_map(|f| volume_path(mount_path, &f, suffixes).map(|path| (f, path)))
+            .map(|(f, path)| {
+                let file_size = fs::metadata(&f)?.len() as i64;
+                Ok((path, file_size))
+            })
+            .collect();
+        let entries = entries?;
+
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+
+        let query = postgres_query::query!(
+            "DELETE FROM movie_collection_on_dvd WHERE volume_id = $volume_id",
+            volume_id = self.id
+        );
+        tran.execute(query.sql(), query.parameters()).await?;
+
+        for (path, file_size) in &entries {
+            let query = postgres_query::query!(
+                r#"
+                    INSERT INTO movie_collection_on_dvd (path, file_size, volume_id, last_modified)
+                    VALUES ($path, $file_size, $volume_id, now())
+                "#,
+                path = path,
+                file_size = file_size,
+                volume_id = self.id
+            );
+            tran.execute(query.sql(), query.parameters()).await?;
+        }
+
+        tran.commit().await?;
+        Ok(entries.len())
+    }
+}
+
+/// The path of `file` as stored for a volume mounted at `mount_path`, `None`
+/// if it isn't a video file under the mount point.
+fn volume_path<T: AsRef<str>>(
+    mount_path: &Path,
+    file: &Path,
+    suffixes: &[T],
+) -> Option<StackString> {
+    let ext = file.extension()?.to_string_lossy();
+    if !suffixes.iter().any(|s| s.as_ref() == ext) {
+        return None;
+    }
+    let path = file.strip_prefix(mount_path).ok()?;
+    if path.as_os_str().is_empty() {
+        return None;
+    }
+    Some(path.to_string_lossy().into_owned().into())
+}
+
+#[derive(Default, Debug, Clone, Serialize, Deserialize, FromSqlRow)]
+pub struct OfflineArchiveEntry {
+    pub id: i32,
+    pub label: StackString,
+    pub path: StackString,
+    pub file_size: Option<i64>,
+}
+
+impl fmt::Display for OfflineArchiveEntry {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} {} {}",
+            self.label,
+            self.path,
+            self.file_size.unwrap_or(-1)
+        )
+    }
+}
+
+impl OfflineArchiveEntry {
+    pub async fn search_offline_archive<T: AsRef<str>>(
+        search_strs: &[T],
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let mut builder = QueryBuilder::new();
+        builder.and_contains_any("a.path", search_strs);
+        let query = format!(
+            r#"
+                SELECT a.id, b.label, a.path, a.file_size
+                FROM movie_collection_on_dvd a
+                JOIN offline_volumes b ON a.volume_id = b.id
+                {}
+                ORDER BY b.label, a.path
+            "#,
+            builder.where_clause()
+        );
+        pool.get()
+            .await?
+            .query(query.as_str(), &builder.params())
+            .await?
+            .iter()
+            .map(|row| Ok(Self::from_row(row)?))
+            .collect()
+    }
+
+    /// Find archived copies of a file, matching on the file name only since
+    /// the directory layout on the volume may differ from the live collection.
+    pub async fn find_by_file_name(file_name: &str, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        let query = postgres_query::query!(
+            r#"
+                SELECT a.id, b.label, a.path, a.file_size
+                FROM movie_collection_on_dvd a
+                JOIN offline_volumes b ON a.volume_id = b.id
+                WHERE a.path = $file_name
+                    OR right(a.path, length($file_name) + 1) = '/' || $file_name
+                ORDER BY b.label
+            "#,
+            file_name = file_name
+        );
+        pool.get()
+            .await?
+            .query(query.sql(), query.parameters())
+            .await?
+            .iter()
+            .map(|row| Ok(Self::from_row(row)?))
+            .collect()
+    }
+
+    pub async fn find_by_path(path: &str, pool: &PgPool) -> Result<Vec<Self>, Error> {
+        match Path::new(path).file_name() {
+            Some(file_name) => Self::find_by_file_name(&file_name.to_string_lossy(), pool).await,
+            None => Ok(Vec::new()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use std::path::Path;
+
+    use crate::{
+        config::Config,
+        offline_archive::{volume_path, OfflineArchiveEntry, OfflineVolume},
+        pgpool::PgPool,
+    };
+
+    #[test]
+    fn test_volume_path() {
+        let mount = Path::new("/media/dvd");
+        let suffixes = ["mp4", "mkv"];
+        assert_eq!(
+            volume_path(
+                mount,
+                Path::new("/media/dvd/tv/the_show_s01_ep01.mp4"),
+                &suffixes
+            ),
+            Some("tv/the_show_s01_ep01.mp4".into())
+        );
+        assert_eq!(
+            volume_path(mount, Path::new("/media/dvd/notes.txt"), &suffixes),
+            None
+        );
+        assert_eq!(
+            volume_path(mount, Path::new("/media/other/a_movie.mkv"), &suffixes),
+            None
+        );
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_find_by_file_name_is_exact() -> Result<(), Error> {
+        let config = Config::with_config()?;
+        let pool = PgPool::new(&config.pgurl)?;
+        let volume = OfflineVolume::register("test_volume_find", None, &pool).await?;
+        let query = postgres_query::query!(
+            r#"
+                INSERT INTO movie_collection_on_dvd (path, file_size, volume_id, last_modified)
+                VALUES ('tv/theXshow_s01_ep01.mp4', 1, $volume_id, now()),
+                       ('tv/the_show_s01_ep01.mp4', 1, $volume_id, now())
+            "#,
+            volume_id = volume.id
+        );
+        pool.get()
+            .await?
+            .execute(query.sql(), query.parameters())
+            .await?;
+
+        let result = OfflineArchiveEntry::find_by_path("/tv/the_show_s01_ep01.mp4", &pool).await;
+
+        let query = postgres_query::query!(
+            "DELETE FROM movie_collection_on_dvd WHERE volume_id = $volume_id",
+            volume_id = volume.id
+        );
+        pool.get()
+            .await?
+            .execute(query.sql(), query.parameters())
+            .await?;
+        let query = postgres_query::query!(
+            "DELETE FROM offline_volumes WHERE id = $volume_id",
+            volume_id = volume.id
+        );
+        pool.get()
+            .await?
+            .execute(query.sql(), query.parameters())
+            .await?;
+
+        let paths: Vec<_> = result?.into_iter().map(|e| e.path.to_string()).collect();
+        assert_eq!(paths, vec!["tv/the_show_s01_ep01.mp4".to_string()]);
+        Ok(())
+    }
+}
@@ -1,5 +1,6 @@
 use anyhow::Error;
 use chrono::NaiveDate;
+use serde::Serialize;
 use stack_string::StackString;
 use std::collections::HashMap;
 use structopt::StructOpt;
@@ -9,6 +10,20 @@ use crate::{
     movie_collection::MovieCollection, pgpool::PgPool, trakt_utils::WatchListMap,
 };
 
+#[derive(Serialize)]
+pub struct ImdbColumn {
+    pub value: StackString,
+    /// Imdb ids are rendered as links
+    pub is_link: bool,
+}
+
+#[derive(Serialize)]
+pub struct ImdbSearchRow {
+    pub imdb_url: StackString,
+    pub on_watchlist: bool,
+    pub columns: Vec<ImdbColumn>,
+}
+
 #[derive(StructOpt, Default, Debug)]
 /// Parse IMDB.com
 pub struct ParseImdbOptions {
@@ -251,50 +266,32 @@ impl ParseImdb {
         &self,
         opts: &ParseImdbOptions,
         watchlist: &WatchListMap,
-    ) -> Result<StackString, Error> {
-        let button_add = format!(
-            "{}{}",
-            r#"<td><button type="submit" id="ID" "#,
-            r#"onclick="watchlist_add('SHOW');">add to watchlist</button></td>"#
-        );
-        let button_rm = format!(
-            "{}{}",
-            r#"<td><button type="submit" id="ID" "#,
-            r#"onclick="watchlist_rm('SHOW');">remove from watchlist</button></td>"#
-        );
-
-        let output: Vec<_> = self
+    ) -> Result<Vec<ImdbSearchRow>, Error> {
+        let output = self
             .parse_imdb_worker(&opts)
             .await?
             .into_iter()
             .map(|line| {
-                let mut imdb_url: StackString = "".into();
-                let tmp: Vec<_> =
-                    line.into_iter()
-                        .map(|imdb_url_| {
-                            if imdb_url_.starts_with("tt") {
-                                imdb_url = imdb_url_;
-                                format!(
-                                r#"<a href="https://www.imdb.com/title/{}" target="_blank">{}</a>"#,
-                                imdb_url, imdb_url
-                            ).into()
-                            } else {
-                                imdb_url_
-                            }
-                        })
-                        .collect();
-                format!(
-                    "<tr><td>{}</td><td>{}</td></tr>",
-                    tmp.join("</td><td>"),
-                    if watchlist.contains_key(&imdb_url) {
-                        button_rm.replace("SHOW", &imdb_url)
-                    } else {
-                        button_add.replace("SHOW", &imdb_url)
-                    }
-                )
+                let imdb_url = line
+                    .iter()
+                    .filter(|s| s.starts_with("tt"))
+                    .last()
+                    .cloned()
+                    .unwrap_or_else(|| "".into());
+                let columns = line
+                    .into_iter()
+                    .map(|value| ImdbColumn {
+                        is_link: value.starts_with("tt"),
+                        value,
+                    })
+                    .collect();
+                ImdbSearchRow {
+                    on_watchlist: watchlist.contains_key(&imdb_url),
+                    imdb_url,
+                    columns,
+                }
             })
             .collect();
-
-        Ok(output.join("\n").into())
+        Ok(output)
     }
 }
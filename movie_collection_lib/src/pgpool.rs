@@ -5,6 +5,8 @@ use stack_string::StackString;
 use std::fmt;
 use tokio_postgres::{error::Error as PgError, Config as PgConfig, NoTls};
 
+use crate::storage::is_sqlite_url;
+
 #[derive(Clone, Default)]
 pub struct PgPool {
     pgurl: StackString,
@@ -24,12 +26,22 @@ impl PartialEq for PgPool {
 }
 
 impl PgPool {
-    pub fn new(pgurl: &str) -> Self {
-        let pgconf: PgConfig = pgurl.parse().expect("Failed to parse Url");
+    /// Fails rather than panics on a url that isn't postgres, so that a
+    /// `sqlite://` pgurl gets a clear error from the tools that need postgres.
+    pub fn new(pgurl: &str) -> Result<Self, Error> {
+        if is_sqlite_url(pgurl) {
+            return Err(format_err!(
+                "{} is a sqlite url, only make-queue can run on sqlite",
+                pgurl
+            ));
+        }
+        let pgconf: PgConfig = pgurl
+            .parse()
+            .map_err(|e| format_err!("Failed to parse postgres url: {}", e))?;
 
         let mut config = Config::default();
 
-        if let tokio_postgres::config::Host::Tcp(s) = &pgconf.get_hosts()[0] {
+        if let Some(tokio_postgres::config::Host::Tcp(s)) = pgconf.get_hosts().get(0) {
             config.host.replace(s.to_string());
         }
         if let Some(u) = pgconf.get_user() {
@@ -44,14 +56,13 @@ impl PgPool {
             config.dbname.replace(db.to_string());
         }
 
-        Self {
+        let pool = config
+            .create_pool(NoTls)
+            .map_err(|e| format_err!("Failed to create pool: {}", e))?;
+        Ok(Self {
             pgurl: pgurl.into(),
-            pool: Some(
-                config
-                    .create_pool(NoTls)
-                    .unwrap_or_else(|_| panic!("Failed to create pool {}", pgurl)),
-            ),
-        }
+            pool: Some(pool),
+        })
     }
 
     pub async fn get(&self) -> Result<Object<ClientWrapper, PgError>, Error> {
@@ -0,0 +1,215 @@
+use anyhow::Error;
+use postgres_query::FromSqlRow;
+use serde::{Deserialize, Serialize};
+use stack_string::StackString;
+use std::path::Path;
+
+use crate::{
+    movie_queue::{MovieQueueDB, DEFAULT_QUEUE},
+    pgpool::PgPool,
+    trakt_utils::{watched_action_http_worker, TraktActions},
+    utils::parse_file_stem,
+};
+
+/// Fraction of the duration after which an item counts as watched.
+pub const WATCHED_FRACTION: f64 = 0.9;
+
+/// Positions closer than this to the start aren't worth offering a resume.
+const MIN_RESUME_SECONDS: f64 = 30.0;
+
+#[derive(FromSqlRow, Serialize, Deserialize, Default, Debug, Clone, PartialEq)]
+pub struct PlaybackPosition {
+    pub collection_idx: i32,
+    pub position: f64,
+    pub duration: f64,
+    pub watched: bool,
+}
+
+impl PlaybackPosition {
+    pub async fn get(
+        pool: &PgPool,
+        collection_idx: i32,
+        user: &str,
+    ) -> Result<Option<Self>, Error> {
+        let query = postgres_query::query!(
+            r#"
+                SELECT collection_idx, position, duration, watched
+                FROM playback_position
+                WHERE collection_idx = $collection_idx AND user_email = $user
+            "#,
+            collection_idx = collection_idx,
+            user = user
+        );
+        pool.get()
+            .await?
+            .query(query.sql(), query.parameters())
+            .await?
+            .get(0)
+            .map(|row| Self::from_row(row).map_err(Into::into))
+            .transpose()
+    }
+
+    async fn upsert(&self, pool: &PgPool, user: &str) -> Result<(), Error> {
+        let query = postgres_query::query!(
+            r#"
+                INSERT INTO playback_position
+                (user_email, collection_idx, position, duration, watched, last_modified)
+                VALUES ($user, $collection_idx, $position, $duration, $watched, now())
+                ON CONFLICT (user_email, collection_idx) DO UPDATE
+                SET position=EXCLUDED.position, duration=EXCLUDED.duration,
+                    watched=EXCLUDED.watched, last_modified=now()
+            "#,
+            user = user,
+            collection_idx = self.collection_idx,
+            position = self.position,
+            duration = self.duration,
+            watched = self.watched
+        );
+        pool.get()
+            .await?
+            .execute(query.sql(), query.parameters())
+            .await
+            .map(|_| ())
+            .map_err(Into::into)
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.duration > 0.0 && self.position >= WATCHED_FRACTION * self.duration
+    }
+
+    /// Position to offer as "resume from", `None` for items that were
+    /// finished or barely started.
+    pub fn resume_position(&self) -> Option<f64> {
+        if self.is_finished() || self.position < MIN_RESUME_SECONDS {
+            None
+        } else {
+            Some(self.position)
+        }
+    }
+}
+
+/// Format seconds as `HH:MM:SS`.
+pub fn format_position(seconds: f64) -> StackString {
+    let seconds = seconds.max(0.0) as u64;
+    format!(
+        "{:02}:{:02}:{:02}",
+        seconds / 3600,
+        (seconds / 60) % 60,
+        seconds % 60
+    )
+    .into()
+}
+
+#[derive(FromSqlRow)]
+struct CollectionLink {
+    path: StackString,
+    link: Option<StackString>,
+    istv: Option<bool>,
+}
+
+async fn mark_watched(
+    pool: &PgPool,
+    collection_idx: i32,
+    user: &str,
+) -> Result<Option<StackString>, Error> {
+    let query = postgres_query::query!(
+        r#"
+            SELECT a.path, b.link, b.istv
+            FROM movie_collection a
+            LEFT JOIN imdb_ratings b ON a.show_id = b.index
+            WHERE a.idx = $collection_idx
+        "#,
+        collection_idx = collection_idx
+    );
+    let row = match pool
+        .get()
+        .await?
+        .query(query.sql(), query.parameters())
+        .await?
+        .get(0)
+    {
+        Some(row) => CollectionLink::from_row(row)?,
+        None => return Ok(None),
+    };
+    // Files that aren't matched to an imdb entry can't be marked watched
+    let link = match row.link {
+        Some(link) => link,
+        None => return Ok(None),
+    };
+    let istv = row.istv.unwrap_or(false);
+    let (season, episode) = if istv {
+        let file_stem = Path::new(row.path.as_str())
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let (_, season, episode) = parse_file_stem(&file_stem);
+        (season, episode)
+    } else {
+        (-1, -1)
+    };
+    if istv && (season == -1 || episode == -1) {
+        return Ok(None);
+    }
+    watched_action_http_worker(pool, TraktActions::Add, &link, season, episode, user)
+        .await
+        .map(Some)
+}
+
+/// Store the position reported by the player. The first time playback passes
+/// `WATCHED_FRACTION` the item is marked watched the same way the watched
+/// buttons do, and optionally removed from the user's default queue.
+pub async fn update_playback_position(
+    pool: &PgPool,
+    collection_idx: i32,
+    position: f64,
+    duration: f64,
+    user: &str,
+    remove_from_queue: bool,
+) -> Result<PlaybackPosition, Error> {
+    let previous = PlaybackPosition::get(pool, collection_idx, user).await?;
+    let mut current = PlaybackPosition {
+        collection_idx,
+        position,
+        duration,
+        watched: previous.map_or(false, |p| p.watched),
+    };
+    if !current.watched && current.is_finished() {
+        mark_watched(pool, collection_idx, user).await?;
+        if remove_from_queue {
+            MovieQueueDB::with_user(pool, user, DEFAULT_QUEUE)
+                .remove_from_queue_by_collection_idx(collection_idx)
+                .await?;
+        }
+        current.watched = true;
+    }
+    current.upsert(pool, user).await?;
+    Ok(current)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::playback_position::{format_position, PlaybackPosition};
+
+    #[test]
+    fn test_format_position() {
+        assert_eq!(format_position(2533.7).as_str(), "00:42:13");
+        assert_eq!(format_position(36000.0).as_str(), "10:00:00");
+        assert_eq!(format_position(-5.0).as_str(), "00:00:00");
+    }
+
+    #[test]
+    fn test_resume_position() {
+        let mut pos = PlaybackPosition {
+            collection_idx: 1,
+            position: 2533.0,
+            duration: 3600.0,
+            watched: false,
+        };
+        assert_eq!(pos.resume_position(), Some(2533.0));
+        pos.position = 3300.0;
+        assert!(pos.is_finished());
+        assert_eq!(pos.resume_position(), None);
+        pos.position = 10.0;
+        assert_eq!(pos.resume_position(), None);
+    }
+}
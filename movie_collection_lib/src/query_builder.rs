@@ -0,0 +1,137 @@
+use std::fmt::Write;
+use tokio_postgres::types::ToSql;
+
+/// Escape the `LIKE` wildcards of `s` and match it anywhere in a column.
+pub fn contains_pattern(s: &str) -> String {
+    let mut pattern = String::with_capacity(s.len() + 2);
+    pattern.push('%');
+    for c in s.chars() {
+        if c == '%' || c == '_' || c == '\\' {
+            pattern.push('\\');
+        }
+        pattern.push(c);
+    }
+    pattern.push('%');
+    pattern
+}
+
+/// Filters for queries whose `WHERE` depends on the request. Values are
+/// only ever bound as parameters, the sql holds placeholders `$1`, `$2`, ...
+/// in the order the values were added. Column names and fixed conditions
+/// are `&'static str` so that they can't come from input.
+#[derive(Default)]
+pub struct QueryBuilder {
+    conditions: Vec<String>,
+    params: Vec<Box<dyn ToSql + Sync + Send>>,
+}
+
+impl QueryBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Bind a value and return its placeholder.
+    pub fn bind<T: ToSql + Sync + Send + 'static>(&mut self, value: T) -> String {
+        self.params.push(Box::new(value));
+        format!("${}", self.params.len())
+    }
+
+    /// A condition without values, e.g. `c.istv`.
+    pub fn and(&mut self, condition: &'static str) -> &mut Self {
+        self.conditions.push(condition.to_string());
+        self
+    }
+
+    pub fn and_eq<T: ToSql + Sync + Send + 'static>(
+        &mut self,
+        column: &'static str,
+        value: T,
+    ) -> &mut Self {
+        let placeholder = self.bind(value);
+        self.conditions
+            .push(format!("{} = {}", column, placeholder));
+        self
+    }
+
+    /// `column` contains any of `patterns`, no condition if there are none.
+    pub fn and_contains_any<T: AsRef<str>>(
+        &mut self,
+        column: &'static str,
+        patterns: &[T],
+    ) -> &mut Self {
+        if patterns.is_empty() {
+            return self;
+        }
+        let mut condition = String::from("(");
+        for (i, pattern) in patterns.iter().enumerate() {
+            let placeholder = self.bind(contains_pattern(pattern.as_ref()));
+            if i > 0 {
+                condition.push_str(" OR ");
+            }
+            write!(condition, "{} LIKE {}", column, placeholder).unwrap();
+        }
+        condition.push(')');
+        self.conditions.push(condition);
+        self
+    }
+
+    /// `WHERE` and the conditions joined by `AND`, empty without conditions.
+    pub fn where_clause(&self) -> String {
+        if self.conditions.is_empty() {
+            "".to_string()
+        } else {
+            format!("WHERE {}", self.conditions.join(" AND "))
+        }
+    }
+
+    pub fn params(&self) -> Vec<&(dyn ToSql + Sync)> {
+        self.params
+            .iter()
+            .map(|p| p.as_ref() as &(dyn ToSql + Sync))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::query_builder::{contains_pattern, QueryBuilder};
+
+    #[test]
+    fn test_contains_pattern() {
+        assert_eq!(contains_pattern("the_show"), r"%the\_show%");
+        assert_eq!(contains_pattern(r"100%\"), r"%100\%\\%");
+        assert_eq!(contains_pattern("it's"), "%it's%");
+    }
+
+    #[test]
+    fn test_hostile_input_is_bound() {
+        let hostile = [
+            "'; DROP TABLE movie_queue; --",
+            "x' OR '1'='1",
+            "grey's_anatomy",
+        ];
+        let mut builder = QueryBuilder::new();
+        builder
+            .and_eq("a.user_email", "robert'); --".to_string())
+            .and_contains_any("b.path", &hostile)
+            .and("c.istv");
+        let clause = builder.where_clause();
+        assert_eq!(
+            clause,
+            "WHERE a.user_email = $1 AND (b.path LIKE $2 OR b.path LIKE $3 OR b.path LIKE $4) \
+             AND c.istv"
+        );
+        for s in &hostile {
+            assert!(!clause.contains(s));
+        }
+        assert_eq!(builder.params().len(), 4);
+    }
+
+    #[test]
+    fn test_empty_filters() {
+        let mut builder = QueryBuilder::new();
+        builder.and_contains_any::<&str>("b.path", &[]);
+        assert_eq!(builder.where_clause(), "");
+        assert!(builder.params().is_empty());
+    }
+}
@@ -0,0 +1,428 @@
+use anyhow::{format_err, Error};
+use chrono::{DateTime, TimeZone, Utc};
+use log::debug;
+use reqwest::{Client, RequestBuilder, Url};
+use serde::{de::DeserializeOwned, Serialize};
+use stack_string::StackString;
+use std::{collections::HashMap, fmt};
+
+use crate::{
+    imdb_episodes::ImdbEpisodes,
+    imdb_ratings::ImdbRatings,
+    movie_collection::{LastModifiedResponse, MovieCollection, MovieCollectionRow},
+    movie_queue::{MovieQueueDB, MovieQueueRow},
+    pgpool::PgPool,
+    tombstones::Tombstone,
+};
+
+/// Tables kept in sync, in the order they are applied: collection entries
+/// reference imdb_ratings, queue entries reference the collection and
+/// deletions come last so they win over rows in the same batch.
+pub const SYNC_TABLES: [&str; 5] = [
+    "imdb_ratings",
+    "imdb_episodes",
+    "movie_collection",
+    "movie_queue",
+    "tombstones",
+];
+
+/// How far `remote` has been synced for one table, timestamps are the
+/// `last_modified` of the side the rows came from.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SyncMark {
+    pub remote: StackString,
+    pub table_name: StackString,
+    pub last_pulled: Option<DateTime<Utc>>,
+    pub last_pushed: Option<DateTime<Utc>>,
+}
+
+impl SyncMark {
+    pub async fn get(pool: &PgPool, remote: &str, table_name: &str) -> Result<Self, Error> {
+        let query = postgres_query::query!(
+            r#"
+                SELECT last_pulled, last_pushed
+                FROM remote_sync_marks
+                WHERE remote = $remote AND table_name = $table_name
+            "#,
+            remote = remote,
+            table_name = table_name
+        );
+        let rows = pool
+            .get()
+            .await?
+            .query(query.sql(), query.parameters())
+            .await?;
+        let mut mark = Self {
+            remote: remote.into(),
+            table_name: table_name.into(),
+            last_pulled: None,
+            last_pushed: None,
+        };
+        if let Some(row) = rows.get(0) {
+            mark.last_pulled = row.try_get("last_pulled")?;
+            mark.last_pushed = row.try_get("last_pushed")?;
+        }
+        Ok(mark)
+    }
+
+    pub async fn upsert(&self, pool: &PgPool) -> Result<(), Error> {
+        let query = postgres_query::query!(
+            r#"
+                INSERT INTO remote_sync_marks (remote, table_name, last_pulled, last_pushed)
+                VALUES ($remote, $table_name, $last_pulled, $last_pushed)
+                ON CONFLICT (remote, table_name) DO UPDATE
+                SET last_pulled=EXCLUDED.last_pulled, last_pushed=EXCLUDED.last_pushed
+            "#,
+            remote = self.remote,
+            table_name = self.table_name,
+            last_pulled = self.last_pulled,
+            last_pushed = self.last_pushed
+        );
+        pool.get()
+            .await?
+            .execute(query.sql(), query.parameters())
+            .await?;
+        Ok(())
+    }
+
+    /// Local rows modified after the returned time go to the remote, without a
+    /// mark that is everything newer than the remote's `last_modified`.
+    pub fn push_since(
+        &self,
+        local: &HashMap<String, DateTime<Utc>>,
+        remote: &HashMap<String, DateTime<Utc>>,
+    ) -> Option<DateTime<Utc>> {
+        let table = self.table_name.as_str();
+        let since = self
+            .last_pushed
+            .or_else(|| remote.get(table).copied())
+            .unwrap_or_else(|| Utc.timestamp(0, 0));
+        local.get(table).filter(|t| **t > since).map(|_| since)
+    }
+
+    /// Remote rows modified after the returned time are pulled, without a
+    /// mark that is everything newer than the local `last_modified`.
+    pub fn pull_since(
+        &self,
+        local: &HashMap<String, DateTime<Utc>>,
+        remote: &HashMap<String, DateTime<Utc>>,
+    ) -> Option<DateTime<Utc>> {
+        let table = self.table_name.as_str();
+        let since = self
+            .last_pulled
+            .or_else(|| local.get(table).copied())
+            .unwrap_or_else(|| Utc.timestamp(0, 0));
+        remote.get(table).filter(|t| **t > since).map(|_| since)
+    }
+
+    /// Record how far both sides are synced, `remote` has to be read after
+    /// pushing and `local` after pulling so neither side's writes from this
+    /// sync are sent back on the next.
+    pub fn update(
+        &mut self,
+        local: &HashMap<String, DateTime<Utc>>,
+        remote: &HashMap<String, DateTime<Utc>>,
+    ) {
+        let table = self.table_name.as_str();
+        if let Some(t) = remote.get(table) {
+            self.last_pulled = Some(*t);
+        }
+        if let Some(t) = local.get(table) {
+            self.last_pushed = Some(*t);
+        }
+    }
+}
+
+/// Rows moved for one table by `RemoteSync::sync`.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct SyncSummary {
+    pub table_name: StackString,
+    pub pushed: usize,
+    pub pulled: usize,
+}
+
+impl fmt::Display for SyncSummary {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} pushed {} pulled {}",
+            self.table_name, self.pushed, self.pulled
+        )
+    }
+}
+
+fn last_modified_map(entries: Vec<LastModifiedResponse>) -> HashMap<String, DateTime<Utc>> {
+    entries
+        .into_iter()
+        .map(|entry| (entry.table.to_string(), entry.last_modified))
+        .collect()
+}
+
+/// Key of the rows in the body of the `/list/{table}` update routes.
+fn update_key(table: &str) -> Result<&'static str, Error> {
+    match table {
+        "imdb_ratings" => Ok("shows"),
+        "imdb_episodes" => Ok("episodes"),
+        "movie_collection" => Ok("collection"),
+        "movie_queue" => Ok("queue"),
+        "tombstones" => Ok("tombstones"),
+        _ => Err(format_err!("Unknown table {}", table)),
+    }
+}
+
+/// Two way sync with another `movie-queue-http` over the `/list/last_modified`
+/// and `/list/{table}` routes, authenticated with a sync scoped api token.
+///
+/// Every table is pushed before anything is pulled. The pull mark is the
+/// remote's `last_modified` read after pushing and the push mark is the local
+/// `last_modified` read after pulling, so rows written by the sync itself
+/// aren't moved again by the next one. Rows pushed in a sync are pulled back
+/// once by the same sync, which rewrites them unchanged. Rows written on either
+/// side while a sync runs can be missed until they are modified again.
+pub struct RemoteSync {
+    pool: PgPool,
+    client: Client,
+    remote: StackString,
+    token: Option<StackString>,
+}
+
+impl RemoteSync {
+    pub fn new(pool: &PgPool, remote: &str, token: Option<&str>) -> Self {
+        Self {
+            pool: pool.clone(),
+            client: Client::new(),
+            remote: remote.trim_end_matches('/').into(),
+            token: token.map(Into::into),
+        }
+    }
+
+    fn authorize(&self, builder: RequestBuilder) -> RequestBuilder {
+        match &self.token {
+            Some(token) => builder.bearer_auth(token),
+            None => builder,
+        }
+    }
+
+    async fn get_remote<T: DeserializeOwned>(
+        &self,
+        path: &str,
+        start_timestamp: Option<DateTime<Utc>>,
+    ) -> Result<T, Error> {
+        let url = format!("{}/list/{}", self.remote, path);
+        let url = match start_timestamp {
+            Some(t) => Url::parse_with_params(&url, &[("start_timestamp", t.to_rfc3339())])?,
+            None => Url::parse(&url)?,
+        };
+        self.authorize(self.client.get(url))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await
+            .map_err(Into::into)
+    }
+
+    async fn post_remote<T: Serialize>(&self, table: &str, rows: &[T]) -> Result<(), Error> {
+        let url = Url::parse(&format!("{}/list/{}", self.remote, table))?;
+        let mut body = HashMap::new();
+        body.insert(update_key(table)?, rows);
+        self.authorize(self.client.post(url))
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    async fn remote_last_modified(&self) -> Result<HashMap<String, DateTime<Utc>>, Error> {
+        let entries: Vec<LastModifiedResponse> = self.get_remote("last_modified", None).await?;
+        Ok(last_modified_map(entries))
+    }
+
+    async fn local_last_modified(&self) -> Result<HashMap<String, DateTime<Utc>>, Error> {
+        let entries = LastModifiedResponse::get_last_modified(&self.pool).await?;
+        Ok(last_modified_map(entries))
+    }
+
+    /// Send local rows modified since `since`.
+    async fn push_table(&self, table: &str, since: DateTime<Utc>) -> Result<usize, Error> {
+        match table {
+            "imdb_ratings" => {
+                let shows = ImdbRatings::get_shows_after_timestamp(since, &self.pool).await?;
+                self.post_remote(table, &shows).await?;
+                Ok(shows.len())
+            }
+            "imdb_episodes" => {
+                let episodes =
+                    ImdbEpisodes::get_episodes_after_timestamp(since, &self.pool).await?;
+                self.post_remote(table, &episodes).await?;
+                Ok(episodes.len())
+            }
+            "movie_collection" => {
+                let rows = MovieCollection::with_pool(&self.pool)?
+                    .get_collection_after_timestamp(since)
+                    .await?;
+                self.post_remote(table, &rows).await?;
+                Ok(rows.len())
+            }
+            "movie_queue" => {
+                let rows = MovieQueueDB::with_pool(&self.pool)
+                    .get_queue_after_timestamp(since)
+                    .await?;
+                self.post_remote(table, &rows).await?;
+                Ok(rows.len())
+            }
+            "tombstones" => {
+                let tombstones = Tombstone::get_after_timestamp(since, &self.pool).await?;
+                self.post_remote(table, &tombstones).await?;
+                Ok(tombstones.len())
+            }
+            _ => Err(format_err!("Unknown table {}", table)),
+        }
+    }
+
+    /// Fetch remote rows modified since `since` and apply them locally.
+    async fn pull_table(&self, table: &str, since: DateTime<Utc>) -> Result<usize, Error> {
+        match table {
+            "imdb_ratings" => {
+                let shows: Vec<ImdbRatings> = self.get_remote(table, Some(since)).await?;
+                for show in &shows {
+                    match ImdbRatings::get_show_by_link(show.link.as_ref(), &self.pool).await? {
+                        Some(_) => show.update_show(&self.pool).await?,
+                        None => show.insert_show(&self.pool).await?,
+                    }
+                }
+                Ok(shows.len())
+            }
+            "imdb_episodes" => {
+                let episodes: Vec<ImdbEpisodes> = self.get_remote(table, Some(since)).await?;
+                for episode in &episodes {
+                    match episode.get_index(&self.pool).await? {
+                        Some(_) => episode.update_episode(&self.pool).await?,
+                        None => episode.insert_episode(&self.pool).await?,
+                    }
+                }
+                Ok(episodes.len())
+            }
+            "movie_collection" => {
+                let rows: Vec<MovieCollectionRow> = self.get_remote(table, Some(since)).await?;
+                let mc = MovieCollection::with_pool(&self.pool)?;
+                for row in &rows {
+                    mc.sync_collection_row(row).await?;
+                }
+                Ok(rows.len())
+            }
+            "movie_queue" => {
+                let rows: Vec<MovieQueueRow> = self.get_remote(table, Some(since)).await?;
+                for row in &rows {
+                    row.sync_into(&self.pool).await?;
+                }
+                Ok(rows.len())
+            }
+            "tombstones" => {
+                let tombstones: Vec<Tombstone> = self.get_remote(table, Some(since)).await?;
+                for tombstone in &tombstones {
+                    tombstone.apply(&self.pool).await?;
+                }
+                Ok(tombstones.len())
+            }
+            _ => Err(format_err!("Unknown table {}", table)),
+        }
+    }
+
+    /// Without a mark for a table, rows newer than the other side's
+    /// `last_modified` are synced, which is what the old sync script did.
+    pub async fn sync(&self) -> Result<Vec<SyncSummary>, Error> {
+        let local = self.local_last_modified().await?;
+        let remote = self.remote_last_modified().await?;
+
+        let mut marks = Vec::new();
+        let mut summaries = Vec::new();
+        for table in &SYNC_TABLES {
+            let mark = SyncMark::get(&self.pool, &self.remote, table).await?;
+            let mut summary = SyncSummary {
+                table_name: (*table).into(),
+                ..SyncSummary::default()
+            };
+            if let Some(since) = mark.push_since(&local, &remote) {
+                debug!("push {} since {}", table, since);
+                summary.pushed = self.push_table(table, since).await?;
+            }
+            marks.push(mark);
+            summaries.push(summary);
+        }
+
+        let remote = self.remote_last_modified().await?;
+        for (mark, summary) in marks.iter().zip(summaries.iter_mut()) {
+            if let Some(since) = mark.pull_since(&local, &remote) {
+                debug!("pull {} since {}", mark.table_name, since);
+                summary.pulled = self.pull_table(&mark.table_name, since).await?;
+            }
+        }
+
+        let local = self.local_last_modified().await?;
+        for mark in &mut marks {
+            mark.update(&local, &remote);
+            mark.upsert(&self.pool).await?;
+        }
+        Ok(summaries)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use chrono::{DateTime, TimeZone, Utc};
+    use std::collections::HashMap;
+
+    use crate::remote_sync::{update_key, SyncMark, SYNC_TABLES};
+
+    fn modified(t: i64) -> HashMap<String, DateTime<Utc>> {
+        let mut map = HashMap::new();
+        map.insert("movie_queue".to_string(), Utc.timestamp(t, 0));
+        map
+    }
+
+    #[test]
+    fn test_update_key() {
+        let keys: Vec<_> = SYNC_TABLES.iter().map(|t| update_key(t).unwrap()).collect();
+        assert_eq!(
+            keys,
+            vec!["shows", "episodes", "collection", "queue", "tombstones"]
+        );
+        assert!(update_key("authorized_users").is_err());
+    }
+
+    #[test]
+    fn test_second_sync_moves_nothing() {
+        let mut mark = SyncMark {
+            remote: "https://remote".into(),
+            table_name: "movie_queue".into(),
+            last_pulled: None,
+            last_pushed: None,
+        };
+
+        // First sync: the local rows are newer than the remote, applying the
+        // push and the pull sets last_modified to the time they are written.
+        let (local, remote) = (modified(10), modified(5));
+        assert_eq!(mark.push_since(&local, &remote), Some(Utc.timestamp(5, 0)));
+        let remote = modified(20);
+        assert_eq!(mark.pull_since(&local, &remote), Some(Utc.timestamp(10, 0)));
+        let local = modified(30);
+        mark.update(&local, &remote);
+
+        // Second sync without changes on either side
+        assert_eq!(mark.push_since(&local, &remote), None);
+        assert_eq!(mark.pull_since(&local, &remote), None);
+        mark.update(&local, &remote);
+
+        // A change on the remote is pulled, the rows it writes locally are not
+        // pushed back afterwards.
+        let remote = modified(40);
+        assert_eq!(mark.push_since(&local, &remote), None);
+        assert_eq!(mark.pull_since(&local, &remote), Some(Utc.timestamp(20, 0)));
+        let local = modified(50);
+        mark.update(&local, &remote);
+        assert_eq!(mark.push_since(&local, &remote), None);
+        assert_eq!(mark.pull_since(&local, &remote), None);
+    }
+}
@@ -0,0 +1,273 @@
+use anyhow::{format_err, Error};
+use postgres_query::FromSqlRow;
+use serde::{Deserialize, Serialize};
+use stack_string::StackString;
+use std::{collections::HashSet, fmt, str::FromStr};
+
+use crate::pgpool::PgPool;
+
+/// Share of the trigrams of a query that a field has to contain to match.
+pub const SEARCH_THRESHOLD: f64 = 0.4;
+
+pub const DEFAULT_SEARCH_LIMIT: usize = 50;
+
+/// Upper bound of the limit a client can ask for.
+pub const MAX_SEARCH_LIMIT: usize = 500;
+
+/// The limit requested by a client, or the default, capped at
+/// `MAX_SEARCH_LIMIT`.
+pub fn search_limit(limit: Option<usize>) -> usize {
+    limit.unwrap_or(DEFAULT_SEARCH_LIMIT).min(MAX_SEARCH_LIMIT)
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+#[serde(rename_all = "snake_case")]
+pub enum SearchKind {
+    /// A file in the collection, matched on its path or parsed show name
+    Collection,
+    /// An imdb show or movie, matched on its title or show name
+    Show,
+    /// An imdb episode, matched on its title
+    Episode,
+}
+
+impl Default for SearchKind {
+    fn default() -> Self {
+        Self::Collection
+    }
+}
+
+impl SearchKind {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::Collection => "collection",
+            Self::Show => "show",
+            Self::Episode => "episode",
+        }
+    }
+}
+
+impl FromStr for SearchKind {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "collection" => Ok(Self::Collection),
+            "show" => Ok(Self::Show),
+            "episode" => Ok(Self::Episode),
+            _ => Err(format_err!("Invalid search kind {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for SearchKind {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Default, Clone, PartialEq)]
+pub struct SearchResult {
+    pub kind: SearchKind,
+    pub score: f64,
+    pub show: StackString,
+    pub title: StackString,
+    pub link: Option<StackString>,
+    pub path: Option<StackString>,
+    pub season: Option<i32>,
+    pub episode: Option<i32>,
+}
+
+impl fmt::Display for SearchResult {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:.2} {} {}", self.score, self.kind, self.title)?;
+        if let (Some(season), Some(episode)) = (self.season, self.episode) {
+            write!(f, " s{:02} ep{:02}", season, episode)?;
+        }
+        if let Some(path) = &self.path {
+            write!(f, " {}", path)?;
+        }
+        Ok(())
+    }
+}
+
+/// Trigrams of every word of `s` the way pg_trgm builds them: lowercased,
+/// padded with two spaces in front and one behind.
+fn trigrams(s: &str) -> HashSet<[char; 3]> {
+    let mut result = HashSet::new();
+    for word in s.split(|c: char| !c.is_alphanumeric()) {
+        if word.is_empty() {
+            continue;
+        }
+        let chars: Vec<char> = "  "
+            .chars()
+            .chain(word.chars().flat_map(char::to_lowercase))
+            .chain(" ".chars())
+            .collect();
+        for w in chars.windows(3) {
+            result.insert([w[0], w[1], w[2]]);
+        }
+    }
+    result
+}
+
+/// Share of the trigrams of `query` found in `target`, an approximation of
+/// pg_trgm's `word_similarity` for storage without the extension.
+pub fn word_similarity(query: &str, target: &str) -> f64 {
+    let query = trigrams(query);
+    if query.is_empty() {
+        return 0.0;
+    }
+    let target = trigrams(target);
+    query.intersection(&target).count() as f64 / query.len() as f64
+}
+
+/// Best score first, then collection files before shows and episodes, the
+/// same order as the `ORDER BY` of `Search::search`.
+pub fn rank_results(results: &mut Vec<SearchResult>, limit: usize) {
+    results.sort_by(|a, b| {
+        b.score
+            .partial_cmp(&a.score)
+            .unwrap_or(std::cmp::Ordering::Equal)
+            .then(a.kind.cmp(&b.kind))
+            .then(a.title.cmp(&b.title))
+    });
+    results.truncate(limit);
+}
+
+#[derive(FromSqlRow)]
+struct SearchRow {
+    kind: StackString,
+    score: f64,
+    show: StackString,
+    title: StackString,
+    link: Option<StackString>,
+    path: Option<StackString>,
+    season: Option<i32>,
+    episode: Option<i32>,
+}
+
+/// Typo tolerant search over show and episode titles, collection paths and
+/// the show names parsed from them, using the pg_trgm indexes of
+/// `scripts/search_indexes.sql`.
+pub struct Search {
+    pool: PgPool,
+}
+
+impl Search {
+    pub fn new(pool: &PgPool) -> Self {
+        Self { pool: pool.clone() }
+    }
+
+    pub async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>, Error> {
+        if query.trim().is_empty() {
+            return Ok(Vec::new());
+        }
+        let mut conn = self.pool.get().await?;
+        let tran = conn.transaction().await?;
+        // `<%` uses the trigram indexes, its threshold is a setting
+        tran.execute(
+            "SELECT set_config('pg_trgm.word_similarity_threshold', $1, true)",
+            &[&SEARCH_THRESHOLD.to_string()],
+        )
+        .await?;
+        let query = postgres_query::query!(
+            r#"
+                SELECT * FROM (
+                    SELECT 'show' AS kind,
+                           greatest(word_similarity($query, title),
+                                    word_similarity($query, show))::float8 AS score,
+                           show, coalesce(title, show) AS title, link,
+                           NULL::text AS path, NULL::integer AS season, NULL::integer AS episode
+                    FROM imdb_ratings
+                    WHERE $query <% title OR $query <% show
+                    UNION ALL
+                    SELECT 'episode', word_similarity($query, eptitle)::float8,
+                           show, eptitle, epurl, NULL, season, episode
+                    FROM imdb_episodes
+                    WHERE $query <% eptitle
+                    UNION ALL
+                    SELECT 'collection',
+                           greatest(word_similarity($query, a.path),
+                                    word_similarity($query, a.show))::float8,
+                           a.show, coalesce(b.title, a.show), b.link, a.path, NULL, NULL
+                    FROM movie_collection a
+                    LEFT JOIN imdb_ratings b ON a.show_id = b.index
+                    WHERE $query <% a.path OR $query <% a.show
+                ) s
+                ORDER BY score DESC,
+                         CASE kind WHEN 'collection' THEN 0 WHEN 'show' THEN 1 ELSE 2 END,
+                         title
+                LIMIT $limit
+            "#,
+            query = query,
+            limit = limit as i64
+        );
+        let rows = tran.query(query.sql(), query.parameters()).await?;
+        tran.commit().await?;
+        let mut results = rows
+            .iter()
+            .map(|row| {
+                let row = SearchRow::from_row(row)?;
+                Ok(SearchResult {
+                    kind: row.kind.parse()?,
+                    score: row.score,
+                    show: row.show,
+                    title: row.title,
+                    link: row.link,
+                    path: row.path,
+                    season: row.season,
+                    episode: row.episode,
+                })
+            })
+            .collect::<Result<Vec<_>, Error>>()?;
+        rank_results(&mut results, limit);
+        Ok(results)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::search::{
+        rank_results, search_limit, word_similarity, SearchKind, SearchResult,
+        DEFAULT_SEARCH_LIMIT, MAX_SEARCH_LIMIT,
+    };
+
+    #[test]
+    fn test_word_similarity() {
+        assert!(word_similarity("mr robto", "Mr. Robot") > 0.6);
+        assert!(word_similarity("mr robto", "/tv/mr_robot/season1/mr_robot_s01_ep01.mp4") > 0.6);
+        assert!((word_similarity("Robot", "mr_robot") - 1.0).abs() < 1e-9);
+        assert!(word_similarity("mr robto", "The Wire") < 0.2);
+        assert!(word_similarity("", "The Wire").abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_rank_results() {
+        let result = |kind, score, title: &str| SearchResult {
+            kind,
+            score,
+            title: title.into(),
+            ..SearchResult::default()
+        };
+        let mut results = vec![
+            result(SearchKind::Episode, 0.5, "eps2.0"),
+            result(SearchKind::Show, 0.9, "Mr. Robot"),
+            result(SearchKind::Collection, 0.9, "Mr. Robot"),
+        ];
+        rank_results(&mut results, 2);
+        let kinds: Vec<_> = results.iter().map(|r| r.kind).collect();
+        assert_eq!(kinds, vec![SearchKind::Collection, SearchKind::Show]);
+        assert_eq!(
+            "episode".parse::<SearchKind>().unwrap(),
+            SearchKind::Episode
+        );
+    }
+
+    #[test]
+    fn test_search_limit() {
+        assert_eq!(search_limit(None), DEFAULT_SEARCH_LIMIT);
+        assert_eq!(search_limit(Some(10)), 10);
+        assert_eq!(search_limit(Some(usize::MAX)), MAX_SEARCH_LIMIT);
+    }
+}
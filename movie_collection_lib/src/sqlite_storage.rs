@@ -0,0 +1,916 @@
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use rusqlite::{params, Connection, OptionalExtension, Row};
+use stack_string::StackString;
+use std::{
+    path::Path,
+    sync::{Arc, Mutex},
+};
+use tokio::task::spawn_blocking;
+
+use crate::{
+    imdb_episodes::ImdbEpisodes,
+    imdb_ratings::ImdbRatings,
+    movie_collection::MovieCollectionRow,
+    movie_queue::MovieQueueRow,
+    search::{rank_results, word_similarity, SearchKind, SearchResult, SEARCH_THRESHOLD},
+    storage::Storage,
+    trakt_utils::{WatchListShow, WatchedEpisode, WatchedMovie},
+    utils::parse_file_stem,
+};
+
+/// The tables of `scripts/*.sql` that `Storage` covers, sequences become
+/// `INTEGER PRIMARY KEY` columns and timestamps are stored as rfc3339 text.
+const SCHEMA: &str = r#"
+    CREATE TABLE IF NOT EXISTS imdb_ratings (
+        "index" INTEGER PRIMARY KEY,
+        show TEXT NOT NULL UNIQUE,
+        title TEXT,
+        link TEXT,
+        rating REAL,
+        istv INTEGER,
+        source TEXT,
+        last_modified TEXT
+    );
+    CREATE TABLE IF NOT EXISTS imdb_episodes (
+        id INTEGER PRIMARY KEY,
+        show TEXT NOT NULL REFERENCES imdb_ratings (show),
+        season INTEGER,
+        episode INTEGER,
+        epurl TEXT,
+        airdate TEXT,
+        rating REAL,
+        eptitle TEXT,
+        last_modified TEXT,
+        UNIQUE (show, season, episode)
+    );
+    CREATE TABLE IF NOT EXISTS movie_collection (
+        idx INTEGER NOT NULL PRIMARY KEY,
+        path TEXT UNIQUE,
+        show TEXT,
+        show_id INTEGER REFERENCES imdb_ratings ("index"),
+        last_modified TEXT
+    );
+    CREATE TABLE IF NOT EXISTS movie_queue (
+        user_email TEXT NOT NULL DEFAULT '',
+        queue_name TEXT NOT NULL DEFAULT 'default',
+        idx INTEGER NOT NULL,
+        collection_idx INTEGER NOT NULL REFERENCES movie_collection (idx),
+        last_modified TEXT,
+        PRIMARY KEY (user_email, queue_name, idx)
+    );
+    CREATE TABLE IF NOT EXISTS trakt_watchlist (
+        id INTEGER PRIMARY KEY,
+        user_email TEXT NOT NULL DEFAULT '',
+        link TEXT NOT NULL,
+        title TEXT,
+        year INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS trakt_watched_episodes (
+        id INTEGER PRIMARY KEY,
+        user_email TEXT NOT NULL DEFAULT '',
+        link TEXT NOT NULL,
+        season INTEGER,
+        episode INTEGER
+    );
+    CREATE TABLE IF NOT EXISTS trakt_watched_movies (
+        id INTEGER PRIMARY KEY,
+        user_email TEXT NOT NULL DEFAULT '',
+        link TEXT NOT NULL,
+        rating REAL,
+        source TEXT NOT NULL DEFAULT 'trakt',
+        UNIQUE (user_email, link)
+    );
+"#;
+
+const NOW: &str = "strftime('%Y-%m-%dT%H:%M:%fZ', 'now')";
+
+/// Rows of each table that `search` reads and scores.
+const SEARCH_SCAN_LIMIT: i64 = 50_000;
+
+/// `Storage` on a single SQLite file, for an install on one machine or a
+/// test without a database server. Unlike postgres there are no tombstones
+/// or change notifications, so it can't take part in remote sync.
+#[derive(Clone)]
+pub struct SqliteStorage {
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl SqliteStorage {
+    pub fn open<T: AsRef<Path>>(path: T) -> Result<Self, Error> {
+        Self::with_connection(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self, Error> {
+        Self::with_connection(Connection::open_in_memory()?)
+    }
+
+    fn with_connection(conn: Connection) -> Result<Self, Error> {
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self {
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    /// rusqlite blocks, so every call runs on the blocking pool with the
+    /// connection locked for its duration.
+    async fn run<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, Error> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        spawn_blocking(move || {
+            let mut conn = conn.lock().map_err(|e| format_err!("{}", e))?;
+            f(&mut conn)
+        })
+        .await?
+    }
+}
+
+fn get_string(row: &Row, idx: usize) -> Result<StackString, rusqlite::Error> {
+    row.get::<_, Option<String>>(idx)
+        .map(|s| s.unwrap_or_default().into())
+}
+
+/// Entries negated by a shift get their final position, see `MovieQueueDB`.
+fn restore_negated(conn: &Connection, user: &str, queue: &str) -> Result<(), Error> {
+    conn.execute(
+        &format!(
+            "UPDATE movie_queue SET idx = -idx - 1, last_modified = {} \
+             WHERE user_email = ?1 AND queue_name = ?2 AND idx < 0",
+            NOW
+        ),
+        params![user, queue],
+    )?;
+    Ok(())
+}
+
+fn get_queue_idx(
+    conn: &Connection,
+    user: &str,
+    queue: &str,
+    collection_idx: i32,
+) -> Result<Option<i32>, Error> {
+    conn.query_row(
+        "SELECT idx FROM movie_queue \
+         WHERE user_email = ?1 AND queue_name = ?2 AND collection_idx = ?3",
+        params![user, queue, collection_idx],
+        |row| row.get(0),
+    )
+    .optional()
+    .map_err(Into::into)
+}
+
+fn remove_at(conn: &Connection, user: &str, queue: &str, idx: i32) -> Result<(), Error> {
+    conn.execute(
+        "DELETE FROM movie_queue WHERE user_email = ?1 AND queue_name = ?2 AND idx = ?3",
+        params![user, queue, idx],
+    )?;
+    conn.execute(
+        "UPDATE movie_queue SET idx = -idx \
+         WHERE user_email = ?1 AND queue_name = ?2 AND idx > ?3",
+        params![user, queue, idx],
+    )?;
+    restore_negated(conn, user, queue)
+}
+
+fn insert_at(
+    conn: &Connection,
+    user: &str,
+    queue: &str,
+    idx: i32,
+    collection_idx: i32,
+) -> Result<(), Error> {
+    conn.execute(
+        "UPDATE movie_queue SET idx = -idx - 2 \
+         WHERE user_email = ?1 AND queue_name = ?2 AND idx >= ?3",
+        params![user, queue, idx],
+    )?;
+    restore_negated(conn, user, queue)?;
+    conn.execute(
+        &format!(
+            "INSERT INTO movie_queue (user_email, queue_name, idx, collection_idx, last_modified) \
+             VALUES (?1, ?2, ?3, ?4, {})",
+            NOW
+        ),
+        params![user, queue, idx, collection_idx],
+    )?;
+    Ok(())
+}
+
+#[async_trait]
+impl Storage for SqliteStorage {
+    async fn get_collection_index(&self, path: &str) -> Result<Option<i32>, Error> {
+        let path = path.to_string();
+        self.run(move |conn| {
+            conn.query_row(
+                "SELECT idx FROM movie_collection WHERE path = ?1",
+                params![path],
+                |row| row.get(0),
+            )
+            .optional()
+            .map_err(Into::into)
+        })
+        .await
+    }
+
+    async fn get_collection_path(&self, idx: i32) -> Result<Option<StackString>, Error> {
+        self.run(move |conn| {
+            conn.query_row(
+                "SELECT path FROM movie_collection WHERE idx = ?1",
+                params![idx],
+                |row| get_string(row, 0),
+            )
+            .optional()
+            .map_err(Into::into)
+        })
+        .await
+    }
+
+    async fn sync_collection_row(&self, row: &MovieCollectionRow) -> Result<i32, Error> {
+        let path = row.path.to_string();
+        let idx = row.idx;
+        let file_stem = Path::new(&path)
+            .file_stem()
+            .ok_or_else(|| format_err!("Invalid path {}", path))?
+            .to_string_lossy()
+            .to_string();
+        let (show, _, _) = parse_file_stem(&file_stem);
+        let show = show.to_string();
+        self.run(move |conn| {
+            let tran = conn.transaction()?;
+            let existing: Option<i32> = tran
+                .query_row(
+                    "SELECT idx FROM movie_collection WHERE path = ?1",
+                    params![path],
+                    |row| row.get(0),
+                )
+                .optional()?;
+            if let Some(existing) = existing {
+                return Ok(existing);
+            }
+            tran.execute(
+                &format!(
+                    r#"
+                        INSERT INTO movie_collection (idx, path, show, last_modified)
+                        VALUES (
+                            CASE WHEN EXISTS (SELECT 1 FROM movie_collection WHERE idx = ?1)
+                                THEN (SELECT max(idx) + 1 FROM movie_collection)
+                                ELSE ?1
+                            END,
+                            ?2, ?3, {}
+                        )
+                    "#,
+                    NOW
+                ),
+                params![idx, path, show],
+            )?;
+            let idx = tran.query_row(
+                "SELECT idx FROM movie_collection WHERE path = ?1",
+                params![path],
+                |row| row.get(0),
+            )?;
+            tran.commit()?;
+            Ok(idx)
+        })
+        .await
+    }
+
+    async fn remove_from_collection(&self, path: &str) -> Result<(), Error> {
+        let path = path.to_string();
+        self.run(move |conn| {
+            conn.execute(
+                "DELETE FROM movie_collection WHERE path = ?1",
+                params![path],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_queue(&self, user: &str, queue: &str) -> Result<Vec<MovieQueueRow>, Error> {
+        let user = user.to_string();
+        let queue = queue.to_string();
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(
+                r#"
+                    SELECT a.user_email, a.queue_name, a.idx, a.collection_idx, b.path, b.show
+                    FROM movie_queue a
+                    JOIN movie_collection b ON a.collection_idx = b.idx
+                    WHERE a.user_email = ?1 AND a.queue_name = ?2
+                    ORDER BY a.idx
+                "#,
+            )?;
+            let rows = stmt.query_map(params![user, queue], |row| {
+                Ok(MovieQueueRow {
+                    user_email: get_string(row, 0)?,
+                    queue_name: get_string(row, 1)?,
+                    idx: row.get(2)?,
+                    collection_idx: row.get(3)?,
+                    path: get_string(row, 4)?,
+                    show: get_string(row, 5)?,
+                })
+            })?;
+            rows.collect::<Result<_, _>>().map_err(Into::into)
+        })
+        .await
+    }
+
+    async fn insert_into_queue(
+        &self,
+        user: &str,
+        queue: &str,
+        idx: i32,
+        collection_idx: i32,
+    ) -> Result<(), Error> {
+        let user = user.to_string();
+        let queue = queue.to_string();
+        self.run(move |conn| {
+            let tran = conn.transaction()?;
+            let current_idx = get_queue_idx(&tran, &user, &queue, collection_idx)?;
+            if current_idx == Some(idx) {
+                return Ok(());
+            }
+            if let Some(current_idx) = current_idx {
+                remove_at(&tran, &user, &queue, current_idx)?;
+            }
+            let max_idx: i32 = tran.query_row(
+                "SELECT coalesce(max(idx), -1) FROM movie_queue \
+                 WHERE user_email = ?1 AND queue_name = ?2",
+                params![user, queue],
+                |row| row.get(0),
+            )?;
+            let idx = idx.min(max_idx + 1);
+            insert_at(&tran, &user, &queue, idx, collection_idx)?;
+            tran.commit().map_err(Into::into)
+        })
+        .await
+    }
+
+    async fn remove_from_queue(
+        &self,
+        user: &str,
+        queue: &str,
+        collection_idx: i32,
+    ) -> Result<(), Error> {
+        let user = user.to_string();
+        let queue = queue.to_string();
+        self.run(move |conn| {
+            let tran = conn.transaction()?;
+            if let Some(idx) = get_queue_idx(&tran, &user, &queue, collection_idx)? {
+                remove_at(&tran, &user, &queue, idx)?;
+            }
+            tran.commit().map_err(Into::into)
+        })
+        .await
+    }
+
+    async fn get_show_by_link(&self, link: &str) -> Result<Option<ImdbRatings>, Error> {
+        let link = link.to_string();
+        self.run(move |conn| {
+            let row = conn
+                .query_row(
+                    r#"
+                        SELECT "index", show, title, link, rating, istv, source
+                        FROM imdb_ratings
+                        WHERE (link = ?1 OR show = ?1)
+                    "#,
+                    params![link],
+                    |row| {
+                        Ok((
+                            ImdbRatings {
+                                index: row.get(0)?,
+                                show: get_string(row, 1)?,
+                                title: row.get::<_, Option<String>>(2)?.map(Into::into),
+                                link: get_string(row, 3)?,
+                                rating: row.get(4)?,
+                                istv: row.get(5)?,
+                                source: None,
+                            },
+                            row.get::<_, Option<String>>(6)?,
+                        ))
+                    },
+                )
+                .optional()?;
+            if let Some((mut show, source)) = row {
+                show.source = source.map(|s| s.parse()).transpose()?;
+                Ok(Some(show))
+            } else {
+                Ok(None)
+            }
+        })
+        .await
+    }
+
+    async fn upsert_show(&self, show: &ImdbRatings) -> Result<(), Error> {
+        let name = show.show.to_string();
+        let title = show.title.as_ref().map(ToString::to_string);
+        let link = show.link.to_string();
+        let rating = show.rating;
+        let istv = show.istv;
+        let source = show.source.as_ref().map(ToString::to_string);
+        self.run(move |conn| {
+            conn.execute(
+                &format!(
+                    r#"
+                        INSERT INTO imdb_ratings
+                        (show, title, link, rating, istv, source, last_modified)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, {0})
+                        ON CONFLICT (show) DO UPDATE
+                        SET rating = excluded.rating, title = excluded.title,
+                            last_modified = {0}
+                    "#,
+                    NOW
+                ),
+                params![name, title, link, rating, istv, source],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_episodes(&self, show: &str) -> Result<Vec<ImdbEpisodes>, Error> {
+        let show = show.to_string();
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(
+                r#"
+                    SELECT a.show, b.title, a.season, a.episode, a.airdate,
+                           a.rating, a.eptitle, a.epurl
+                    FROM imdb_episodes a
+                    JOIN imdb_ratings b ON a.show = b.show
+                    WHERE a.show = ?1
+                    ORDER BY a.season, a.episode
+                "#,
+            )?;
+            let rows = stmt.query_map(params![show], |row| {
+                Ok(ImdbEpisodes {
+                    show: get_string(row, 0)?,
+                    title: get_string(row, 1)?,
+                    season: row.get(2)?,
+                    episode: row.get(3)?,
+                    airdate: row.get::<_, NaiveDate>(4)?,
+                    rating: row.get::<_, Option<f64>>(5)?.unwrap_or(-1.0),
+                    eptitle: get_string(row, 6)?,
+                    epurl: get_string(row, 7)?,
+                })
+            })?;
+            rows.collect::<Result<_, _>>().map_err(Into::into)
+        })
+        .await
+    }
+
+    async fn upsert_episode(&self, episode: &ImdbEpisodes) -> Result<(), Error> {
+        let show = episode.show.to_string();
+        let season = episode.season;
+        let number = episode.episode;
+        let airdate = episode.airdate;
+        let rating = episode.rating;
+        let eptitle = episode.eptitle.to_string();
+        let epurl = episode.epurl.to_string();
+        self.run(move |conn| {
+            conn.execute(
+                &format!(
+                    r#"
+                        INSERT INTO imdb_episodes
+                        (show, season, episode, airdate, rating, eptitle, epurl, last_modified)
+                        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, {0})
+                        ON CONFLICT (show, season, episode) DO UPDATE
+                        SET rating = excluded.rating, eptitle = excluded.eptitle,
+                            epurl = excluded.epurl, airdate = excluded.airdate,
+                            last_modified = {0}
+                    "#,
+                    NOW
+                ),
+                params![show, season, number, airdate, rating, eptitle, epurl],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_watchlist(&self, user: &str) -> Result<Vec<WatchListShow>, Error> {
+        let user = user.to_string();
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(
+                "SELECT link, title, year FROM trakt_watchlist WHERE user_email = ?1 ORDER BY link",
+            )?;
+            let rows = stmt.query_map(params![user], |row| {
+                Ok(WatchListShow {
+                    link: get_string(row, 0)?,
+                    title: get_string(row, 1)?,
+                    year: row.get::<_, Option<i32>>(2)?.unwrap_or(0),
+                })
+            })?;
+            rows.collect::<Result<_, _>>().map_err(Into::into)
+        })
+        .await
+    }
+
+    async fn insert_watchlist(&self, user: &str, show: &WatchListShow) -> Result<(), Error> {
+        let user = user.to_string();
+        let link = show.link.to_string();
+        let title = show.title.to_string();
+        let year = show.year;
+        self.run(move |conn| {
+            conn.execute(
+                "INSERT INTO trakt_watchlist (user_email, link, title, year) VALUES (?1, ?2, ?3, ?4)",
+                params![user, link, title, year],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_watchlist(&self, user: &str, show: &WatchListShow) -> Result<(), Error> {
+        let user = user.to_string();
+        let link = show.link.to_string();
+        self.run(move |conn| {
+            conn.execute(
+                "DELETE FROM trakt_watchlist WHERE link = ?1 AND user_email = ?2",
+                params![link, user],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_watched_episodes(&self, user: &str) -> Result<Vec<WatchedEpisode>, Error> {
+        let user = user.to_string();
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(
+                r#"
+                    SELECT a.link, b.title, a.season, a.episode
+                    FROM trakt_watched_episodes a
+                    JOIN imdb_ratings b ON a.link = b.link
+                    WHERE a.user_email = ?1
+                    ORDER BY 2, 3, 4
+                "#,
+            )?;
+            let rows = stmt.query_map(params![user], |row| {
+                Ok(WatchedEpisode {
+                    imdb_url: get_string(row, 0)?,
+                    title: get_string(row, 1)?,
+                    season: row.get(2)?,
+                    episode: row.get(3)?,
+                })
+            })?;
+            rows.collect::<Result<_, _>>().map_err(Into::into)
+        })
+        .await
+    }
+
+    async fn insert_watched_episode(
+        &self,
+        user: &str,
+        episode: &WatchedEpisode,
+    ) -> Result<(), Error> {
+        let user = user.to_string();
+        let link = episode.imdb_url.to_string();
+        let season = episode.season;
+        let number = episode.episode;
+        self.run(move |conn| {
+            conn.execute(
+                "INSERT INTO trakt_watched_episodes (user_email, link, season, episode) \
+                 VALUES (?1, ?2, ?3, ?4)",
+                params![user, link, season, number],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_watched_episode(
+        &self,
+        user: &str,
+        episode: &WatchedEpisode,
+    ) -> Result<(), Error> {
+        let user = user.to_string();
+        let link = episode.imdb_url.to_string();
+        let season = episode.season;
+        let number = episode.episode;
+        self.run(move |conn| {
+            conn.execute(
+                "DELETE FROM trakt_watched_episodes \
+                 WHERE user_email = ?1 AND link = ?2 AND season = ?3 AND episode = ?4",
+                params![user, link, season, number],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn get_watched_movies(&self, user: &str) -> Result<Vec<WatchedMovie>, Error> {
+        let user = user.to_string();
+        self.run(move |conn| {
+            let mut stmt = conn.prepare(
+                r#"
+                    SELECT a.link, b.title
+                    FROM trakt_watched_movies a
+                    JOIN imdb_ratings b ON a.link = b.link
+                    WHERE a.user_email = ?1
+                    ORDER BY b.show
+                "#,
+            )?;
+            let rows = stmt.query_map(params![user], |row| {
+                Ok(WatchedMovie {
+                    imdb_url: get_string(row, 0)?,
+                    title: get_string(row, 1)?,
+                })
+            })?;
+            rows.collect::<Result<_, _>>().map_err(Into::into)
+        })
+        .await
+    }
+
+    async fn insert_watched_movie(&self, user: &str, movie: &WatchedMovie) -> Result<(), Error> {
+        let user = user.to_string();
+        let link = movie.imdb_url.to_string();
+        self.run(move |conn| {
+            conn.execute(
+                "INSERT INTO trakt_watched_movies (user_email, link) VALUES (?1, ?2)",
+                params![user, link],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    async fn delete_watched_movie(&self, user: &str, movie: &WatchedMovie) -> Result<(), Error> {
+        let user = user.to_string();
+        let link = movie.imdb_url.to_string();
+        self.run(move |conn| {
+            conn.execute(
+                "DELETE FROM trakt_watched_movies WHERE user_email = ?1 AND link = ?2",
+                params![user, link],
+            )?;
+            Ok(())
+        })
+        .await
+    }
+
+    /// Without pg_trgm there is no index to narrow the candidates, every
+    /// title, path and show name is read and scored here. That costs a scan
+    /// of `imdb_ratings`, `imdb_episodes` and `movie_collection` per search,
+    /// so each scan stops after `SEARCH_SCAN_LIMIT` rows, the most recently
+    /// modified collection entries first. Rows past the cap aren't found.
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>, Error> {
+        let query = query.to_string();
+        self.run(move |conn| {
+            let mut results = Vec::new();
+            if query.trim().is_empty() {
+                return Ok(results);
+            }
+            let mut stmt = conn.prepare("SELECT show, title, link FROM imdb_ratings LIMIT ?1")?;
+            let mut rows = stmt.query(params![SEARCH_SCAN_LIMIT])?;
+            while let Some(row) = rows.next()? {
+                let show = get_string(row, 0)?;
+                let title = get_string(row, 1)?;
+                let score = word_similarity(&query, &title).max(word_similarity(&query, &show));
+                if score >= SEARCH_THRESHOLD {
+                    results.push(SearchResult {
+                        kind: SearchKind::Show,
+                        score,
+                        title: if title.is_empty() {
+                            show.clone()
+                        } else {
+                            title
+                        },
+                        show,
+                        link: row.get::<_, Option<String>>(2)?.map(Into::into),
+                        ..SearchResult::default()
+                    });
+                }
+            }
+            let mut stmt = conn.prepare(
+                "SELECT show, eptitle, epurl, season, episode FROM imdb_episodes LIMIT ?1",
+            )?;
+            let mut rows = stmt.query(params![SEARCH_SCAN_LIMIT])?;
+            while let Some(row) = rows.next()? {
+                let title = get_string(row, 1)?;
+                let score = word_similarity(&query, &title);
+                if score >= SEARCH_THRESHOLD {
+                    results.push(SearchResult {
+                        kind: SearchKind::Episode,
+                        score,
+                        show: get_string(row, 0)?,
+                        title,
+                        link: row.get::<_, Option<String>>(2)?.map(Into::into),
+                        season: row.get(3)?,
+                        episode: row.get(4)?,
+                        ..SearchResult::default()
+                    });
+                }
+            }
+            let mut stmt = conn.prepare(
+                r#"
+                    SELECT a.path, a.show, b.title, b.link
+                    FROM movie_collection a
+                    LEFT JOIN imdb_ratings b ON a.show_id = b."index"
+                    ORDER BY a.last_modified DESC
+                    LIMIT ?1
+                "#,
+            )?;
+            let mut rows = stmt.query(params![SEARCH_SCAN_LIMIT])?;
+            while let Some(row) = rows.next()? {
+                let path = get_string(row, 0)?;
+                let show = get_string(row, 1)?;
+                let score = word_similarity(&query, &path).max(word_similarity(&query, &show));
+                if score >= SEARCH_THRESHOLD {
+                    let title = row
+                        .get::<_, Option<String>>(2)?
+                        .map_or_else(|| show.clone(), Into::into);
+                    results.push(SearchResult {
+                        kind: SearchKind::Collection,
+                        score,
+                        show,
+                        title,
+                        link: row.get::<_, Option<String>>(3)?.map(Into::into),
+                        path: Some(path),
+                        ..SearchResult::default()
+                    });
+                }
+            }
+            rank_results(&mut results, limit);
+            Ok(results)
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use chrono::NaiveDate;
+
+    use crate::{
+        imdb_episodes::ImdbEpisodes,
+        imdb_ratings::ImdbRatings,
+        movie_collection::MovieCollectionRow,
+        movie_queue::{MovieQueueRow, DEFAULT_QUEUE, DEFAULT_USER},
+        pgpool::PgPool,
+        search::SearchKind,
+        storage::{open_storage, Storage},
+        trakt_utils::{WatchListShow, WatchedEpisode},
+    };
+
+    async fn add_path(storage: &dyn Storage, idx: i32, path: &str) -> Result<i32, Error> {
+        let row = MovieCollectionRow {
+            idx,
+            path: path.into(),
+            ..MovieCollectionRow::default()
+        };
+        storage.sync_collection_row(&row).await
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_queue() -> Result<(), Error> {
+        let storage = open_storage("sqlite::memory:")?;
+        let storage = storage.as_ref();
+        let a = add_path(storage, 1, "/tv/the_show/the_show_s01_ep01.mp4").await?;
+        let b = add_path(storage, 1, "/tv/the_show/the_show_s01_ep02.mp4").await?;
+        let c = add_path(storage, 5, "/movies/a_movie.mp4").await?;
+        assert_eq!((a, b, c), (1, 2, 5));
+        assert_eq!(
+            add_path(storage, 7, "/movies/a_movie.mp4").await?,
+            5,
+            "existing paths keep their idx"
+        );
+        assert_eq!(
+            storage.get_collection_path(2).await?,
+            Some("/tv/the_show/the_show_s01_ep02.mp4".into())
+        );
+
+        for (idx, collection_idx) in &[(0, a), (1, b), (0, c)] {
+            storage
+                .insert_into_queue(DEFAULT_USER, DEFAULT_QUEUE, *idx, *collection_idx)
+                .await?;
+        }
+        let order = |rows: Vec<MovieQueueRow>| -> Vec<(i32, i32)> {
+            rows.into_iter()
+                .map(|r| (r.idx, r.collection_idx))
+                .collect()
+        };
+        let queue = storage.get_queue(DEFAULT_USER, DEFAULT_QUEUE).await?;
+        assert_eq!(queue[0].show.as_str(), "a_movie");
+        assert_eq!(order(queue), vec![(0, c), (1, a), (2, b)]);
+
+        storage
+            .insert_into_queue(DEFAULT_USER, DEFAULT_QUEUE, 2, c)
+            .await?;
+        let queue = storage.get_queue(DEFAULT_USER, DEFAULT_QUEUE).await?;
+        assert_eq!(order(queue), vec![(0, a), (1, b), (2, c)]);
+
+        storage
+            .remove_from_queue(DEFAULT_USER, DEFAULT_QUEUE, a)
+            .await?;
+        assert!(
+            storage
+                .remove_from_collection("/tv/the_show/the_show_s01_ep02.mp4")
+                .await
+                .is_err(),
+            "queued entries can't be removed"
+        );
+        storage
+            .remove_from_collection("/tv/the_show/the_show_s01_ep01.mp4")
+            .await?;
+        assert_eq!(
+            storage
+                .get_collection_index("/tv/the_show/the_show_s01_ep01.mp4")
+                .await?,
+            None
+        );
+        let queue = storage.get_queue(DEFAULT_USER, DEFAULT_QUEUE).await?;
+        assert_eq!(order(queue), vec![(0, b), (1, c)]);
+        assert!(storage.get_queue("other", DEFAULT_QUEUE).await?.is_empty());
+        Ok(())
+    }
+
+    #[tokio::test]
+    async fn test_sqlite_imdb_and_trakt() -> Result<(), Error> {
+        let storage = open_storage("sqlite::memory:")?;
+        let mut show = ImdbRatings {
+            show: "the_show".into(),
+            title: Some("The Show".into()),
+            link: "tt0000001".into(),
+            rating: Some(7.5),
+            istv: Some(true),
+            ..ImdbRatings::default()
+        };
+        storage.upsert_show(&show).await?;
+        show.rating = Some(8.0);
+        storage.upsert_show(&show).await?;
+        let stored = storage.get_show_by_link("tt0000001").await?.unwrap();
+        assert_eq!(stored.rating, Some(8.0));
+        assert_eq!(stored.istv, Some(true));
+        assert!(storage.get_show_by_link("the_show").await?.is_some());
+        assert!(storage.get_show_by_link("' OR 1=1 --").await?.is_none());
+
+        let mut episode = ImdbEpisodes {
+            show: "the_show".into(),
+            season: 1,
+            episode: 1,
+            airdate: NaiveDate::from_ymd(2020, 1, 2),
+            rating: 6.5,
+            eptitle: "Pilot".into(),
+            epurl: "tt0000002".into(),
+            ..ImdbEpisodes::new()
+        };
+        storage.upsert_episode(&episode).await?;
+        episode.rating = 7.0;
+        storage.upsert_episode(&episode).await?;
+        let episodes = storage.get_episodes("the_show").await?;
+        assert_eq!(episodes.len(), 1);
+        assert_eq!(episodes[0].title.as_str(), "The Show");
+        assert_eq!(episodes[0].airdate, NaiveDate::from_ymd(2020, 1, 2));
+        assert!((episodes[0].rating - 7.0).abs() < 1e-6);
+
+        let watchlist = WatchListShow {
+            link: "tt0000001".into(),
+            title: "The Show".into(),
+            year: 2020,
+        };
+        storage.insert_watchlist("user", &watchlist).await?;
+        assert_eq!(storage.get_watchlist("user").await?.len(), 1);
+        assert!(storage.get_watchlist(DEFAULT_USER).await?.is_empty());
+        storage.delete_watchlist("user", &watchlist).await?;
+        assert!(storage.get_watchlist("user").await?.is_empty());
+
+        let watched = WatchedEpisode {
+            imdb_url: "tt0000001".into(),
+            season: 1,
+            episode: 1,
+            ..WatchedEpisode::default()
+        };
+        storage.insert_watched_episode("user", &watched).await?;
+        let episodes = storage.get_watched_episodes("user").await?;
+        assert_eq!(episodes.len(), 1);
+        assert_eq!(episodes[0].title.as_str(), "The Show");
+        storage.delete_watched_episode("user", &watched).await?;
+        assert!(storage.get_watched_episodes("user").await?.is_empty());
+
+        let row = MovieCollectionRow {
+            idx: 1,
+            path: "/tv/the_show/season1/the_show_s01_ep01.mp4".into(),
+            ..MovieCollectionRow::default()
+        };
+        storage.sync_collection_row(&row).await?;
+        let results = storage.search("teh sho", 10).await?;
+        let kinds: Vec<_> = results.iter().map(|r| r.kind).collect();
+        assert_eq!(kinds, vec![SearchKind::Collection, SearchKind::Show]);
+        let results = storage.search("pilto", 10).await?;
+        assert_eq!(results[0].kind, SearchKind::Episode);
+        assert_eq!(results[0].season, Some(1));
+        assert!(storage.search("robot", 10).await?.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_open_storage_scheme() {
+        assert!(open_storage("mysql://localhost/movies").is_err());
+        assert!(PgPool::new("sqlite:///var/lib/movies.db").is_err());
+        assert!(open_storage("sqlite::memory:").is_ok());
+    }
+}
@@ -0,0 +1,281 @@
+use anyhow::{format_err, Error};
+use async_trait::async_trait;
+use postgres_query::FromSqlRow;
+use stack_string::StackString;
+use std::sync::Arc;
+
+use crate::{
+    imdb_episodes::ImdbEpisodes,
+    imdb_ratings::ImdbRatings,
+    movie_collection::{MovieCollection, MovieCollectionRow},
+    movie_queue::{MovieQueueDB, MovieQueueRow},
+    pgpool::PgPool,
+    search::{Search, SearchResult},
+    sqlite_storage::SqliteStorage,
+    trakt_utils::{
+        get_watched_movies_db, get_watched_shows_db, get_watchlist_shows_db, WatchListShow,
+        WatchedEpisode, WatchedMovie,
+    },
+};
+
+/// The queries of the collection, queues, imdb tables and trakt lists that
+/// don't depend on a particular database. `PgStorage` runs them against
+/// postgres through the existing types, `SqliteStorage` against a local
+/// SQLite file for installs and tests without a database server.
+#[async_trait]
+pub trait Storage: Send + Sync {
+    async fn get_collection_index(&self, path: &str) -> Result<Option<i32>, Error>;
+    async fn get_collection_path(&self, idx: i32) -> Result<Option<StackString>, Error>;
+    /// Add an entry, or find it by path, and return its idx. The idx of the
+    /// row is kept if it isn't taken, otherwise the entry gets the next one.
+    async fn sync_collection_row(&self, row: &MovieCollectionRow) -> Result<i32, Error>;
+    async fn remove_from_collection(&self, path: &str) -> Result<(), Error>;
+
+    /// Entries of a queue in order, with the path and show of the collection
+    /// entry.
+    async fn get_queue(&self, user: &str, queue: &str) -> Result<Vec<MovieQueueRow>, Error>;
+    /// Put a collection entry at `idx`, shifting the entries from there on
+    /// down and moving the entry if it was already queued. An `idx` past the
+    /// end appends.
+    async fn insert_into_queue(
+        &self,
+        user: &str,
+        queue: &str,
+        idx: i32,
+        collection_idx: i32,
+    ) -> Result<(), Error>;
+    async fn remove_from_queue(
+        &self,
+        user: &str,
+        queue: &str,
+        collection_idx: i32,
+    ) -> Result<(), Error>;
+
+    /// Look up a show by imdb link, or by show name.
+    async fn get_show_by_link(&self, link: &str) -> Result<Option<ImdbRatings>, Error>;
+    async fn upsert_show(&self, show: &ImdbRatings) -> Result<(), Error>;
+    async fn get_episodes(&self, show: &str) -> Result<Vec<ImdbEpisodes>, Error>;
+    async fn upsert_episode(&self, episode: &ImdbEpisodes) -> Result<(), Error>;
+
+    async fn get_watchlist(&self, user: &str) -> Result<Vec<WatchListShow>, Error>;
+    async fn insert_watchlist(&self, user: &str, show: &WatchListShow) -> Result<(), Error>;
+    async fn delete_watchlist(&self, user: &str, show: &WatchListShow) -> Result<(), Error>;
+    async fn get_watched_episodes(&self, user: &str) -> Result<Vec<WatchedEpisode>, Error>;
+    async fn insert_watched_episode(
+        &self,
+        user: &str,
+        episode: &WatchedEpisode,
+    ) -> Result<(), Error>;
+    async fn delete_watched_episode(
+        &self,
+        user: &str,
+        episode: &WatchedEpisode,
+    ) -> Result<(), Error>;
+    async fn get_watched_movies(&self, user: &str) -> Result<Vec<WatchedMovie>, Error>;
+    async fn insert_watched_movie(&self, user: &str, movie: &WatchedMovie) -> Result<(), Error>;
+    async fn delete_watched_movie(&self, user: &str, movie: &WatchedMovie) -> Result<(), Error>;
+
+    /// Ranked, typo tolerant search of titles, paths and show names.
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>, Error>;
+}
+
+/// Only `make-queue` runs on a SQLite database, everything else needs
+/// postgres.
+pub fn is_sqlite_url(pgurl: &str) -> bool {
+    pgurl.starts_with("sqlite:")
+}
+
+/// Pick the storage for `pgurl` by its scheme: `postgres://` and
+/// `postgresql://` use postgres, `sqlite://<path>` a SQLite file and
+/// `sqlite::memory:` an in-memory database.
+pub fn open_storage(pgurl: &str) -> Result<Arc<dyn Storage>, Error> {
+    if pgurl == "sqlite::memory:" {
+        Ok(Arc::new(SqliteStorage::open_in_memory()?))
+    } else if let Some(path) = pgurl.strip_prefix("sqlite://") {
+        Ok(Arc::new(SqliteStorage::open(path)?))
+    } else if pgurl.starts_with("postgres://") || pgurl.starts_with("postgresql://") {
+        Ok(Arc::new(PgStorage::new(&PgPool::new(pgurl)?)))
+    } else {
+        Err(format_err!("Unsupported database url {}", pgurl))
+    }
+}
+
+pub struct PgStorage {
+    pool: PgPool,
+}
+
+impl PgStorage {
+    pub fn new(pool: &PgPool) -> Self {
+        Self { pool: pool.clone() }
+    }
+
+    fn collection(&self) -> Result<MovieCollection, Error> {
+        MovieCollection::with_pool(&self.pool)
+    }
+}
+
+#[async_trait]
+impl Storage for PgStorage {
+    async fn get_collection_index(&self, path: &str) -> Result<Option<i32>, Error> {
+        self.collection()?.get_collection_index(path).await
+    }
+
+    async fn get_collection_path(&self, idx: i32) -> Result<Option<StackString>, Error> {
+        let query = postgres_query::query!(
+            "SELECT path FROM movie_collection WHERE idx = $idx",
+            idx = idx
+        );
+        self.pool
+            .get()
+            .await?
+            .query(query.sql(), query.parameters())
+            .await?
+            .get(0)
+            .map(|row| row.try_get("path"))
+            .transpose()
+            .map_err(Into::into)
+    }
+
+    async fn sync_collection_row(&self, row: &MovieCollectionRow) -> Result<i32, Error> {
+        self.collection()?.sync_collection_row(row).await
+    }
+
+    async fn remove_from_collection(&self, path: &str) -> Result<(), Error> {
+        self.collection()?.remove_from_collection(path).await
+    }
+
+    async fn get_queue(&self, user: &str, queue: &str) -> Result<Vec<MovieQueueRow>, Error> {
+        let query = postgres_query::query!(
+            r#"
+                SELECT a.user_email, a.queue_name, a.idx, a.collection_idx, b.path, b.show
+                FROM movie_queue a
+                JOIN movie_collection b ON a.collection_idx = b.idx
+                WHERE a.user_email = $user AND a.queue_name = $queue
+                ORDER BY a.idx
+            "#,
+            user = user,
+            queue = queue
+        );
+        self.pool
+            .get()
+            .await?
+            .query(query.sql(), query.parameters())
+            .await?
+            .iter()
+            .map(|row| MovieQueueRow::from_row(row).map_err(Into::into))
+            .collect()
+    }
+
+    async fn insert_into_queue(
+        &self,
+        user: &str,
+        queue: &str,
+        idx: i32,
+        collection_idx: i32,
+    ) -> Result<(), Error> {
+        MovieQueueDB::with_user(&self.pool, user, queue)
+            .insert_into_queue_by_collection_idx(idx, collection_idx)
+            .await
+    }
+
+    async fn remove_from_queue(
+        &self,
+        user: &str,
+        queue: &str,
+        collection_idx: i32,
+    ) -> Result<(), Error> {
+        MovieQueueDB::with_user(&self.pool, user, queue)
+            .remove_from_queue_by_collection_idx(collection_idx)
+            .await
+    }
+
+    async fn get_show_by_link(&self, link: &str) -> Result<Option<ImdbRatings>, Error> {
+        ImdbRatings::get_show_by_link(link, &self.pool).await
+    }
+
+    async fn upsert_show(&self, show: &ImdbRatings) -> Result<(), Error> {
+        match ImdbRatings::get_show_by_link(show.show.as_str(), &self.pool).await? {
+            Some(_) => show.update_show(&self.pool).await,
+            None => show.insert_show(&self.pool).await,
+        }
+    }
+
+    async fn get_episodes(&self, show: &str) -> Result<Vec<ImdbEpisodes>, Error> {
+        let query = postgres_query::query!(
+            r#"
+                SELECT a.show, b.title, a.season, a.episode, a.airdate,
+                       cast(a.rating as double precision) as rating, a.eptitle, a.epurl
+                FROM imdb_episodes a
+                JOIN imdb_ratings b ON a.show = b.show
+                WHERE a.show = $show
+                ORDER BY a.season, a.episode
+            "#,
+            show = show
+        );
+        self.pool
+            .get()
+            .await?
+            .query(query.sql(), query.parameters())
+            .await?
+            .iter()
+            .map(|row| ImdbEpisodes::from_row(row).map_err(Into::into))
+            .collect()
+    }
+
+    async fn upsert_episode(&self, episode: &ImdbEpisodes) -> Result<(), Error> {
+        episode.insert_episode(&self.pool).await
+    }
+
+    async fn get_watchlist(&self, user: &str) -> Result<Vec<WatchListShow>, Error> {
+        let mut shows: Vec<_> = get_watchlist_shows_db(&self.pool, user)
+            .await?
+            .into_iter()
+            .collect();
+        shows.sort_by(|a, b| a.link.cmp(&b.link));
+        Ok(shows)
+    }
+
+    async fn insert_watchlist(&self, user: &str, show: &WatchListShow) -> Result<(), Error> {
+        show.insert_show(&self.pool, user).await
+    }
+
+    async fn delete_watchlist(&self, user: &str, show: &WatchListShow) -> Result<(), Error> {
+        show.delete_show(&self.pool, user).await
+    }
+
+    async fn get_watched_episodes(&self, user: &str) -> Result<Vec<WatchedEpisode>, Error> {
+        get_watched_shows_db(&self.pool, "", None, user).await
+    }
+
+    async fn insert_watched_episode(
+        &self,
+        user: &str,
+        episode: &WatchedEpisode,
+    ) -> Result<(), Error> {
+        episode.insert_episode(&self.pool, user).await
+    }
+
+    async fn delete_watched_episode(
+        &self,
+        user: &str,
+        episode: &WatchedEpisode,
+    ) -> Result<(), Error> {
+        episode.delete_episode(&self.pool, user).await
+    }
+
+    async fn get_watched_movies(&self, user: &str) -> Result<Vec<WatchedMovie>, Error> {
+        get_watched_movies_db(&self.pool, user).await
+    }
+
+    async fn insert_watched_movie(&self, user: &str, movie: &WatchedMovie) -> Result<(), Error> {
+        movie.insert_movie(&self.pool, user).await
+    }
+
+    async fn delete_watched_movie(&self, user: &str, movie: &WatchedMovie) -> Result<(), Error> {
+        movie.delete_movie(&self.pool, user).await
+    }
+
+    async fn search(&self, query: &str, limit: usize) -> Result<Vec<SearchResult>, Error> {
+        Search::new(&self.pool).search(query, limit).await
+    }
+}
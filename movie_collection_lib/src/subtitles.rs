@@ -0,0 +1,461 @@
+use anyhow::{format_err, Error};
+use postgres_query::FromSqlRow;
+use serde::{Deserialize, Serialize};
+use stack_string::StackString;
+use std::{
+    collections::HashMap,
+    fmt,
+    path::{Path, PathBuf},
+    process::Stdio,
+    str::FromStr,
+};
+use tokio::{fs, process::Command};
+
+use crate::pgpool::PgPool;
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub enum SubtitleFormat {
+    #[serde(rename = "srt")]
+    Srt,
+    #[serde(rename = "vtt")]
+    Vtt,
+    #[serde(rename = "ass")]
+    Ass,
+}
+
+impl fmt::Display for SubtitleFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}",
+            match self {
+                Self::Srt => "srt",
+                Self::Vtt => "vtt",
+                Self::Ass => "ass",
+            }
+        )
+    }
+}
+
+impl FromStr for SubtitleFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "srt" => Ok(Self::Srt),
+            "vtt" => Ok(Self::Vtt),
+            "ass" | "ssa" => Ok(Self::Ass),
+            _ => Err(format_err!("Is not SubtitleFormat")),
+        }
+    }
+}
+
+impl SubtitleFormat {
+    /// Text based codecs as reported by ffprobe, image based subtitles (pgs,
+    /// vobsub) can't be turned into WebVTT and are ignored.
+    fn from_codec(codec: &str) -> Option<Self> {
+        match codec {
+            "subrip" | "mov_text" => Some(Self::Srt),
+            "webvtt" => Some(Self::Vtt),
+            "ass" | "ssa" => Some(Self::Ass),
+            _ => None,
+        }
+    }
+}
+
+/// A subtitle track for a collection entry, either a sidecar file next to
+/// the video or a stream embedded in it.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Subtitle {
+    pub id: i32,
+    pub collection_idx: i32,
+    pub path: Option<StackString>,
+    pub stream_index: Option<i32>,
+    pub language: Option<StackString>,
+    pub format: SubtitleFormat,
+}
+
+#[derive(FromSqlRow)]
+struct SubtitleRow {
+    id: i32,
+    collection_idx: i32,
+    path: Option<StackString>,
+    stream_index: Option<i32>,
+    language: Option<StackString>,
+    format: StackString,
+}
+
+impl Subtitle {
+    fn from_db_row(row: SubtitleRow) -> Result<Self, Error> {
+        Ok(Self {
+            id: row.id,
+            collection_idx: row.collection_idx,
+            path: row.path,
+            stream_index: row.stream_index,
+            language: row.language,
+            format: row.format.parse()?,
+        })
+    }
+
+    pub fn label(&self) -> StackString {
+        let language = self
+            .language
+            .as_ref()
+            .map_or("unknown", StackString::as_str);
+        if self.path.is_some() {
+            language.into()
+        } else {
+            format!("{} (embedded)", language).into()
+        }
+    }
+
+    pub async fn get_by_collection_idx(
+        pool: &PgPool,
+        collection_idx: i32,
+    ) -> Result<Vec<Self>, Error> {
+        let query = postgres_query::query!(
+            r#"
+                SELECT id, collection_idx, path, stream_index, language, format
+                FROM movie_collection_subtitles
+                WHERE collection_idx = $collection_idx
+                ORDER BY language, id
+            "#,
+            collection_idx = collection_idx
+        );
+        pool.get()
+            .await?
+            .query(query.sql(), query.parameters())
+            .await?
+            .iter()
+            .map(|row| Self::from_db_row(SubtitleRow::from_row(row)?))
+            .collect()
+    }
+
+    pub async fn get_by_id(pool: &PgPool, id: i32) -> Result<Option<Self>, Error> {
+        let query = postgres_query::query!(
+            r#"
+                SELECT id, collection_idx, path, stream_index, language, format
+                FROM movie_collection_subtitles
+                WHERE id = $id
+            "#,
+            id = id
+        );
+        pool.get()
+            .await?
+            .query(query.sql(), query.parameters())
+            .await?
+            .get(0)
+            .map(|row| Self::from_db_row(SubtitleRow::from_row(row)?))
+            .transpose()
+    }
+
+    /// Replace the subtitles of a collection entry with `subtitles`, the `id`
+    /// and `collection_idx` of the arguments are ignored.
+    pub async fn replace_for_collection_idx(
+        pool: &PgPool,
+        collection_idx: i32,
+        subtitles: &[Self],
+    ) -> Result<(), Error> {
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        let query = postgres_query::query!(
+            "DELETE FROM movie_collection_subtitles WHERE collection_idx = $collection_idx",
+            collection_idx = collection_idx
+        );
+        tran.execute(query.sql(), query.parameters()).await?;
+        for subtitle in subtitles {
+            let format = subtitle.format.to_string();
+            let query = postgres_query::query!(
+                r#"
+                    INSERT INTO movie_collection_subtitles
+                    (collection_idx, path, stream_index, language, format, last_modified)
+                    VALUES ($collection_idx, $path, $stream_index, $language, $format, now())
+                "#,
+                collection_idx = collection_idx,
+                path = subtitle.path,
+                stream_index = subtitle.stream_index,
+                language = subtitle.language,
+                format = format
+            );
+            tran.execute(query.sql(), query.parameters()).await?;
+        }
+        tran.commit().await.map_err(Into::into)
+    }
+
+    /// The track as WebVTT, srt is converted directly while ass and embedded
+    /// streams go through ffmpeg.
+    pub async fn to_vtt(&self, video: &Path) -> Result<String, Error> {
+        match (&self.path, self.format) {
+            (Some(path), SubtitleFormat::Vtt) => Ok(read_text(Path::new(path.as_str())).await?),
+            (Some(path), SubtitleFormat::Srt) => {
+                let srt = read_text(Path::new(path.as_str())).await?;
+                Ok(srt_to_vtt(&srt))
+            }
+            (Some(path), SubtitleFormat::Ass) => ffmpeg_to_vtt(Path::new(path.as_str()), 0).await,
+            (None, _) => {
+                let stream_index = self
+                    .stream_index
+                    .ok_or_else(|| format_err!("Subtitle has neither path nor stream"))?;
+                ffmpeg_to_vtt(video, stream_index).await
+            }
+        }
+    }
+}
+
+async fn read_text(path: &Path) -> Result<String, Error> {
+    let bytes = fs::read(path).await?;
+    Ok(String::from_utf8_lossy(&bytes).into_owned())
+}
+
+async fn ffmpeg_to_vtt(input: &Path, stream_index: i32) -> Result<String, Error> {
+    let output = Command::new("ffmpeg")
+        .args(&["-nostdin", "-loglevel", "error", "-i"])
+        .arg(input)
+        .args(&["-map", &format!("0:{}", stream_index), "-f", "webvtt", "-"])
+        .stdin(Stdio::null())
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(format_err!(
+            "ffmpeg failed to extract subtitles {:?}",
+            input
+        ));
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// `HH:MM:SS,mmm`, the hours may have more or fewer digits.
+fn is_srt_timestamp(s: &str) -> bool {
+    let digits = |s: &str, n: Option<usize>| {
+        !s.is_empty() && s.chars().all(|c| c.is_ascii_digit()) && n.map_or(true, |n| s.len() == n)
+    };
+    let (hms, millis) = match s.find(',') {
+        Some(i) => (&s[..i], &s[i + 1..]),
+        None => return false,
+    };
+    let parts: Vec<_> = hms.split(':').collect();
+    parts.len() == 3
+        && digits(parts[0], None)
+        && digits(parts[1], Some(2))
+        && digits(parts[2], Some(2))
+        && digits(millis, Some(3))
+}
+
+/// The cue timing line with `.` as decimal separator, `None` for any other
+/// line. Anything after the end time, e.g. position hints, is kept.
+fn srt_timing_to_vtt(line: &str) -> Option<String> {
+    let (start, rest) = line.split_at(line.find("-->")?);
+    let start = start.trim();
+    let rest = rest["-->".len()..].trim_start();
+    let end = rest.split_whitespace().next()?;
+    if !is_srt_timestamp(start) || !is_srt_timestamp(end) {
+        return None;
+    }
+    Some(format!(
+        "{} --> {}{}",
+        start.replace(',', "."),
+        end.replace(',', "."),
+        &rest[end.len()..]
+    ))
+}
+
+/// SubRip and WebVTT differ in the header and the decimal separator of the
+/// cue timings.
+pub fn srt_to_vtt(srt: &str) -> String {
+    let mut vtt = String::from("WEBVTT\n\n");
+    for line in srt.trim_start_matches('\u{feff}').lines() {
+        match srt_timing_to_vtt(line) {
+            Some(timing) => vtt.push_str(&timing),
+            None => vtt.push_str(line),
+        }
+        vtt.push('\n');
+    }
+    vtt
+}
+
+/// Language tag of a sidecar file, `movie.en.srt` and `movie.eng.forced.srt`
+/// are both sidecars of `movie.mkv`.
+fn sidecar_language(video_stem: &str, file_name: &str) -> Option<Option<StackString>> {
+    let rest = file_name.strip_prefix(video_stem)?;
+    if !rest.starts_with('.') {
+        return None;
+    }
+    let mut parts: Vec<_> = rest[1..].split('.').collect();
+    parts.pop()?;
+    let language = parts
+        .into_iter()
+        .find(|p| (2..=3).contains(&p.len()) && p.chars().all(|c| c.is_ascii_alphabetic()))
+        .map(|p| p.to_lowercase().into());
+    Some(language)
+}
+
+/// Subtitle files in the same directory as `video` named after it.
+pub fn find_sidecar_subtitles(video: &Path) -> Result<Vec<Subtitle>, Error> {
+    let directory = video
+        .parent()
+        .ok_or_else(|| format_err!("No parent directory"))?;
+    let video_stem = video
+        .file_stem()
+        .ok_or_else(|| format_err!("Invalid path"))?
+        .to_string_lossy();
+    let mut subtitles = Vec::new();
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+        let format = match path
+            .extension()
+            .and_then(|e| e.to_string_lossy().to_lowercase().parse().ok())
+        {
+            Some(format) => format,
+            None => continue,
+        };
+        let file_name = path
+            .file_name()
+            .map(|f| f.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if let Some(language) = sidecar_language(&video_stem, &file_name) {
+            subtitles.push(Subtitle {
+                id: -1,
+                collection_idx: -1,
+                path: Some(path.to_string_lossy().into_owned().into()),
+                stream_index: None,
+                language,
+                format,
+            });
+        }
+    }
+    subtitles.sort_by(|x, y| x.path.cmp(&y.path));
+    Ok(subtitles)
+}
+
+#[derive(Deserialize)]
+struct ProbeStream {
+    index: i32,
+    codec_name: Option<String>,
+    #[serde(default)]
+    tags: HashMap<String, String>,
+}
+
+#[derive(Deserialize)]
+struct ProbeOutput {
+    #[serde(default)]
+    streams: Vec<ProbeStream>,
+}
+
+fn parse_probe_output(output: &[u8]) -> Result<Vec<Subtitle>, Error> {
+    let output: ProbeOutput = serde_json::from_slice(output)?;
+    let subtitles = output
+        .streams
+        .into_iter()
+        .filter_map(|s| {
+            let format = SubtitleFormat::from_codec(s.codec_name.as_ref()?)?;
+            Some(Subtitle {
+                id: -1,
+                collection_idx: -1,
+                path: None,
+                stream_index: Some(s.index),
+                language: s.tags.get("language").map(|l| l.as_str().into()),
+                format,
+            })
+        })
+        .collect();
+    Ok(subtitles)
+}
+
+/// Text subtitle streams embedded in `video`.
+pub async fn probe_embedded_subtitles(video: &Path) -> Result<Vec<Subtitle>, Error> {
+    let output = Command::new("ffprobe")
+        .args(&[
+            "-v",
+            "error",
+            "-select_streams",
+            "s",
+            "-show_entries",
+            "stream=index,codec_name:stream_tags=language",
+            "-of",
+            "json",
+        ])
+        .arg(video)
+        .stdin(Stdio::null())
+        .output()
+        .await?;
+    if !output.status.success() {
+        return Err(format_err!("ffprobe failed for {:?}", video));
+    }
+    parse_probe_output(&output.stdout)
+}
+
+/// Find the sidecar and embedded subtitles of a collection entry and store
+/// them, returns the number of tracks found.
+pub async fn scan_subtitles(
+    pool: &PgPool,
+    collection_idx: i32,
+    video: &Path,
+) -> Result<usize, Error> {
+    let mut subtitles = find_sidecar_subtitles(video)?;
+    subtitles.extend(probe_embedded_subtitles(video).await?);
+    Subtitle::replace_for_collection_idx(pool, collection_idx, &subtitles).await?;
+    Ok(subtitles.len())
+}
+
+/// Sidecar file for `language` next to `video`, used to hand subtitles to the
+/// transcoder.
+pub fn find_sidecar_for_language(video: &Path, language: &str) -> Result<Option<PathBuf>, Error> {
+    Ok(find_sidecar_subtitles(video)?
+        .into_iter()
+        .filter(|s| s.format == SubtitleFormat::Srt)
+        .find(|s| s.language.as_ref().map(StackString::as_str) == Some(language))
+        .and_then(|s| s.path)
+        .map(|p| p.as_str().into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::subtitles::{parse_probe_output, sidecar_language, srt_to_vtt, SubtitleFormat};
+
+    #[test]
+    fn test_srt_to_vtt() {
+        let srt = "\u{feff}1\n00:00:01,000 --> 00:00:02,500\nHello, world\n";
+        let vtt = srt_to_vtt(srt);
+        assert_eq!(
+            vtt,
+            "WEBVTT\n\n1\n00:00:01.000 --> 00:00:02.500\nHello, world\n"
+        );
+
+        let srt = "2\n00:00:03,000 --> 00:00:04,000 X1:40\nLeft, right --> up, down\n";
+        let vtt = srt_to_vtt(srt);
+        assert_eq!(
+            vtt,
+            "WEBVTT\n\n2\n00:00:03.000 --> 00:00:04.000 X1:40\nLeft, right --> up, down\n"
+        );
+    }
+
+    #[test]
+    fn test_sidecar_language() {
+        assert_eq!(sidecar_language("movie", "movie.srt"), Some(None));
+        assert_eq!(
+            sidecar_language("movie", "movie.en.srt"),
+            Some(Some("en".into()))
+        );
+        assert_eq!(
+            sidecar_language("movie", "movie.ENG.forced.srt"),
+            Some(Some("eng".into()))
+        );
+        assert_eq!(sidecar_language("movie", "movie_2.srt"), None);
+        assert_eq!(sidecar_language("movie", "other.srt"), None);
+    }
+
+    #[test]
+    fn test_parse_probe_output() {
+        let output = br#"{"streams": [
+            {"index": 2, "codec_name": "subrip", "tags": {"language": "eng"}},
+            {"index": 3, "codec_name": "hdmv_pgs_subtitle", "tags": {"language": "fre"}},
+            {"index": 4, "codec_name": "ass"}
+        ]}"#;
+        let subtitles = parse_probe_output(output).unwrap();
+        assert_eq!(subtitles.len(), 2);
+        assert_eq!(subtitles[0].stream_index, Some(2));
+        assert_eq!(subtitles[0].language.as_ref().unwrap().as_str(), "eng");
+        assert_eq!(subtitles[1].format, SubtitleFormat::Ass);
+        assert!(subtitles[1].language.is_none());
+    }
+}
@@ -0,0 +1,155 @@
+use anyhow::{format_err, Error};
+use log::debug;
+use rand::{thread_rng, Rng};
+use sha2::{Digest, Sha256};
+use std::{
+    path::{Path, PathBuf},
+    process::Stdio,
+    time::UNIX_EPOCH,
+};
+use tokio::{fs, process::Command};
+
+use crate::hls::probe_duration;
+
+/// Width of generated thumbnails, the height keeps the aspect ratio.
+const THUMBNAIL_WIDTH: u32 = 320;
+
+/// Frames are taken this far into the video to skip intros and black frames.
+const THUMBNAIL_FRACTION: f64 = 0.1;
+
+const POSTER_NAMES: [&str; 2] = ["poster.jpg", "folder.jpg"];
+
+/// A `poster.jpg` or `folder.jpg` in the directory of the video or the one
+/// above it, `show/season1/show_s01_ep01.mp4` uses the poster in `show/`.
+pub fn find_poster(video: &Path) -> Option<PathBuf> {
+    video
+        .ancestors()
+        .skip(1)
+        .take(2)
+        .flat_map(|d| POSTER_NAMES.iter().map(move |n| d.join(n)))
+        .find(|p| p.exists())
+}
+
+/// The key has to stay the same across builds, or every cached thumbnail is
+/// orphaned, so it is a sha256 rather than `DefaultHasher`.
+fn cache_key(source: &Path, mtime: u128) -> String {
+    let digest = Sha256::digest(format!("{}\n{}", source.to_string_lossy(), mtime).as_bytes());
+    let key: String = digest.iter().map(|b| format!("{:02x}", b)).collect();
+    format!("{}.jpg", key)
+}
+
+/// Thumbnails of collection entries cached on disk, keyed by the path and
+/// modification time of the file they were made from so that a replaced
+/// video or poster gets a new thumbnail.
+pub struct ThumbnailCache {
+    cache_dir: PathBuf,
+}
+
+impl ThumbnailCache {
+    pub fn new(cache_dir: &Path) -> Self {
+        Self {
+            cache_dir: cache_dir.to_path_buf(),
+        }
+    }
+
+    /// Path of the thumbnail for `video`, generated if it isn't cached.
+    pub async fn get_thumbnail(&self, video: &Path) -> Result<PathBuf, Error> {
+        let poster = find_poster(video);
+        let source = poster.as_deref().unwrap_or(video);
+        let mtime = fs::metadata(source)
+            .await?
+            .modified()?
+            .duration_since(UNIX_EPOCH)?
+            .as_nanos();
+        let thumbnail = self.cache_dir.join(cache_key(source, mtime));
+        if thumbnail.exists() {
+            return Ok(thumbnail);
+        }
+        fs::create_dir_all(&self.cache_dir).await?;
+
+        let seek = if poster.is_some() {
+            0.0
+        } else {
+            probe_duration(video).await? * THUMBNAIL_FRACTION
+        };
+        // Write to a temp file of this request and rename so a concurrent
+        // request never sees a partial image
+        let tmp = thumbnail.with_extension(format!(
+            "{}.{:08x}.tmp.jpg",
+            std::process::id(),
+            thread_rng().gen::<u32>()
+        ));
+        debug!("thumbnail {:?} from {:?}", thumbnail, source);
+        let status = Command::new("ffmpeg")
+            .args(&[
+                "-nostdin",
+                "-loglevel",
+                "error",
+                "-y",
+                "-ss",
+                &format!("{:.3}", seek),
+                "-i",
+            ])
+            .arg(source)
+            .args(&[
+                "-frames:v",
+                "1",
+                "-vf",
+                &format!("scale={}:-2", THUMBNAIL_WIDTH),
+                "-q:v",
+                "4",
+            ])
+            .arg(&tmp)
+            .stdin(Stdio::null())
+            .stdout(Stdio::null())
+            .stderr(Stdio::null())
+            .status()
+            .await?;
+        if !status.success() || !tmp.exists() {
+            fs::remove_file(&tmp).await.ok();
+            return Err(format_err!("ffmpeg failed to make thumbnail {:?}", source));
+        }
+        fs::rename(&tmp, &thumbnail).await?;
+        Ok(thumbnail)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        env::temp_dir,
+        fs::{create_dir_all, remove_dir_all, write},
+        path::Path,
+    };
+
+    use crate::thumbnails::{cache_key, find_poster};
+
+    #[test]
+    fn test_find_poster() {
+        let show_dir = temp_dir().join("test_find_poster").join("the_show");
+        let season_dir = show_dir.join("season1");
+        create_dir_all(&season_dir).unwrap();
+        let video = season_dir.join("the_show_s01_ep01.mp4");
+        assert_eq!(find_poster(&video), None);
+
+        write(show_dir.join("folder.jpg"), b"").unwrap();
+        assert_eq!(find_poster(&video), Some(show_dir.join("folder.jpg")));
+
+        write(season_dir.join("poster.jpg"), b"").unwrap();
+        assert_eq!(find_poster(&video), Some(season_dir.join("poster.jpg")));
+
+        remove_dir_all(temp_dir().join("test_find_poster")).unwrap();
+    }
+
+    #[test]
+    fn test_cache_key() {
+        let path = Path::new("/a/b.mp4");
+        assert_eq!(cache_key(path, 1), cache_key(path, 1));
+        assert_ne!(cache_key(path, 1), cache_key(path, 2));
+        assert_ne!(cache_key(path, 1), cache_key(Path::new("/a/c.mp4"), 1));
+        assert_eq!(
+            cache_key(path, 1),
+            "a200f2e5172f1a6ee7b58e61dc15e5a0d99e2cd9ba612545899afd61e8dc66f8.jpg"
+        );
+    }
+}
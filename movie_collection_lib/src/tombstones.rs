@@ -0,0 +1,469 @@
+use anyhow::{format_err, Error};
+use chrono::{DateTime, Utc};
+use deadpool_postgres::Transaction;
+use serde::{Deserialize, Serialize};
+use stack_string::StackString;
+use tokio_postgres::Row;
+
+use crate::{events::Event, movie_queue::MovieQueueDB, pgpool::PgPool};
+
+/// Tables whose deletions are recorded, and the columns making up the key of
+/// a tombstone for each of them.
+pub const TOMBSTONE_TABLES: [(&str, &[&str]); 7] = [
+    ("imdb_ratings", &["show"]),
+    ("imdb_episodes", &["show", "season", "episode"]),
+    ("movie_collection", &["path"]),
+    ("movie_queue", &["user_email", "queue_name", "path"]),
+    (
+        "trakt_watched_episodes",
+        &["user_email", "link", "season", "episode"],
+    ),
+    ("trakt_watched_movies", &["user_email", "link"]),
+    ("trakt_watchlist", &["user_email", "link"]),
+];
+
+/// Record of a deleted row, so that the deletion replicates through export /
+/// import and the sync routes instead of the row coming back on the next
+/// import. Rows are keyed by their natural key since ids differ between nodes.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct Tombstone {
+    pub table_name: StackString,
+    pub key: Vec<StackString>,
+    pub deleted_at: DateTime<Utc>,
+}
+
+/// A tombstone as stored, with the key as json text, for flat formats like
+/// csv.
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct TombstoneRecord {
+    pub table_name: StackString,
+    pub key: StackString,
+    pub deleted_at: DateTime<Utc>,
+}
+
+impl Tombstone {
+    pub fn new(table_name: &str, key: &[&str]) -> Self {
+        Self {
+            table_name: table_name.into(),
+            key: key.iter().map(|k| (*k).into()).collect(),
+            deleted_at: Utc::now(),
+        }
+    }
+
+    fn validate(&self) -> Result<(), Error> {
+        let (_, columns) = TOMBSTONE_TABLES
+            .iter()
+            .find(|(t, _)| *t == self.table_name.as_str())
+            .ok_or_else(|| format_err!("No tombstones for {}", self.table_name))?;
+        if columns.len() == self.key.len() {
+            Ok(())
+        } else {
+            Err(format_err!(
+                "Invalid key {:?} for {}",
+                self.key,
+                self.table_name
+            ))
+        }
+    }
+
+    /// The key is stored as a json array so that composite keys fit in one
+    /// column.
+    fn key_text(&self) -> Result<String, Error> {
+        serde_json::to_string(&self.key).map_err(Into::into)
+    }
+
+    pub fn to_record(&self) -> Result<TombstoneRecord, Error> {
+        Ok(TombstoneRecord {
+            table_name: self.table_name.clone(),
+            key: self.key_text()?.into(),
+            deleted_at: self.deleted_at,
+        })
+    }
+
+    fn from_row(row: &Row) -> Result<Self, Error> {
+        let key: StackString = row.try_get("key")?;
+        Ok(Self {
+            table_name: row.try_get("table_name")?,
+            key: serde_json::from_str(&key)?,
+            deleted_at: row.try_get("deleted_at")?,
+        })
+    }
+
+    fn key_i32(&self, i: usize) -> Result<i32, Error> {
+        self.key[i].parse().map_err(Into::into)
+    }
+
+    pub async fn record_in(&self, tran: &Transaction<'_>) -> Result<(), Error> {
+        self.validate()?;
+        let key = self.key_text()?;
+        let query = postgres_query::query!(
+            r#"
+                INSERT INTO tombstones (table_name, key, deleted_at, last_modified)
+                VALUES ($table_name, $key, $deleted_at, now())
+                ON CONFLICT (table_name, key) DO UPDATE
+                SET deleted_at=EXCLUDED.deleted_at, last_modified=now()
+            "#,
+            table_name = self.table_name,
+            key = key,
+            deleted_at = self.deleted_at
+        );
+        tran.execute(query.sql(), query.parameters()).await?;
+        Ok(())
+    }
+
+    /// Forget the deletion of a row that has been added again.
+    pub async fn clear_in(
+        tran: &Transaction<'_>,
+        table_name: &str,
+        key: &[&str],
+    ) -> Result<(), Error> {
+        let key = Self::new(table_name, key).key_text()?;
+        let query = postgres_query::query!(
+            "DELETE FROM tombstones WHERE table_name = $table_name AND key = $key",
+            table_name = table_name,
+            key = key
+        );
+        tran.execute(query.sql(), query.parameters()).await?;
+        Ok(())
+    }
+
+    pub async fn get_after_timestamp(
+        timestamp: DateTime<Utc>,
+        pool: &PgPool,
+    ) -> Result<Vec<Self>, Error> {
+        let query = postgres_query::query!(
+            r#"
+                SELECT table_name, key, deleted_at
+                FROM tombstones
+                WHERE last_modified >= $timestamp
+            "#,
+            timestamp = timestamp
+        );
+        pool.get()
+            .await?
+            .query(query.sql(), query.parameters())
+            .await?
+            .iter()
+            .map(Self::from_row)
+            .collect()
+    }
+
+    /// Remove tombstones of deletions older than `before`, a node that hasn't
+    /// synced since then will no longer see those deletions.
+    pub async fn purge(before: DateTime<Utc>, pool: &PgPool) -> Result<u64, Error> {
+        let query = postgres_query::query!(
+            "DELETE FROM tombstones WHERE deleted_at < $before",
+            before = before
+        );
+        pool.get()
+            .await?
+            .execute(query.sql(), query.parameters())
+            .await
+            .map_err(Into::into)
+    }
+
+    /// Apply a tombstone from another node: record it and delete the row,
+    /// unless the row was modified here after it was deleted there.
+    pub async fn apply(&self, pool: &PgPool) -> Result<(), Error> {
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        self.apply_in(&tran).await?;
+        tran.commit().await.map_err(Into::into)
+    }
+
+    /// Returns whether a row was deleted.
+    pub async fn apply_in(&self, tran: &Transaction<'_>) -> Result<bool, Error> {
+        self.validate()?;
+        let deleted = match self.table_name.as_str() {
+            "imdb_ratings" => self.apply_imdb_ratings(tran).await?,
+            "imdb_episodes" => {
+                let query = postgres_query::query!(
+                    r#"
+                        DELETE FROM imdb_episodes
+                        WHERE show = $show AND season = $season AND episode = $episode
+                        AND (last_modified IS NULL OR last_modified <= $deleted_at)
+                    "#,
+                    show = self.key[0],
+                    season = self.key_i32(1)?,
+                    episode = self.key_i32(2)?,
+                    deleted_at = self.deleted_at
+                );
+                tran.execute(query.sql(), query.parameters()).await? > 0
+            }
+            "movie_collection" => self.apply_collection(tran).await?,
+            "movie_queue" => self.apply_queue(tran).await?,
+            "trakt_watched_episodes" => {
+                let query = postgres_query::query!(
+                    r#"
+                        DELETE FROM trakt_watched_episodes
+                        WHERE user_email = $user AND link = $link
+                        AND season = $season AND episode = $episode
+                    "#,
+                    user = self.key[0],
+                    link = self.key[1],
+                    season = self.key_i32(2)?,
+                    episode = self.key_i32(3)?
+                );
+                tran.execute(query.sql(), query.parameters()).await? > 0
+            }
+            "trakt_watched_movies" => {
+                let query = postgres_query::query!(
+                    "DELETE FROM trakt_watched_movies WHERE user_email = $user AND link = $link",
+                    user = self.key[0],
+                    link = self.key[1]
+                );
+                tran.execute(query.sql(), query.parameters()).await? > 0
+            }
+            "trakt_watchlist" => {
+                let query = postgres_query::query!(
+                    "DELETE FROM trakt_watchlist WHERE user_email = $user AND link = $link",
+                    user = self.key[0],
+                    link = self.key[1]
+                );
+                tran.execute(query.sql(), query.parameters()).await? > 0
+            }
+            _ => false,
+        };
+        // Recorded last, removing queue entries records a tombstone with the
+        // local time of the removal. A row that was modified here after the
+        // deletion is kept, and so must not get a tombstone.
+        if deleted || !self.row_exists_in(tran).await? {
+            self.record_in(tran).await?;
+        }
+        Ok(deleted)
+    }
+
+    async fn row_exists_in(&self, tran: &Transaction<'_>) -> Result<bool, Error> {
+        let rows = match self.table_name.as_str() {
+            "imdb_ratings" => {
+                let query = postgres_query::query!(
+                    "SELECT 1 FROM imdb_ratings WHERE show = $show",
+                    show = self.key[0]
+                );
+                tran.query(query.sql(), query.parameters()).await?
+            }
+            "imdb_episodes" => {
+                let season = self.key_i32(1)?;
+                let episode = self.key_i32(2)?;
+                let query = postgres_query::query!(
+                    r#"
+                        SELECT 1 FROM imdb_episodes
+                        WHERE show = $show AND season = $season AND episode = $episode
+                    "#,
+                    show = self.key[0],
+                    season = season,
+                    episode = episode
+                );
+                tran.query(query.sql(), query.parameters()).await?
+            }
+            "movie_collection" => {
+                let query = postgres_query::query!(
+                    "SELECT 1 FROM movie_collection WHERE path = $path",
+                    path = self.key[0]
+                );
+                tran.query(query.sql(), query.parameters()).await?
+            }
+            "movie_queue" => {
+                let query = postgres_query::query!(
+                    r#"
+                        SELECT 1
+                        FROM movie_queue a
+                        JOIN movie_collection b ON a.collection_idx = b.idx
+                        WHERE a.user_email = $user AND a.queue_name = $queue AND b.path = $path
+                    "#,
+                    user = self.key[0],
+                    queue = self.key[1],
+                    path = self.key[2]
+                );
+                tran.query(query.sql(), query.parameters()).await?
+            }
+            "trakt_watched_episodes" => {
+                let season = self.key_i32(2)?;
+                let episode = self.key_i32(3)?;
+                let query = postgres_query::query!(
+                    r#"
+                        SELECT 1 FROM trakt_watched_episodes
+                        WHERE user_email = $user AND link = $link
+                        AND season = $season AND episode = $episode
+                    "#,
+                    user = self.key[0],
+                    link = self.key[1],
+                    season = season,
+                    episode = episode
+                );
+                tran.query(query.sql(), query.parameters()).await?
+            }
+            "trakt_watched_movies" => {
+                let query = postgres_query::query!(
+                    "SELECT 1 FROM trakt_watched_movies WHERE user_email = $user AND link = $link",
+                    user = self.key[0],
+                    link = self.key[1]
+                );
+                tran.query(query.sql(), query.parameters()).await?
+            }
+            "trakt_watchlist" => {
+                let query = postgres_query::query!(
+                    "SELECT 1 FROM trakt_watchlist WHERE user_email = $user AND link = $link",
+                    user = self.key[0],
+                    link = self.key[1]
+                );
+                tran.query(query.sql(), query.parameters()).await?
+            }
+            _ => return Ok(false),
+        };
+        Ok(!rows.is_empty())
+    }
+
+    /// Episodes reference the show and collection entries may, so those go
+    /// with it.
+    async fn apply_imdb_ratings(&self, tran: &Transaction<'_>) -> Result<bool, Error> {
+        let query = postgres_query::query!(
+            r#"
+                SELECT index FROM imdb_ratings
+                WHERE show = $show
+                AND (last_modified IS NULL OR last_modified <= $deleted_at)
+            "#,
+            show = self.key[0],
+            deleted_at = self.deleted_at
+        );
+        let index: i32 = match tran.query(query.sql(), query.parameters()).await?.get(0) {
+            Some(row) => row.try_get("index")?,
+            None => return Ok(false),
+        };
+        let query = postgres_query::query!(
+            "UPDATE movie_collection SET show_id = NULL, last_modified = now() WHERE show_id = $index",
+            index = index
+        );
+        tran.execute(query.sql(), query.parameters()).await?;
+        let query = postgres_query::query!(
+            "DELETE FROM imdb_episodes WHERE show = $show",
+            show = self.key[0]
+        );
+        tran.execute(query.sql(), query.parameters()).await?;
+        let query = postgres_query::query!(
+            "DELETE FROM imdb_ratings WHERE index = $index",
+            index = index
+        );
+        tran.execute(query.sql(), query.parameters()).await?;
+        Ok(true)
+    }
+
+    /// Queue entries reference the collection entry, they are removed
+    /// through `MovieQueueDB` so the remaining entries are renumbered.
+    async fn apply_collection(&self, tran: &Transaction<'_>) -> Result<bool, Error> {
+        let query = postgres_query::query!(
+            r#"
+                SELECT a.user_email, a.queue_name, a.collection_idx
+                FROM movie_queue a
+                JOIN movie_collection b ON a.collection_idx = b.idx
+                WHERE b.path = $path
+                AND (b.last_modified IS NULL OR b.last_modified <= $deleted_at)
+            "#,
+            path = self.key[0],
+            deleted_at = self.deleted_at
+        );
+        for row in tran.query(query.sql(), query.parameters()).await? {
+            let user: StackString = row.try_get("user_email")?;
+            let queue: StackString = row.try_get("queue_name")?;
+            let collection_idx: i32 = row.try_get("collection_idx")?;
+            MovieQueueDB::for_transaction(&user, &queue)
+                .remove_from_queue_by_collection_idx_in(tran, collection_idx)
+                .await?;
+        }
+
+        let query = postgres_query::query!(
+            r#"
+                DELETE FROM movie_collection
+                WHERE path = $path
+                AND (last_modified IS NULL OR last_modified <= $deleted_at)
+            "#,
+            path = self.key[0],
+            deleted_at = self.deleted_at
+        );
+        if tran.execute(query.sql(), query.parameters()).await? > 0 {
+            Event::CollectionChanged.publish_in(tran).await?;
+            Ok(true)
+        } else {
+            Ok(false)
+        }
+    }
+
+    async fn apply_queue(&self, tran: &Transaction<'_>) -> Result<bool, Error> {
+        let query = postgres_query::query!(
+            r#"
+                SELECT a.collection_idx
+                FROM movie_queue a
+                JOIN movie_collection b ON a.collection_idx = b.idx
+                WHERE a.user_email = $user AND a.queue_name = $queue AND b.path = $path
+                AND (a.last_modified IS NULL OR a.last_modified <= $deleted_at)
+            "#,
+            user = self.key[0],
+            queue = self.key[1],
+            path = self.key[2],
+            deleted_at = self.deleted_at
+        );
+        match tran.query(query.sql(), query.parameters()).await?.get(0) {
+            Some(row) => {
+                let collection_idx: i32 = row.try_get("collection_idx")?;
+                MovieQueueDB::for_transaction(&self.key[0], &self.key[1])
+                    .remove_from_queue_by_collection_idx_in(tran, collection_idx)
+                    .await
+            }
+            None => Ok(false),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+    use chrono::{Duration, Utc};
+
+    use crate::{
+        config::Config, movie_collection::MovieCollection, pgpool::PgPool, tombstones::Tombstone,
+    };
+
+    #[test]
+    fn test_tombstone_key() {
+        let tombstone = Tombstone::new("movie_queue", &["", "default", "/a/b \"c\".mp4"]);
+        assert!(tombstone.validate().is_ok());
+        assert_eq!(
+            tombstone.key_text().unwrap(),
+            r#"["","default","/a/b \"c\".mp4"]"#
+        );
+
+        let tombstone = Tombstone::new("movie_queue", &["/a/b.mp4"]);
+        assert!(tombstone.validate().is_err());
+        let tombstone = Tombstone::new("authorized_users", &["user@localhost"]);
+        assert!(tombstone.validate().is_err());
+
+        let tombstone = Tombstone::new("imdb_episodes", &["the_show", "1", "x"]);
+        assert_eq!(tombstone.key_i32(1).unwrap(), 1);
+        assert!(tombstone.key_i32(2).is_err());
+    }
+
+    #[tokio::test]
+    #[ignore]
+    async fn test_apply_keeps_newer_row_without_tombstone() -> Result<(), Error> {
+        let config = Config::with_config()?;
+        let pool = PgPool::new(&config.pgurl)?;
+        let mc = MovieCollection::with_pool(&pool)?;
+        let path = "/tmp/test_tombstone_newer_row.mp4";
+        let start = Utc::now() - Duration::seconds(1);
+        mc.insert_into_collection_by_idx(i32::MAX - 1, path).await?;
+
+        let mut tombstone = Tombstone::new("movie_collection", &[path]);
+        tombstone.deleted_at = Utc::now() - Duration::days(1);
+        tombstone.apply(&pool).await?;
+
+        assert!(mc.get_collection_index(path).await?.is_some());
+        let recorded = Tombstone::get_after_timestamp(start, &pool)
+            .await?
+            .into_iter()
+            .any(|t| t.table_name == "movie_collection" && t.key[0] == path);
+        assert!(!recorded);
+
+        mc.remove_from_collection(path).await?;
+        Ok(())
+    }
+}
@@ -16,9 +16,17 @@ use std::{
 };
 
 use crate::{
-    config::Config, imdb_episodes::ImdbEpisodes, imdb_ratings::ImdbRatings,
-    movie_collection::MovieCollection, movie_queue::MovieQueueDB, pgpool::PgPool,
+    config::Config,
+    events::Event,
+    imdb_episodes::ImdbEpisodes,
+    imdb_ratings::ImdbRatings,
+    movie_collection::MovieCollection,
+    movie_queue::{MovieQueueDB, DEFAULT_QUEUE, DEFAULT_USER},
+    pgpool::PgPool,
+    query_builder::QueryBuilder,
+    tombstones::Tombstone,
     trakt_connection::TraktConnection,
+    watched_import::get_imported_links,
 };
 
 use crate::{tv_show_source::TvShowSource, utils::option_string_wrapper};
@@ -152,10 +160,15 @@ impl fmt::Display for WatchListShow {
 }
 
 impl WatchListShow {
-    pub async fn get_show_by_link(link: &str, pool: &PgPool) -> Result<Option<Self>, Error> {
+    pub async fn get_show_by_link(
+        link: &str,
+        pool: &PgPool,
+        user: &str,
+    ) -> Result<Option<Self>, Error> {
         let query = postgres_query::query!(
-            "SELECT title, year FROM trakt_watchlist WHERE link = $link",
-            link = link
+            "SELECT title, year FROM trakt_watchlist WHERE link = $link AND user_email = $user",
+            link = link,
+            user = user
         );
         if let Some(row) = pool
             .get()
@@ -176,10 +189,11 @@ impl WatchListShow {
         }
     }
 
-    pub async fn get_index(&self, pool: &PgPool) -> Result<Option<i32>, Error> {
+    pub async fn get_index(&self, pool: &PgPool, user: &str) -> Result<Option<i32>, Error> {
         let query = postgres_query::query!(
-            "SELECT id FROM trakt_watchlist WHERE link = $link",
-            link = self.link
+            "SELECT id FROM trakt_watchlist WHERE link = $link AND user_email = $user",
+            link = self.link,
+            user = user
         );
         if let Some(row) = pool
             .get()
@@ -195,43 +209,55 @@ impl WatchListShow {
         }
     }
 
-    pub async fn insert_show(&self, pool: &PgPool) -> Result<(), Error> {
+    pub async fn insert_show(&self, pool: &PgPool, user: &str) -> Result<(), Error> {
         let query = postgres_query::query!(
-            "INSERT INTO trakt_watchlist (link, title, year) VALUES ($link, $title, $year)",
+            r#"
+                INSERT INTO trakt_watchlist (user_email, link, title, year)
+                VALUES ($user, $link, $title, $year)
+            "#,
+            user = user,
             link = self.link,
             title = self.title,
             year = self.year
         );
-        pool.get()
-            .await?
-            .execute(query.sql(), query.parameters())
-            .await
-            .map(|_| ())
-            .map_err(Into::into)
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        tran.execute(query.sql(), query.parameters()).await?;
+        Tombstone::clear_in(&tran, "trakt_watchlist", &[user, self.link.as_str()]).await?;
+        tran.commit().await.map_err(Into::into)
     }
 
-    pub async fn delete_show(&self, pool: &PgPool) -> Result<(), Error> {
+    pub async fn delete_show(&self, pool: &PgPool, user: &str) -> Result<(), Error> {
         let query = postgres_query::query!(
-            "DELETE FROM trakt_watchlist WHERE link=$link",
-            link = self.link
+            "DELETE FROM trakt_watchlist WHERE link=$link AND user_email=$user",
+            link = self.link,
+            user = user
         );
-        pool.get()
-            .await?
-            .execute(query.sql(), query.parameters())
-            .await
-            .map(|_| ())
-            .map_err(Into::into)
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        tran.execute(query.sql(), query.parameters()).await?;
+        Tombstone::new("trakt_watchlist", &[user, self.link.as_str()])
+            .record_in(&tran)
+            .await?;
+        tran.commit().await.map_err(Into::into)
     }
 }
 
-pub async fn get_watchlist_shows_db(pool: &PgPool) -> Result<HashSet<WatchListShow>, Error> {
-    let query = r#"
-        SELECT a.link, a.title, a.year
-        FROM trakt_watchlist a
-    "#;
+pub async fn get_watchlist_shows_db(
+    pool: &PgPool,
+    user: &str,
+) -> Result<HashSet<WatchListShow>, Error> {
+    let query = postgres_query::query!(
+        r#"
+            SELECT a.link, a.title, a.year
+            FROM trakt_watchlist a
+            WHERE a.user_email = $user
+        "#,
+        user = user
+    );
     pool.get()
         .await?
-        .query(query, &[])
+        .query(query.sql(), query.parameters())
         .await?
         .iter()
         .map(|row| {
@@ -241,9 +267,21 @@ pub async fn get_watchlist_shows_db(pool: &PgPool) -> Result<HashSet<WatchListSh
         .collect()
 }
 
+/// Users with at least one show on their watchlist.
+pub async fn get_watchlist_users(pool: &PgPool) -> Result<Vec<StackString>, Error> {
+    let query = "SELECT DISTINCT user_email FROM trakt_watchlist ORDER BY user_email";
+    pool.get()
+        .await?
+        .query(query, &[])
+        .await?
+        .iter()
+        .map(|row| row.try_get("user_email").map_err(Into::into))
+        .collect()
+}
+
 pub type WatchListMap = HashMap<StackString, (StackString, WatchListShow, Option<TvShowSource>)>;
 
-pub async fn get_watchlist_shows_db_map(pool: &PgPool) -> Result<WatchListMap, Error> {
+pub async fn get_watchlist_shows_db_map(pool: &PgPool, user: &str) -> Result<WatchListMap, Error> {
     #[derive(FromSqlRow)]
     struct WatchlistShowDbMap {
         show: StackString,
@@ -253,15 +291,19 @@ pub async fn get_watchlist_shows_db_map(pool: &PgPool) -> Result<WatchListMap, E
         source: Option<StackString>,
     }
 
-    let query = r#"
-        SELECT b.show, a.link, a.title, a.year, b.source
-        FROM trakt_watchlist a
-        JOIN imdb_ratings b ON a.link=b.link
-    "#;
+    let query = postgres_query::query!(
+        r#"
+            SELECT b.show, a.link, a.title, a.year, b.source
+            FROM trakt_watchlist a
+            JOIN imdb_ratings b ON a.link=b.link
+            WHERE a.user_email = $user
+        "#,
+        user = user
+    );
 
     pool.get()
         .await?
-        .query(query, &[])
+        .query(query.sql(), query.parameters())
         .await?
         .iter()
         .map(|row| {
@@ -307,13 +349,14 @@ impl fmt::Display for WatchedEpisode {
 }
 
 impl WatchedEpisode {
-    pub async fn get_index(&self, pool: &PgPool) -> Result<Option<i32>, Error> {
+    pub async fn get_index(&self, pool: &PgPool, user: &str) -> Result<Option<i32>, Error> {
         let query = postgres_query::query!(
             r#"
                 SELECT id
                 FROM trakt_watched_episodes
-                WHERE link=$link AND season=$season AND episode=$episode
+                WHERE user_email=$user AND link=$link AND season=$season AND episode=$episode
             "#,
+            user = user,
             link = self.imdb_url,
             season = self.season,
             episode = self.episode
@@ -337,14 +380,17 @@ impl WatchedEpisode {
         link: &str,
         season: i32,
         episode: i32,
+        user: &str,
     ) -> Result<Option<Self>, Error> {
         let query = postgres_query::query!(
             r#"
                 SELECT a.link, b.title
                 FROM trakt_watched_episodes a
                 JOIN imdb_ratings b ON a.link = b.link
-                WHERE a.link = $link AND a.season = $season AND a.episode = $episode
+                WHERE a.user_email = $user AND a.link = $link AND a.season = $season
+                    AND a.episode = $episode
             "#,
+            user = user,
             link = link,
             season = season,
             episode = episode
@@ -369,40 +415,60 @@ impl WatchedEpisode {
         }
     }
 
-    pub async fn insert_episode(&self, pool: &PgPool) -> Result<(), Error> {
+    pub async fn insert_episode(&self, pool: &PgPool, user: &str) -> Result<(), Error> {
         let query = postgres_query::query!(
             r#"
-                INSERT INTO trakt_watched_episodes (link, season, episode)
-                VALUES ($link, $season, $episode)
+                INSERT INTO trakt_watched_episodes (user_email, link, season, episode)
+                VALUES ($user, $link, $season, $episode)
             "#,
+            user = user,
             link = self.imdb_url,
             season = self.season,
             episode = self.episode
         );
-        pool.get()
-            .await?
-            .execute(query.sql(), query.parameters())
-            .await
-            .map(|_| ())
-            .map_err(Into::into)
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        tran.execute(query.sql(), query.parameters()).await?;
+        Tombstone::clear_in(
+            &tran,
+            "trakt_watched_episodes",
+            &[
+                user,
+                self.imdb_url.as_str(),
+                &self.season.to_string(),
+                &self.episode.to_string(),
+            ],
+        )
+        .await?;
+        tran.commit().await.map_err(Into::into)
     }
 
-    pub async fn delete_episode(&self, pool: &PgPool) -> Result<(), Error> {
+    pub async fn delete_episode(&self, pool: &PgPool, user: &str) -> Result<(), Error> {
         let query = postgres_query::query!(
             r#"
             DELETE FROM trakt_watched_episodes
-            WHERE link=$link AND season=$season AND episode=$episode
+            WHERE user_email=$user AND link=$link AND season=$season AND episode=$episode
         "#,
+            user = user,
             link = self.imdb_url,
             season = self.season,
             episode = self.episode
         );
-        pool.get()
-            .await?
-            .execute(query.sql(), query.parameters())
-            .await
-            .map(|_| ())
-            .map_err(Into::into)
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        tran.execute(query.sql(), query.parameters()).await?;
+        Tombstone::new(
+            "trakt_watched_episodes",
+            &[
+                user,
+                self.imdb_url.as_str(),
+                &self.season.to_string(),
+                &self.episode.to_string(),
+            ],
+        )
+        .record_in(&tran)
+        .await?;
+        tran.commit().await.map_err(Into::into)
     }
 }
 
@@ -410,22 +476,18 @@ pub async fn get_watched_shows_db(
     pool: &PgPool,
     show: &str,
     season: Option<i32>,
+    user: &str,
 ) -> Result<Vec<WatchedEpisode>, Error> {
-    let mut where_vec = Vec::new();
+    let mut builder = QueryBuilder::new();
+    builder.and_eq("a.user_email", user.to_string());
     if !show.is_empty() {
-        where_vec.push(format!("show='{}'", show));
+        builder.and_eq("b.show", show.to_string());
     }
     if let Some(season) = season {
-        where_vec.push(format!("season={}", season));
+        builder.and_eq("a.season", season);
     }
 
-    let where_str = if where_vec.is_empty() {
-        "".to_string()
-    } else {
-        format!("WHERE {}", where_vec.join(" AND "))
-    };
-
-    let query = postgres_query::query_dyn!(&format!(
+    let query = format!(
         r#"
             SELECT a.link, b.title, a.season, a.episode
             FROM trakt_watched_episodes a
@@ -433,12 +495,12 @@ pub async fn get_watched_shows_db(
             {}
             ORDER BY 2,3,4
         "#,
-        where_str
-    ))?;
+        builder.where_clause()
+    );
 
     pool.get()
         .await?
-        .query(query.sql(), &[])
+        .query(query.as_str(), &builder.params())
         .await?
         .iter()
         .map(|row| {
@@ -490,13 +552,14 @@ impl fmt::Display for WatchedMovie {
 }
 
 impl WatchedMovie {
-    pub async fn get_index(&self, pool: &PgPool) -> Result<Option<i32>, Error> {
+    pub async fn get_index(&self, pool: &PgPool, user: &str) -> Result<Option<i32>, Error> {
         let query = postgres_query::query!(
             r#"
                 SELECT id
                 FROM trakt_watched_movies
-                WHERE link=$link
+                WHERE user_email=$user AND link=$link
             "#,
+            user = user,
             link = self.imdb_url
         );
         if let Some(row) = pool
@@ -513,14 +576,19 @@ impl WatchedMovie {
         }
     }
 
-    pub async fn get_watched_movie(pool: &PgPool, link: &str) -> Result<Option<Self>, Error> {
+    pub async fn get_watched_movie(
+        pool: &PgPool,
+        link: &str,
+        user: &str,
+    ) -> Result<Option<Self>, Error> {
         let query = postgres_query::query!(
             r#"
                 SELECT a.link, b.title
                 FROM trakt_watched_movies a
                 JOIN imdb_ratings b ON a.link = b.link
-                WHERE a.link = $link
+                WHERE a.user_email = $user AND a.link = $link
             "#,
+            user = user,
             link = link
         );
         if let Some(row) = pool
@@ -538,51 +606,60 @@ impl WatchedMovie {
         }
     }
 
-    pub async fn insert_movie(&self, pool: &PgPool) -> Result<(), Error> {
+    pub async fn insert_movie(&self, pool: &PgPool, user: &str) -> Result<(), Error> {
         let query = postgres_query::query!(
             r#"
-                INSERT INTO trakt_watched_movies (link)
-                VALUES ($link)
+                INSERT INTO trakt_watched_movies (user_email, link)
+                VALUES ($user, $link)
             "#,
+            user = user,
             link = self.imdb_url
         );
-        pool.get()
-            .await?
-            .execute(query.sql(), query.parameters())
-            .await
-            .map(|_| ())
-            .map_err(Into::into)
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        tran.execute(query.sql(), query.parameters()).await?;
+        Tombstone::clear_in(
+            &tran,
+            "trakt_watched_movies",
+            &[user, self.imdb_url.as_str()],
+        )
+        .await?;
+        tran.commit().await.map_err(Into::into)
     }
 
-    pub async fn delete_movie(&self, pool: &PgPool) -> Result<(), Error> {
+    pub async fn delete_movie(&self, pool: &PgPool, user: &str) -> Result<(), Error> {
         let query = postgres_query::query!(
             r#"
                 DELETE FROM trakt_watched_movies
-                WHERE link=$link
+                WHERE user_email=$user AND link=$link
             "#,
+            user = user,
             link = self.imdb_url
         );
-        pool.get()
-            .await?
-            .execute(query.sql(), query.parameters())
-            .await
-            .map(|_| ())
-            .map_err(Into::into)
+        let mut conn = pool.get().await?;
+        let tran = conn.transaction().await?;
+        tran.execute(query.sql(), query.parameters()).await?;
+        Tombstone::new("trakt_watched_movies", &[user, self.imdb_url.as_str()])
+            .record_in(&tran)
+            .await?;
+        tran.commit().await.map_err(Into::into)
     }
 }
 
-pub async fn get_watched_movies_db(pool: &PgPool) -> Result<Vec<WatchedMovie>, Error> {
+pub async fn get_watched_movies_db(pool: &PgPool, user: &str) -> Result<Vec<WatchedMovie>, Error> {
     let query = postgres_query::query!(
         r#"
             SELECT a.link, b.title
             FROM trakt_watched_movies a
             JOIN imdb_ratings b ON a.link = b.link
+            WHERE a.user_email = $user
             ORDER BY b.show
-        "#
+        "#,
+        user = user
     );
     pool.get()
         .await?
-        .query(query.sql(), &[])
+        .query(query.sql(), query.parameters())
         .await?
         .iter()
         .map(|row| {
@@ -594,7 +671,12 @@ pub async fn get_watched_movies_db(pool: &PgPool) -> Result<Vec<WatchedMovie>, E
 }
 
 pub async fn sync_trakt_with_db(mc: &MovieCollection) -> Result<(), Error> {
-    let watchlist_shows_db = Arc::new(get_watchlist_shows_db(&mc.pool).await?);
+    sync_trakt_worker(mc).await?;
+    Event::TraktSyncComplete.publish(&mc.pool).await
+}
+
+async fn sync_trakt_worker(mc: &MovieCollection) -> Result<(), Error> {
+    let watchlist_shows_db = Arc::new(get_watchlist_shows_db(&mc.pool, DEFAULT_USER).await?);
     TRAKT_CONN.init().await;
     let watchlist_shows = TRAKT_CONN.get_watchlist_shows().await?;
     if watchlist_shows.is_empty() {
@@ -605,7 +687,7 @@ pub async fn sync_trakt_with_db(mc: &MovieCollection) -> Result<(), Error> {
         let watchlist_shows_db = watchlist_shows_db.clone();
         async move {
             if !watchlist_shows_db.contains(link.as_str()) {
-                show.insert_show(&mc.pool).await?;
+                show.insert_show(&mc.pool, DEFAULT_USER).await?;
                 mc.stdout.send(format!("insert watchlist {}", show));
             }
             Ok(())
@@ -615,7 +697,7 @@ pub async fn sync_trakt_with_db(mc: &MovieCollection) -> Result<(), Error> {
     results?;
 
     let watched_shows_db: HashMap<(StackString, i32, i32), _> =
-        get_watched_shows_db(&mc.pool, "", None)
+        get_watched_shows_db(&mc.pool, "", None, DEFAULT_USER)
             .await?
             .into_iter()
             .map(|s| ((s.imdb_url.clone(), s.season, s.episode), s))
@@ -629,7 +711,7 @@ pub async fn sync_trakt_with_db(mc: &MovieCollection) -> Result<(), Error> {
         let watched_shows_db = watched_shows_db.clone();
         async move {
             if !watched_shows_db.contains_key(&key) {
-                episode.insert_episode(&mc.pool).await?;
+                episode.insert_episode(&mc.pool, DEFAULT_USER).await?;
                 mc.stdout.send(format!("insert watched {}", episode));
             }
             Ok(())
@@ -638,8 +720,10 @@ pub async fn sync_trakt_with_db(mc: &MovieCollection) -> Result<(), Error> {
     let results: Result<Vec<_>, Error> = try_join_all(futures).await;
     results?;
 
-    let watched_movies_db: HashSet<_> =
-        get_watched_movies_db(&mc.pool).await?.into_iter().collect();
+    let watched_movies_db: HashSet<_> = get_watched_movies_db(&mc.pool, DEFAULT_USER)
+        .await?
+        .into_iter()
+        .collect();
     let watched_movies_db = Arc::new(watched_movies_db);
     let watched_movies = TRAKT_CONN.get_watched_movies().await?;
     let watched_movies = Arc::new(watched_movies);
@@ -651,7 +735,7 @@ pub async fn sync_trakt_with_db(mc: &MovieCollection) -> Result<(), Error> {
         let watched_movies_db = watched_movies_db.clone();
         async move {
             if !watched_movies_db.contains(movie.imdb_url.as_str()) {
-                movie.insert_movie(&mc.pool).await?;
+                movie.insert_movie(&mc.pool, DEFAULT_USER).await?;
                 mc.stdout.send(format!("insert watched {}", movie));
             }
             Ok(())
@@ -660,11 +744,16 @@ pub async fn sync_trakt_with_db(mc: &MovieCollection) -> Result<(), Error> {
     let results: Result<Vec<_>, Error> = try_join_all(futures).await;
     results?;
 
+    // Movies imported from an imdb or Letterboxd history aren't on trakt
+    let imported = Arc::new(get_imported_links(&mc.pool, DEFAULT_USER).await?);
     let futures = watched_movies_db.iter().map(|movie| {
         let watched_movies = watched_movies.clone();
+        let imported = imported.clone();
         async move {
-            if !watched_movies.contains(movie.imdb_url.as_str()) {
-                movie.delete_movie(&mc.pool).await?;
+            if !watched_movies.contains(movie.imdb_url.as_str())
+                && !imported.contains(&movie.imdb_url)
+            {
+                movie.delete_movie(&mc.pool, DEFAULT_USER).await?;
                 mc.stdout.send(format!("delete watched {}", movie));
             }
             Ok(())
@@ -738,7 +827,7 @@ async fn watchlist_add(mc: &MovieCollection, show: Option<&str>) -> Result<(), E
             .get(imdb_url.as_str())
         {
             debug!("INSERT SHOW {}", show);
-            show.insert_show(&mc.pool).await?;
+            show.insert_show(&mc.pool, DEFAULT_USER).await?;
         }
     }
     Ok(())
@@ -752,15 +841,17 @@ async fn watchlist_rm(mc: &MovieCollection, show: Option<&str>) -> Result<(), Er
             "result: {}",
             TRAKT_CONN.remove_watchlist_show(&imdb_url_).await?
         ));
-        if let Some(show) = WatchListShow::get_show_by_link(&imdb_url, &mc.pool).await? {
-            show.delete_show(&mc.pool).await?;
+        if let Some(show) =
+            WatchListShow::get_show_by_link(&imdb_url, &mc.pool, DEFAULT_USER).await?
+        {
+            show.delete_show(&mc.pool, DEFAULT_USER).await?;
         }
     }
     Ok(())
 }
 
 async fn watchlist_list(mc: &MovieCollection) -> Result<(), Error> {
-    let show_map = get_watchlist_shows_db(&mc.pool).await?;
+    let show_map = get_watchlist_shows_db(&mc.pool, DEFAULT_USER).await?;
     let results: Vec<_> = show_map.iter().map(ToString::to_string).collect();
     mc.stdout.send(results.join("\n"));
     Ok(())
@@ -787,7 +878,7 @@ async fn watched_add(
                     episode: *epi,
                     ..WatchedEpisode::default()
                 }
-                .insert_episode(&mc.pool)
+                .insert_episode(&mc.pool, DEFAULT_USER)
                 .await?;
             }
         } else {
@@ -797,7 +888,7 @@ async fn watched_add(
                 imdb_url,
                 title: "".into(),
             }
-            .insert_movie(&mc.pool)
+            .insert_movie(&mc.pool, DEFAULT_USER)
             .await?;
         }
     }
@@ -819,17 +910,25 @@ async fn watched_rm(
                 TRAKT_CONN
                     .remove_episode_to_watched(&imdb_url_, season, epi_)
                     .await?;
-                if let Some(epi_) =
-                    WatchedEpisode::get_watched_episode(&mc.pool, &imdb_url, season, *epi).await?
+                if let Some(epi_) = WatchedEpisode::get_watched_episode(
+                    &mc.pool,
+                    &imdb_url,
+                    season,
+                    *epi,
+                    DEFAULT_USER,
+                )
+                .await?
                 {
-                    epi_.delete_episode(&mc.pool).await?;
+                    epi_.delete_episode(&mc.pool, DEFAULT_USER).await?;
                 }
             }
         } else {
             let imdb_url_ = imdb_url.clone();
             TRAKT_CONN.remove_movie_to_watched(&imdb_url_).await?;
-            if let Some(movie) = WatchedMovie::get_watched_movie(&mc.pool, &imdb_url).await? {
-                movie.delete_movie(&mc.pool).await?;
+            if let Some(movie) =
+                WatchedMovie::get_watched_movie(&mc.pool, &imdb_url, DEFAULT_USER).await?
+            {
+                movie.delete_movie(&mc.pool, DEFAULT_USER).await?;
             }
         }
     }
@@ -837,8 +936,8 @@ async fn watched_rm(
 }
 
 async fn watched_list(mc: &MovieCollection, show: Option<&str>, season: i32) -> Result<(), Error> {
-    let watched_shows = get_watched_shows_db(&mc.pool, "", None).await?;
-    let watched_movies = get_watched_movies_db(&mc.pool).await?;
+    let watched_shows = get_watched_shows_db(&mc.pool, "", None, DEFAULT_USER).await?;
+    let watched_movies = get_watched_movies_db(&mc.pool, DEFAULT_USER).await?;
 
     if let Some(imdb_url) = get_imdb_url_from_show(&mc, show).await? {
         let lines: Vec<_> = watched_shows
@@ -902,34 +1001,46 @@ pub async fn trakt_app_parse(
     mc.stdout.close().await
 }
 
+#[derive(Serialize)]
+pub struct WatchedSeasonEpisode {
+    pub episode: i32,
+    pub eptitle: StackString,
+    pub epurl: StackString,
+    pub rating: f64,
+    pub airdate: NaiveDate,
+    /// Set when the episode is queued and can be played
+    pub collection_idx: Option<i32>,
+    pub watched: bool,
+}
+
+#[derive(Serialize)]
+pub struct WatchedSeason {
+    pub show: StackString,
+    pub link: StackString,
+    pub rating: f64,
+    pub season: i32,
+    pub episodes: Vec<WatchedSeasonEpisode>,
+}
+
 pub async fn watch_list_http_worker(
     pool: &PgPool,
     imdb_url: &str,
     season: i32,
-) -> Result<StackString, Error> {
-    let button_add = format!(
-        "{}{}",
-        r#"<button type="submit" id="ID" "#,
-        r#"onclick="watched_add('SHOW', SEASON, EPISODE);">add to watched</button>"#
-    );
-    let button_rm = format!(
-        "{}{}",
-        r#"<button type="submit" id="ID" "#,
-        r#"onclick="watched_rm('SHOW', SEASON, EPISODE);">remove from watched</button>"#
-    );
-
+    user: &str,
+) -> Result<WatchedSeason, Error> {
     let mc = MovieCollection::with_pool(&pool)?;
-    let mq = MovieQueueDB::with_pool(&pool);
+    let mq = MovieQueueDB::with_user(&pool, user, DEFAULT_QUEUE);
 
     let show = ImdbRatings::get_show_by_link(imdb_url, &pool)
         .await?
         .ok_or_else(|| format_err!("Show Doesn't exist"))?;
 
-    let watched_episodes_db: HashSet<i32> = get_watched_shows_db(&pool, &show.show, Some(season))
-        .await?
-        .into_iter()
-        .map(|s| s.episode)
-        .collect();
+    let watched_episodes_db: HashSet<i32> =
+        get_watched_shows_db(&pool, &show.show, Some(season), user)
+            .await?
+            .into_iter()
+            .map(|s| s.episode)
+            .collect();
 
     let queue: HashMap<(StackString, i32, i32), _> = mq
         .print_movie_queue(&[show.show.as_str()])
@@ -958,88 +1069,51 @@ pub async fn watch_list_http_worker(
         }
     }
 
-    let entries: Vec<_> = entries
-        .iter()
-        .map(|s| {
-            let entry = if let Some(collection_idx) = collection_idx_map.get(&s.episode) {
-                format!(
-                    r#"<a href="javascript:updateMainArticle('{}');">{}</a>"#,
-                    &format!("{}/{}", "/list/play", collection_idx),
-                    s.eptitle
-                )
-            } else {
-                s.eptitle.to_string()
-            };
-
-            format!(
-                "<tr><td>{}</td><td><td>{}</td><td>{}</td><td>{}</td><td>{}</td><td>{}</td></tr>",
-                show.show,
-                entry,
-                format!(
-                    r#"<a href="https://www.imdb.com/title/{}" target="_blank">s{} ep{}</a>"#,
-                    s.epurl, season, s.episode,
-                ),
-                format!(
-                    "rating: {:0.1} / {:0.1}",
-                    s.rating,
-                    show.rating.as_ref().unwrap_or(&-1.0)
-                ),
-                s.airdate,
-                if watched_episodes_db.contains(&s.episode) {
-                    button_rm
-                        .replace("SHOW", &show.link)
-                        .replace("SEASON", &season.to_string())
-                        .replace("EPISODE", &s.episode.to_string())
-                } else {
-                    button_add
-                        .replace("SHOW", &show.link)
-                        .replace("SEASON", &season.to_string())
-                        .replace("EPISODE", &s.episode.to_string())
-                }
-            )
+    let episodes = entries
+        .into_iter()
+        .map(|s| WatchedSeasonEpisode {
+            episode: s.episode,
+            collection_idx: collection_idx_map.get(&s.episode).copied(),
+            watched: watched_episodes_db.contains(&s.episode),
+            eptitle: s.eptitle,
+            epurl: s.epurl,
+            rating: s.rating,
+            airdate: s.airdate,
         })
         .collect();
 
-    let previous = format!(
-        r#"<a href="javascript:updateMainArticle('/list/trakt/watched/list/{}')">Go Back</a><br>"#,
-        imdb_url
-    );
-    let buttons = format!(
-        r#"
-        <button name="remcomout" id="remcomoutput"> &nbsp; </button>
-        <button type="submit" id="ID"
-            onclick="imdb_update('{show}', '{link}', {season},
-            '/list/trakt/watched/list/{link}/{season}');"
-            >update database</button><br>
-    "#,
-        show = show.show,
-        link = show.link,
-        season = season
-    );
-
-    let entries = format!(
-        r#"{}{}<table border="0">{}</table>"#,
-        previous,
-        buttons,
-        entries.join("\n")
-    )
-    .into();
-    Ok(entries)
+    Ok(WatchedSeason {
+        rating: show.rating.unwrap_or(-1.0),
+        show: show.show,
+        link: show.link,
+        season,
+        episodes,
+    })
 }
 
+/// Only `DEFAULT_USER` is linked to the trakt account, watched marks for other
+/// users are kept in the database alone.
 pub async fn watched_action_http_worker(
     pool: &PgPool,
     action: TraktActions,
     imdb_url: &str,
     season: i32,
     episode: i32,
+    user: &str,
 ) -> Result<StackString, Error> {
     let mc = MovieCollection::with_pool(&pool)?;
     let imdb_url = Arc::new(imdb_url.to_owned());
-    TRAKT_CONN.init().await;
+    let use_trakt = user == DEFAULT_USER;
+    if use_trakt {
+        TRAKT_CONN.init().await;
+    }
     let body = match action {
         TraktActions::Add => {
-            let result = if season != -1 && episode != -1 {
+            let result = if !use_trakt {
+                TraktResult {
+                    status: "local".into(),
+                }
+            } else if season != -1 && episode != -1 {
                 let imdb_url_ = Arc::clone(&imdb_url);
                 TRAKT_CONN
                     .add_episode_to_watched(&imdb_url_, season, episode)
@@ -1055,14 +1129,14 @@ pub async fn watched_action_http_worker(
                     episode,
                     ..WatchedEpisode::default()
                 }
-                .insert_episode(&mc.pool)
+                .insert_episode(&mc.pool, user)
                 .await?;
             } else {
                 WatchedMovie {
                     imdb_url: imdb_url.to_string().into(),
                     title: "".into(),
                 }
-                .insert_movie(&mc.pool)
+                .insert_movie(&mc.pool, user)
                 .await?;
             }
 
@@ -1070,7 +1144,11 @@ pub async fn watched_action_http_worker(
         }
         TraktActions::Remove => {
             let imdb_url_ = Arc::clone(&imdb_url);
-            let result = if season != -1 && episode != -1 {
+            let result = if !use_trakt {
+                TraktResult {
+                    status: "local".into(),
+                }
+            } else if season != -1 && episode != -1 {
                 TRAKT_CONN
                     .remove_episode_to_watched(&imdb_url_, season, episode)
                     .await?
@@ -1080,14 +1158,15 @@ pub async fn watched_action_http_worker(
 
             if season != -1 && episode != -1 {
                 if let Some(epi_) =
-                    WatchedEpisode::get_watched_episode(&mc.pool, &imdb_url, season, episode)
+                    WatchedEpisode::get_watched_episode(&mc.pool, &imdb_url, season, episode, user)
                         .await?
                 {
-                    epi_.delete_episode(&mc.pool).await?;
+                    epi_.delete_episode(&mc.pool, user).await?;
                 }
-            } else if let Some(movie) = WatchedMovie::get_watched_movie(&mc.pool, &imdb_url).await?
+            } else if let Some(movie) =
+                WatchedMovie::get_watched_movie(&mc.pool, &imdb_url, user).await?
             {
-                movie.delete_movie(&mc.pool).await?;
+                movie.delete_movie(&mc.pool, user).await?;
             };
 
             format!("{}", result)
@@ -1098,13 +1177,21 @@ pub async fn watched_action_http_worker(
     Ok(body)
 }
 
-pub async fn trakt_cal_http_worker(pool: &PgPool) -> Result<Vec<StackString>, Error> {
-    let button_add = format!(
-        "{}{}",
-        r#"<td><button type="submit" id="ID" "#,
-        r#"onclick="imdb_update('SHOW', 'LINK', SEASON, '/list/trakt/cal');"
-            >update database</button></td>"#
-    );
+#[derive(Serialize)]
+pub struct TraktCalHttpEntry {
+    /// Show name in imdb_ratings, empty if the show isn't in the database
+    pub show: StackString,
+    pub title: StackString,
+    pub link: StackString,
+    pub season: i32,
+    pub episode: i32,
+    pub ep_link: Option<StackString>,
+    pub airdate: NaiveDate,
+    /// Whether the episode is already in imdb_episodes
+    pub in_database: bool,
+}
+
+pub async fn trakt_cal_http_worker(pool: &PgPool) -> Result<Vec<TraktCalHttpEntry>, Error> {
     TRAKT_CONN.init().await;
     let cal_list = TRAKT_CONN.get_calendar().await?;
     let results: Vec<_> = cal_list
@@ -1114,53 +1201,36 @@ pub async fn trakt_cal_http_worker(pool: &PgPool) -> Result<Vec<StackString>, Er
                 Some(s) => s.show,
                 None => "".into(),
             };
-            let exists = if show.is_empty() {None} else {
+            let exists = if show.is_empty() {
+                None
+            } else {
                 let idx_opt = ImdbEpisodes {
                     show: show.clone(),
                     season: cal.season,
                     episode: cal.episode,
                     ..ImdbEpisodes::default()
                 }
-                .get_index(&pool).await?;
+                .get_index(&pool)
+                .await?;
 
                 match idx_opt {
                     Some(idx) => ImdbEpisodes::from_index(idx, &pool).await?,
                     None => None,
                 }
             };
+            let in_database = exists.is_some();
+            let ep_link = cal.ep_link.or_else(|| exists.map(|e| e.epurl));
 
-            let entry = format!(
-                "<tr><td>{}</td><td>{}</td><td>{}</td><td>{}</td>{}</tr>",
-                format!(
-                    r#"<a href="javascript:updateMainArticle('/list/trakt/watched/list/{}/{}')">{}</a>"#,
-                    cal.link, cal.season, cal.show,
-                ),
-                format!(
-                    r#"<a href="https://www.imdb.com/title/{}" target="_blank">imdb</a>"#,
-                    cal.link
-                ),
-                if let Some(link) = cal.ep_link {
-                    format!(
-                        r#"<a href="https://www.imdb.com/title/{}" target="_blank">{} {}</a>"#,
-                        link, cal.season, cal.episode,
-                    )
-                } else if let Some(link) = exists.as_ref() {
-                    format!(
-                        r#"<a href="https://www.imdb.com/title/{}" target="_blank">{} {}</a>"#,
-                        link, cal.season, cal.episode,
-                    )
-                } else {
-                    format!("{} {}", cal.season, cal.episode,)
-                },
-                cal.airdate,
-                if exists.is_some() {"".to_string()} else {
-                    button_add
-                        .replace("SHOW", &show)
-                        .replace("LINK", &cal.link)
-                        .replace("SEASON", &cal.season.to_string())
-                },
-            ).into();
-            Ok(entry)
+            Ok(TraktCalHttpEntry {
+                show,
+                title: cal.show,
+                link: cal.link,
+                season: cal.season,
+                episode: cal.episode,
+                ep_link,
+                airdate: cal.airdate,
+                in_database,
+            })
         })
         .collect();
     join_all(results).await.into_iter().collect()
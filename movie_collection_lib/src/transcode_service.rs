@@ -9,6 +9,7 @@ use lapin::{
     types::FieldTable,
     BasicProperties, Channel, Queue,
 };
+use log::debug;
 use serde::{Deserialize, Serialize};
 use stack_string::StackString;
 use std::{
@@ -23,8 +24,10 @@ use tokio::{
 };
 
 use crate::{
-    config::Config, make_queue::make_queue_worker, movie_collection::MovieCollection,
-    stdout_channel::StdoutChannel, utils::parse_file_stem,
+    auto_queue::auto_queue_new_episodes, config::Config, events::Event,
+    make_queue::make_queue_worker, movie_collection::MovieCollection, movie_queue::DEFAULT_QUEUE,
+    pgpool::PgPool, stdout_channel::StdoutChannel, subtitles::find_sidecar_for_language,
+    utils::parse_file_stem,
 };
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
@@ -33,12 +36,57 @@ pub enum JobType {
     Move,
 }
 
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone, Copy)]
+pub enum SubtitleMode {
+    Burn,
+    PassThrough,
+}
+
+/// Subtitle language to keep when transcoding, either burned into the video
+/// or passed through as a soft track.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct SubtitleOption {
+    pub language: StackString,
+    pub mode: SubtitleMode,
+}
+
+impl SubtitleOption {
+    /// `HandBrakeCLI` arguments, a matching srt sidecar takes precedence over
+    /// embedded subtitle streams.
+    fn handbrake_args(&self, sidecar: Option<&Path>) -> Vec<String> {
+        let language = self.language.to_string();
+        let burn = self.mode == SubtitleMode::Burn;
+        let mut args = Vec::new();
+        if let Some(sidecar) = sidecar {
+            args.push("--srt-file".to_string());
+            args.push(sidecar.to_string_lossy().into_owned());
+            args.push("--srt-lang".to_string());
+            args.push(language);
+            if burn {
+                args.push("--srt-burn".to_string());
+            }
+        } else {
+            args.push("--subtitle-lang-list".to_string());
+            args.push(language);
+            if burn {
+                args.push("--first-subtitle".to_string());
+                args.push("--subtitle-burned".to_string());
+            } else {
+                args.push("--all-subtitles".to_string());
+            }
+        }
+        args
+    }
+}
+
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct TranscodeServiceRequest {
     job_type: JobType,
     prefix: StackString,
     input_path: PathBuf,
     output_path: PathBuf,
+    #[serde(default)]
+    subtitles: Option<SubtitleOption>,
 }
 
 impl TranscodeServiceRequest {
@@ -48,9 +96,15 @@ impl TranscodeServiceRequest {
             prefix: prefix.into(),
             input_path: input_path.to_path_buf(),
             output_path: output_path.to_path_buf(),
+            subtitles: None,
         }
     }
 
+    pub fn with_subtitles(mut self, subtitles: Option<SubtitleOption>) -> Self {
+        self.subtitles = subtitles;
+        self
+    }
+
     pub fn create_transcode_request(config: &Config, path: &Path) -> Result<Self, Error> {
         let input_path = path.to_path_buf();
         let fstem = path.file_stem().ok_or_else(|| format_err!("No stem"))?;
@@ -76,6 +130,7 @@ impl TranscodeServiceRequest {
                 prefix,
                 input_path,
                 output_path: output_file,
+                subtitles: None,
             })
         }
     }
@@ -146,6 +201,7 @@ impl TranscodeServiceRequest {
                 prefix,
                 input_path,
                 output_path,
+                subtitles: None,
             })
         } else {
             Self::create_transcode_request(config, path)
@@ -153,17 +209,28 @@ impl TranscodeServiceRequest {
     }
 }
 
+/// Percent complete from a HandBrakeCLI progress line such as
+/// `Encoding: task 1 of 1, 45.67 % (120.23 fps, avg 118.34 fps, ETA 00h05m12s)`
+fn parse_handbrake_progress(line: &str) -> Option<f64> {
+    let line = line.trim().strip_prefix("Encoding:")?;
+    let (percent, _) = line.split_at(line.find(" %")?);
+    percent.rsplit(", ").next()?.trim().parse().ok()
+}
+
 pub struct TranscodeService {
     config: Config,
     queue: StackString,
+    pool: PgPool,
 }
 
 impl TranscodeService {
-    pub fn new(config: Config, queue: &str) -> Self {
-        Self {
+    pub fn new(config: Config, queue: &str) -> Result<Self, Error> {
+        let pool = PgPool::new(&config.pgurl)?;
+        Ok(Self {
             config,
             queue: queue.into(),
-        }
+            pool,
+        })
     }
 
     pub async fn init(&self) -> Result<Queue, Error> {
@@ -226,8 +293,13 @@ impl TranscodeService {
             let payload: TranscodeServiceRequest = serde_json::from_slice(&delivery.data)?;
             match payload.job_type {
                 JobType::Transcode => {
-                    self.run_transcode(&payload.prefix, &payload.input_path, &payload.output_path)
-                        .await?
+                    self.run_transcode(
+                        &payload.prefix,
+                        &payload.input_path,
+                        &payload.output_path,
+                        payload.subtitles.as_ref(),
+                    )
+                    .await?
                 }
                 JobType::Move => {
                     self.run_move(&payload.prefix, &payload.input_path, &payload.output_path)
@@ -264,36 +336,70 @@ impl TranscodeService {
         }
     }
 
+    /// Copy the output of HandBrakeCLI to a file, if `progress` is given the
+    /// percent complete is published as `Event::TranscodeProgress` whenever it
+    /// reaches the next whole percent.
     async fn output_to_file<T>(
         mut reader: BufReader<T>,
         output_path: &Path,
         eol: u8,
+        progress: Option<(PgPool, StackString)>,
     ) -> Result<(), Error>
     where
         T: AsyncRead + Unpin,
     {
         let mut f = File::create(&output_path).await?;
         let mut buf = Vec::new();
+        let mut last_percent = -1.0;
         while let Ok(bytes) = reader.read_until(eol, &mut buf).await {
             if bytes > 0 {
                 f.write_all(&buf).await?;
             } else {
                 break;
             }
+            if let Some((pool, prefix)) = &progress {
+                if let Some(percent) = parse_handbrake_progress(&String::from_utf8_lossy(&buf)) {
+                    let percent = percent.floor();
+                    if percent > last_percent {
+                        last_percent = percent;
+                        Self::publish_progress(pool, prefix, percent).await;
+                    }
+                }
+            }
             buf.clear();
         }
         Ok(())
     }
 
+    /// Progress is informational, failing to publish it shouldn't stop the
+    /// transcode.
+    async fn publish_progress(pool: &PgPool, prefix: &str, percent: f64) {
+        let event = Event::TranscodeProgress {
+            prefix: prefix.into(),
+            percent,
+        };
+        if let Err(e) = event.publish(pool).await {
+            debug!("failed to publish {:?} {}", event, e);
+        }
+    }
+
     async fn run_transcode(
         &self,
         prefix: &str,
         input_file: &Path,
         output_file: &Path,
+        subtitles: Option<&SubtitleOption>,
     ) -> Result<(), Error> {
         if !input_file.exists() {
             return Err(format_err!("{:?} does not exist", input_file));
         }
+        let subtitle_args = match subtitles {
+            Some(subtitles) => {
+                let sidecar = find_sidecar_for_language(input_file, &subtitles.language)?;
+                subtitles.handbrake_args(sidecar.as_deref())
+            }
+            None => Vec::new(),
+        };
         let output_path = output_file
             .file_name()
             .ok_or_else(|| format_err!("No Output File"))?;
@@ -321,6 +427,7 @@ impl TranscodeService {
                 "--preset",
                 "Android 480p30",
             ])
+            .args(&subtitle_args)
             .kill_on_drop(true)
             .stdout(Stdio::piped())
             .stderr(Stdio::piped())
@@ -329,12 +436,13 @@ impl TranscodeService {
         let stderr = p.stderr.take().ok_or_else(|| format_err!("No Stderr"))?;
 
         let reader = BufReader::new(stdout);
+        let progress = Some((self.pool.clone(), prefix.into()));
         let stdout_task: JoinHandle<Result<(), Error>> =
-            spawn(async move { Self::output_to_file(reader, &stdout_path, b'\r').await });
+            spawn(async move { Self::output_to_file(reader, &stdout_path, b'\r', progress).await });
 
         let reader = BufReader::new(stderr);
         let stderr_task: JoinHandle<Result<(), Error>> =
-            spawn(async move { Self::output_to_file(reader, &stderr_path, b'\n').await });
+            spawn(async move { Self::output_to_file(reader, &stderr_path, b'\n', None).await });
 
         let transcode_task = spawn(async move { p.await });
 
@@ -342,6 +450,9 @@ impl TranscodeService {
         println!("Handbrake exited with {}", status);
         stdout_task.await??;
         stderr_task.await??;
+        if status.success() {
+            Self::publish_progress(&self.pool, prefix, 100.0).await;
+        }
 
         if output_file.exists() && fs::rename(&output_file, &output_path).await.is_err() {
             fs::copy(&output_file, &output_path).await?;
@@ -442,15 +553,38 @@ impl TranscodeService {
             .await?;
         fs::rename(&new_path, &output_file).await?;
         let stdout = StdoutChannel::new();
-        make_queue_worker(&[], &[output_file.into()], false, &[], false, &stdout).await?;
+        make_queue_worker(
+            DEFAULT_QUEUE,
+            &[],
+            &[output_file.into()],
+            false,
+            &[],
+            false,
+            &stdout,
+        )
+        .await?;
         debug_output_file
             .write_all(format!("add {} to queue\n", output_file.to_string_lossy()).as_bytes())
             .await?;
-        make_queue_worker(&[output_file.into()], &[], false, &[], false, &stdout).await?;
+        make_queue_worker(
+            DEFAULT_QUEUE,
+            &[output_file.into()],
+            &[],
+            false,
+            &[],
+            false,
+            &stdout,
+        )
+        .await?;
         let mc = MovieCollection::new();
         debug_output_file.write_all(b"update collection\n").await?;
         mc.make_collection().await?;
         mc.fix_collection_show_id().await?;
+        for path in auto_queue_new_episodes(&mc).await? {
+            debug_output_file
+                .write_all(format!("auto-queue {}\n", path).as_bytes())
+                .await?;
+        }
 
         debug_output_file.flush().await?;
 
@@ -475,7 +609,10 @@ mod tests {
 
     use crate::{
         config::Config,
-        transcode_service::{JobType, TranscodeService, TranscodeServiceRequest},
+        transcode_service::{
+            parse_handbrake_progress, JobType, SubtitleMode, SubtitleOption, TranscodeService,
+            TranscodeServiceRequest,
+        },
     };
 
     fn init_env() {
@@ -560,15 +697,66 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_subtitle_handbrake_args() {
+        let mut subtitles = SubtitleOption {
+            language: "eng".into(),
+            mode: SubtitleMode::Burn,
+        };
+        assert_eq!(
+            subtitles.handbrake_args(Some(Path::new("/tmp/a.eng.srt"))),
+            vec![
+                "--srt-file",
+                "/tmp/a.eng.srt",
+                "--srt-lang",
+                "eng",
+                "--srt-burn"
+            ]
+        );
+        assert_eq!(
+            subtitles.handbrake_args(None),
+            vec![
+                "--subtitle-lang-list",
+                "eng",
+                "--first-subtitle",
+                "--subtitle-burned"
+            ]
+        );
+        subtitles.mode = SubtitleMode::PassThrough;
+        assert_eq!(
+            subtitles.handbrake_args(None),
+            vec!["--subtitle-lang-list", "eng", "--all-subtitles"]
+        );
+    }
+
+    #[test]
+    fn test_parse_handbrake_progress() {
+        assert_eq!(
+            parse_handbrake_progress(
+                "Encoding: task 1 of 1, 45.67 % (120.23 fps, avg 118.34 fps, ETA 00h05m12s)\r"
+            ),
+            Some(45.67)
+        );
+        assert_eq!(
+            parse_handbrake_progress("Encoding: task 1 of 1, 0.12 %"),
+            Some(0.12)
+        );
+        assert_eq!(
+            parse_handbrake_progress("Muxing: this may take awhile..."),
+            None
+        );
+        assert_eq!(parse_handbrake_progress("Encoding: task 1 of 1"), None);
+    }
+
     #[tokio::test]
     #[ignore]
     async fn test_transcode_service() -> Result<(), Error> {
         let config = Config::with_config()?;
-        let service = TranscodeService::new(config.clone(), "test_queue");
+        let service = TranscodeService::new(config.clone(), "test_queue")?;
         let queue = service.init().await?;
         println!("{:?}", queue);
         let task = spawn(async move { service.get_single_job().await });
-        let service = TranscodeService::new(config, "test_queue");
+        let service = TranscodeService::new(config, "test_queue")?;
         let req = TranscodeServiceRequest::new(
             JobType::Transcode,
             "test_prefix",
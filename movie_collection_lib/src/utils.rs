@@ -1,7 +1,5 @@
 use anyhow::{format_err, Error};
 use async_trait::async_trait;
-use handlebars::Handlebars;
-use lazy_static::lazy_static;
 use rand::{
     distributions::{Distribution, Uniform},
     thread_rng,
@@ -18,16 +16,6 @@ use subprocess::Exec;
 use tokio::time::{delay_for, Duration};
 use walkdir::WalkDir;
 
-lazy_static! {
-    pub static ref HBR: Handlebars<'static> = get_templates().expect("Failed to parse templates");
-}
-
-fn get_templates() -> Result<Handlebars<'static>, Error> {
-    let mut h = Handlebars::new();
-    h.register_template_string("index.html", include_str!("../../templates/index.html"))?;
-    Ok(h)
-}
-
 #[inline]
 pub fn option_string_wrapper<T: AsRef<str>>(s: &Option<T>) -> &str {
     s.as_ref().map_or("", AsRef::as_ref)
@@ -0,0 +1,577 @@
+use anyhow::{format_err, Error};
+use deadpool_postgres::Transaction;
+use log::debug;
+use serde::{de::DeserializeOwned, Deserialize};
+use stack_string::StackString;
+use std::{
+    collections::{HashMap, HashSet},
+    fmt,
+    path::Path,
+    str::FromStr,
+};
+use tokio::{
+    fs::File,
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, BufReader},
+};
+
+use crate::{
+    bulk_import::{ImportAction, ImportError, ImportReport},
+    imdb_utils::ImdbConnection,
+    pgpool::PgPool,
+    tombstones::Tombstone,
+};
+
+/// Title types of the imdb exports and dataset that go in
+/// `trakt_watched_movies`, older exports use the long names.
+const MOVIE_TYPES: [&str; 9] = [
+    "movie",
+    "tvMovie",
+    "video",
+    "short",
+    "tvSpecial",
+    "Feature Film",
+    "TV Movie",
+    "Video",
+    "Short Film",
+];
+
+/// CSV exports of a watched history.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum WatchedFormat {
+    /// The "Your Ratings" export of imdb, rows carry the imdb id
+    ImdbCsv,
+    /// The ratings, watched or diary csv of a Letterboxd export, rows are
+    /// matched to an imdb id by title and year
+    Letterboxd,
+}
+
+impl WatchedFormat {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Self::ImdbCsv => "imdb-csv",
+            Self::Letterboxd => "letterboxd",
+        }
+    }
+
+    /// Tell the exports apart by the columns of their header, only the imdb
+    /// export has `Const` and only Letterboxd has `Name`.
+    pub fn detect(data: &str) -> Result<Self, Error> {
+        let header = data.lines().next().unwrap_or("");
+        let columns: Vec<_> = header
+            .split(',')
+            .map(|c| c.trim().trim_matches('"'))
+            .collect();
+        if columns.contains(&"Const") {
+            Ok(Self::ImdbCsv)
+        } else if columns.contains(&"Name") {
+            Ok(Self::Letterboxd)
+        } else {
+            Err(format_err!(
+                "Can't tell the format of the watched history from its header, pass --format"
+            ))
+        }
+    }
+}
+
+impl FromStr for WatchedFormat {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "imdb-csv" => Ok(Self::ImdbCsv),
+            "letterboxd" => Ok(Self::Letterboxd),
+            _ => Err(format_err!("Invalid watched format {}", s)),
+        }
+    }
+}
+
+impl fmt::Display for WatchedFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[derive(Deserialize)]
+struct ImdbCsvRow {
+    #[serde(rename = "Const")]
+    link: StackString,
+    #[serde(rename = "Your Rating")]
+    rating: Option<f64>,
+    #[serde(rename = "Title")]
+    title: StackString,
+    #[serde(rename = "Title Type", default)]
+    title_type: Option<StackString>,
+    #[serde(rename = "Year", default)]
+    year: Option<i32>,
+}
+
+/// Letterboxd ratings are 0.5 to 5 stars, `watched.csv` has no ratings.
+#[derive(Deserialize)]
+struct LetterboxdRow {
+    #[serde(rename = "Name")]
+    title: StackString,
+    #[serde(rename = "Year", default)]
+    year: Option<i32>,
+    #[serde(rename = "Rating", default)]
+    rating: Option<f64>,
+}
+
+/// A row of the export, `rating` is out of 10.
+#[derive(Debug, Clone, PartialEq)]
+struct WatchedEntry {
+    line: usize,
+    title: StackString,
+    year: Option<i32>,
+    link: Option<StackString>,
+    rating: Option<f64>,
+    title_type: Option<StackString>,
+}
+
+impl WatchedEntry {
+    fn key(&self) -> StackString {
+        match self.year {
+            Some(year) => format!("{} ({})", self.title, year).into(),
+            None => self.title.clone(),
+        }
+    }
+}
+
+fn deserialize_rows<T: DeserializeOwned>(data: &str) -> Vec<(usize, Result<T, Error>)> {
+    let mut reader = csv::Reader::from_reader(data.as_bytes());
+    reader
+        .deserialize()
+        .enumerate()
+        .map(|(i, row)| (i + 2, row.map_err(Into::into)))
+        .collect()
+}
+
+fn parse_entries(format: WatchedFormat, data: &str) -> Vec<(usize, Result<WatchedEntry, Error>)> {
+    match format {
+        WatchedFormat::ImdbCsv => deserialize_rows(data)
+            .into_iter()
+            .map(|(line, row)| {
+                let entry = row.map(|row: ImdbCsvRow| WatchedEntry {
+                    line,
+                    title: row.title,
+                    year: row.year,
+                    link: Some(row.link),
+                    rating: row.rating,
+                    title_type: row.title_type,
+                });
+                (line, entry)
+            })
+            .collect(),
+        WatchedFormat::Letterboxd => deserialize_rows(data)
+            .into_iter()
+            .map(|(line, row)| {
+                let entry = row.map(|row: LetterboxdRow| WatchedEntry {
+                    line,
+                    title: row.title,
+                    year: row.year,
+                    link: None,
+                    rating: row.rating.map(|r| r * 2.0),
+                    title_type: None,
+                });
+                (line, entry)
+            })
+            .collect(),
+    }
+}
+
+/// `imdb_ratings.show` for a movie that isn't there yet, e.g. `the_matrix`.
+fn show_name(title: &str) -> StackString {
+    let name: String = title
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    let name: Vec<_> = name.split('_').filter(|s| !s.is_empty()).collect();
+    name.join("_").into()
+}
+
+/// Movies of the imdb `title.basics.tsv` dataset by lowercased title.
+#[derive(Default)]
+pub struct ImdbDataset {
+    titles: HashMap<StackString, Vec<(Option<i32>, StackString)>>,
+}
+
+impl ImdbDataset {
+    pub async fn from_file(path: &Path) -> Result<Self, Error> {
+        let f = File::open(path).await?;
+        Self::from_reader(f).await
+    }
+
+    async fn from_reader<R: AsyncRead + Unpin>(input: R) -> Result<Self, Error> {
+        let mut dataset = Self::default();
+        let mut reader = BufReader::new(input);
+        let mut line = String::new();
+        while reader.read_line(&mut line).await? > 0 {
+            let fields: Vec<_> = line.trim_end_matches('\n').split('\t').collect();
+            // tconst titleType primaryTitle originalTitle isAdult startYear ...
+            if fields.len() > 5 && MOVIE_TYPES.contains(&fields[1]) {
+                let year = fields[5].parse().ok();
+                for title in &fields[2..4] {
+                    let entries = dataset
+                        .titles
+                        .entry(title.to_lowercase().into())
+                        .or_insert_with(Vec::new);
+                    if !entries.iter().any(|(_, link)| link.as_str() == fields[0]) {
+                        entries.push((year, fields[0].into()));
+                    }
+                }
+            }
+            line.clear();
+        }
+        Ok(dataset)
+    }
+
+    /// Without a year the title has to be unique.
+    fn find(&self, title: &str, year: Option<i32>) -> Option<StackString> {
+        let title: StackString = title.to_lowercase().into();
+        let entries = self.titles.get(&title)?;
+        match year {
+            Some(year) => entries
+                .iter()
+                .find(|(y, _)| *y == Some(year))
+                .map(|(_, link)| link.clone()),
+            None if entries.len() == 1 => Some(entries[0].1.clone()),
+            None => None,
+        }
+    }
+}
+
+/// Adds the movies of an imdb or Letterboxd export to `trakt_watched_movies`
+/// with the personal rating of the export. Rows without an imdb id are
+/// matched against `imdb_ratings`, then the dataset if there is one, and
+/// otherwise the imdb search. Everything is written in one transaction once
+/// all rows are resolved.
+pub struct WatchedImport {
+    pool: PgPool,
+    format: Option<WatchedFormat>,
+    user: StackString,
+    dry_run: bool,
+    dataset: Option<ImdbDataset>,
+    imdb: ImdbConnection,
+}
+
+impl WatchedImport {
+    pub fn new(
+        pool: &PgPool,
+        format: Option<WatchedFormat>,
+        user: &str,
+        dry_run: bool,
+        dataset: Option<ImdbDataset>,
+    ) -> Self {
+        Self {
+            pool: pool.clone(),
+            format,
+            user: user.into(),
+            dry_run,
+            dataset,
+            imdb: ImdbConnection::new(),
+        }
+    }
+
+    async fn find_local(&self, title: &str) -> Result<Option<StackString>, Error> {
+        let query = postgres_query::query!(
+            r#"
+                SELECT link
+                FROM imdb_ratings
+                WHERE lower(title) = lower($title) AND link IS NOT NULL
+                AND istv IS NOT TRUE
+            "#,
+            title = title
+        );
+        let rows = self
+            .pool
+            .get()
+            .await?
+            .query(query.sql(), query.parameters())
+            .await?;
+        if rows.len() == 1 {
+            Ok(Some(rows[0].try_get("link")?))
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// Search results look like `The Matrix (1999)`, with a year only a
+    /// result of that year is taken.
+    async fn search(&self, entry: &WatchedEntry) -> Result<Option<StackString>, Error> {
+        let results = self.imdb.parse_imdb(&entry.key()).await?;
+        let result = match entry.year {
+            Some(year) => {
+                let year = format!("({})", year);
+                results
+                    .into_iter()
+                    .find(|r| r.title.contains(year.as_str()))
+            }
+            None => results.into_iter().next(),
+        };
+        Ok(result.map(|r| r.link))
+    }
+
+    async fn resolve(&self, entry: &WatchedEntry) -> Result<Option<StackString>, Error> {
+        if let Some(link) = &entry.link {
+            return Ok(Some(link.clone()));
+        }
+        if let Some(link) = self.find_local(&entry.title).await? {
+            return Ok(Some(link));
+        }
+        match &self.dataset {
+            Some(dataset) => Ok(dataset.find(&entry.title, entry.year)),
+            None => self.search(entry).await,
+        }
+    }
+
+    async fn insert_in(
+        &self,
+        tran: &Transaction<'_>,
+        format: WatchedFormat,
+        entry: &WatchedEntry,
+        link: &str,
+    ) -> Result<ImportAction, Error> {
+        let query = postgres_query::query!(
+            "SELECT show FROM imdb_ratings WHERE link = $link",
+            link = link
+        );
+        if tran
+            .query(query.sql(), query.parameters())
+            .await?
+            .is_empty()
+        {
+            let mut show = show_name(&entry.title);
+            let query = postgres_query::query!(
+                "SELECT 1 FROM imdb_ratings WHERE show = $show",
+                show = show
+            );
+            if show.is_empty()
+                || !tran
+                    .query(query.sql(), query.parameters())
+                    .await?
+                    .is_empty()
+            {
+                show = format!("{}_{}", show, link).into();
+            }
+            debug!("insert imdb_ratings {} {}", show, link);
+            let query = postgres_query::query!(
+                r#"
+                    INSERT INTO imdb_ratings (show, title, link, istv, last_modified)
+                    VALUES ($show, $title, $link, false, now())
+                "#,
+                show = show,
+                title = entry.title,
+                link = link
+            );
+            tran.execute(query.sql(), query.parameters()).await?;
+        }
+
+        let query = postgres_query::query!(
+            r#"
+                SELECT rating FROM trakt_watched_movies
+                WHERE user_email = $user AND link = $link
+            "#,
+            user = self.user,
+            link = link
+        );
+        let action = match tran.query(query.sql(), query.parameters()).await?.get(0) {
+            Some(row) => {
+                let rating: Option<f64> = row.try_get("rating")?;
+                if entry.rating.is_none() || rating == entry.rating {
+                    return Ok(ImportAction::Unchanged);
+                }
+                ImportAction::Updated
+            }
+            None => ImportAction::Inserted,
+        };
+        let query = postgres_query::query!(
+            r#"
+                INSERT INTO trakt_watched_movies (user_email, link, rating, source)
+                VALUES ($user, $link, $rating, $source)
+                ON CONFLICT (user_email, link) DO UPDATE SET rating = EXCLUDED.rating
+            "#,
+            user = self.user,
+            link = link,
+            rating = entry.rating,
+            source = format.as_str()
+        );
+        tran.execute(query.sql(), query.parameters()).await?;
+        Tombstone::clear_in(tran, "trakt_watched_movies", &[self.user.as_str(), link]).await?;
+        Ok(action)
+    }
+
+    pub async fn run<R: AsyncRead + Unpin>(&self, mut input: R) -> Result<ImportReport, Error> {
+        let mut data = String::new();
+        input.read_to_string(&mut data).await?;
+        let format = match self.format {
+            Some(format) => format,
+            None => WatchedFormat::detect(&data)?,
+        };
+
+        let mut report = ImportReport::new("watched", self.dry_run);
+        let mut resolved = Vec::new();
+        let mut seen = HashSet::new();
+        for (line, entry) in parse_entries(format, &data) {
+            let entry = match entry {
+                Ok(entry) => entry,
+                Err(e) => {
+                    report.add_error(line, None, &e);
+                    continue;
+                }
+            };
+            if let Some(title_type) = &entry.title_type {
+                if !MOVIE_TYPES.contains(&title_type.as_str()) {
+                    let e = format_err!("not a movie: {}", title_type);
+                    report.unmatched.push(unmatched(&entry, &e));
+                    continue;
+                }
+            }
+            match self.resolve(&entry).await {
+                Ok(Some(link)) => {
+                    // A diary lists rewatches again, the first row wins
+                    if seen.insert(link.clone()) {
+                        resolved.push((entry, link));
+                    }
+                }
+                Ok(None) => {
+                    let e = format_err!("no imdb match");
+                    report.unmatched.push(unmatched(&entry, &e));
+                }
+                Err(e) => report.unmatched.push(unmatched(&entry, &e)),
+            }
+        }
+
+        let mut conn = self.pool.get().await?;
+        let tran = conn.transaction().await?;
+        for (entry, link) in &resolved {
+            match self.insert_in(&tran, format, entry, link).await {
+                Ok(action) => {
+                    let key = format!("{} {}", entry.key(), link).into();
+                    report.add_change(entry.line, action, key);
+                }
+                Err(e) => {
+                    report.add_error(entry.line, Some(entry.key()), &e);
+                    break;
+                }
+            }
+        }
+        let applied = report.changes.len();
+        if self.dry_run || !report.errors.is_empty() {
+            tran.rollback().await?;
+            report.rolled_back = applied;
+        } else {
+            tran.commit().await?;
+            report.committed = applied;
+        }
+        Ok(report)
+    }
+}
+
+fn unmatched(entry: &WatchedEntry, error: &Error) -> ImportError {
+    ImportError {
+        line: entry.line,
+        key: Some(entry.key()),
+        message: error.to_string().into(),
+    }
+}
+
+/// Links of `user` that were imported rather than synced from trakt.
+pub async fn get_imported_links(pool: &PgPool, user: &str) -> Result<HashSet<StackString>, Error> {
+    let query = postgres_query::query!(
+        r#"
+            SELECT link FROM trakt_watched_movies
+            WHERE user_email = $user AND source != 'trakt'
+        "#,
+        user = user
+    );
+    pool.get()
+        .await?
+        .query(query.sql(), query.parameters())
+        .await?
+        .iter()
+        .map(|row| row.try_get("link").map_err(Into::into))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Error;
+
+    use crate::watched_import::{
+        parse_entries, show_name, ImdbDataset, WatchedEntry, WatchedFormat,
+    };
+
+    #[test]
+    fn test_parse_entries() {
+        let imdb = concat!(
+            "Const,Your Rating,Date Rated,Title,URL,Title Type,IMDb Rating,",
+            "Runtime (mins),Year,Genres,Num Votes,Release Date,Directors\n",
+            "tt0133093,9,2019-01-05,The Matrix,https://www.imdb.com/title/tt0133093/,movie,8.7,",
+            "136,1999,\"Action, Sci-Fi\",1700000,1999-03-24,\"Lana Wachowski, Lilly Wachowski\"\n",
+        );
+        let entries: Vec<_> = parse_entries(WatchedFormat::ImdbCsv, imdb)
+            .into_iter()
+            .map(|(_, e)| e.unwrap())
+            .collect();
+        assert_eq!(
+            entries,
+            vec![WatchedEntry {
+                line: 2,
+                title: "The Matrix".into(),
+                year: Some(1999),
+                link: Some("tt0133093".into()),
+                rating: Some(9.0),
+                title_type: Some("movie".into()),
+            }]
+        );
+
+        let letterboxd = concat!(
+            "Date,Name,Year,Letterboxd URI,Rating\n",
+            "2020-04-01,Heat,1995,https://boxd.it/2bbs,4.5\n",
+            "2020-04-02,Alien,,https://boxd.it/2b0k,\n",
+        );
+        let entries: Vec<_> = parse_entries(WatchedFormat::Letterboxd, letterboxd)
+            .into_iter()
+            .map(|(_, e)| e.unwrap())
+            .collect();
+        assert_eq!(entries[0].rating, Some(9.0));
+        assert_eq!(entries[0].key().as_str(), "Heat (1995)");
+        assert_eq!(entries[1].year, None);
+        assert_eq!(entries[1].rating, None);
+        assert_eq!(entries[1].line, 3);
+
+        assert_eq!(WatchedFormat::detect(imdb).unwrap(), WatchedFormat::ImdbCsv);
+        assert_eq!(
+            WatchedFormat::detect(letterboxd).unwrap(),
+            WatchedFormat::Letterboxd
+        );
+        assert!(WatchedFormat::detect("title,year\nHeat,1995\n").is_err());
+    }
+
+    #[test]
+    fn test_show_name() {
+        assert_eq!(show_name("The Matrix").as_str(), "the_matrix");
+        assert_eq!(
+            show_name("Alien: Resurrection!").as_str(),
+            "alien_resurrection"
+        );
+        assert!(show_name("?").is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_imdb_dataset() -> Result<(), Error> {
+        let tsv = concat!(
+            "tconst\ttitleType\tprimaryTitle\toriginalTitle\tisAdult\tstartYear\tendYear\n",
+            "tt0081505\tmovie\tThe Shining\tThe Shining\t0\t1980\t\\N\n",
+            "tt0115355\ttvMiniSeries\tThe Shining\tThe Shining\t0\t1997\t1997\n",
+            "tt0078748\tmovie\tAlien\tAlien\t0\t1979\t\\N\n",
+            "tt1234567\tmovie\tAlien\tAlien\t0\t2030\t\\N\n",
+        );
+        let dataset = ImdbDataset::from_reader(tsv.as_bytes()).await?;
+        assert_eq!(dataset.find("the shining", None), Some("tt0081505".into()));
+        assert_eq!(dataset.find("Alien", Some(1979)), Some("tt0078748".into()));
+        assert_eq!(dataset.find("Alien", None), None);
+        assert_eq!(dataset.find("Heat", None), None);
+        Ok(())
+    }
+}
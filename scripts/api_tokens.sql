@@ -0,0 +1,11 @@
+CREATE SEQUENCE api_tokens_id_seq;
+
+CREATE TABLE IF NOT EXISTS api_tokens (
+    id INTEGER NOT NULL PRIMARY KEY DEFAULT nextval('api_tokens_id_seq'::regclass),
+    token_hash TEXT NOT NULL UNIQUE,
+    scope TEXT NOT NULL CHECK (scope IN ('read', 'sync')),
+    description TEXT NOT NULL DEFAULT '',
+    created_at timestamp with time zone NOT NULL DEFAULT now(),
+    last_used timestamp with time zone,
+    revoked_at timestamp with time zone
+);
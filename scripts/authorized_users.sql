@@ -1,3 +1,11 @@
 CREATE TABLE IF NOT EXISTS authorized_users (
-  email VARCHAR(100) NOT NULL UNIQUE PRIMARY KEY
+  email VARCHAR(100) NOT NULL UNIQUE PRIMARY KEY,
+  role TEXT NOT NULL DEFAULT 'viewer'
 );
+
+-- users added before roles existed keep the access they had
+ALTER TABLE authorized_users ADD COLUMN IF NOT EXISTS role TEXT NOT NULL DEFAULT 'admin';
+ALTER TABLE authorized_users ALTER COLUMN role SET DEFAULT 'viewer';
+ALTER TABLE authorized_users DROP CONSTRAINT IF EXISTS authorized_users_role_check;
+ALTER TABLE authorized_users ADD CONSTRAINT authorized_users_role_check
+    CHECK (role IN ('viewer', 'editor', 'admin'));
@@ -0,0 +1,14 @@
+CREATE TABLE IF NOT EXISTS auto_queue_rules (
+    show text NOT NULL PRIMARY KEY,
+    policy text NOT NULL DEFAULT 'queue',
+    min_rating DOUBLE PRECISION,
+    last_modified timestamp with time zone
+);
+
+CREATE TABLE IF NOT EXISTS auto_queue_decisions (
+    user_email text NOT NULL DEFAULT '',
+    path text NOT NULL,
+    decision text NOT NULL,
+    last_modified timestamp with time zone,
+    PRIMARY KEY (user_email, path)
+);
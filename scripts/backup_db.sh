@@ -6,12 +6,19 @@ BUCKET=movie-queue-db-backup
 TABLES="
 imdb_ratings
 imdb_episodes
+offline_volumes
 movie_collection_on_dvd
 movie_collection
 movie_queue
 trakt_watched_episodes
 trakt_watched_movies
-trakt_watchlist"
+trakt_watchlist
+auto_queue_rules
+auto_queue_decisions
+playback_position
+movie_collection_subtitles
+remote_sync_marks
+tombstones"
 
 mkdir -p backup
 for T in $TABLES;
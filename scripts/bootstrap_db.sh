@@ -27,11 +27,19 @@ CALLBACK_URL=https://${DOMAIN}/auth/register.html
 EOL
 
 psql movie_queue < ./scripts/authorized_users.sql
+psql movie_queue < ./scripts/api_tokens.sql
 psql movie_queue < ./scripts/imdb_ratings.sql
 psql movie_queue < ./scripts/imdb_episodes.sql
+psql movie_queue < ./scripts/offline_volumes.sql
 psql movie_queue < ./scripts/movie_collection_on_dvd.sql
 psql movie_queue < ./scripts/movie_collection.sql
 psql movie_queue < ./scripts/movie_queue.sql
 psql movie_queue < ./scripts/trakt_watched_episodes.sql
 psql movie_queue < ./scripts/trakt_watched_movies.sql
 psql movie_queue < ./scripts/trakt_watchlist.sql
+psql movie_queue < ./scripts/auto_queue_rules.sql
+psql movie_queue < ./scripts/playback_position.sql
+psql movie_queue < ./scripts/movie_collection_subtitles.sql
+psql movie_queue < ./scripts/remote_sync_marks.sql
+psql movie_queue < ./scripts/tombstones.sql
+psql movie_queue < ./scripts/search_indexes.sql
@@ -5,12 +5,19 @@ DB="movie_queue"
 TABLES="
 imdb_episodes
 movie_collection_on_dvd
+playback_position
+movie_collection_subtitles
 movie_queue
 trakt_watched_episodes
 trakt_watched_movies
 trakt_watchlist
 movie_collection
 imdb_ratings
+offline_volumes
+auto_queue_rules
+auto_queue_decisions
+remote_sync_marks
+tombstones
 "
 
 for T in $TABLES;
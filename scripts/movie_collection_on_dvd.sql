@@ -3,5 +3,12 @@ CREATE SEQUENCE movie_collection_on_dvd_id_seq;
 CREATE TABLE IF NOT EXISTS movie_collection_on_dvd (
     id INTEGER NOT NULL PRIMARY KEY DEFAULT nextval('movie_collection_on_dvd_id_seq'::regclass),
     path text NOT NULL,
-    file_size INTEGER
+    file_size BIGINT,
+    volume_id INTEGER REFERENCES offline_volumes (id),
+    last_modified timestamp with time zone
 );
+
+ALTER TABLE movie_collection_on_dvd ALTER COLUMN file_size TYPE BIGINT;
+ALTER TABLE movie_collection_on_dvd
+    ADD COLUMN IF NOT EXISTS volume_id INTEGER REFERENCES offline_volumes (id);
+ALTER TABLE movie_collection_on_dvd ADD COLUMN IF NOT EXISTS last_modified timestamp with time zone;
@@ -0,0 +1,11 @@
+CREATE SEQUENCE movie_collection_subtitles_id_seq;
+
+CREATE TABLE IF NOT EXISTS movie_collection_subtitles (
+    id INTEGER NOT NULL PRIMARY KEY DEFAULT nextval('movie_collection_subtitles_id_seq'::regclass),
+    collection_idx INTEGER NOT NULL REFERENCES movie_collection (idx) ON DELETE CASCADE,
+    path TEXT,
+    stream_index INTEGER,
+    language TEXT,
+    format TEXT NOT NULL,
+    last_modified timestamp with time zone
+);
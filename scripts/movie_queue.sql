@@ -1,5 +1,13 @@
 CREATE TABLE IF NOT EXISTS movie_queue (
-    idx INTEGER NOT NULL PRIMARY KEY,
+    user_email TEXT NOT NULL DEFAULT '',
+    queue_name TEXT NOT NULL DEFAULT 'default',
+    idx INTEGER NOT NULL,
     collection_idx INTEGER NOT NULL REFERENCES movie_collection (idx),
-    last_modified timestamp with time zone
+    last_modified timestamp with time zone,
+    PRIMARY KEY (user_email, queue_name, idx)
 );
+
+ALTER TABLE movie_queue ADD COLUMN IF NOT EXISTS queue_name TEXT NOT NULL DEFAULT 'default';
+ALTER TABLE movie_queue ADD COLUMN IF NOT EXISTS user_email TEXT NOT NULL DEFAULT '';
+ALTER TABLE movie_queue DROP CONSTRAINT IF EXISTS movie_queue_pkey;
+ALTER TABLE movie_queue ADD PRIMARY KEY (user_email, queue_name, idx);
@@ -0,0 +1,8 @@
+CREATE SEQUENCE offline_volumes_id_seq;
+
+CREATE TABLE IF NOT EXISTS offline_volumes (
+    id INTEGER NOT NULL PRIMARY KEY DEFAULT nextval('offline_volumes_id_seq'::regclass),
+    label text NOT NULL UNIQUE,
+    description text,
+    last_modified timestamp with time zone
+);
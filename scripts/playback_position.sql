@@ -0,0 +1,9 @@
+CREATE TABLE IF NOT EXISTS playback_position (
+    user_email text NOT NULL DEFAULT '',
+    collection_idx INTEGER NOT NULL REFERENCES movie_collection (idx) ON DELETE CASCADE,
+    position DOUBLE PRECISION NOT NULL DEFAULT 0,
+    duration DOUBLE PRECISION NOT NULL DEFAULT 0,
+    watched BOOLEAN NOT NULL DEFAULT false,
+    last_modified timestamp with time zone,
+    PRIMARY KEY (user_email, collection_idx)
+);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS remote_sync_marks (
+    remote TEXT NOT NULL,
+    table_name TEXT NOT NULL,
+    last_pulled timestamp with time zone,
+    last_pushed timestamp with time zone,
+    PRIMARY KEY (remote, table_name)
+);
@@ -6,12 +6,19 @@ BUCKET=movie-queue-db-backup
 TABLES="
 imdb_ratings
 imdb_episodes
+offline_volumes
 movie_collection_on_dvd
 movie_collection
 movie_queue
 trakt_watched_episodes
 trakt_watched_movies
-trakt_watchlist"
+trakt_watchlist
+auto_queue_rules
+auto_queue_decisions
+playback_position
+movie_collection_subtitles
+remote_sync_marks
+tombstones"
 
 mkdir -p backup
 for T in $TABLES;
@@ -25,3 +32,6 @@ psql $DB -c "select setval('imdb_episodes_id_seq', (select max(id) from imdb_epi
 psql $DB -c "select setval('trakt_watched_episodes_id_seq', (select max(id) from trakt_watched_episodes), TRUE)"
 psql $DB -c "select setval('trakt_watched_movies_id_seq', (select max(id) from trakt_watched_movies), TRUE)"
 psql $DB -c "select setval('trakt_watchlist_id_seq', (select max(id) from trakt_watchlist), TRUE)"
+psql $DB -c "select setval('offline_volumes_id_seq', (select max(id) from offline_volumes), TRUE)"
+psql $DB -c "select setval('movie_collection_on_dvd_id_seq', (select max(id) from movie_collection_on_dvd), TRUE)"
+psql $DB -c "select setval('movie_collection_subtitles_id_seq', (select max(id) from movie_collection_subtitles), TRUE)"
@@ -0,0 +1,7 @@
+CREATE EXTENSION IF NOT EXISTS pg_trgm;
+
+CREATE INDEX IF NOT EXISTS imdb_ratings_title_trgm ON imdb_ratings USING gin (title gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS imdb_ratings_show_trgm ON imdb_ratings USING gin (show gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS imdb_episodes_eptitle_trgm ON imdb_episodes USING gin (eptitle gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS movie_collection_path_trgm ON movie_collection USING gin (path gin_trgm_ops);
+CREATE INDEX IF NOT EXISTS movie_collection_show_trgm ON movie_collection USING gin (show gin_trgm_ops);
@@ -0,0 +1,7 @@
+CREATE TABLE IF NOT EXISTS tombstones (
+    table_name TEXT NOT NULL,
+    key TEXT NOT NULL,
+    deleted_at timestamp with time zone NOT NULL,
+    last_modified timestamp with time zone NOT NULL,
+    PRIMARY KEY (table_name, key)
+);
@@ -2,7 +2,10 @@ CREATE SEQUENCE trakt_watched_episodes_id_seq;
 
 CREATE TABLE IF NOT EXISTS trakt_watched_episodes (
     id INTEGER NOT NULL PRIMARY KEY DEFAULT nextval('trakt_watched_episodes_id_seq'::regclass),
+    user_email text NOT NULL DEFAULT '',
     link text not null,
     season INTEGER,
     episode INTEGER
 );
+
+ALTER TABLE trakt_watched_episodes ADD COLUMN IF NOT EXISTS user_email text NOT NULL DEFAULT '';
@@ -2,5 +2,20 @@ CREATE SEQUENCE trakt_watched_movies_id_seq;
 
 CREATE TABLE IF NOT EXISTS trakt_watched_movies (
     id INTEGER NOT NULL PRIMARY KEY DEFAULT nextval('trakt_watched_movies_id_seq'::regclass),
-    link text not null unique
+    user_email text NOT NULL DEFAULT '',
+    link text not null,
+    rating double precision,
+    source text NOT NULL DEFAULT 'trakt',
+    UNIQUE (user_email, link)
 );
+
+ALTER TABLE trakt_watched_movies ADD COLUMN IF NOT EXISTS user_email text NOT NULL DEFAULT '';
+ALTER TABLE trakt_watched_movies DROP CONSTRAINT IF EXISTS trakt_watched_movies_link_key;
+ALTER TABLE trakt_watched_movies DROP CONSTRAINT IF EXISTS trakt_watched_movies_user_email_link_key;
+ALTER TABLE trakt_watched_movies ADD CONSTRAINT trakt_watched_movies_user_email_link_key
+    UNIQUE (user_email, link);
+
+-- Personal rating out of 10 and where the entry came from, only entries
+-- from trakt are removed when trakt no longer has them
+ALTER TABLE trakt_watched_movies ADD COLUMN IF NOT EXISTS rating double precision;
+ALTER TABLE trakt_watched_movies ADD COLUMN IF NOT EXISTS source text NOT NULL DEFAULT 'trakt';
@@ -2,7 +2,10 @@ CREATE SEQUENCE trakt_watchlist_id_seq;
 
 CREATE TABLE IF NOT EXISTS trakt_watchlist (
     id INTEGER NOT NULL PRIMARY KEY DEFAULT nextval('trakt_watchlist_id_seq'::regclass),
+    user_email text NOT NULL DEFAULT '',
     link text not null,
     title text,
     year INTEGER
 );
+
+ALTER TABLE trakt_watchlist ADD COLUMN IF NOT EXISTS user_email text NOT NULL DEFAULT '';
@@ -0,0 +1,19 @@
+#!/bin/bash
+
+# Install hls.js for /list/static/hls.min.js, npm checks the package against
+# the integrity hash published in the registry.
+
+VERSION="0.14.17"
+DEST="${1:-/usr/share/movie_collection_rust}"
+
+set -e
+
+TMPDIR=`mktemp -d`
+trap "rm -rf $TMPDIR" EXIT
+
+cd $TMPDIR
+npm pack hls.js@${VERSION}
+tar xzf hls.js-${VERSION}.tgz package/dist/hls.min.js
+
+mkdir -p ${DEST}
+cp package/dist/hls.min.js ${DEST}/hls.min.js
@@ -0,0 +1,83 @@
+#![allow(clippy::used_underscore_binding)]
+
+use anyhow::{format_err, Error};
+use stack_string::StackString;
+use structopt::StructOpt;
+
+use movie_collection_lib::{
+    auto_queue::{auto_queue_new_episodes, AutoQueuePolicy, AutoQueueRule},
+    movie_collection::MovieCollection,
+};
+
+#[derive(StructOpt)]
+/// Auto Queue
+///
+/// Queue new episodes of watchlist shows according to per show rules
+enum AutoQueueOpts {
+    /// Apply the rules to newly collected episodes
+    Run,
+    /// List configured rules
+    List,
+    /// Set the rule for a show (queue or skip)
+    Set {
+        show: StackString,
+        policy: AutoQueuePolicy,
+        /// Only queue episodes rated at least this
+        #[structopt(long, short)]
+        min_rating: Option<f64>,
+    },
+    /// Remove the rule for a show
+    Rm { show: StackString },
+}
+
+async fn auto_queue() -> Result<(), Error> {
+    let opts = AutoQueueOpts::from_args();
+    let mc = MovieCollection::new();
+
+    match opts {
+        AutoQueueOpts::Run => {
+            auto_queue_new_episodes(&mc).await?;
+        }
+        AutoQueueOpts::List => {
+            for rule in AutoQueueRule::get_rules(&mc.pool).await? {
+                mc.stdout.send(rule.to_string());
+            }
+        }
+        AutoQueueOpts::Set {
+            show,
+            policy,
+            min_rating,
+        } => {
+            let rule = AutoQueueRule {
+                show,
+                policy,
+                min_rating,
+            };
+            rule.set(&mc.pool).await?;
+            mc.stdout.send(format!("set {}", rule));
+        }
+        AutoQueueOpts::Rm { show } => {
+            AutoQueueRule::get_rule(&show, &mc.pool)
+                .await?
+                .ok_or_else(|| format_err!("No rule for {}", show))?;
+            AutoQueueRule::delete(&show, &mc.pool).await?;
+            mc.stdout.send(format!("removed {}", show));
+        }
+    }
+    mc.stdout.close().await
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    match auto_queue().await {
+        Ok(_) => (),
+        Err(e) => {
+            if e.to_string().contains("Broken pipe") {
+            } else {
+                panic!("{}", e)
+            }
+        }
+    }
+}
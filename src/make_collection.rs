@@ -7,7 +7,12 @@ use std::path::Path;
 use structopt::StructOpt;
 use tokio::task::spawn_blocking;
 
-use movie_collection_lib::{movie_collection::MovieCollection, utils::get_video_runtime};
+use movie_collection_lib::{
+    auto_queue::auto_queue_new_episodes,
+    movie_collection::MovieCollection,
+    search::{Search, DEFAULT_SEARCH_LIMIT},
+    utils::get_video_runtime,
+};
 
 #[derive(StructOpt)]
 /// Collection Query/Parser
@@ -22,6 +27,14 @@ struct MakeCollectionOpts {
     #[structopt(short, long)]
     time: bool,
 
+    /// Rescan sidecar and embedded subtitles
+    #[structopt(short, long)]
+    subtitles: bool,
+
+    /// Ranked search of titles, episodes and paths that tolerates typos
+    #[structopt(long)]
+    search: Option<StackString>,
+
     /// Shows to display
     shows: Vec<StackString>,
 }
@@ -36,9 +49,22 @@ async fn make_collection() -> Result<(), Error> {
     if do_parse {
         mc.make_collection().await?;
         mc.fix_collection_show_id().await?;
+        auto_queue_new_episodes(&mc).await?;
+    } else if let Some(query) = &opts.search {
+        for result in Search::new(&mc.pool)
+            .search(query, DEFAULT_SEARCH_LIMIT)
+            .await?
+        {
+            mc.stdout.send(result.to_string());
+        }
     } else {
         let shows = mc.search_movie_collection(&opts.shows).await?;
-        if do_time {
+        if opts.subtitles {
+            for result in shows {
+                let n = mc.update_subtitles(&result.path).await?;
+                mc.stdout.send(format!("{} subtitles {}", n, result.path));
+            }
+        } else if do_time {
             let futures = shows.into_iter().map(|result| async move {
                 let path = result.path.clone();
                 let timeval = spawn_blocking(move || {
@@ -1,17 +1,31 @@
 #![allow(clippy::used_underscore_binding)]
 
-use anyhow::Error;
+use anyhow::{format_err, Error};
 use stack_string::StackString;
 use structopt::StructOpt;
 
 use movie_collection_lib::{
-    make_queue::{make_queue_worker, PathOrIndex},
+    config::Config,
+    make_queue::{
+        make_queue_list, make_queue_reorder, make_queue_storage_worker, make_queue_transfer,
+        make_queue_worker, PathOrIndex, QueueReorder,
+    },
+    movie_queue::DEFAULT_QUEUE,
     stdout_channel::StdoutChannel,
+    storage::{is_sqlite_url, open_storage},
 };
 
 #[derive(StructOpt)]
 /// Manage Video Queue
 struct MakeQueueOpts {
+    /// Name of the queue to operate on
+    #[structopt(long, short, default_value = DEFAULT_QUEUE)]
+    queue: StackString,
+
+    /// List all queues
+    #[structopt(long)]
+    list_queues: bool,
+
     /// Add files(s) to queue
     #[structopt(long, short, parse(from_os_str))]
     add: Vec<PathOrIndex>,
@@ -28,6 +42,30 @@ struct MakeQueueOpts {
     #[structopt(long, short)]
     shows: bool,
 
+    /// Move entry FROM to position TO
+    #[structopt(long = "move", number_of_values = 2, value_names = &["FROM", "TO"])]
+    move_idx: Vec<i32>,
+
+    /// Swap two entries
+    #[structopt(long, number_of_values = 2, value_names = &["IDX0", "IDX1"])]
+    swap: Vec<i32>,
+
+    /// Move entry to the top of the queue
+    #[structopt(long)]
+    top: Option<i32>,
+
+    /// Move entry to the bottom of the queue
+    #[structopt(long)]
+    bottom: Option<i32>,
+
+    /// Move entries to the end of the queue given by --to-queue
+    #[structopt(long)]
+    transfer: Vec<i32>,
+
+    /// Destination queue for --transfer
+    #[structopt(long)]
+    to_queue: Option<StackString>,
+
     /// String patterns to filter on
     patterns: Vec<StackString>,
 }
@@ -37,7 +75,58 @@ async fn make_queue() -> Result<(), Error> {
     let stdout = StdoutChannel::new();
     let patterns: Vec<_> = opts.patterns.iter().map(StackString::as_str).collect();
 
+    let mut reorder: Vec<_> = opts
+        .move_idx
+        .chunks(2)
+        .map(|x| QueueReorder::Move(x[0], x[1]))
+        .collect();
+    reorder.extend(opts.swap.chunks(2).map(|x| QueueReorder::Swap(x[0], x[1])));
+    reorder.extend(opts.top.map(QueueReorder::Top));
+    reorder.extend(opts.bottom.map(QueueReorder::Bottom));
+
+    let config = Config::with_config()?;
+    if is_sqlite_url(&config.pgurl) {
+        if opts.list_queues || opts.shows || !opts.transfer.is_empty() || !reorder.is_empty() {
+            return Err(format_err!(
+                "--list-queues, --shows, --move, --swap, --top, --bottom and --transfer need a \
+                 postgres pgurl"
+            ));
+        }
+        let storage = open_storage(&config.pgurl)?;
+        make_queue_storage_worker(
+            storage.as_ref(),
+            &opts.queue,
+            &opts.add,
+            &opts.remove,
+            opts.time,
+            &patterns,
+            &stdout,
+        )
+        .await?;
+        return stdout.close().await;
+    }
+
+    if opts.list_queues {
+        make_queue_list(&stdout).await?;
+        return stdout.close().await;
+    }
+
+    if !opts.transfer.is_empty() {
+        let target = opts
+            .to_queue
+            .as_ref()
+            .ok_or_else(|| format_err!("--transfer requires --to-queue"))?;
+        make_queue_transfer(&opts.queue, &opts.transfer, target, &stdout).await?;
+        return stdout.close().await;
+    }
+
+    if !reorder.is_empty() {
+        make_queue_reorder(&opts.queue, &reorder, &stdout).await?;
+        return stdout.close().await;
+    }
+
     make_queue_worker(
+        &opts.queue,
         &opts.add,
         &opts.remove,
         opts.time,
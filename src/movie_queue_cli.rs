@@ -1,21 +1,29 @@
-use anyhow::Error;
+use anyhow::{format_err, Error};
 use chrono::{DateTime, Duration, Utc};
-use futures::future::try_join_all;
 use stack_string::StackString;
 use std::path::PathBuf;
 use structopt::StructOpt;
 use tokio::{
-    fs::{read_to_string, File},
-    io::{stdin, stdout, AsyncReadExt, AsyncWrite, AsyncWriteExt},
+    fs::File,
+    io::{stdin, stdout, AsyncRead, AsyncWrite, AsyncWriteExt},
 };
 
 use movie_collection_lib::{
+    api_tokens::{ApiToken, TokenScope},
+    authorized_users::{AuthorizedUser, Role},
+    backup::Backup,
+    bulk_import::{BulkImport, ImportOptions},
     config::Config,
+    export_format::ExportFormat,
     imdb_episodes::ImdbEpisodes,
     imdb_ratings::ImdbRatings,
-    movie_collection::{LastModifiedResponse, MovieCollection, MovieCollectionRow},
-    movie_queue::{MovieQueueDB, MovieQueueRow},
+    movie_collection::{LastModifiedResponse, MovieCollection},
+    movie_queue::{MovieQueueDB, DEFAULT_USER},
+    nfo::NfoExporter,
     pgpool::PgPool,
+    remote_sync::{RemoteSync, SyncMark, SYNC_TABLES},
+    tombstones::Tombstone,
+    watched_import::{ImdbDataset, WatchedImport},
 };
 
 #[derive(StructOpt)]
@@ -23,144 +31,278 @@ enum MovieQueueCli {
     Import {
         #[structopt(short, long)]
         /// table -- possible values:
-        /// ['imdb_ratings', 'imdb_episodes', 'movie_collection', 'movie_queue']
+        /// ['imdb_ratings', 'imdb_episodes', 'movie_collection', 'movie_queue',
+        /// 'tombstones', 'watched']
         table: StackString,
         #[structopt(short, long)]
+        /// A json array or newline delimited json rows, as written by export
         filepath: Option<PathBuf>,
+        #[structopt(long)]
+        /// json or ndjson, or for the watched table imdb-csv or letterboxd,
+        /// detected from the input when left out
+        format: Option<StackString>,
+        #[structopt(long)]
+        /// imdb title.basics.tsv used to match letterboxd titles instead of
+        /// the imdb search
+        dataset: Option<PathBuf>,
+        #[structopt(short, long)]
+        /// Import movie_queue entries, or the watched history, for this user
+        /// instead of the users in the file
+        user: Option<StackString>,
+        #[structopt(long)]
+        /// Apply the rows and roll back, printing what would change
+        dry_run: bool,
+        #[structopt(short, long, default_value = "0")]
+        /// Rows per transaction, by default the whole import is one transaction
+        batch_size: usize,
+        #[structopt(long)]
+        /// Print the report as json
+        json: bool,
     },
     Export {
         #[structopt(short, long)]
         /// table -- possible values:
         /// ['last_modified', 'imdb_ratings', 'imdb_episodes',
-        /// 'movie_collection', 'movie_queue']
+        /// 'movie_collection', 'movie_queue', 'tombstones']
         table: StackString,
         #[structopt(short, long)]
         filepath: Option<PathBuf>,
         #[structopt(short, long)]
         start_timestamp: Option<DateTime<Utc>>,
+        #[structopt(short, long)]
+        /// Only export movie_queue entries for this user
+        user: Option<StackString>,
+        #[structopt(short, long)]
+        /// Export what changed since the last export to this remote, when no
+        /// start_timestamp is given, and record this export
+        remote: Option<StackString>,
+        #[structopt(long, default_value = "json")]
+        /// json, ndjson or csv
+        format: ExportFormat,
+    },
+    /// Write Kodi / Jellyfin nfo files next to the videos in the collection
+    /// from the imdb ratings and episodes
+    ExportNfo {
+        #[structopt(short, long)]
+        /// Only write nfo files for this show
+        show: Option<StackString>,
+        #[structopt(long)]
+        /// Replace existing nfo files
+        overwrite: bool,
+    },
+    /// Push and pull whatever changed since the last sync with a remote
+    /// movie-queue-http
+    Sync {
+        #[structopt(short, long)]
+        /// e.g. https://cloud.example.com
+        remote: StackString,
+        #[structopt(short, long)]
+        /// Sync scoped api token of the remote, defaults to SYNC_TOKEN
+        token: Option<StackString>,
+    },
+    /// Remove the tombstones of deletions older than this many days, remotes
+    /// that haven't synced since then will keep the deleted rows
+    PurgeTombstones {
+        #[structopt(short, long, default_value = "90")]
+        days: i64,
+    },
+    /// Dump every table into a directory as gzipped ndjson plus a manifest,
+    /// in one consistent snapshot. The directory is the archive: manifest.json
+    /// and one <table>.ndjson.gz per table
+    Backup {
+        #[structopt(short, long)]
+        directory: PathBuf,
+    },
+    /// Load a backup into a database whose tables exist but are empty
+    Restore {
+        #[structopt(short, long)]
+        directory: PathBuf,
+    },
+    /// Manage the users allowed to use the web interface
+    User(UserCommand),
+    /// Manage the api tokens accepted by the read and sync routes
+    Token(TokenCommand),
+}
+
+#[derive(StructOpt)]
+enum UserCommand {
+    /// List authorized users and their roles
+    List,
+    /// Authorize a user, or change the role of an existing one
+    Set {
+        email: StackString,
+        /// viewer, editor or admin
+        #[structopt(default_value = "viewer")]
+        role: Role,
+    },
+    /// Remove a user
+    Remove { email: StackString },
+}
+
+impl UserCommand {
+    async fn run(self, pool: &PgPool) -> Result<(), Error> {
+        let output = match self {
+            Self::List => {
+                let users = AuthorizedUser::get_all(pool).await?;
+                let lines: Vec<_> = users.iter().map(ToString::to_string).collect();
+                lines.join("\n")
+            }
+            Self::Set { email, role } => {
+                let user = AuthorizedUser { email, role };
+                user.upsert(pool).await?;
+                format!("set {}", user)
+            }
+            Self::Remove { email } => {
+                if AuthorizedUser::delete(pool, &email).await? {
+                    format!("removed {}", email)
+                } else {
+                    format!("no such user {}", email)
+                }
+            }
+        };
+        stdout()
+            .write_all(format!("{}\n", output).as_bytes())
+            .await?;
+        Ok(())
+    }
+}
+
+#[derive(StructOpt)]
+enum TokenCommand {
+    /// Create a token, it is only shown once
+    Create {
+        /// read or sync
+        #[structopt(long)]
+        scope: TokenScope,
+        #[structopt(long, default_value = "")]
+        description: StackString,
     },
+    /// List tokens, without the tokens themselves
+    List,
+    /// Revoke a token by id
+    Revoke { id: i32 },
+}
+
+impl TokenCommand {
+    async fn run(self, pool: &PgPool) -> Result<(), Error> {
+        let output = match self {
+            Self::Create { scope, description } => {
+                let (token, secret) = ApiToken::create(pool, scope, &description).await?;
+                format!("created {}\n{}", token, secret)
+            }
+            Self::List => {
+                let tokens = ApiToken::get_all(pool).await?;
+                let lines: Vec<_> = tokens.iter().map(ToString::to_string).collect();
+                lines.join("\n")
+            }
+            Self::Revoke { id } => {
+                if ApiToken::revoke(pool, id).await? {
+                    format!("revoked {}", id)
+                } else {
+                    format!("no active token {}", id)
+                }
+            }
+        };
+        stdout()
+            .write_all(format!("{}\n", output).as_bytes())
+            .await?;
+        Ok(())
+    }
 }
 
 impl MovieQueueCli {
     #[allow(clippy::too_many_lines)]
     async fn run() -> Result<(), Error> {
         let config = Config::with_config()?;
-        let pool = PgPool::new(&config.pgurl);
+        let pool = PgPool::new(&config.pgurl)?;
         match Self::from_args() {
-            Self::Import { table, filepath } => {
-                let data = if let Some(filepath) = filepath {
-                    read_to_string(&filepath).await?
+            Self::Import {
+                table,
+                filepath,
+                format,
+                dataset,
+                user,
+                dry_run,
+                batch_size,
+                json,
+            } => {
+                let input: Box<dyn AsyncRead + Unpin> = if let Some(filepath) = filepath {
+                    Box::new(File::open(&filepath).await?)
                 } else {
-                    let mut stdin = stdin();
-                    let mut buf = String::new();
-                    stdin.read_to_string(&mut buf).await?;
-                    buf
+                    Box::new(stdin())
                 };
-                match table.as_str() {
-                    "imdb_ratings" => {
-                        let shows: Vec<ImdbRatings> = serde_json::from_str(&data)?;
-                        let futures = shows.into_iter().map(|show| {
-                            let pool = pool.clone();
-                            async move {
-                                match ImdbRatings::get_show_by_link(show.link.as_ref(), &pool)
-                                    .await?
-                                {
-                                    Some(_) => show.update_show(&pool).await?,
-                                    None => show.insert_show(&pool).await?,
-                                };
-                                Ok(())
-                            }
-                        });
-                        let results: Result<Vec<_>, Error> = try_join_all(futures).await;
-                        stdout()
-                            .write_all(format!("imdb_ratings {}\n", results?.len()).as_bytes())
-                            .await?;
-                    }
-                    "imdb_episodes" => {
-                        let episodes: Vec<ImdbEpisodes> = serde_json::from_str(&data)?;
-                        let futures = episodes.into_iter().map(|episode| {
-                            let pool = pool.clone();
-                            async move {
-                                match episode.get_index(&pool).await? {
-                                    Some(_) => episode.update_episode(&pool).await?,
-                                    None => episode.insert_episode(&pool).await?,
-                                };
-                                Ok(())
-                            }
-                        });
-                        let results: Result<Vec<_>, Error> = try_join_all(futures).await;
-                        stdout()
-                            .write_all(format!("imdb_episodes {}\n", results?.len()).as_bytes())
-                            .await?;
-                    }
-                    "movie_collection" => {
-                        let rows: Vec<MovieCollectionRow> = serde_json::from_str(&data)?;
-                        let mc = MovieCollection::with_pool(&pool)?;
-                        let futures = rows.into_iter().map(|entry| {
-                            let mc = mc.clone();
-                            async move {
-                                if let Some(cidx) =
-                                    mc.get_collection_index(entry.path.as_ref()).await?
-                                {
-                                    if cidx == entry.idx {
-                                        return Ok(());
-                                    }
-                                    mc.remove_from_collection(entry.path.as_ref()).await?;
-                                };
-                                mc.insert_into_collection_by_idx(entry.idx, entry.path.as_ref())
-                                    .await?;
-                                Ok(())
-                            }
-                        });
-                        let results: Result<Vec<_>, Error> = try_join_all(futures).await;
-                        stdout()
-                            .write_all(format!("movie_collection {}\n", results?.len()).as_bytes())
-                            .await?;
-                    }
-                    "movie_queue" => {
-                        let mq = MovieQueueDB::with_pool(&pool);
-                        let mc = MovieCollection::with_pool(&pool)?;
-                        let entries: Vec<MovieQueueRow> = serde_json::from_str(&data)?;
-                        let futures = entries.into_iter().map(|entry| {
-                            let mq = mq.clone();
-                            let mc = mc.clone();
-                            async move {
-                                let cidx = if let Some(i) =
-                                    mc.get_collection_index(entry.path.as_ref()).await?
-                                {
-                                    i
-                                } else {
-                                    mc.insert_into_collection_by_idx(
-                                        entry.collection_idx,
-                                        entry.path.as_ref(),
-                                    )
-                                    .await?;
-                                    entry.collection_idx
-                                };
-                                assert_eq!(cidx, entry.collection_idx);
-                                mq.insert_into_queue_by_collection_idx(
-                                    entry.idx,
-                                    entry.collection_idx,
-                                )
-                                .await?;
-                                Ok(())
-                            }
-                        });
-                        let results: Result<Vec<_>, Error> = try_join_all(futures).await;
-                        stdout()
-                            .write_all(format!("movie_queue {}\n", results?.len()).as_bytes())
-                            .await?;
+                let report = if table.as_str() == "watched" {
+                    let dataset = match dataset {
+                        Some(dataset) => Some(ImdbDataset::from_file(&dataset).await?),
+                        None => None,
+                    };
+                    let user = user.as_ref().map_or(DEFAULT_USER, StackString::as_str);
+                    let format = match &format {
+                        Some(format) => Some(format.parse()?),
+                        None => None,
+                    };
+                    WatchedImport::new(&pool, format, user, dry_run, dataset)
+                        .run(input)
+                        .await?
+                } else if format
+                    .as_ref()
+                    .map_or(true, |f| ["json", "ndjson"].contains(&f.as_str()))
+                {
+                    let options = ImportOptions {
+                        dry_run,
+                        batch_size,
+                        user,
+                    };
+                    BulkImport::new(&pool, table.parse()?, options)
+                        .run(input)
+                        .await?
+                } else {
+                    let format = format.unwrap_or_default();
+                    return Err(format_err!("{} can't be imported from {}", table, format));
+                };
+                let mut output = Vec::new();
+                if json {
+                    output.push(serde_json::to_string(&report)?);
+                } else {
+                    if dry_run {
+                        output.extend(report.changes.iter().map(ToString::to_string));
                     }
-                    _ => {}
+                    output.extend(report.errors.iter().map(ToString::to_string));
+                    output.extend(report.unmatched.iter().map(|u| format!("unmatched {}", u)));
+                    output.push(report.to_string());
+                }
+                stdout()
+                    .write_all(format!("{}\n", output.join("\n")).as_bytes())
+                    .await?;
+                if !report.errors.is_empty() {
+                    return Err(format_err!("{} rows failed to import", report.errors.len()));
                 }
             }
             Self::Export {
                 table,
                 filepath,
                 start_timestamp,
+                user,
+                remote,
+                format,
             } => {
-                let start_timestamp =
-                    start_timestamp.unwrap_or_else(|| Utc::now() - Duration::days(7));
+                let mark = match &remote {
+                    Some(remote) => Some(SyncMark::get(&pool, remote, &table).await?),
+                    None => None,
+                };
+                let start_timestamp = start_timestamp
+                    .or_else(|| mark.as_ref().and_then(|m| m.last_pushed))
+                    .unwrap_or_else(|| Utc::now() - Duration::days(7));
+                // Read before exporting so nothing modified meanwhile is skipped next time
+                let exported_at = if mark.is_some() {
+                    LastModifiedResponse::get_last_modified(&pool)
+                        .await?
+                        .into_iter()
+                        .find(|m| m.table == table)
+                        .map(|m| m.last_modified)
+                } else {
+                    None
+                };
                 let mut file: Box<dyn AsyncWrite + Unpin> = if let Some(filepath) = filepath {
                     Box::new(File::create(&filepath).await?)
                 } else {
@@ -169,32 +311,86 @@ impl MovieQueueCli {
                 match table.as_str() {
                     "last_modified" => {
                         let last_modified = LastModifiedResponse::get_last_modified(&pool).await?;
-                        file.write_all(&serde_json::to_vec(&last_modified)?).await?;
+                        file.write_all(&format.to_vec(&last_modified)?).await?;
                     }
                     "imdb_ratings" => {
                         let shows =
                             ImdbRatings::get_shows_after_timestamp(start_timestamp, &pool).await?;
-                        file.write_all(&serde_json::to_vec(&shows)?).await?;
+                        file.write_all(&format.to_vec(&shows)?).await?;
                     }
                     "imdb_episodes" => {
                         let episodes =
                             ImdbEpisodes::get_episodes_after_timestamp(start_timestamp, &pool)
                                 .await?;
-                        file.write_all(&serde_json::to_vec(&episodes)?).await?;
+                        file.write_all(&format.to_vec(&episodes)?).await?;
                     }
                     "movie_collection" => {
                         let mc = MovieCollection::with_pool(&pool)?;
                         let entries = mc.get_collection_after_timestamp(start_timestamp).await?;
-                        file.write_all(&serde_json::to_vec(&entries)?).await?;
+                        file.write_all(&format.to_vec(&entries)?).await?;
                     }
                     "movie_queue" => {
                         let mq = MovieQueueDB::with_pool(&pool);
-                        let entries = mq.get_queue_after_timestamp(start_timestamp).await?;
-                        file.write_all(&serde_json::to_vec(&entries)?).await?;
+                        let mut entries = mq.get_queue_after_timestamp(start_timestamp).await?;
+                        if let Some(user) = &user {
+                            entries.retain(|entry| &entry.user_email == user);
+                        }
+                        file.write_all(&format.to_vec(&entries)?).await?;
+                    }
+                    "tombstones" => {
+                        let tombstones =
+                            Tombstone::get_after_timestamp(start_timestamp, &pool).await?;
+                        let output = if format == ExportFormat::Csv {
+                            let records: Result<Vec<_>, Error> =
+                                tombstones.iter().map(Tombstone::to_record).collect();
+                            format.to_vec(&records?)?
+                        } else {
+                            format.to_vec(&tombstones)?
+                        };
+                        file.write_all(&output).await?;
                     }
                     _ => {}
                 }
+                if let Some(mut mark) = mark {
+                    if SYNC_TABLES.contains(&table.as_str()) {
+                        mark.last_pushed = exported_at.or(mark.last_pushed);
+                        mark.upsert(&pool).await?;
+                    }
+                }
+            }
+            Self::ExportNfo { show, overwrite } => {
+                let summary = NfoExporter::new(&pool, overwrite)
+                    .export(show.as_ref().map(StackString::as_str))
+                    .await?;
+                stdout()
+                    .write_all(format!("{}\n", summary).as_bytes())
+                    .await?;
+            }
+            Self::Sync { remote, token } => {
+                let token = token.or_else(|| config.sync_token.clone());
+                let sync = RemoteSync::new(&pool, &remote, token.as_ref().map(StackString::as_str));
+                for summary in sync.sync().await? {
+                    stdout()
+                        .write_all(format!("{}\n", summary).as_bytes())
+                        .await?;
+                }
+            }
+            Self::PurgeTombstones { days } => {
+                let purged = Tombstone::purge(Utc::now() - Duration::days(days), &pool).await?;
+                stdout()
+                    .write_all(format!("purged {} tombstones\n", purged).as_bytes())
+                    .await?;
+            }
+            Self::Backup { directory } => {
+                let manifest = Backup::new(&pool).backup(&directory).await?;
+                stdout().write_all(manifest.to_string().as_bytes()).await?;
+            }
+            Self::Restore { directory } => {
+                let manifest = Backup::new(&pool).restore(&directory).await?;
+                stdout().write_all(manifest.to_string().as_bytes()).await?;
             }
+            Self::User(command) => command.run(&pool).await?,
+            Self::Token(command) => command.run(&pool).await?,
         }
 
         Ok(())
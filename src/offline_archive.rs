@@ -0,0 +1,87 @@
+#![allow(clippy::used_underscore_binding)]
+
+use anyhow::{format_err, Error};
+use stack_string::StackString;
+use std::path::PathBuf;
+use structopt::StructOpt;
+
+use movie_collection_lib::{
+    movie_collection::MovieCollection,
+    offline_archive::{OfflineArchiveEntry, OfflineVolume},
+};
+
+#[derive(StructOpt)]
+/// Manage Offline Archive
+///
+/// Index discs and external volumes into movie_collection_on_dvd
+enum OfflineArchiveOpts {
+    /// Register a volume by label
+    Register {
+        label: StackString,
+        #[structopt(long, short)]
+        description: Option<StackString>,
+    },
+    /// Index the contents of a mounted volume
+    Index {
+        label: StackString,
+        /// Mount point of the volume
+        path: PathBuf,
+    },
+    /// List registered volumes
+    List,
+    /// Search archived files
+    Search { patterns: Vec<StackString> },
+}
+
+async fn offline_archive() -> Result<(), Error> {
+    let opts = OfflineArchiveOpts::from_args();
+    let mc = MovieCollection::new();
+
+    match opts {
+        OfflineArchiveOpts::Register { label, description } => {
+            let volume = OfflineVolume::register(
+                &label,
+                description.as_ref().map(StackString::as_str),
+                &mc.pool,
+            )
+            .await?;
+            mc.stdout.send(format!("registered {}", volume));
+        }
+        OfflineArchiveOpts::Index { label, path } => {
+            let volume = OfflineVolume::get_by_label(&label, &mc.pool)
+                .await?
+                .ok_or_else(|| format_err!("Volume {} is not registered", label))?;
+            let nfiles = volume
+                .index_volume(&path, &mc.get_config().suffixes, &mc.pool)
+                .await?;
+            mc.stdout
+                .send(format!("indexed {} files on {}", nfiles, label));
+        }
+        OfflineArchiveOpts::List => {
+            for volume in OfflineVolume::get_volumes(&mc.pool).await? {
+                mc.stdout.send(volume.to_string());
+            }
+        }
+        OfflineArchiveOpts::Search { patterns } => {
+            for entry in OfflineArchiveEntry::search_offline_archive(&patterns, &mc.pool).await? {
+                mc.stdout.send(entry.to_string());
+            }
+        }
+    }
+    mc.stdout.close().await
+}
+
+#[tokio::main]
+async fn main() {
+    env_logger::init();
+
+    match offline_archive().await {
+        Ok(_) => (),
+        Err(e) => {
+            if e.to_string().contains("Broken pipe") {
+            } else {
+                panic!("{}", e)
+            }
+        }
+    }
+}
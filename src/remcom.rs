@@ -28,7 +28,7 @@ async fn remcom() -> Result<(), Error> {
     let stdout = StdoutChannel::new();
     let config = Config::with_config()?;
 
-    let remcom_service = TranscodeService::new(config.clone(), &config.remcom_queue);
+    let remcom_service = TranscodeService::new(config.clone(), &config.remcom_queue)?;
 
     for file in opts.files {
         let payload = TranscodeServiceRequest::create_remcom_request(
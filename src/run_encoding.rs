@@ -9,9 +9,9 @@ use movie_collection_lib::{config::Config, transcode_service::TranscodeService};
 async fn main() -> Result<(), Error> {
     env_logger::init();
     let config = Config::with_config().unwrap();
-    let transcode_service = TranscodeService::new(config.clone(), &config.transcode_queue);
+    let transcode_service = TranscodeService::new(config.clone(), &config.transcode_queue)?;
     transcode_service.init().await?;
-    let remcom_service = TranscodeService::new(config.clone(), &config.remcom_queue);
+    let remcom_service = TranscodeService::new(config.clone(), &config.remcom_queue)?;
     remcom_service.init().await?;
 
     let transcode_task = spawn(async move { transcode_service.read_transcode_job().await });
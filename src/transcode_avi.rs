@@ -1,17 +1,26 @@
 #![allow(clippy::used_underscore_binding)]
 
 use anyhow::Error;
+use stack_string::StackString;
 use std::path::PathBuf;
 use structopt::StructOpt;
 
 use movie_collection_lib::{
     config::Config,
     stdout_channel::StdoutChannel,
-    transcode_service::{TranscodeService, TranscodeServiceRequest},
+    transcode_service::{SubtitleMode, SubtitleOption, TranscodeService, TranscodeServiceRequest},
 };
 
 #[derive(StructOpt)]
 struct TranscodeAviOpts {
+    /// Subtitle language to keep, e.g. eng
+    #[structopt(long)]
+    subtitle_lang: Option<StackString>,
+
+    /// Burn the subtitles into the video instead of passing them through
+    #[structopt(long, requires = "subtitle-lang")]
+    burn_subtitles: bool,
+
     files: Vec<PathBuf>,
 }
 
@@ -20,7 +29,15 @@ async fn transcode_avi() -> Result<(), Error> {
     let config = Config::with_config()?;
 
     let opts = TranscodeAviOpts::from_args();
-    let transcode_service = TranscodeService::new(config.clone(), &config.transcode_queue);
+    let subtitles = opts.subtitle_lang.map(|language| SubtitleOption {
+        language,
+        mode: if opts.burn_subtitles {
+            SubtitleMode::Burn
+        } else {
+            SubtitleMode::PassThrough
+        },
+    });
+    let transcode_service = TranscodeService::new(config.clone(), &config.transcode_queue)?;
     transcode_service.init().await?;
 
     for path in opts.files {
@@ -35,7 +52,8 @@ async fn transcode_avi() -> Result<(), Error> {
         if !path.exists() {
             panic!("file doesn't exist {}", path.to_string_lossy());
         }
-        let payload = TranscodeServiceRequest::create_transcode_request(&config, &path)?;
+        let payload = TranscodeServiceRequest::create_transcode_request(&config, &path)?
+            .with_subtitles(subtitles.clone());
         transcode_service.publish_transcode_job(&payload).await?;
         stdout.send(format!("script {:?}", payload));
     }